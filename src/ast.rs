@@ -0,0 +1,110 @@
+use serde::Serialize;
+
+use crate::prelude::*;
+
+/// A position in the parsed input. Offsets are in bytes, lines and columns start at 1
+#[derive(Debug,Clone,Copy,PartialEq,Eq,Serialize)]
+pub struct AstSpan {
+	pub start: usize,
+	pub end: usize,
+	pub line: usize,
+	pub col: usize
+}
+
+impl<'a> From<Span<'a>> for AstSpan {
+	fn from(span: Span<'a>) -> Self {
+		let (line,col) = span.start_pos().line_col();
+		Self { start: span.start(), end: span.end(), line, col }
+	}
+}
+
+/// An owned copy of a node in the parse tree, for consumption by external tooling
+/// Nodes without children are the tokens of the input
+#[derive(Debug,Clone,PartialEq,Eq,Serialize)]
+pub struct AstNode {
+	pub rule: String,
+	pub text: String,
+	pub span: AstSpan,
+	#[serde(skip_serializing_if = "Vec::is_empty")]
+	pub children: Vec<AstNode>
+}
+
+impl<'a> From<Pair<'a,Rule>> for AstNode {
+	fn from(pair: Pair<'a,Rule>) -> Self {
+		let rule = format!("{:?}",pair.as_rule());
+		let text = pair.as_str().to_string();
+		let span = AstSpan::from(pair.as_span());
+		let children = pair.into_inner().map(AstNode::from).collect();
+		Self { rule, text, span, children }
+	}
+}
+
+impl AstNode {
+	pub fn is_token(&self) -> bool {
+		self.children.is_empty()
+	}
+	/// Flattens the tree into its leaf nodes, in source order
+	pub fn tokens(&self) -> Vec<&AstNode> {
+		if self.is_token() {
+			return vec![self]
+		}
+		self.children.iter().flat_map(|child| child.tokens()).collect()
+	}
+	pub fn to_json(&self) -> SlashResult<String> {
+		serde_json::to_string_pretty(self).map_err(|e| Low(SlashErrLow::InternalErr(e.to_string())))
+	}
+}
+
+/// Parses a string of shell input without executing or expanding anything
+pub fn parse(input: &str) -> SlashResult<AstNode> {
+	let main = SlashParse::parse(Rule::main, input)
+		.map_err(|e| Low(SlashErrLow::Parse(e.to_string())))?
+		.next()
+		.unpack()?;
+	Ok(AstNode::from(main))
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn test_ast_json() {
+		let ast = parse("echo hi | cat").unwrap();
+		assert_eq!(ast.rule, "main");
+		let words = ast.tokens().into_iter().filter(|tk| tk.rule == "word").map(|tk| tk.text.as_str()).collect::<Vec<_>>();
+		assert_eq!(words, vec!["echo","hi","cat"]);
+
+		let json: serde_json::Value = serde_json::from_str(&ast.to_json().unwrap()).unwrap();
+		assert_eq!(json["rule"], "main");
+		assert_eq!(json["span"]["end"], 13);
+		assert!(parse("if true; then").is_err());
+	}
+
+	#[test]
+	fn test_separators() {
+		let lists = |input: &str| parse(input).unwrap().children.iter().filter(|child| child.rule == "cmd_list").count();
+		assert_eq!(lists("a & b & c"), 3);
+		assert_eq!(lists("a; b\nc &\n\nd;"), 4);
+		assert_eq!(lists("a && b & c"), 2);
+
+		// A `&` ends a list inside of a body, just like a separator
+		for input in [
+			"if true; then a & fi",
+			"if true; then a & b; elif false; then c &\nelse d & e & fi",
+			"while false; do a & b & done",
+			"for i < 1 2; do a &\ndone",
+			"{ a & b & }",
+			"f() { a & }"
+		] {
+			assert!(parse(input).is_ok(), "{}", input);
+		}
+		assert!(parse("if true; then a fi").is_err());
+
+		// A trailing `&` applies to the whole compound command
+		let ast = parse("if true; then a; fi &").unwrap();
+		let bg_cmd = &ast.children[0].children[0];
+		assert_eq!(bg_cmd.rule, "bg_cmd");
+		assert_eq!(bg_cmd.children[0].rule, "shell_cmd");
+	}
+}
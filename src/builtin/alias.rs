@@ -1,14 +1,15 @@
-use crate::{helper, pest_ext::ARG_RULES, prelude::*, utils};
+use crate::{helper::{self, StrExtension}, pest_ext::ARG_RULES, prelude::*, utils};
 
 /// Creates a new alias from the given arguments
 /// Can create more than one alias at a time
 /// Expects the "arg_assign" rule in the inner pairs
 /// "arg_assign" appears in argument positions and looks like this: foo=bar
+/// With no arguments, or with `-p`, every alias is printed in a form that can be sourced again
 pub fn execute<'a>(alias_call: Pair<'a,Rule>, slash: &mut Slash) -> SlashResult<()> {
 	let mut stdout = utils::SmartFD::new(STDOUT_FILENO)?;
 
 	let mut args = alias_call.filter(&ARG_RULES[..]);
-	let redirs = helper::prepare_redirs(alias_call)?;
+	let redirs = helper::prepare_redirs(alias_call,slash)?;
 
 	slash.ctx_mut().extend_redirs(redirs);
 
@@ -18,18 +19,44 @@ pub fn execute<'a>(alias_call: Pair<'a,Rule>, slash: &mut Slash) -> SlashResult<
 		redirs.activate()?;
 	}
 
+	args.retain(|arg| arg.as_rule() != Rule::cmd_name);
+	let mut print_all = args.is_empty();
+	while args.front().is_some_and(|arg| arg.as_rule() == Rule::word && arg.as_str().starts_with('-')) {
+		let arg = args.pop_front().unpack()?;
+		for opt in arg.as_str().chars().skip(1) {
+			match opt {
+				'p' => print_all = true,
+				_ => {
+					let msg = format!("Invalid flag in alias call: -{}",opt);
+					return Err(High(SlashErrHigh::syntax_err(msg, arg)))
+				}
+			}
+		}
+	}
+
+	if print_all {
+		let mut aliases = slash.logic().borrow_aliases().iter().collect::<Vec<_>>();
+		aliases.sort();
+		for (alias,body) in aliases {
+			writeln!(stdout,"{}",format_alias(alias,body))?;
+		}
+	}
+
 	while let Some(arg) = args.pop_front() {
 		match arg.as_rule() {
 			Rule::arg_assign => {
 				let mut assign_inner = arg.into_inner();
 				let alias = assign_inner.next().unpack()?.as_str();
 				let body = assign_inner.next().map(|pair| pair.as_str()).unwrap_or_default();
-				helper::write_alias(slash, alias, &body.trim_quotes())?;
+				helper::write_alias(slash, alias, &body.dequote())?;
 			}
 			Rule::word => {
 				let alias = slash.logic().get_alias(arg.as_str());
-				if let Some(alias) = alias {
-					write!(stdout,"{alias}\n")?;
+				if let Some(body) = alias {
+					writeln!(stdout,"{}",format_alias(arg.as_str(),&body))?;
+				} else {
+					let msg = format!("alias: {}: not found",arg.as_str());
+					return Err(High(SlashErrHigh::exec_err(msg, arg)))
 				}
 			}
 			_ => unreachable!()
@@ -38,6 +65,11 @@ pub fn execute<'a>(alias_call: Pair<'a,Rule>, slash: &mut Slash) -> SlashResult<
 	Ok(())
 }
 
+/// Formats an alias as `alias name=body`, with the body quoted so that the line can be run again
+pub(crate) fn format_alias(alias: &str, body: &str) -> String {
+	format!("alias {}={}",alias,helper::shell_quote(body))
+}
+
 /// Removes an alias from the logic table
 pub fn unalias<'a>(pair: Pair<'a,Rule>, slash: &mut Slash) -> SlashResult<()> {
 	let mut argv = helper::prepare_argv(pair, slash)?;
@@ -63,4 +95,22 @@ use super::*;
 		execute::dispatch::exec_input(input.to_string(), &mut slash).unwrap();
 		assert!(slash.logic().get_alias("foo").is_some_and(|al| &al == "bar"))
 	}
+
+	#[test]
+	fn test_alias_quoting() {
+		let mut slash = Slash::new();
+		let input = "alias greet='echo it\\'s \"here\"';";
+		execute::dispatch::exec_input(input.to_string(), &mut slash).unwrap();
+		let body = slash.logic().get_alias("greet").unwrap();
+		assert_eq!(body, "echo it's \"here\"");
+		assert_eq!(format_alias("greet", &body), "alias greet='echo it'\\''s \"here\"'");
+
+		// Printed aliases define the same body when they are run again, even one that ends in a backslash
+		for body in ["echo it's \"here\"", "printf '%s\\n' a\\", "ls"] {
+			slash.logic_mut().remove_alias("copy");
+			let printed = format_alias("copy", body);
+			execute::dispatch::exec_input(printed, &mut slash).unwrap();
+			assert_eq!(slash.logic().get_alias("copy").unwrap(), body);
+		}
+	}
 }
@@ -0,0 +1,18 @@
+use crate::{expand::arithmetic, helper, prelude::*};
+
+/// The `let` builtin
+/// Evaluates each argument as an arithmetic expression. The exit status is 0 if the last one is non-zero, and 1 otherwise
+pub fn execute<'a>(let_call: Pair<'a,Rule>, slash: &mut Slash) -> SlashResult<()> {
+	let blame = let_call.clone();
+	let mut argv = helper::prepare_argv(let_call,slash)?;
+	argv.pop_front();
+	if argv.is_empty() {
+		return Err(High(SlashErrHigh::syntax_err("let: expression expected", blame)))
+	}
+	let mut value = 0;
+	for expr in argv {
+		value = arithmetic::eval(&expr, slash).blame(blame.clone())?;
+	}
+	slash.set_code(if value != 0 { 0 } else { 1 });
+	Ok(())
+}
@@ -1,26 +1,123 @@
 use crate::prelude::*;
 
-use crate::{helper::{self}, shellenv::Slash, SlashResult, pest_ext::Rule};
+use crate::{helper::{self}, shellenv::{EnvFlags, Slash}, SlashResult, pest_ext::Rule};
 
+/// The `cd` builtin
+/// `cd [-L|-P] [dir]` changes to `dir`, to `HOME` without one, or to `OLDPWD` given `-`.
+/// With `-L`, the default unless `set -P` is on, `PWD` keeps the path as it was written, symlinks included, and `..` goes back up it.
+/// With `-P`, symlinks are resolved and `PWD` is the real path
 pub fn execute<'a>(cd_call: Pair<'a,Rule>, slash: &mut Slash) -> SlashResult<()> {
 	let blame = cd_call.clone();
 	let mut argv = helper::prepare_argv(cd_call,slash)?;
 	argv.pop_front();
+	let mut physical = slash.meta().flags().contains(EnvFlags::NO_CD_SYMLINKS);
+	while let Some(arg) = argv.front() {
+		if arg == "--" {
+			argv.pop_front();
+			break
+		}
+		if arg.len() < 2 || !arg.starts_with('-') {
+			break
+		}
+		for opt in argv.pop_front().unwrap()[1..].chars() {
+			match opt {
+				'L' => physical = false,
+				'P' => physical = true,
+				_ => return Err(High(SlashErrHigh::syntax_err(format!("cd: -{}: invalid option", opt), blame)))
+			}
+		}
+	}
 	let new_pwd;
+	let mut from_cd_path = false;
 	match argv.pop_front() {
 		Some(arg) => {
 			if arg.as_str() == "-" {
 				new_pwd = slash.vars().get_evar("OLDPWD").unwrap_or("/".into());
 			} else {
-				new_pwd = arg.as_str().into();
+				let (target, found) = helper::cd_target(slash, &arg);
+				new_pwd = target.to_string_lossy().to_string();
+				from_cd_path = found;
 			}
 		}
 		None => {
 			new_pwd = env::var("HOME").unwrap_or("/".into());
 		}
 	}
-	slash.vars_mut().export_var("OLDPWD", &env::var("PWD").unwrap_or_default());
-	env::set_current_dir(new_pwd)?;
-	slash.vars_mut().export_var("PWD", env::current_dir().unwrap().to_str().unwrap());
+	slash.change_dir_as(Path::new(&new_pwd), physical)?;
+	// A directory found through CDPATH is printed, since it may not be the one that was expected
+	if from_cd_path {
+		println!("{}",slash.vars().get_evar("PWD").unwrap_or_default());
+	}
 	Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+	use crate::{execute, shellenv::SlashVal};
+
+use super::*;
+
+	#[test]
+	fn test_cd_path() {
+		let dir = env::temp_dir().join(format!("slash_cd_path_{}",std::process::id()));
+		std::fs::create_dir_all(dir.join("projects/inner")).unwrap();
+		std::fs::create_dir_all(dir.join("prose")).unwrap();
+		std::fs::write(dir.join("project_notes"), "").unwrap();
+		let mut slash = Slash::new();
+		slash.vars_mut().set_var("CDPATH", SlashVal::String(format!(":{}",dir.display())));
+
+		// Only directories are offered to cd, and ones found through CDPATH are marked as directories
+		let (start, comps) = crate::prompt::comp::complete_from_context(&slash, "cd pro", 6).unwrap();
+		assert_eq!(start, 3);
+		assert_eq!(comps.iter().map(|comp| comp.replacement_str()).collect::<Vec<_>>(), ["projects/", "prose/"]);
+		let line = format!("rmdir {}/pro", dir.display());
+		let (_, comps) = crate::prompt::comp::complete_from_context(&slash, &line, line.len()).unwrap();
+		assert_eq!(comps.len(), 2);
+		// Options get no paths, except for the value of a long option
+		assert!(crate::prompt::comp::complete_from_context(&slash, "slash_no_such_cmd -", 19).unwrap().1.is_empty());
+		let line = format!("ls --dir={}/project_", dir.display());
+		let (start, comps) = crate::prompt::comp::complete_from_context(&slash, &line, line.len()).unwrap();
+		assert_eq!(start, 9);
+		assert_eq!(comps.iter().map(|comp| comp.replacement_str().to_string()).collect::<Vec<_>>(), [format!("{}/project_notes ", dir.display())]);
+
+		assert_eq!(helper::cd_target(&slash, "projects/inner"), (dir.join("projects/inner"), true));
+		assert_eq!(helper::cd_target(&slash, "./projects"), (PathBuf::from("./projects"), false));
+		std::fs::remove_dir_all(dir).ok();
+	}
+
+	#[test]
+	fn test_cd_symlinks() {
+		let Some(_isolated) = crate::utils::in_own_process() else { return };
+		let dir = env::temp_dir().join(format!("slash_cd_symlinks_{}",std::process::id()));
+		std::fs::create_dir_all(dir.join("real/inner")).unwrap();
+		std::os::unix::fs::symlink(dir.join("real/inner"), dir.join("link")).unwrap();
+		let real = dir.canonicalize().unwrap();
+		let d = dir.display();
+		let mut slash = Slash::new();
+
+		// The logical path keeps the symlink, and `..` goes back the way it came
+		let output = slash.exec_captured(&format!("cd {d}/link; echo $PWD; cd ..; echo $PWD $OLDPWD")).unwrap();
+		assert_eq!(output, format!("{d}/link\n{d} {d}/link\n"));
+		let output = slash.exec_captured(&format!("cd -P {d}/link; echo $PWD; cd ..; echo $PWD")).unwrap();
+		assert_eq!(output, format!("{r}/real/inner\n{r}/real\n", r = real.display()));
+		let output = slash.exec_captured(&format!("set -P; cd {d}/link/..; echo $PWD; cd -L {d}/link/..; echo $PWD")).unwrap();
+		assert_eq!(output, format!("{r}/real\n{d}\n", r = real.display()));
+
+		// A failed cd leaves both variables alone
+		let (pwd, oldpwd) = (slash.vars().get_evar("PWD"), slash.vars().get_evar("OLDPWD"));
+		assert!(slash.change_dir_as(&dir.join("missing"), false).is_err());
+		assert_eq!((slash.vars().get_evar("PWD"), slash.vars().get_evar("OLDPWD")), (pwd, oldpwd));
+		std::fs::remove_dir_all(dir).ok();
+	}
+
+	#[test]
+	fn test_chpwd_hook() {
+		let out = env::temp_dir().join(format!("slash_chpwd_{}",std::process::id()));
+		let mut slash = Slash::new();
+		let def = format!("chpwd() {{ touch {}; }}",out.display());
+		execute::dispatch::exec_input(def, &mut slash).unwrap();
+		slash.on_dir_change(&env::temp_dir());
+		assert!(out.exists());
+		std::fs::remove_file(out).ok();
+	}
+}
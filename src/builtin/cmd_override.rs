@@ -1,6 +1,6 @@
 use crate::{helper, prelude::*, utils};
 
-use super::BUILTINS;
+use super::is_builtin;
 
 pub fn execute<'a>(pair: Pair<'a,Rule>, slash: &mut Slash, builtin: bool) -> SlashResult<()> {
 	let blame = pair.clone();
@@ -9,24 +9,34 @@ pub fn execute<'a>(pair: Pair<'a,Rule>, slash: &mut Slash, builtin: bool) -> Sla
 	let override_cmd = inner.next().unwrap();
 	let span = override_cmd.as_span();
 	let relative_span_end = span.end() - span.start();
-	let new_cmd = &old_cmd[relative_span_end..];
+	let mut new_cmd = old_cmd[relative_span_end..].trim_start();
+	// `command -p` looks the command up in the default PATH, instead of the shell's
+	let mut default_path = false;
+	if let Some(rest) = new_cmd.strip_prefix("-p").filter(|rest| !builtin && rest.starts_with(char::is_whitespace)) {
+		default_path = true;
+		new_cmd = rest.trim_start();
+	}
 	if new_cmd.trim().is_empty() {
 		return Err(High(SlashErrHigh::exec_err("Expected a command name here", blame)))
 	}
-	let new_pair = SlashParse::parse(Rule::cmd_list,new_cmd.trim_start())?
+	let mut new_cmd = new_cmd.to_string();
+	if default_path {
+		resolve_default_path(&mut new_cmd)?;
+	}
+	let new_pair = SlashParse::parse(Rule::cmd_list,&new_cmd)?
 		.next()
 		.unpack()?
 		.step(1)
 		.unpack()?;
 	let command_name = new_pair.clone().into_inner().find(|pair| pair.as_rule() == Rule::cmd_name).unpack()?.as_str();
 	if builtin {
-		if BUILTINS.contains(&command_name) {
+		if is_builtin(command_name) {
 			crate::execute::dispatch::exec_builtin(new_pair, command_name, slash)?
 		} else {
 			return Err(High(SlashErrHigh::exec_err("Expected a builtin command here", blame)))
 		}
 	} else {
-		if !BUILTINS.contains(&command_name) {
+		if !is_builtin(command_name) {
 			*slash.ctx_mut().flags_mut() |= utils::ExecFlags::IGN_FUNC;
 			crate::execute::dispatch::dispatch_exec(new_pair, slash)?
 		} else {
@@ -35,3 +45,20 @@ pub fn execute<'a>(pair: Pair<'a,Rule>, slash: &mut Slash, builtin: bool) -> Sla
 	}
 	Ok(())
 }
+
+/// Replaces the name of the command in `cmd` with the path that the default PATH gives it
+/// Builtins and names that already contain a slash are left alone
+fn resolve_default_path(cmd: &mut String) -> SlashResult<()> {
+	let (name,start,end) = {
+		let parsed = SlashParse::parse(Rule::cmd_list,cmd)?.next().unpack()?.step(1).unpack()?;
+		let name = parsed.into_inner().find(|pair| pair.as_rule() == Rule::cmd_name).unpack()?;
+		(name.as_str().to_string(),name.as_span().start(),name.as_span().end())
+	};
+	if is_builtin(&name) || name.contains('/') {
+		return Ok(())
+	}
+	if let Some(path) = helper::search_dirs(&helper::default_path(), &name) {
+		cmd.replace_range(start..end, &path.to_string_lossy());
+	}
+	Ok(())
+}
@@ -0,0 +1,133 @@
+use crate::{helper, prelude::*, utils};
+
+use super::is_builtin;
+
+/// Words that are part of the shell's syntax
+pub const KEYWORDS: [&str;14] = [
+	"if", "then", "elif", "else", "fi", "for", "while", "until", "do", "done", "in", "select", "match", "coproc"
+];
+
+/// The `type` builtin
+/// `type [-a] [-t] name...` describes how each name would be interpreted as a command.
+/// With `-a`, every alias, function, builtin, and executable in `PATH` that the name matches is shown, in the order they take precedence,
+/// so that it's clear which ones are shadowed. `-t` prints a single word instead: `alias`, `keyword`, `function`, `builtin`, or `file`.
+/// The exit status is 1 if any name is not found
+pub fn execute<'a>(type_call: Pair<'a,Rule>, slash: &mut Slash) -> SlashResult<()> {
+	let blame = type_call.clone();
+	let redirs = helper::prepare_redirs(type_call.clone(),slash)?;
+	let mut argv = helper::prepare_argv(type_call,slash)?;
+	argv.pop_front();
+	slash.consume_redirs(redirs)?;
+
+	let mut show_all = false;
+	let mut terse = false;
+	while let Some(arg) = argv.front().filter(|arg| arg.len() > 1 && arg.starts_with('-')) {
+		for opt in arg[1..].chars() {
+			match opt {
+				'a' => show_all = true,
+				't' => terse = true,
+				_ => return Err(High(SlashErrHigh::syntax_err(format!("type: -{}: invalid option", opt), blame)))
+			}
+		}
+		argv.pop_front();
+	}
+
+	let mut stdout = utils::SmartFD::new(STDOUT_FILENO)?;
+	let mut status = 0;
+	for name in argv {
+		let mut found = describe(slash, &name);
+		if !show_all {
+			found.truncate(1);
+		}
+		if found.is_empty() {
+			let mut stderr = utils::SmartFD::new(STDERR_FILENO)?;
+			writeln!(stderr,"type: {}: not found",name)?;
+			status = 1;
+			continue
+		}
+		for (kind,description) in found {
+			if terse {
+				writeln!(stdout,"{}",kind)?;
+			} else {
+				writeln!(stdout,"{} {}",name,description)?;
+			}
+		}
+	}
+	slash.set_code(status);
+	Ok(())
+}
+
+/// Everything that a name matches as a command, from highest to lowest precedence, as (kind, description) pairs
+fn describe(slash: &mut Slash, name: &str) -> Vec<(&'static str,String)> {
+	let mut found = vec![];
+	if let Some(alias) = slash.logic().get_alias(name) {
+		found.push(("alias", format!("is aliased to `{}'", alias)));
+	}
+	if KEYWORDS.contains(&name) {
+		found.push(("keyword", "is a shell keyword".into()));
+	}
+	if slash.logic().get_func(name).is_some() {
+		found.push(("function", "is a function".into()));
+	}
+	if is_builtin(name) {
+		found.push(("builtin", "is a shell builtin".into()));
+	}
+	let hashed = slash.logic().get_hashed(name);
+	let mut paths = helper::search_path_all(slash, name);
+	// A path pinned with `hash -p` is what would run, even if it isn't in PATH
+	if let Some(pinned) = hashed.as_ref().filter(|_| slash.logic().is_pinned(name)) {
+		paths.retain(|path| path != pinned);
+		paths.insert(0, pinned.clone());
+	}
+	for path in paths {
+		let description = if hashed.as_ref() == Some(&path) {
+			format!("is hashed ({})", path.display())
+		} else {
+			format!("is {}", path.display())
+		};
+		found.push(("file", description));
+	}
+	found
+}
+
+#[cfg(test)]
+mod tests {
+	use crate::{execute::dispatch::exec_input, shellenv::SlashVal};
+
+	use super::*;
+
+	#[test]
+	fn test_type() {
+		let mut slash = Slash::new();
+		exec_input("alias echo='echo -n'; cd() { builtin cd $1; }".into(), &mut slash).unwrap();
+		let kinds = describe(&mut slash, "echo").into_iter().map(|(kind,_)| kind).collect::<Vec<_>>();
+		assert_eq!(&kinds[..3], ["alias", "builtin", "file"]);
+		assert_eq!(describe(&mut slash, "cd")[0], ("function", "is a function".to_string()));
+		assert_eq!(describe(&mut slash, "if")[0].0, "keyword");
+		assert!(describe(&mut slash, "slash_no_such_command").is_empty());
+
+		assert_eq!(slash.exec_captured("type -t cd").unwrap(), "function\n");
+		assert_eq!(slash.exec_captured("type -a -t cd").unwrap(), "function\nbuiltin\n");
+		slash.exec_captured("type slash_no_such_command").unwrap();
+		assert_eq!(slash.get_status(), 1);
+	}
+
+	#[test]
+	fn test_unrunnable_cmd() {
+		let Some(_isolated) = crate::utils::in_own_process() else { return };
+		let dir = env::temp_dir().join(format!("slash_unrunnable_{}",std::process::id()));
+		std::fs::create_dir_all(dir.join("slash_subdir")).unwrap();
+		std::fs::write(dir.join("slash_data"), "not a program").unwrap();
+
+		let mut slash = Slash::new();
+		slash.vars_mut().set_var("PATH", SlashVal::String(format!("{}:/bin:/usr/bin", dir.display())));
+		exec_input("slash_data".into(), &mut slash).ok();
+		assert_eq!(slash.get_status(), 126);
+		exec_input("slash_subdir".into(), &mut slash).ok();
+		assert_eq!(slash.get_status(), 126);
+		exec_input("slash_no_such_command".into(), &mut slash).ok();
+		assert_ne!(slash.get_status(), 126);
+		assert!(describe(&mut slash, "slash_data").is_empty());
+		std::fs::remove_dir_all(dir).ok();
+	}
+}
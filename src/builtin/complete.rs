@@ -0,0 +1,148 @@
+use crate::{helper, prelude::*, prompt::comp::{CompOpts, CompSpec}, utils};
+
+/// Reads the value of an option that takes an argument, like `-F func` or `-o nospace`
+fn opt_arg<'a>(argv: &mut VecDeque<String>, builtin: &str, opt: &str, blame: Pair<'a,Rule>) -> SlashResult<String> {
+	argv.pop_front().ok_or_else(|| High(SlashErrHigh::syntax_err(format!("{}: {} requires an argument", builtin, opt), blame)))
+}
+
+/// Looks up the option named by `-o` or `+o`
+fn comp_opt<'a>(name: &str, builtin: &str, blame: Pair<'a,Rule>) -> SlashResult<CompOpts> {
+	CompOpts::by_name(name).ok_or_else(|| High(SlashErrHigh::syntax_err(format!("{}: {}: invalid option name", builtin, name), blame)))
+}
+
+fn write_spec(stdout: &mut utils::SmartFD, cmd: &str, spec: &CompSpec) -> SlashResult<()> {
+	let mut args = vec!["complete".to_string()];
+	if !spec.opts.is_empty() {
+		args.push(spec.opts.to_args());
+	}
+	if let Some(func) = &spec.func {
+		args.push(format!("-F {}", func));
+	}
+	args.push(cmd.to_string());
+	writeln!(stdout,"{}",args.join(" "))?;
+	Ok(())
+}
+
+/// The `complete` builtin
+/// `complete [-o option]... [-F func] name...` sets how the arguments of each named command are completed.
+/// The function fills `COMPREPLY` with candidates, and the options change how they are inserted: `nospace`, `filenames`, `dirnames`, and `nosort`.
+/// `complete -r name...` removes the specs, and `complete` or `complete -p` with no names prints them all
+pub fn execute<'a>(complete_call: Pair<'a,Rule>, slash: &mut Slash) -> SlashResult<()> {
+	let blame = complete_call.clone();
+	let redirs = helper::prepare_redirs(complete_call.clone(),slash)?;
+	let mut argv = helper::prepare_argv(complete_call,slash)?;
+	argv.pop_front();
+	slash.consume_redirs(redirs)?;
+
+	let mut spec = CompSpec { func: None, opts: CompOpts::empty() };
+	let mut remove = false;
+	let mut print = false;
+	while let Some(arg) = argv.front().filter(|arg| arg.len() > 1 && arg.starts_with('-')).cloned() {
+		argv.pop_front();
+		match arg.as_str() {
+			"--" => break,
+			"-F" => spec.func = Some(opt_arg(&mut argv, "complete", "-F", blame.clone())?),
+			"-o" => {
+				let name = opt_arg(&mut argv, "complete", "-o", blame.clone())?;
+				spec.opts |= comp_opt(&name, "complete", blame.clone())?;
+			}
+			"-r" => remove = true,
+			"-p" => print = true,
+			_ => return Err(High(SlashErrHigh::syntax_err(format!("complete: {}: invalid option", arg), blame)))
+		}
+	}
+
+	if remove {
+		for cmd in argv {
+			slash.logic_mut().remove_comp_spec(&cmd);
+		}
+		return Ok(())
+	}
+	if print || argv.is_empty() {
+		let mut stdout = utils::SmartFD::new(STDOUT_FILENO)?;
+		for (cmd,cmd_spec) in slash.logic().borrow_comp_specs() {
+			if argv.is_empty() || argv.contains(cmd) {
+				write_spec(&mut stdout, cmd, cmd_spec)?;
+			}
+		}
+		return Ok(())
+	}
+	for cmd in argv {
+		slash.logic_mut().set_comp_spec(&cmd, spec.clone());
+	}
+	Ok(())
+}
+
+/// The `compopt` builtin
+/// `compopt [-o option] [+o option] [name...]` turns completion options on with `-o` and off with `+o`.
+/// With names, the specs of those commands are changed. Without them, the change applies to the completion in progress,
+/// which lets a completion function decide how its candidates are inserted
+pub fn compopt<'a>(compopt_call: Pair<'a,Rule>, slash: &mut Slash) -> SlashResult<()> {
+	let blame = compopt_call.clone();
+	let redirs = helper::prepare_redirs(compopt_call.clone(),slash)?;
+	let mut argv = helper::prepare_argv(compopt_call,slash)?;
+	argv.pop_front();
+	slash.consume_redirs(redirs)?;
+
+	let mut add = CompOpts::empty();
+	let mut remove = CompOpts::empty();
+	while let Some(arg) = argv.front().filter(|arg| arg.as_str() == "-o" || arg.as_str() == "+o").cloned() {
+		argv.pop_front();
+		let name = opt_arg(&mut argv, "compopt", &arg, blame.clone())?;
+		let opt = comp_opt(&name, "compopt", blame.clone())?;
+		if arg == "-o" {
+			add |= opt;
+		} else {
+			remove |= opt;
+		}
+	}
+
+	if argv.is_empty() {
+		let Some(opts) = slash.meta().comp_opts() else {
+			return Err(High(SlashErrHigh::exec_err("compopt: not currently executing a completion function", blame)))
+		};
+		slash.meta_mut().set_comp_opts(Some((opts | add) - remove));
+		return Ok(())
+	}
+	for cmd in argv {
+		let Some(spec) = slash.logic_mut().get_comp_spec_mut(&cmd) else {
+			return Err(High(SlashErrHigh::exec_err(format!("compopt: {}: no completion specification", cmd), blame)))
+		};
+		spec.opts = (spec.opts | add) - remove;
+	}
+	Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+	use crate::{execute::dispatch::exec_input, prompt::comp::complete_from_spec};
+
+	use super::*;
+
+	#[test]
+	fn test_compopt() {
+		let dir = env::temp_dir().join(format!("slash_compopt_{}",std::process::id()));
+		std::fs::create_dir_all(dir.join("sub dir")).unwrap();
+		let mut slash = Slash::new();
+		let input = "_greet() { COMPREPLY=\"zed $2-$COMP_CWORD\"; compopt -o nospace; }; complete -o nosort -F _greet greet";
+		exec_input(input.into(), &mut slash).unwrap();
+		assert_eq!(slash.exec_captured("complete -p").unwrap(), "complete -o nosort -F _greet greet\n");
+
+		// The function turned on nospace for this completion only, and the spec kept the candidates in order
+		let (start, comps) = complete_from_spec(&slash, "greet a", 7).unwrap();
+		assert_eq!(start, 6);
+		assert_eq!(comps.iter().map(|comp| comp.replacement_str()).collect::<Vec<_>>(), ["zed", "a-1"]);
+		assert_eq!(slash.logic().get_comp_spec("greet").unwrap().opts, CompOpts::NOSORT);
+		assert!(exec_input("compopt -o nospace".into(), &mut slash).is_err());
+
+		// With no candidates, dirnames falls back to directories, which filenames escapes
+		let input = "complete -o dirnames look; compopt -o filenames look; compopt +o dirnames -o nosort look; compopt -o dirnames look".to_string();
+		exec_input(input, &mut slash).unwrap();
+		let line = format!("look {}/su", dir.display());
+		let (_, comps) = complete_from_spec(&slash, &line, line.len()).unwrap();
+		assert_eq!(comps.iter().map(|comp| comp.replacement_str().to_string()).collect::<Vec<_>>(), [format!("{}/sub\\ dir/", dir.display())]);
+		exec_input("complete -r look greet".into(), &mut slash).unwrap();
+		assert!(complete_from_spec(&slash, "greet a", 7).is_none());
+		std::fs::remove_dir_all(dir).ok();
+	}
+}
@@ -0,0 +1,187 @@
+use crate::{helper, prelude::*, shellenv::FuncAttrs, utils};
+
+/// The attributes that `declare` can add (`-r`, `-t`) or remove (`+t`)
+struct AttrChange {
+	add: FuncAttrs,
+	remove: FuncAttrs
+}
+
+/// Formats the attributes of a function as the flags that would set them, like `-ft`
+pub(crate) fn attr_flags(attrs: FuncAttrs) -> String {
+	let mut flags = String::from("-f");
+	if attrs.contains(FuncAttrs::READONLY) {
+		flags.push('r');
+	}
+	if attrs.contains(FuncAttrs::TRACE) {
+		flags.push('t');
+	}
+	flags
+}
+
+/// Writes a function in a form that can be sourced again
+/// The definition is followed by a `declare` call that restores its attributes, if it has any
+fn write_func(stdout: &mut utils::SmartFD, slash: &Slash, name: &str, names_only: bool) -> SlashResult<()> {
+	let attrs = slash.logic().func_attrs(name);
+	if names_only {
+		writeln!(stdout,"declare {} {}",attr_flags(attrs),name)?;
+		return Ok(())
+	}
+	let body = slash.logic().get_func(name).unwrap_or_default();
+	writeln!(stdout,"{}() {{ {} }}",name,body)?;
+	if !attrs.is_empty() {
+		writeln!(stdout,"declare {} {}",attr_flags(attrs),name)?;
+	}
+	Ok(())
+}
+
+/// The `declare` builtin, which manages function attributes
+/// `declare -f [name...]` prints function definitions, and `declare -F [name...]` prints only their names.
+/// `-r` makes the named functions readonly, and `-t` gives them the trace attribute so that they inherit the DEBUG and RETURN traps.
+/// Using `+` instead of `-` removes an attribute, though a readonly function stays readonly
+pub fn execute<'a>(declare_call: Pair<'a,Rule>, slash: &mut Slash) -> SlashResult<()> {
+	let blame = declare_call.clone();
+	let redirs = helper::prepare_redirs(declare_call.clone(),slash)?;
+	let mut argv = helper::prepare_argv(declare_call,slash)?;
+	argv.pop_front();
+	slash.consume_redirs(redirs)?;
+
+	let mut funcs = false;
+	let mut names_only = false;
+	let mut change = AttrChange { add: FuncAttrs::empty(), remove: FuncAttrs::empty() };
+	while let Some(arg) = argv.front().filter(|arg| arg.len() > 1 && (arg.starts_with('-') || arg.starts_with('+'))) {
+		let adding = arg.starts_with('-');
+		for opt in arg[1..].chars() {
+			let attr = match opt {
+				'f' => {
+					funcs = true;
+					continue
+				}
+				'F' => {
+					funcs = true;
+					names_only = true;
+					continue
+				}
+				'p' => continue,
+				'r' => FuncAttrs::READONLY,
+				't' => FuncAttrs::TRACE,
+				_ => return Err(High(SlashErrHigh::syntax_err(format!("declare: {}{}: invalid option", &arg[..1], opt), blame)))
+			};
+			if adding {
+				change.add |= attr;
+			} else {
+				change.remove |= attr;
+			}
+		}
+		argv.pop_front();
+	}
+	if !funcs {
+		return Err(High(SlashErrHigh::exec_err("declare: only functions are supported, use -f or -F", blame)))
+	}
+
+	let mut stdout = utils::SmartFD::new(STDOUT_FILENO)?;
+	if argv.is_empty() {
+		// Listing can be narrowed down to the functions that have the given attributes
+		let mut names = slash.logic().borrow_functions().iter()
+			.filter(|(_,func)| func.attrs().contains(change.add))
+			.map(|(name,_)| name.clone())
+			.collect::<Vec<_>>();
+		names.sort();
+		for name in names {
+			write_func(&mut stdout, slash, &name, names_only)?;
+		}
+		return Ok(())
+	}
+
+	for name in argv {
+		if slash.logic().get_func(&name).is_none() {
+			return Err(High(SlashErrHigh::exec_err(format!("declare: {}: not found", name), blame)))
+		}
+		if change.add.is_empty() && change.remove.is_empty() {
+			write_func(&mut stdout, slash, &name, names_only)?;
+			continue
+		}
+		let attrs = slash.logic().func_attrs(&name);
+		if change.remove.contains(FuncAttrs::READONLY) && attrs.contains(FuncAttrs::READONLY) {
+			return Err(High(SlashErrHigh::exec_err(format!("declare: {}: readonly function", name), blame)))
+		}
+		slash.logic_mut().set_func_attrs(&name, (attrs | change.add) - change.remove);
+	}
+	Ok(())
+}
+
+/// The `readonly` builtin
+/// `readonly -f name...` makes functions readonly, so that they can't be redefined or unset.
+/// With no names, or with `-p`, every readonly function is printed
+pub fn readonly<'a>(readonly_call: Pair<'a,Rule>, slash: &mut Slash) -> SlashResult<()> {
+	let blame = readonly_call.clone();
+	let redirs = helper::prepare_redirs(readonly_call.clone(),slash)?;
+	let mut argv = helper::prepare_argv(readonly_call,slash)?;
+	argv.pop_front();
+	slash.consume_redirs(redirs)?;
+
+	let mut funcs = false;
+	while let Some(arg) = argv.front().filter(|arg| arg.len() > 1 && arg.starts_with('-')) {
+		for opt in arg[1..].chars() {
+			match opt {
+				'f' => funcs = true,
+				'p' => {}
+				_ => return Err(High(SlashErrHigh::syntax_err(format!("readonly: -{}: invalid option", opt), blame)))
+			}
+		}
+		argv.pop_front();
+	}
+	if !funcs {
+		return Err(High(SlashErrHigh::exec_err("readonly: only functions can be made readonly, use -f", blame)))
+	}
+
+	if argv.is_empty() {
+		let mut stdout = utils::SmartFD::new(STDOUT_FILENO)?;
+		let mut names = slash.logic().borrow_functions().iter()
+			.filter(|(_,func)| func.attrs().contains(FuncAttrs::READONLY))
+			.map(|(name,_)| name.clone())
+			.collect::<Vec<_>>();
+		names.sort();
+		for name in names {
+			writeln!(stdout,"readonly -f {}",name)?;
+		}
+		return Ok(())
+	}
+
+	for name in argv {
+		let attrs = slash.logic().func_attrs(&name);
+		if !slash.logic_mut().set_func_attrs(&name, attrs | FuncAttrs::READONLY) {
+			return Err(High(SlashErrHigh::exec_err(format!("readonly: {}: not a function", name), blame)))
+		}
+	}
+	Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+	use crate::execute::dispatch::exec_input;
+
+	use super::*;
+
+	#[test]
+	fn test_func_attrs() {
+		let mut slash = Slash::new();
+		exec_input("greet() { echo hello; }; other() { echo other; }; readonly -f greet; declare -ft other".into(), &mut slash).unwrap();
+		assert_eq!(slash.logic().func_attrs("greet"), FuncAttrs::READONLY);
+		assert_eq!(slash.logic().func_attrs("other"), FuncAttrs::TRACE);
+
+		// Readonly functions can't be redefined, unset, or made writable again
+		assert!(exec_input("greet() { echo changed; }".into(), &mut slash).is_err());
+		assert!(exec_input("unset -f greet".into(), &mut slash).is_err());
+		assert!(exec_input("declare +r -f greet".into(), &mut slash).is_err());
+		assert_eq!(slash.logic().get_func("greet").as_deref(), Some("echo hello;"));
+
+		// Redefining a function keeps its attributes
+		exec_input("other() { echo redefined; }".into(), &mut slash).unwrap();
+		assert_eq!(slash.logic().func_attrs("other"), FuncAttrs::TRACE);
+		assert_eq!(slash.exec_captured("declare -F").unwrap(), "declare -fr greet\ndeclare -ft other\n");
+		assert_eq!(slash.exec_captured("declare -f other").unwrap(), "other() { echo redefined; }\ndeclare -ft other\n");
+		assert_eq!(slash.exec_captured("readonly -f").unwrap(), "readonly -f greet\n");
+		exec_input("declare +t -f other; unset -f other".into(), &mut slash).unwrap();
+		assert!(slash.logic().get_func("other").is_none());
+	}
+}
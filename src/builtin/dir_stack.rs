@@ -1,6 +1,6 @@
 use crate::prelude::*;
 
-use crate::{helper, shellenv::Slash, SlashResult};
+use crate::{helper, shellenv::Slash, utils, SlashResult};
 
 pub fn popd<'a>(popd_call: Pair<'a,Rule>, slash: &mut Slash) -> SlashResult<()> {
 	let mut argv = helper::prepare_argv(popd_call.clone(),slash)?;
@@ -45,24 +45,159 @@ pub fn popd<'a>(popd_call: Pair<'a,Rule>, slash: &mut Slash) -> SlashResult<()>
 	Ok(())
 }
 
+/// Changes to a directory, saving the current one on the directory stack
+/// With `+N` or `-N`, the stack is rotated so that entry N of `dirs` becomes the current directory,
+/// and with no argument the top two entries are swapped
 pub fn pushd<'a>(pushd_call: Pair<'a,Rule>, slash: &mut Slash) -> SlashResult<()> {
 	let blame = pushd_call.clone();
 	let mut argv = helper::prepare_argv(pushd_call,slash)?;
 	argv.pop_front();
-	match argv.pop_front() {
-		Some(arg) => {
-			let path = Path::new(arg.as_str());
-			if path.exists() {
-				if path.is_dir() {
-					slash.change_dir(path)?;
-				} else {
-					return Err(High(SlashErrHigh::syntax_err("Path is not a directory", blame)))
-				}
-			} else {
-				return Err(High(SlashErrHigh::syntax_err("Path does not exist", blame)))
+	let arg = argv.pop_front();
+	let rotation = match arg.as_deref() {
+		None => Some(1),
+		Some(arg) if is_stack_index(arg) => {
+			let Some(index) = helper::dir_stack_index(slash, arg) else {
+				return Err(High(SlashErrHigh::exec_err(format!("pushd: {}: directory stack index out of range", arg), blame)))
+			};
+			Some(index)
+		}
+		Some(_) => None
+	};
+	if let Some(rotation) = rotation {
+		let mut stack = helper::dir_stack(slash);
+		if rotation >= stack.len() {
+			return Err(High(SlashErrHigh::exec_err("pushd: no other directory", blame)))
+		}
+		if arg.is_none() {
+			stack.swap(0, 1);
+		} else {
+			stack.rotate_left(rotation);
+		}
+		let target = stack.remove(0);
+		change_dir_checked(slash, &target, blame)?;
+		slash.meta_mut().clear_dir_stack();
+		for dir in stack.into_iter().rev() {
+			slash.meta_mut().push_dir(dir);
+		}
+		return Ok(())
+	}
+	let arg = arg.unwrap_or_default();
+	let (path, _) = helper::cd_target(slash, &arg);
+	let old = helper::dir_stack(slash).remove(0);
+	change_dir_checked(slash, &path, blame)?;
+	slash.meta_mut().push_dir(old);
+	Ok(())
+}
+
+/// Whether an argument refers to an entry of the directory stack, like `+2` or `-0`
+fn is_stack_index(arg: &str) -> bool {
+	arg.len() > 1 && arg.starts_with(['+', '-']) && arg[1..].chars().all(|ch| ch.is_ascii_digit())
+}
+
+fn change_dir_checked<'a>(slash: &mut Slash, path: &Path, blame: Pair<'a,Rule>) -> SlashResult<()> {
+	if path.exists() {
+		if path.is_dir() {
+			slash.change_dir(path)
+		} else {
+			Err(High(SlashErrHigh::syntax_err("Path is not a directory", blame)))
+		}
+	} else {
+		Err(High(SlashErrHigh::syntax_err("Path does not exist", blame)))
+	}
+}
+
+/// The `dirs` builtin, which prints the directory stack with the current directory first
+/// `-v` numbers the entries one per line, `-p` prints them one per line, and `-l` doesn't shorten `HOME` to `~`.
+/// `-c` empties the stack, and `+N` or `-N` prints just that entry
+pub fn dirs<'a>(dirs_call: Pair<'a,Rule>, slash: &mut Slash) -> SlashResult<()> {
+	let blame = dirs_call.clone();
+	let redirs = helper::prepare_redirs(dirs_call.clone(),slash)?;
+	let mut argv = helper::prepare_argv(dirs_call,slash)?;
+	argv.pop_front();
+	slash.consume_redirs(redirs)?;
+	let mut stdout = utils::SmartFD::new(STDOUT_FILENO)?;
+
+	let (mut verbose, mut per_line, mut long) = (false, false, false);
+	let mut entry = None;
+	for arg in argv {
+		match arg.as_str() {
+			"-c" => {
+				slash.meta_mut().clear_dir_stack();
+				return Ok(())
 			}
+			"-v" => verbose = true,
+			"-p" => per_line = true,
+			"-l" => long = true,
+			_ if is_stack_index(&arg) => entry = Some(arg),
+			_ => return Err(High(SlashErrHigh::syntax_err(format!("dirs: {}: invalid option", arg), blame)))
+		}
+	}
+
+	let home = env::var("HOME").unwrap_or_default();
+	let display = |dir: &Path| {
+		let dir = dir.to_string_lossy().to_string();
+		if !long && !home.is_empty() && dir.starts_with(&home) {
+			dir.replacen(&home, "~", 1)
+		} else {
+			dir
+		}
+	};
+	if let Some(entry) = entry {
+		let Some(dir) = helper::dir_stack_entry(slash, &entry) else {
+			return Err(High(SlashErrHigh::exec_err(format!("dirs: {}: directory stack index out of range", entry), blame)))
+		};
+		writeln!(stdout,"{}",display(dir.as_path()))?;
+		return Ok(())
+	}
+	let stack = helper::dir_stack(slash);
+	if verbose {
+		for (i,dir) in stack.iter().enumerate() {
+			writeln!(stdout,"{:>2}  {}",i,display(dir.as_path()))?;
 		}
-		None => return Err(High(SlashErrHigh::syntax_err("Expected a directory path in pushd args", blame)))
+	} else if per_line {
+		for dir in &stack {
+			writeln!(stdout,"{}",display(dir.as_path()))?;
+		}
+	} else {
+		writeln!(stdout,"{}",stack.iter().map(|dir| display(dir.as_path())).collect::<Vec<_>>().join(" "))?;
 	}
 	Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+	use crate::expand;
+
+	use super::*;
+
+	#[test]
+	fn test_dir_stack() {
+		let Some(_isolated) = crate::utils::in_own_process() else { return };
+		let dir = env::temp_dir().join(format!("slash_dir_stack_{}",std::process::id()));
+		for sub in ["one", "two", "three"] {
+			std::fs::create_dir_all(dir.join(sub)).unwrap();
+		}
+		let mut slash = Slash::new();
+		slash.vars_mut().export_var("PWD", &dir.join("three").to_string_lossy());
+		slash.meta_mut().push_dir(dir.join("one"));
+		slash.meta_mut().push_dir(dir.join("two"));
+		let d = dir.display();
+
+		assert_eq!(slash.exec_captured("dirs").unwrap(), format!("{d}/three {d}/two {d}/one\n"));
+		assert_eq!(slash.exec_captured("dirs -v").unwrap(), format!(" 0  {d}/three\n 1  {d}/two\n 2  {d}/one\n"));
+		assert_eq!(slash.exec_captured("dirs -0").unwrap(), format!("{d}/one\n"));
+		assert_eq!(slash.exec_captured("echo ~+1 ~-0/inner").unwrap(), format!("{d}/two {d}/one/inner\n"));
+		// Rotating brings an entry to the top without losing any
+		assert_eq!(slash.exec_captured("pushd +2; dirs").unwrap(), format!("{d}/one {d}/three {d}/two\n"));
+		// Without an argument, the top two entries trade places
+		assert_eq!(slash.exec_captured("pushd +2; pushd; dirs").unwrap(), format!("{d}/three {d}/one {d}/two\n"));
+
+		assert_eq!(expand::misc::expand_prompt(Some("[\\p]"), &mut slash).unwrap(), "[2]");
+		let (_, comps) = crate::prompt::comp::complete_from_context(&slash, "cd ~+", 5).unwrap();
+		assert_eq!(comps.iter().map(|comp| comp.replacement_str().to_string()).collect::<Vec<_>>(), [format!("{d}/three/"), format!("{d}/two/"), format!("{d}/one/")]);
+
+		slash.meta_mut().clear_dir_stack();
+		assert_eq!(expand::misc::expand_prompt(Some("[\\p]"), &mut slash).unwrap(), "[]");
+		std::fs::remove_dir_all(dir).ok();
+	}
+}
@@ -1,6 +1,6 @@
 use crate::{prelude::*, utils};
 
-use crate::{error::{SlashErr::*, SlashErrHigh}, helper::{self, StrExtension}, shellenv::{write_jobs, ChildProc, JobBuilder, Slash}, SlashResult, pest_ext::Rule};
+use crate::{error::{SlashErr::*, SlashErrHigh}, helper, shellenv::{ChildProc, JobBuilder, Slash}, SlashResult, pest_ext::Rule};
 
 bitflags! {
 	#[derive(Debug)]
@@ -18,10 +18,19 @@ pub fn execute<'a>(echo_call: Pair<'a,Rule>, slash: &mut Slash) -> SlashResult<(
 	let mut argv = helper::prepare_argv(echo_call.clone(),slash)?;
 	argv.pop_front();
 	let mut arg_buffer = vec![];
-	let redirs = helper::prepare_redirs(echo_call)?;
+	let redirs = helper::prepare_redirs(echo_call,slash)?;
 
+	// POSIX echo only understands -n, and only before the first operand
+	let posix = slash.is_posix();
+	let mut operands = false;
+	let mut target_fd = None;
 	while let Some(arg) = argv.pop_front() {
-		if arg.as_str().starts_with('-') {
+		if posix && (operands || arg.len() < 2 || !arg.starts_with('-') || !arg[1..].chars().all(|ch| ch == 'n')) {
+			operands = true;
+			arg_buffer.push(arg);
+		} else if arg == "-u" {
+			target_fd = Some(helper::user_fd(argv.pop_front(), "echo", blame.clone())?);
+		} else if arg.as_str().starts_with('-') {
 			let mut options = arg.as_str().strip_prefix('-').unwrap().chars();
 			let mut new_flags = EchoFlags::empty();
 			while let Some(opt) = options.next() {
@@ -45,12 +54,12 @@ pub fn execute<'a>(echo_call: Pair<'a,Rule>, slash: &mut Slash) -> SlashResult<(
 				}
 			}
 			if new_flags.is_empty() {
-				arg_buffer.push(arg.as_str().trim_quotes().to_string());
+				arg_buffer.push(arg);
 			} else {
 				flags |= new_flags;
 			}
 		} else {
-			arg_buffer.push(arg.as_str().trim_quotes().to_string());
+			arg_buffer.push(arg);
 		}
 	}
 
@@ -58,10 +67,10 @@ pub fn execute<'a>(echo_call: Pair<'a,Rule>, slash: &mut Slash) -> SlashResult<(
 
 	let newline = !flags.contains(EchoFlags::NO_NEWLINE);
 
-	let mut target_fd = if flags.contains(EchoFlags::STDERR) {
-		utils::SmartFD::new(STDERR_FILENO)?
-	} else {
-		utils::SmartFD::new(STDOUT_FILENO)?
+	let mut target_fd = match target_fd {
+		Some(fd) => fd,
+		None if flags.contains(EchoFlags::STDERR) => utils::SmartFD::new(STDERR_FILENO)?,
+		None => utils::SmartFD::new(STDOUT_FILENO)?
 	};
 
 	slash.consume_redirs(redirs)?;
@@ -84,7 +93,8 @@ pub fn execute<'a>(echo_call: Pair<'a,Rule>, slash: &mut Slash) -> SlashResult<(
 			std::process::exit(0);
 		}
 		Ok(ForkResult::Parent { child }) => {
-			setpgid(child, child).map_err(|_| High(SlashErrHigh::io_err(blame.clone())))?;
+			// The child may already have exited
+			setpgid(child, child).ok();
 			let children = vec![
 				ChildProc::new(child, Some("echo"), None)?
 			];
@@ -94,7 +104,7 @@ pub fn execute<'a>(echo_call: Pair<'a,Rule>, slash: &mut Slash) -> SlashResult<(
 				.build();
 
 			if slash.ctx().flags().contains(utils::ExecFlags::BACKGROUND) {
-				write_jobs(|j| j.insert_job(job,false))??;
+				helper::handle_bg(slash,job)?;
 			} else {
 				helper::handle_fg(slash,job)?;
 			}
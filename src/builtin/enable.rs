@@ -0,0 +1,191 @@
+use std::{ffi::{c_char, c_int, CStr}, sync::RwLock};
+
+use once_cell::sync::Lazy;
+
+use crate::{helper, prelude::*, utils};
+
+/// The version of the loadable builtin ABI. Libraries must export `slash_builtin_abi_version`, returning this value
+///
+/// A loadable builtin named `foo` is a function exported as `slash_builtin_foo`, with the signature
+/// `int slash_builtin_foo(int argc, const char **argv)`. `argv[0]` is the name of the builtin,
+/// and the return value is used as the exit status
+pub const BUILTIN_ABI_VERSION: u32 = 1;
+
+type AbiVersionFn = unsafe extern "C" fn() -> u32;
+type BuiltinFn = unsafe extern "C" fn(c_int, *const *const c_char) -> c_int;
+
+struct LoadedBuiltin {
+	path: String,
+	handle: *mut c_void,
+	func: BuiltinFn
+}
+
+// The handle is only ever touched while holding the registry lock
+unsafe impl Send for LoadedBuiltin {}
+unsafe impl Sync for LoadedBuiltin {}
+
+static LOADED: Lazy<RwLock<HashMap<String,LoadedBuiltin>>> = Lazy::new(|| RwLock::new(HashMap::new()));
+
+/// Lets the registry be locked again after a panic that happened while it was held
+pub fn clear_poison() {
+	LOADED.clear_poison();
+}
+
+pub fn is_loaded(name: &str) -> bool {
+	LOADED.read().is_ok_and(|loaded| loaded.contains_key(name))
+}
+
+/// Manages builtins loaded from shared libraries
+/// `enable -f path name...` loads builtins, `enable -d name...` unloads them, and `enable` lists them
+pub fn execute<'a>(enable_call: Pair<'a,Rule>, slash: &mut Slash) -> SlashResult<()> {
+	let blame = enable_call.clone();
+	let redirs = helper::prepare_redirs(enable_call.clone(),slash)?;
+	let mut argv = helper::prepare_argv(enable_call,slash)?;
+	argv.pop_front();
+
+	match argv.front().map(|arg| arg.as_str()) {
+		None => {
+			slash.consume_redirs(redirs)?;
+			list_loaded()
+		}
+		Some("-f") => {
+			argv.pop_front();
+			let Some(path) = argv.pop_front() else {
+				return Err(High(SlashErrHigh::syntax_err("enable: -f requires a library path", blame)))
+			};
+			if argv.is_empty() {
+				return Err(High(SlashErrHigh::syntax_err("enable: expected a builtin name after the library path", blame)))
+			}
+			for name in argv {
+				load(&path, &name).blame(blame.clone())?;
+			}
+			Ok(())
+		}
+		Some("-d") => {
+			argv.pop_front();
+			for name in argv {
+				unload(&name).blame(blame.clone())?;
+			}
+			Ok(())
+		}
+		Some(arg) => {
+			let msg = format!("enable: invalid option: {}",arg);
+			Err(High(SlashErrHigh::syntax_err(msg, blame)))
+		}
+	}
+}
+
+fn dl_error() -> String {
+	let err = unsafe { libc::dlerror() };
+	if err.is_null() {
+		"unknown error".into()
+	} else {
+		unsafe { CStr::from_ptr(err) }.to_string_lossy().to_string()
+	}
+}
+
+fn load(path: &str, name: &str) -> SlashResult<()> {
+	if super::lookup(name).is_some() {
+		return Err(Low(SlashErrLow::ExecFailed(format!("enable: `{}' is already a builtin",name))))
+	}
+	let c_path = CString::new(path).map_err(|_| Low(SlashErrLow::ExecFailed("enable: invalid library path".into())))?;
+	let handle = unsafe { libc::dlopen(c_path.as_ptr(), libc::RTLD_NOW | libc::RTLD_LOCAL) };
+	if handle.is_null() {
+		return Err(Low(SlashErrLow::ExecFailed(format!("enable: cannot open {}: {}",path,dl_error()))))
+	}
+	let lookup = |symbol: &str| {
+		let c_symbol = CString::new(symbol).unwrap();
+		unsafe { libc::dlsym(handle, c_symbol.as_ptr()) }
+	};
+
+	let version_sym = lookup("slash_builtin_abi_version");
+	let func_sym = lookup(&format!("slash_builtin_{}",name));
+	let result = if version_sym.is_null() {
+		Err(format!("enable: {} does not export slash_builtin_abi_version",path))
+	} else if func_sym.is_null() {
+		Err(format!("enable: {} does not provide the builtin `{}'",path,name))
+	} else {
+		let version = unsafe { std::mem::transmute::<*mut c_void,AbiVersionFn>(version_sym)() };
+		if version != BUILTIN_ABI_VERSION {
+			Err(format!("enable: {} was built for builtin ABI version {}, expected {}",path,version,BUILTIN_ABI_VERSION))
+		} else {
+			Ok(unsafe { std::mem::transmute::<*mut c_void,BuiltinFn>(func_sym) })
+		}
+	};
+	let func = match result {
+		Ok(func) => func,
+		Err(msg) => {
+			unsafe { libc::dlclose(handle) };
+			return Err(Low(SlashErrLow::ExecFailed(msg)))
+		}
+	};
+
+	let mut loaded = LOADED.write().map_err(|_| Low(SlashErrLow::InternalErr("Failed to lock builtin registry".into())))?;
+	if let Some(old) = loaded.insert(name.to_string(), LoadedBuiltin { path: path.to_string(), handle, func }) {
+		unsafe { libc::dlclose(old.handle) };
+	}
+	Ok(())
+}
+
+fn unload(name: &str) -> SlashResult<()> {
+	let mut loaded = LOADED.write().map_err(|_| Low(SlashErrLow::InternalErr("Failed to lock builtin registry".into())))?;
+	match loaded.remove(name) {
+		Some(builtin) => {
+			unsafe { libc::dlclose(builtin.handle) };
+			Ok(())
+		}
+		None => Err(Low(SlashErrLow::ExecFailed(format!("enable: `{}' is not a loaded builtin",name))))
+	}
+}
+
+fn list_loaded() -> SlashResult<()> {
+	let loaded = LOADED.read().map_err(|_| Low(SlashErrLow::InternalErr("Failed to lock builtin registry".into())))?;
+	let mut names = loaded.iter().collect::<Vec<_>>();
+	names.sort_by(|a,b| a.0.cmp(b.0));
+	let mut stdout = utils::SmartFD::new(STDOUT_FILENO)?;
+	for (name,builtin) in names {
+		writeln!(stdout,"enable -f {} {}",builtin.path,name)?;
+	}
+	Ok(())
+}
+
+/// Runs a builtin that was loaded with `enable -f`, and sets the exit status to its return value
+pub fn exec_loaded<'a>(call: Pair<'a,Rule>, slash: &mut Slash) -> SlashResult<()> {
+	let blame = call.clone();
+	let redirs = helper::prepare_redirs(call.clone(),slash)?;
+	let argv = helper::prepare_argv(call,slash)?;
+	let name = argv.front().cloned().unwrap_or_default();
+	let func = LOADED.read()
+		.ok()
+		.and_then(|loaded| loaded.get(&name).map(|builtin| builtin.func))
+		.ok_or_else(|| High(SlashErrHigh::exec_err(format!("`{}' is not a loaded builtin",name), blame.clone())))?;
+
+	let c_args = argv.iter()
+		.map(|arg| CString::new(arg.as_str()))
+		.collect::<Result<Vec<_>,_>>()
+		.map_err(|_| High(SlashErrHigh::exec_err("Arguments can not contain null bytes", blame)))?;
+	let mut c_argv = c_args.iter().map(|arg| arg.as_ptr()).collect::<Vec<_>>();
+	c_argv.push(std::ptr::null());
+
+	slash.consume_redirs(redirs)?;
+	let code = unsafe { func(c_args.len() as c_int, c_argv.as_ptr()) };
+	slash.set_code(code);
+	Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+	use crate::execute;
+
+use super::*;
+
+	#[test]
+	fn test_enable_errors() {
+		let mut slash = Slash::new();
+		assert!(execute::dispatch::exec_input("enable -f /nonexistent/libfoo.so foo".to_string(), &mut slash).is_err());
+		// libc loads fine, but it doesn't speak the builtin ABI
+		assert!(execute::dispatch::exec_input("enable -f libc.so.6 foo".to_string(), &mut slash).is_err());
+		assert!(execute::dispatch::exec_input("enable -d foo".to_string(), &mut slash).is_err());
+		assert!(!is_loaded("foo"));
+	}
+}
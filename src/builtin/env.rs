@@ -0,0 +1,173 @@
+use sha2::{Digest, Sha256};
+
+use crate::{execute::command, helper, prelude::*, shellenv::DirEnv};
+
+/// The names of per-directory environment files, in order of preference
+pub const ENV_FILES: [&str;2] = [".ox-env", ".envrc"];
+
+/// Manages trust for per-directory environment files
+/// `env allow [path]` trusts the env file that applies to `path` (or the current directory), and loads it
+/// `env deny [path]` revokes that trust, and unloads the file if it is applied
+/// Any other invocation runs the external `env` command
+pub fn execute<'a>(env_call: Pair<'a,Rule>, slash: &mut Slash) -> SlashResult<()> {
+	let blame = env_call.clone();
+	let mut argv = helper::prepare_argv(env_call.clone(),slash)?;
+	argv.pop_front();
+	let allow = match argv.front().map(|arg| arg.as_str()) {
+		Some("allow") => true,
+		Some("deny") => false,
+		_ => return command::exec_cmd(env_call, slash)
+	};
+	argv.pop_front();
+
+	let target = match argv.pop_front() {
+		Some(path) => PathBuf::from(path),
+		None => env::current_dir()?
+	};
+	let file = if target.is_dir() { find_env_file(&target) } else { Some(target) };
+	let Some(file) = file.and_then(|file| file.canonicalize().ok()) else {
+		return Err(High(SlashErrHigh::exec_err("env: no env file found", blame)))
+	};
+
+	set_trust(&trust_file(), &file, allow).blame(blame)?;
+
+	update_dir_env(slash, &env::current_dir()?);
+	slash.set_code(0);
+	Ok(())
+}
+
+/// Finds the closest env file in `dir` or any of its parents
+pub fn find_env_file(dir: &Path) -> Option<PathBuf> {
+	dir.ancestors()
+		.flat_map(|dir| ENV_FILES.iter().map(move |name| dir.join(name)))
+		.find(|path| path.is_file())
+		.and_then(|path| path.canonicalize().ok())
+}
+
+/// Applies the env file for `dir` if it is trusted, after unloading the previous one if it no longer applies
+/// Called whenever the working directory changes
+pub fn update_dir_env(slash: &mut Slash, dir: &Path) {
+	apply_dir_env(slash, dir, &trust_file())
+}
+
+/// Does the work of `update_dir_env()`, checking trust against the trust file at `store`
+fn apply_dir_env(slash: &mut Slash, dir: &Path, store: &Path) {
+	let file = find_env_file(dir);
+	let loaded = slash.meta().dir_env().map(|dir_env| dir_env.file.clone());
+	let trusted = file.as_ref().is_some_and(|file| is_trusted(store, file));
+	if loaded.is_some() && loaded == file && trusted {
+		return
+	}
+
+	if let Some(dir_env) = slash.meta_mut().take_dir_env() {
+		eprintln!("env: unloading {}",dir_env.file.display());
+		for (key,val) in dir_env.saved.into_iter().rev() {
+			match val {
+				Some(val) => slash.vars_mut().export_var(&key, &val),
+				None => slash.vars_mut().unset_var(&key)
+			}
+		}
+	}
+
+	let Some(file) = file else { return };
+	if !trusted {
+		eprintln!("env: {} is blocked. Run `env allow` to trust it",file.display());
+		return
+	}
+	eprintln!("env: loading {}",file.display());
+	let before = slash.vars().evars();
+	if let Err(e) = slash.source_file(&file.to_string_lossy()) {
+		eprintln!("{}",e);
+	}
+	let after = slash.vars().evars();
+	let mut saved = before.iter()
+		.filter(|(key,val)| after.get(*key) != Some(*val))
+		.map(|(key,val)| (key.clone(), Some(val.clone())))
+		.collect::<Vec<_>>();
+	saved.extend(after.keys().filter(|key| !before.contains_key(*key)).map(|key| (key.clone(), None)));
+	slash.meta_mut().set_dir_env(DirEnv { file, saved });
+}
+
+fn trust_file() -> PathBuf {
+	let data_home = env::var("XDG_DATA_HOME").unwrap_or_else(|_| format!("{}/.local/share",env::var("HOME").unwrap_or_default()));
+	PathBuf::from(data_home).join("slash/allowed_envs")
+}
+
+/// Trust is tied to the contents of the file, so editing an allowed file blocks it again
+fn content_hash(file: &Path) -> Option<String> {
+	let content = std::fs::read(file).ok()?;
+	Some(Sha256::digest(&content).iter().map(|byte| format!("{:02x}",byte)).collect())
+}
+
+fn is_trusted(store: &Path, file: &Path) -> bool {
+	read_trusted(store).get(file).is_some_and(|hash| Some(hash) == content_hash(file).as_ref())
+}
+
+/// Adds `file` to the trust file at `store` with the hash of its current contents, or removes it
+fn set_trust(store: &Path, file: &Path, allow: bool) -> SlashResult<()> {
+	let mut trusted = read_trusted(store);
+	if allow {
+		let Some(hash) = content_hash(file) else {
+			return Err(Low(SlashErrLow::ExecFailed(format!("env: failed to read {}",file.display()))))
+		};
+		trusted.insert(file.to_path_buf(), hash);
+	} else {
+		trusted.remove(file);
+	}
+	write_trusted(store, &trusted)
+}
+
+/// The trust file holds one `hash path` pair per line
+fn read_trusted(store: &Path) -> HashMap<PathBuf,String> {
+	let content = std::fs::read_to_string(store).unwrap_or_default();
+	content.lines()
+		.filter_map(|line| line.split_once(' '))
+		.map(|(hash,path)| (PathBuf::from(path), hash.to_string()))
+		.collect()
+}
+
+fn write_trusted(store: &Path, trusted: &HashMap<PathBuf,String>) -> SlashResult<()> {
+	if let Some(parent) = store.parent() {
+		std::fs::create_dir_all(parent)?;
+	}
+	let mut lines = trusted.iter().map(|(path,hash)| format!("{} {}",hash,path.display())).collect::<Vec<_>>();
+	lines.sort();
+	std::fs::write(store, lines.join("\n") + "\n")?;
+	Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn test_dir_env() {
+		let root = env::temp_dir().join(format!("slash_dir_env_{}",std::process::id()));
+		let project = root.join("project");
+		let store = root.join("data/allowed_envs");
+		std::fs::create_dir_all(project.join("sub")).unwrap();
+		std::fs::write(project.join(".ox-env"), "export SLASH_DIR_ENV_TEST=loaded").unwrap();
+
+		let mut slash = Slash::new();
+		apply_dir_env(&mut slash, &project.join("sub"), &store);
+		assert!(slash.vars().get_evar("SLASH_DIR_ENV_TEST").is_none());
+
+		set_trust(&store, &find_env_file(&project).unwrap(), true).unwrap();
+		apply_dir_env(&mut slash, &project.join("sub"), &store);
+		assert_eq!(slash.vars().get_evar("SLASH_DIR_ENV_TEST").as_deref(), Some("loaded"));
+
+		apply_dir_env(&mut slash, &root, &store);
+		assert!(slash.vars().get_evar("SLASH_DIR_ENV_TEST").is_none());
+
+		// Changing the file revokes trust
+		std::fs::write(project.join(".ox-env"), "export SLASH_DIR_ENV_TEST=changed").unwrap();
+		apply_dir_env(&mut slash, &project, &store);
+		assert!(slash.vars().get_evar("SLASH_DIR_ENV_TEST").is_none());
+
+		// Denying a file takes it out of the trust file
+		set_trust(&store, &find_env_file(&project).unwrap(), true).unwrap();
+		set_trust(&store, &find_env_file(&project).unwrap(), false).unwrap();
+		assert!(read_trusted(&store).is_empty());
+		std::fs::remove_dir_all(root).ok();
+	}
+}
@@ -1,21 +1,39 @@
-use crate::{execute, helper, prelude::*, utils};
+use crate::{execute, helper, prelude::*, utils::{self, ExecFlags}};
 
 
 pub fn run_exec<'a>(exec_call: Pair<'a,Rule>, slash: &mut Slash) -> SlashResult<()> {
 	let mut inner = exec_call.clone().into_inner();
 	let exec = inner.next().unwrap().as_str();
-	if let Some(pair) = inner.next() {
-		match pair.as_rule() {
-			Rule::word => {
-				// Exec a command
-				let new_input = &exec_call.as_str()[exec.len()..].trim(); // slice off 'exec'
-				*slash.ctx_mut().flags_mut() |= utils::ExecFlags::NO_FORK; // we ain't comin back
-				execute::dispatch::exec_input(new_input.to_string(), slash).blame(exec_call)?;
-			}
-			Rule::redir => {
-				// Mess with file descriptors
+	if inner.clone().any(|pair| pair.as_rule() == Rule::word) {
+		// Exec a command
+		let new_input = &exec_call.as_str()[exec.len()..].trim(); // slice off 'exec'
+		*slash.ctx_mut().flags_mut() |= utils::ExecFlags::NO_FORK; // we ain't comin back
+		execute::dispatch::exec_input(new_input.to_string(), slash).blame(exec_call)?;
+	} else if inner.next().is_some() {
+		// Without a command, the redirections apply to the shell itself and stay in place, like `exec 3< file`
+		let redirs = helper::prepare_redirs(exec_call.clone(),slash)?;
+		for redir in &redirs {
+			let kept = match redir.our_fd() {
+				0 => ExecFlags::NO_RESET_IN,
+				1 => ExecFlags::NO_RESET_OUT,
+				2 => ExecFlags::NO_RESET_ERR,
+				_ => continue // Only the standard descriptors are restored after each command
+			};
+			slash.meta_mut().keep_stdio(kept);
+		}
+		let opened = redirs.iter()
+			.filter(|redir| redir.our_fd() > 2)
+			.map(|redir| (redir.our_fd(), redir.redir_type(), redir.target()))
+			.collect::<Vec<_>>();
+		let mut redirs = utils::CmdRedirs::new(redirs);
+		redirs.activate().blame(exec_call)?;
+		redirs.persist();
+		for (fd,redir_type,target) in opened {
+			if redir_type == Rule::fd_close {
+				slash.meta_mut().untrack_fd(fd);
+			} else {
+				slash.meta_mut().track_fd(fd, target);
 			}
-			_ => unreachable!()
 		}
 	}
 
@@ -1,12 +1,62 @@
 use crate::prelude::*;
 
-use crate::{helper, pest_ext::ARG_RULES, shellenv::Slash, SlashResult};
+use crate::{helper::{self, StrExtension}, pest_ext::ARG_RULES, shellenv::{Slash, SlashVal, FUNC_EXPORT_PREFIX}, utils, SlashResult};
+
+bitflags! {
+	#[derive(Debug,Clone,Copy)]
+	pub struct ExportFlags: u8 {
+		const FUNCS = 0b00001; // -f
+		const PRINT = 0b00010; // -p
+		const UNEXPORT = 0b00100; // -n
+	}
+}
 
 pub fn execute<'a>(export_call: Pair<'a,Rule>, slash: &mut Slash) -> SlashResult<()> {
+	let redirs = helper::prepare_redirs(export_call.clone(),slash)?;
 	let mut argv = export_call.filter(&ARG_RULES[..]);
+	let mut flags = ExportFlags::empty();
+
+	while argv.front().is_some_and(|arg| arg.as_rule() == Rule::word && arg.as_str().starts_with('-')) {
+		let arg = argv.pop_front().unpack()?;
+		for opt in arg.as_str().chars().skip(1) {
+			match opt {
+				'f' => flags |= ExportFlags::FUNCS,
+				'p' => flags |= ExportFlags::PRINT,
+				'n' => flags |= ExportFlags::UNEXPORT,
+				_ => {
+					let msg = format!("Invalid flag in export call: -{}",opt);
+					return Err(High(SlashErrHigh::syntax_err(msg, arg)))
+				}
+			}
+		}
+	}
+
+	argv.retain(|arg| arg.as_rule() != Rule::cmd_name);
+	if argv.is_empty() && !flags.contains(ExportFlags::UNEXPORT) {
+		slash.consume_redirs(redirs)?;
+		return print_exports(slash)
+	}
+
 	while let Some(arg) = argv.pop_front() {
 		match arg.as_rule() {
-			Rule::cmd_name => continue,
+			Rule::word if flags.contains(ExportFlags::FUNCS) => {
+				slash.export_func(arg.as_str()).blame(arg)?;
+			}
+			Rule::word if flags.contains(ExportFlags::UNEXPORT) => {
+				unexport(slash, arg.as_str(), None);
+			}
+			Rule::arg_assign if flags.contains(ExportFlags::UNEXPORT) => {
+				let mut assign_inner = arg.into_inner();
+				let var_name = assign_inner.next().unpack()?.as_str();
+				let val = match assign_inner.next() {
+					Some(pair) => helper::try_expansion(slash,pair)?,
+					None => String::new()
+				};
+				unexport(slash, var_name, Some(val));
+			}
+			Rule::word => {
+				slash.vars_mut().export(arg.as_str());
+			}
 			Rule::arg_assign => {
 				let mut assign_inner = arg.into_inner();
 				let var_name = assign_inner.next().unpack()?.as_str();
@@ -26,6 +76,27 @@ pub fn execute<'a>(export_call: Pair<'a,Rule>, slash: &mut Slash) -> SlashResult
 	Ok(())
 }
 
+/// Prints every exported variable as `export NAME="value"`, in a form that can be sourced again
+fn print_exports(slash: &Slash) -> SlashResult<()> {
+	let mut evars = slash.vars().evars().into_iter()
+		.filter(|(key,_)| !key.starts_with(FUNC_EXPORT_PREFIX))
+		.collect::<Vec<_>>();
+	evars.sort();
+	let mut stdout = utils::SmartFD::new(STDOUT_FILENO)?;
+	for (key,val) in evars {
+		writeln!(stdout,"export {}=\"{}\"",key,val.escape_dquote())?;
+	}
+	Ok(())
+}
+
+/// Removes the export attribute from a variable, keeping its value as a shell variable
+fn unexport(slash: &mut Slash, key: &str, val: Option<String>) {
+	slash.vars_mut().unexport(key);
+	if let Some(val) = val {
+		slash.vars_mut().set_var(key, SlashVal::String(val));
+	}
+}
+
 #[cfg(test)]
 mod tests {
 	use crate::execute;
@@ -44,4 +115,51 @@ use super::*;
 		assert_eq!(external_var, "foo bar".to_string());
 		assert_eq!(internal_var, "foo bar".to_string());
 	}
+
+	#[test]
+	fn test_export_func() {
+		let mut slash = Slash::new();
+		let input = "greet() { echo hello; }; export -f greet";
+
+		execute::dispatch::exec_input(input.to_string(), &mut slash).unwrap();
+
+		let encoded = env::var("OX_FUNC_greet%%").unwrap();
+		assert_eq!(encoded, "() { echo hello; }".to_string());
+
+		// A fresh shell should pick the function back up from the environment
+		let child = Slash::new();
+		assert_eq!(child.logic().get_func("greet"), Some("echo hello;".to_string()));
+	}
+
+	#[test]
+	fn test_export_unexport() {
+		let mut slash = Slash::new();
+		let input = "export UNEXPORTED=\"foo\"; export -n UNEXPORTED";
+
+		execute::dispatch::exec_input(input.to_string(), &mut slash).unwrap();
+
+		assert!(env::var("UNEXPORTED").is_err());
+		assert!(slash.vars().get_evar("UNEXPORTED").is_none());
+		assert_eq!(slash.vars().get_var("UNEXPORTED"), Some(SlashVal::String("foo".into())));
+	}
+
+	#[test]
+	fn test_export_attribute() {
+		let mut slash = Slash::new();
+		let input = "export SLASH_EMPTY_EXPORT=; SLASH_LATE_EXPORT=late; export SLASH_LATE_EXPORT; SLASH_LATE_EXPORT=changed";
+
+		execute::dispatch::exec_input(input.to_string(), &mut slash).unwrap();
+
+		// An empty value is still exported
+		assert_eq!(slash.vars().get_evar("SLASH_EMPTY_EXPORT").as_deref(), Some(""));
+		assert_eq!(env::var("SLASH_EMPTY_EXPORT").as_deref(), Ok(""));
+		// Assigning to an exported variable changes what commands see
+		let envp = slash.child_envp();
+		assert!(envp.contains(&CString::new("SLASH_LATE_EXPORT=changed").unwrap()));
+		assert!(envp.contains(&CString::new("SLASH_EMPTY_EXPORT=").unwrap()));
+
+		execute::dispatch::exec_input("unset SLASH_LATE_EXPORT".to_string(), &mut slash).unwrap();
+		assert!(!slash.vars().is_exported("SLASH_LATE_EXPORT"));
+		assert!(env::var("SLASH_LATE_EXPORT").is_err());
+	}
 }
@@ -0,0 +1,125 @@
+use crate::{helper, prelude::*, shellenv::SlashVal, utils};
+
+/// The `getopts` builtin, which reads one option at a time from the positional parameters, or from the arguments after `name`
+/// The option letter is stored in `name`, and the argument of an option followed by `:` in `optstring` is stored in `OPTARG`.
+/// `OPTIND` holds the index of the next argument to look at. Once there are no options left, `name` is set to `?` and the status is 1.
+/// An unknown option or a missing argument sets `name` to `?` and prints an error, unless `OPTERR` is 0.
+/// If `optstring` starts with `:`, nothing is printed either way, and `OPTARG` holds the option letter instead.
+/// A missing argument then sets `name` to `:`
+pub fn execute<'a>(getopts_call: Pair<'a,Rule>, slash: &mut Slash) -> SlashResult<()> {
+	let blame = getopts_call.clone();
+	let redirs = helper::prepare_redirs(getopts_call.clone(),slash)?;
+	let mut argv = helper::prepare_argv(getopts_call,slash)?;
+	argv.pop_front();
+	slash.consume_redirs(redirs)?;
+	let (Some(optstring), Some(name)) = (argv.pop_front(), argv.pop_front()) else {
+		return Err(High(SlashErrHigh::syntax_err("getopts: usage: getopts optstring name [arg...]", blame)))
+	};
+	let args = if argv.is_empty() { slash.vars().borrow_pos_params().clone() } else { argv };
+	let (silent, optstring) = match optstring.strip_prefix(':') {
+		Some(rest) => (true, rest.to_string()),
+		None => (false, optstring)
+	};
+	let report = !silent && slash.vars().get_var("OPTERR").is_none_or(|val| val.to_string().trim() != "0");
+
+	let optind = slash.vars().get_var("OPTIND")
+		.and_then(|val| val.to_string().trim().parse::<usize>().ok())
+		.filter(|optind| *optind > 0)
+		.unwrap_or(1);
+	// The offset only carries over if OPTIND hasn't been changed since the last call
+	let offset = match slash.meta().getopts_pos() {
+		Some((pos_optind,offset)) if pos_optind == optind => offset,
+		_ => 1
+	};
+	slash.meta_mut().set_getopts_pos(None);
+
+	let arg = args.get(optind - 1).map(|arg| arg.chars().collect::<Vec<_>>()).unwrap_or_default();
+	if arg.len() < 2 || arg[0] != '-' || offset >= arg.len() {
+		return finish(slash, &name, optind)
+	}
+	if offset == 1 && arg == ['-','-'] {
+		return finish(slash, &name, optind + 1)
+	}
+
+	let opt = arg[offset];
+	let rest = arg[offset + 1..].iter().collect::<String>();
+	let mut next_optind = optind;
+	if rest.is_empty() {
+		next_optind += 1;
+	} else {
+		slash.meta_mut().set_getopts_pos(Some((optind, offset + 1)));
+	}
+
+	let mut stderr = utils::SmartFD::new(STDERR_FILENO)?;
+	let takes_arg = match optstring.find(opt) {
+		Some(_) if opt == ':' => None,
+		Some(index) => Some(optstring[index + opt.len_utf8()..].starts_with(':')),
+		None => None
+	};
+	let (value, optarg) = match takes_arg {
+		None => {
+			if report {
+				writeln!(stderr,"getopts: illegal option -- {}",opt)?;
+			}
+			("?".to_string(), silent.then(|| opt.to_string()))
+		}
+		Some(false) => (opt.to_string(), None),
+		Some(true) => {
+			// The argument is the rest of this word, or else the whole next one
+			slash.meta_mut().set_getopts_pos(None);
+			if !rest.is_empty() {
+				next_optind = optind + 1;
+				(opt.to_string(), Some(rest))
+			} else if let Some(optarg) = args.get(next_optind - 1) {
+				next_optind += 1;
+				(opt.to_string(), Some(optarg.clone()))
+			} else if silent {
+				(":".to_string(), Some(opt.to_string()))
+			} else {
+				if report {
+					writeln!(stderr,"getopts: option requires an argument -- {}",opt)?;
+				}
+				("?".to_string(), None)
+			}
+		}
+	};
+
+	slash.vars_mut().set_var(&name, SlashVal::String(value));
+	match optarg {
+		Some(optarg) => slash.vars_mut().set_var("OPTARG", SlashVal::String(optarg)),
+		None => slash.vars_mut().unset_var("OPTARG")
+	}
+	slash.vars_mut().set_var("OPTIND", SlashVal::Int(next_optind as i32));
+	slash.set_code(0);
+	Ok(())
+}
+
+/// Ends option parsing, leaving `OPTIND` at the first argument that isn't an option
+fn finish(slash: &mut Slash, name: &str, optind: usize) -> SlashResult<()> {
+	slash.vars_mut().set_var(name, SlashVal::String("?".into()));
+	slash.vars_mut().unset_var("OPTARG");
+	slash.vars_mut().set_var("OPTIND", SlashVal::Int(optind as i32));
+	slash.set_code(1);
+	Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn test_getopts() {
+		let mut slash = Slash::new();
+		let script = "set -- -ab -c val -cfile -- rest; while getopts abc: opt; do echo \"$opt:$OPTARG\"; done; echo \"$OPTIND\"";
+		let output = slash.exec_captured(script).unwrap();
+		assert_eq!(output, "a:\nb:\nc:val\nc:file\n6\n");
+
+		// Errors are reported unless OPTERR is 0, and a leading colon reports them through the variable instead
+		let output = slash.exec_captured("getopts a opt -x 2>&1; echo \"$opt\"").unwrap();
+		assert_eq!(output, "getopts: illegal option -- x\n?\n");
+		let output = slash.exec_captured("OPTERR=0; getopts a opt -x 2>&1; echo \"$opt\"").unwrap();
+		assert_eq!(output, "?\n");
+		let output = slash.exec_captured("getopts :a: opt -x; echo \"$opt$OPTARG\"; OPTIND=1; getopts :a: opt -a; echo \"$opt$OPTARG\"").unwrap();
+		assert_eq!(output, "?x\n:a\n");
+	}
+}
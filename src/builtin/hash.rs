@@ -0,0 +1,102 @@
+use crate::{helper, prelude::*, utils};
+
+/// The `hash` builtin, which manages the command hash table
+/// With no arguments, every remembered command is printed as `name=path`. `-r` empties the table,
+/// and each name given is looked up in `PATH` and remembered. The exit status is 1 if a name can't be found.
+/// `-p path` maps the names to `path` instead of searching for them. Those names are kept until `hash -r`, even if `PATH` changes
+pub fn execute<'a>(hash_call: Pair<'a,Rule>, slash: &mut Slash) -> SlashResult<()> {
+	let blame = hash_call.clone();
+	let redirs = helper::prepare_redirs(hash_call.clone(),slash)?;
+	let mut argv = helper::prepare_argv(hash_call,slash)?;
+	argv.pop_front();
+	slash.consume_redirs(redirs)?;
+	let mut stdout = utils::SmartFD::new(STDOUT_FILENO)?;
+
+	let mut pin = None;
+	let mut reset = false;
+	while argv.front().is_some_and(|arg| arg.starts_with('-')) {
+		let arg = argv.pop_front().unwrap();
+		match arg.as_str() {
+			"-r" => reset = true,
+			"-p" => {
+				let Some(path) = argv.pop_front() else {
+					return Err(High(SlashErrHigh::syntax_err("hash: -p: option requires an argument", blame)))
+				};
+				pin = Some(PathBuf::from(path));
+			}
+			_ => return Err(High(SlashErrHigh::syntax_err(format!("hash: {}: invalid option", arg), blame)))
+		}
+	}
+	if reset {
+		let path = helper::shell_path(slash);
+		slash.logic_mut().reset_hashed(&path);
+	} else if argv.is_empty() && pin.is_none() {
+		for (name,path) in slash.logic().borrow_hashed() {
+			writeln!(stdout,"{}={}",name,path.display())?;
+		}
+	}
+
+	let mut status = 0;
+	for name in argv {
+		if let Some(path) = &pin {
+			slash.logic_mut().pin_cmd(&name, path.clone());
+			continue
+		}
+		if helper::which(slash, &name).is_none() {
+			let mut stderr = utils::SmartFD::new(STDERR_FILENO)?;
+			writeln!(stderr,"hash: {}: not found",name)?;
+			status = 1;
+		}
+	}
+	slash.set_code(status);
+	Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+	use std::os::unix::fs::PermissionsExt;
+
+	use crate::{execute::dispatch::exec_input, shellenv::SlashVal};
+
+	use super::*;
+
+	#[test]
+	fn test_path_search() {
+		let Some(_isolated) = utils::in_own_process() else { return };
+		let dir = env::temp_dir().join(format!("slash_path_search_{}",std::process::id()));
+		std::fs::create_dir_all(&dir).unwrap();
+		let tool = dir.join("slash_tool");
+		std::fs::write(&tool, "#!/bin/sh\necho tool ran\n").unwrap();
+		std::fs::write(dir.join("slash_data"), "not a program").unwrap();
+		std::fs::set_permissions(&tool, std::fs::Permissions::from_mode(0o755)).unwrap();
+
+		let mut slash = Slash::new();
+		// An unexported PATH is still searched
+		slash.vars_mut().set_var("PATH", SlashVal::String(format!("{}:/bin:/usr/bin", dir.display())));
+		assert_eq!(helper::which(&mut slash, "slash_tool"), Some(tool.display().to_string()));
+		assert_eq!(helper::which(&mut slash, "slash_data"), None);
+		assert_eq!(slash.exec_captured("slash_tool").unwrap(), "tool ran\n");
+		assert_eq!(slash.logic().get_hashed("slash_tool"), Some(tool.clone()));
+
+		// Changing PATH forgets what was hashed
+		exec_input("PATH=/bin:/usr/bin".into(), &mut slash).unwrap();
+		assert_eq!(helper::which(&mut slash, "slash_tool"), None);
+		assert!(slash.logic().get_hashed("slash_tool").is_none());
+
+		// A pinned name survives PATH changes, until `hash -r`
+		exec_input(format!("hash -p {} pinned_tool", tool.display()), &mut slash).unwrap();
+		exec_input("PATH=/usr/bin:/bin".into(), &mut slash).unwrap();
+		assert_eq!(helper::which(&mut slash, "pinned_tool"), Some(tool.display().to_string()));
+		assert_eq!(slash.exec_captured("pinned_tool").unwrap(), "tool ran\n");
+		exec_input("hash -r".into(), &mut slash).unwrap();
+		assert_eq!(helper::which(&mut slash, "pinned_tool"), None);
+
+		// `command -p` searches the default PATH, whatever the shell's PATH is
+		exec_input(format!("PATH={}", dir.display()), &mut slash).unwrap();
+		exec_input("sh -c 'exit 3'".into(), &mut slash).ok();
+		assert_eq!(slash.get_status(), 127);
+		exec_input("command -p sh -c 'exit 3'".into(), &mut slash).unwrap();
+		assert_eq!(slash.get_status(), 3);
+		std::fs::remove_dir_all(dir).ok();
+	}
+}
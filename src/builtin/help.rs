@@ -0,0 +1,48 @@
+use crate::{helper, prelude::*, utils};
+
+/// The `help` builtin, which prints how builtins are called
+/// With no arguments every builtin is listed. The exit status is 1 if a name isn't a builtin
+pub fn execute<'a>(help_call: Pair<'a,Rule>, slash: &mut Slash) -> SlashResult<()> {
+	let redirs = helper::prepare_redirs(help_call.clone(),slash)?;
+	let mut argv = helper::prepare_argv(help_call,slash)?;
+	argv.pop_front();
+	slash.consume_redirs(redirs)?;
+	let mut stdout = utils::SmartFD::new(STDOUT_FILENO)?;
+
+	if argv.is_empty() {
+		for spec in super::BUILTINS {
+			writeln!(stdout,"{}",spec.usage)?;
+		}
+		slash.set_code(0);
+		return Ok(())
+	}
+	let mut status = 0;
+	for name in argv {
+		match super::lookup(&name) {
+			Some(spec) => writeln!(stdout,"{}: {}",spec.name,spec.usage)?,
+			None => {
+				let mut stderr = utils::SmartFD::new(STDERR_FILENO)?;
+				writeln!(stderr,"help: no help topics match `{}'",name)?;
+				status = 1;
+			}
+		}
+	}
+	slash.set_code(status);
+	Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn test_help() {
+		let mut slash = Slash::new();
+		assert_eq!(slash.exec_captured("help cd pushd").unwrap(), "cd: cd [-L|-P] [dir]\npushd: pushd [dir | +N | -N]\n");
+		assert_eq!(slash.exec_captured("help").unwrap().lines().count(), crate::builtin::BUILTINS.len());
+		slash.exec_captured("help slash_no_such_builtin 2> /dev/null").unwrap();
+		assert_eq!(slash.get_status(), 1);
+		assert!(crate::builtin::lookup("trap").is_some_and(|spec| spec.special));
+		assert!(crate::builtin::lookup("echo").is_some_and(|spec| !spec.special));
+	}
+}
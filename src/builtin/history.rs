@@ -0,0 +1,102 @@
+use crate::{helper, prelude::*, utils};
+
+/// The `history` builtin
+/// With no options, the command history is listed, or only the last `n` entries if a number is given. `-c` clears the list.
+/// The other options move entries between the list and the history file, which is `HIST_FILE` unless a file is given:
+/// `-a` appends the commands entered this session that aren't in the file yet, `-w` replaces the file with the whole list,
+/// `-r` adds the file's entries to the list, and `-n` adds only the entries that were added to the file since it was last read.
+/// Commands are appended to the file as they are entered unless `core.hist_autosave` is turned off
+pub fn execute<'a>(history_call: Pair<'a,Rule>, slash: &mut Slash) -> SlashResult<()> {
+	let blame = history_call.clone();
+	let redirs = helper::prepare_redirs(history_call.clone(),slash)?;
+	let mut argv = helper::prepare_argv(history_call,slash)?;
+	argv.pop_front();
+	slash.consume_redirs(redirs)?;
+
+	let mut action = None;
+	while let Some(arg) = argv.front().filter(|arg| arg.len() > 1 && arg.starts_with('-')) {
+		for opt in arg[1..].chars() {
+			if !"carwn".contains(opt) {
+				return Err(High(SlashErrHigh::syntax_err(format!("history: -{}: invalid option", opt), blame)))
+			}
+			if action.is_some_and(|action| action != opt) {
+				return Err(High(SlashErrHigh::syntax_err("history: only one of -a, -c, -n, -r, and -w can be used", blame)))
+			}
+			action = Some(opt);
+		}
+		argv.pop_front();
+	}
+
+	let max_len = slash.meta().borrow_shopts().core.max_hist;
+	let path = || argv.front().map(PathBuf::from).unwrap_or_else(|| helper::hist_file(slash));
+	let result = match action {
+		Some('c') => {
+			slash.meta_mut().history_mut().clear();
+			Ok(())
+		}
+		Some('a') => {
+			let path = path();
+			slash.meta_mut().history_mut().append_file(&path)
+		}
+		Some('w') => {
+			let path = path();
+			slash.meta_mut().history_mut().write_file(&path)
+		}
+		Some('r') => {
+			let path = path();
+			slash.meta_mut().history_mut().read_file(&path, max_len)
+		}
+		Some('n') => {
+			let path = path();
+			slash.meta_mut().history_mut().read_new(&path, max_len)
+		}
+		_ => {
+			let count = match argv.front() {
+				Some(arg) => arg.parse::<usize>().map_err(|_| High(SlashErrHigh::syntax_err(format!("history: {}: invalid number", arg), blame.clone())))?,
+				None => usize::MAX
+			};
+			let entries = slash.meta().history().entries().collect::<Vec<_>>();
+			let skip = entries.len().saturating_sub(count);
+			let mut stdout = utils::SmartFD::new(STDOUT_FILENO)?;
+			for (i,entry) in entries.iter().enumerate().skip(skip) {
+				writeln!(stdout,"{:5}  {}",i + 1,entry)?;
+			}
+			Ok(())
+		}
+	};
+	result.blame(blame)
+}
+
+#[cfg(test)]
+mod tests {
+	use crate::execute::dispatch::exec_input;
+
+	use super::*;
+
+	#[test]
+	fn test_history_file() {
+		let path = env::temp_dir().join(format!("slash_history_{}",std::process::id()));
+		let mut slash = Slash::new();
+		let enter = |slash: &mut Slash, line: &str| slash.meta_mut().history_mut().push(line, true, 1000);
+		enter(&mut slash, "echo one");
+		enter(&mut slash, "echo 'two\nlines'");
+		exec_input(format!("history -a {}", path.display()), &mut slash).unwrap();
+		// Only entries that haven't been appended yet are written
+		enter(&mut slash, "echo three");
+		exec_input(format!("history -a {}", path.display()), &mut slash).unwrap();
+		assert_eq!(std::fs::read_to_string(&path).unwrap(), "#V2\necho one\necho 'two\\nlines'\necho three\n");
+
+		// Another shell sharing the file picks up what was added since it last read it
+		let mut reader = Slash::new();
+		exec_input(format!("history -r {}", path.display()), &mut reader).unwrap();
+		std::fs::write(&path, "#V2\necho one\necho 'two\\nlines'\necho three\necho four\n").unwrap();
+		exec_input(format!("history -n {}", path.display()), &mut reader).unwrap();
+		let entries = reader.meta().history().entries().map(String::from).collect::<Vec<_>>();
+		assert_eq!(entries, ["echo one", "echo 'two\nlines'", "echo three", "echo four"]);
+		assert_eq!(reader.exec_captured("history 1").unwrap(), "    4  echo four\n");
+
+		exec_input(format!("history -c; history -w {}", path.display()), &mut reader).unwrap();
+		assert_eq!(std::fs::read_to_string(&path).unwrap(), "#V2\n");
+		std::fs::remove_file(path).ok();
+	}
+}
@@ -1,10 +1,10 @@
-use crate::{helper, prelude::*, shellenv::{read_jobs, write_jobs, JobCmdFlags, JobID}, utils};
+use crate::{error::SlashErr, helper, prelude::*, shellenv::{attach_tty, disable_reaping, enable_reaping, get_tty_modes, read_jobs, set_tty_modes, write_jobs, Job, JobCmdFlags, JobID}, signal, utils};
 
 pub fn continue_job<'a>(fg_call: Pair<'a,Rule>,slash: &mut Slash, fg: bool) -> SlashResult<()> {
 	let mut stdout = utils::SmartFD::new(1)?;
 	let mut argv = helper::prepare_argv(fg_call.clone(), slash)?;
 	let blame = fg_call.clone();
-	let redirs = helper::prepare_redirs(fg_call)?;
+	let redirs = helper::prepare_redirs(fg_call,slash)?;
 	argv.pop_front();
 	slash.consume_redirs(redirs)?;
 
@@ -12,7 +12,7 @@ pub fn continue_job<'a>(fg_call: Pair<'a,Rule>,slash: &mut Slash, fg: bool) -> S
 		return Err(High(SlashErrHigh::exec_err("Somehow called fg when there is already a foreground process", blame)))
 	}
 
-	let curr_job_id = if let Some(id) = read_jobs(|j| j.curr_job())? {
+	let curr_job_id = if let Some(id) = recent_job(0)? {
 		id
 	} else {
 		return Err(High(SlashErrHigh::exec_err("Did not find a job to move to the foreground", blame)))
@@ -33,11 +33,22 @@ pub fn continue_job<'a>(fg_call: Pair<'a,Rule>,slash: &mut Slash, fg: bool) -> S
 		}
 	})??;
 
-	job.killpg(Signal::SIGCONT)?;
-
 	if fg {
-		helper::handle_fg(slash, job)?;
+		// The job gets its terminal modes and the terminal itself back before it wakes up,
+		// so that a resumed full screen program doesn't start out reading the shell's settings
+		let shell_modes = get_tty_modes();
+		if let Some(modes) = job.take_tty_modes() {
+			set_tty_modes(&modes);
+		}
+		attach_tty(job.pgid())?;
+		job.killpg(Signal::SIGCONT)?;
+		let result = helper::handle_fg(slash, job);
+		if let Some(modes) = &shell_modes {
+			set_tty_modes(modes);
+		}
+		result?;
 	} else {
+		job.killpg(Signal::SIGCONT)?;
 		let job_order = read_jobs(|j| j.job_order().to_vec())?;
 		writeln!(stdout, "{}", job.display(&job_order, JobCmdFlags::PIDS))?;
 
@@ -49,7 +60,7 @@ pub fn continue_job<'a>(fg_call: Pair<'a,Rule>,slash: &mut Slash, fg: bool) -> S
 
 pub fn jobs<'a>(jobs_call: Pair<'a,Rule>,slash: &mut Slash) -> SlashResult<()> {
 	let mut argv = helper::prepare_argv(jobs_call.clone(), slash)?;
-	let mut redirs = helper::prepare_redirs(jobs_call.clone())?;
+	let mut redirs = helper::prepare_redirs(jobs_call.clone(),slash)?;
 	let mut stdout = utils::SmartFD::new(1)?;
 	slash.consume_redirs(redirs)?;
 	let blame = jobs_call;
@@ -57,6 +68,17 @@ pub fn jobs<'a>(jobs_call: Pair<'a,Rule>,slash: &mut Slash) -> SlashResult<()> {
 
 	let mut flags = JobCmdFlags::empty();
 	while let Some(arg) = argv.pop_front() {
+		match arg.as_str() {
+			"--json" => {
+				flags |= JobCmdFlags::JSON;
+				continue
+			}
+			"--porcelain" => {
+				flags |= JobCmdFlags::PORCELAIN;
+				continue
+			}
+			_ => { /* Do nothing */ }
+		}
 		let mut chars = arg.chars().peekable();
 		if chars.peek().is_none_or(|ch| *ch != '-') {
 			return Err(High(SlashErrHigh::syntax_err(format!("Invalid flag in `jobs' call: {}",arg), blame)))
@@ -81,41 +103,391 @@ pub fn jobs<'a>(jobs_call: Pair<'a,Rule>,slash: &mut Slash) -> SlashResult<()> {
 	Ok(())
 }
 
+/// Waits for background jobs to finish
+/// With no arguments every job is waited on. Jobs can be given as job specs like `%n`, `%+`, and `%-`, or as the pid of one of their processes
+/// The exit status is that of the last job waited on
+pub fn wait<'a>(wait_call: Pair<'a,Rule>, slash: &mut Slash) -> SlashResult<()> {
+	let blame = wait_call.clone();
+	let mut argv = helper::prepare_argv(wait_call, slash)?;
+	argv.pop_front();
+
+	let mut targets = vec![];
+	if argv.is_empty() {
+		targets = write_jobs(|j| {
+			j.mut_jobs().iter().flatten().filter_map(|job| job.table_id()).map(WaitTarget::Job).collect::<Vec<_>>()
+		})?;
+	}
+	while let Some(arg) = argv.pop_front() {
+		if !arg.starts_with('%') && arg.parse::<i32>().is_err() {
+			return Err(High(SlashErrHigh::syntax_err(format!("Invalid wait argument: {}",arg), blame)))
+		}
+		// A job that finished and left the table can still be waited on by pid, until its saved status is used
+		let target = match find_job(&arg)? {
+			Some(table_id) => Some(WaitTarget::Job(table_id)),
+			None => match arg.parse::<i32>() {
+				Ok(pid) => write_jobs(|j| j.take_exited(Pid::from_raw(pid)).map(WaitTarget::Exited))?,
+				Err(_) => None
+			}
+		};
+		match target {
+			Some(target) => targets.push(target),
+			None => return Err(High(SlashErrHigh::exec_err(format!("wait: `{}' is not a child of this shell",arg), blame)))
+		}
+	}
+
+	let mut code = 0;
+	disable_reaping();
+	for target in targets {
+		match target {
+			WaitTarget::Job(table_id) => {
+				let job = write_jobs(|j| j.remove_job(JobID::TableID(table_id)))?;
+				if let Some(mut job) = job {
+					code = wait_job(&mut job);
+				}
+			}
+			WaitTarget::Exited(status) => code = status
+		}
+	}
+	enable_reaping()?;
+	slash.set_code(code);
+	Ok(())
+}
+
+/// Something for `wait` to wait on: a job in the table, or the saved status of one that already left it
+enum WaitTarget {
+	Job(usize),
+	Exited(i32)
+}
+
+/// Blocks until every process in the job has finished, and returns the job's exit status
+fn wait_job(job: &mut Job) -> i32 {
+	for child in job.get_children_mut() {
+		if matches!(child.status(), WaitStatus::StillAlive | WaitStatus::Continued(_)) {
+			// ECHILD here means the process was already reaped, in which case the stored status is kept
+			let _ = child.waitpid(None);
+		}
+	}
+	job.exit_code().unwrap_or(0)
+}
+
+/// Finds the table id of the job named by an argument to `fg` or `bg`
+/// Besides job specs and pids, a bare number that isn't a process is taken as a job number
 fn parse_job_id<'a>(arg: &str, blame: Pair<'a,Rule>) -> SlashResult<usize> {
-	if arg.starts_with('%') {
-		let arg = arg.strip_prefix('%').unwrap();
-		if arg.chars().all(|ch| ch.is_ascii_digit()) {
-			Ok(arg.parse::<usize>().unwrap())
-		} else {
-			let result = write_jobs(|j| {
-				let query_result = j.query(JobID::Command(arg.into()));
-				query_result.map(|job| job.table_id().unwrap())
-			})?;
-			match result {
-				Some(id) => Ok(id),
-				None => Err(High(SlashErrHigh::internal_err("Found a job but no table id in parse_job_id()", blame)))
+	let mut result = find_job(arg)?;
+	if result.is_none() && !arg.starts_with('%') {
+		if let Ok(num) = arg.parse::<usize>() {
+			result = read_jobs(|j| j.query(JobID::TableID(num.saturating_sub(1))).and_then(|job| job.table_id()))?;
+		}
+	}
+	match result {
+		Some(id) => Ok(id),
+		None => Err(High(SlashErrHigh::exec_err(format!("{}: no such job",arg), blame)))
+	}
+}
+
+/// Finds the table id of the job named by a job spec, or by the pid or process group of one of its processes
+/// `%n` is job number n. `%%`, `%+`, and a lone `%` are the current job, and `%-` is the one before it.
+/// `%name` and `%?name` are a job whose command contains `name`
+fn find_job(spec: &str) -> SlashResult<Option<usize>> {
+	let id = if let Some(spec) = spec.strip_prefix('%') {
+		match spec {
+			"" | "%" | "+" => return recent_job(0),
+			"-" => return recent_job(1),
+			_ => match spec.parse::<usize>() {
+				Ok(num) => JobID::TableID(num.saturating_sub(1)),
+				Err(_) => JobID::Command(spec.strip_prefix('?').unwrap_or(spec).into())
 			}
 		}
-	} else if arg.chars().all(|ch| ch.is_ascii_digit()) {
-		let result = write_jobs(|j| {
-			let pgid_query_result = j.query(JobID::Pgid(Pid::from_raw(arg.parse::<i32>().unwrap())));
-			if let Some(job) = pgid_query_result {
-				return Some(job.table_id().unwrap())
+	} else if let Ok(pid) = spec.parse::<i32>() {
+		let pid = Pid::from_raw(pid);
+		return read_jobs(|j| {
+			j.query(JobID::Pgid(pid))
+				.or_else(|| j.query(JobID::Pid(pid)))
+				.and_then(|job| job.table_id())
+		})
+	} else {
+		return Ok(None)
+	};
+	read_jobs(|j| j.query(id).and_then(|job| job.table_id()))
+}
+
+/// The table id of the `nth` most recently started or stopped job that is still in the table
+/// The current job is 0 and the previous job is 1
+fn recent_job(nth: usize) -> SlashResult<Option<usize>> {
+	read_jobs(|j| {
+		let mut recent: Vec<usize> = vec![];
+		for id in j.job_order().iter().rev() {
+			if !recent.contains(id) && j.query(JobID::TableID(*id)).is_some() {
+				recent.push(*id);
 			}
+		}
+		recent.get(nth).copied()
+	})
+}
 
-			if arg.parse::<i32>().unwrap() > 0 {
-				let table_id_query_result = j.query(JobID::TableID(arg.parse::<usize>().unwrap()));
-				return table_id_query_result.map(|job| job.table_id().unwrap());
+/// Reads a signal given by name, with or without the `SIG` prefix, or by number
+fn parse_signal(arg: &str) -> Option<Signal> {
+	if let Ok(num) = arg.parse::<i32>() {
+		return Signal::try_from(num).ok()
+	}
+	let name = arg.to_ascii_uppercase();
+	let name = if name.starts_with("SIG") { name } else { format!("SIG{}",name) };
+	name.parse::<Signal>().ok()
+}
+
+/// The `kill` builtin, which sends a signal to jobs and processes
+/// Targets are pids or job specs, and a job is signalled as a whole process group. The signal is SIGTERM unless one is given as `-s sig`, `-n num`, `-sig`, or `-num`.
+/// A stopped job that is sent SIGTERM or SIGHUP is also continued, so that it can act on the signal.
+/// `kill -l` lists the signal names, or translates each signal number or exit status given to it. The exit status is 1 if any target couldn't be signalled
+pub fn kill<'a>(kill_call: Pair<'a,Rule>, slash: &mut Slash) -> SlashResult<()> {
+	let blame = kill_call.clone();
+	let redirs = helper::prepare_redirs(kill_call.clone(),slash)?;
+	let mut argv = helper::prepare_argv(kill_call,slash)?;
+	argv.pop_front();
+	slash.consume_redirs(redirs)?;
+	let mut stderr = utils::SmartFD::new(STDERR_FILENO)?;
+
+	let mut signal = Signal::SIGTERM;
+	match argv.front().cloned().as_deref() {
+		Some("-l" | "-L") => {
+			argv.pop_front();
+			return list_signals(argv, slash, blame)
+		}
+		Some("-s" | "-n") => {
+			argv.pop_front();
+			let Some(arg) = argv.pop_front() else {
+				return Err(High(SlashErrHigh::syntax_err("kill: option requires a signal", blame)))
+			};
+			signal = parse_signal(&arg).ok_or_else(|| High(SlashErrHigh::exec_err(format!("kill: {}: invalid signal", arg), blame.clone())))?;
+		}
+		Some(arg) if arg.len() > 1 && arg.starts_with('-') && arg != "--" => {
+			signal = parse_signal(&arg[1..]).ok_or_else(|| High(SlashErrHigh::exec_err(format!("kill: {}: invalid signal", &arg[1..]), blame.clone())))?;
+			argv.pop_front();
+		}
+		_ => { /* SIGTERM */ }
+	}
+	// Negative pids, which signal a whole process group, come after `--`
+	if argv.front().is_some_and(|arg| arg == "--") {
+		argv.pop_front();
+	}
+	if argv.is_empty() {
+		return Err(High(SlashErrHigh::syntax_err("kill: usage: kill [-s sig | -sig] pid|%job... | kill -l [sig]", blame)))
+	}
+
+	let mut status = 0;
+	for arg in argv {
+		let result = if arg.starts_with('%') {
+			match find_job(&arg)? {
+				Some(id) => {
+					write_jobs(|j| {
+						let job = j.query_mut(JobID::TableID(id)).ok_or(Low(SlashErrLow::ErrNo(Errno::ESRCH)))?;
+						let stopped = job.is_stopped();
+						job.killpg(signal)?;
+						if stopped && matches!(signal, Signal::SIGTERM | Signal::SIGHUP) {
+							job.killpg(Signal::SIGCONT)?;
+						}
+						Ok::<(), SlashErr>(())
+					})?.map_err(|_| "no such process".to_string())
+				}
+				None => Err("no such job".to_string())
+			}
+		} else if let Ok(pid) = arg.parse::<i32>() {
+			nix::sys::signal::kill(Pid::from_raw(pid), signal).map_err(|err| err.desc().to_lowercase())
+		} else {
+			Err("arguments must be process or job IDs".to_string())
+		};
+		if let Err(msg) = result {
+			writeln!(stderr,"kill: {}: {}",arg,msg)?;
+			status = 1;
+		}
+	}
+	slash.set_code(status);
+	Ok(())
+}
+
+/// `kill -l`, which prints every signal name, or translates between the names and numbers given
+/// A number above 128 is taken as the exit status of a process killed by that signal
+fn list_signals<'a>(argv: VecDeque<String>, slash: &mut Slash, blame: Pair<'a,Rule>) -> SlashResult<()> {
+	let mut stdout = utils::SmartFD::new(STDOUT_FILENO)?;
+	if argv.is_empty() {
+		writeln!(stdout,"{}",signal::signal_names().join(" "))?;
+		slash.set_code(0);
+		return Ok(())
+	}
+	for arg in argv {
+		let output = match arg.parse::<i32>() {
+			Ok(num) => {
+				let num = if num > utils::SIG_EXIT_OFFSET { num - utils::SIG_EXIT_OFFSET } else { num };
+				Signal::try_from(num).ok().map(|sig| sig.as_str().trim_start_matches("SIG").to_string())
+			}
+			Err(_) => parse_signal(&arg).map(|sig| (sig as i32).to_string())
+		};
+		match output {
+			Some(output) => writeln!(stdout,"{}",output)?,
+			None => return Err(High(SlashErrHigh::exec_err(format!("kill: {}: invalid signal", arg), blame)))
+		}
+	}
+	slash.set_code(0);
+	Ok(())
+}
+
+/// The `disown` builtin, which stops the shell from tracking jobs
+/// Each job given, or the current job if none are, is taken out of the table, so it is no longer listed, waited on, or sent SIGHUP when the shell exits.
+/// `-a` disowns every job and `-r` every running job. With `-h`, the jobs stay in the table but are left alone when the shell hangs up
+pub fn disown<'a>(disown_call: Pair<'a,Rule>, slash: &mut Slash) -> SlashResult<()> {
+	let blame = disown_call.clone();
+	let redirs = helper::prepare_redirs(disown_call.clone(),slash)?;
+	let mut argv = helper::prepare_argv(disown_call,slash)?;
+	argv.pop_front();
+	slash.consume_redirs(redirs)?;
+
+	let (mut all, mut running, mut nohup) = (false, false, false);
+	while argv.front().is_some_and(|arg| arg.len() > 1 && arg.starts_with('-')) {
+		let arg = argv.pop_front().unwrap();
+		if arg == "--" {
+			break
+		}
+		for ch in arg.chars().skip(1) {
+			match ch {
+				'a' => all = true,
+				'r' => running = true,
+				'h' => nohup = true,
+				_ => return Err(High(SlashErrHigh::syntax_err(format!("disown: -{}: invalid option", ch), blame)))
 			}
+		}
+	}
 
-			None
+	let mut targets = vec![];
+	if argv.is_empty() && (all || running) {
+		targets = read_jobs(|j| {
+			j.job_order().iter()
+				.filter_map(|id| j.query(JobID::TableID(*id)))
+				.filter(|job| !running || (job.is_alive() && !job.is_stopped()))
+				.filter_map(|job| job.table_id())
+				.collect::<Vec<_>>()
 		})?;
+	} else if argv.is_empty() {
+		match recent_job(0)? {
+			Some(id) => targets.push(id),
+			None => return Err(High(SlashErrHigh::exec_err("disown: no current job", blame)))
+		}
+	}
+	for arg in argv {
+		match find_job(&arg)? {
+			Some(id) => targets.push(id),
+			None => return Err(High(SlashErrHigh::exec_err(format!("disown: {}: no such job", arg), blame)))
+		}
+	}
+
+	write_jobs(|j| {
+		for id in targets {
+			if nohup {
+				if let Some(job) = j.query_mut(JobID::TableID(id)) {
+					job.set_nohup();
+				}
+			} else {
+				j.disown(id);
+			}
+		}
+	})?;
+	Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+	use crate::execute;
+
+use super::*;
+
+	#[test]
+	fn test_wait_bg_status() {
+		let mut slash = Slash::new();
+		execute::dispatch::exec_input("sh -c 'exit 3' &".to_string(), &mut slash).unwrap();
+		let pid = slash.vars().get_param("!").unwrap();
+		assert!(pid.parse::<i32>().is_ok());
 
-		match result {
-			Some(id) => Ok(id),
-			None => Err(High(SlashErrHigh::internal_err("Found a job but no table id in parse_job_id()", blame)))
+		execute::dispatch::exec_input("wait $!".to_string(), &mut slash).unwrap();
+		assert_eq!(slash.get_status(), 3);
+	}
+
+	#[test]
+	fn test_wait_retired_job() {
+		let mut slash = Slash::new();
+		execute::dispatch::exec_input("sh -c 'exit 4' &".to_string(), &mut slash).unwrap();
+		let pid = Pid::from_raw(slash.vars().get_param("!").unwrap().parse::<i32>().unwrap());
+		// The child is reaped either here or by a SIGCHLD handler that another test left installed
+		while write_jobs(|j| {
+			let job = j.query_mut(JobID::Pid(pid)).unwrap();
+			for child in job.get_children_mut() {
+				child.waitpid(Some(nix::sys::wait::WaitPidFlag::WNOHANG)).ok();
+			}
+			job.is_alive()
+		}).unwrap() {
+			std::thread::sleep(std::time::Duration::from_millis(10));
 		}
-	} else {
-		Err(High(SlashErrHigh::syntax_err(format!("Invalid fg argument: {}",arg), blame)))
+		write_jobs(|j| j.retire_finished(usize::MAX)).unwrap();
+		assert!(read_jobs(|j| j.query(JobID::Pid(pid)).is_none()).unwrap());
+
+		execute::dispatch::exec_input("wait $!".to_string(), &mut slash).unwrap();
+		assert_eq!(slash.get_status(), 4);
+		// The saved status is only given out once
+		assert!(execute::dispatch::exec_input("wait $!".to_string(), &mut slash).is_err());
+	}
+
+	#[test]
+	fn test_disowned_job() {
+		let mut slash = Slash::new();
+		execute::dispatch::exec_input("sh -c 'exit 0' &!".to_string(), &mut slash).unwrap();
+		let pid = slash.vars().get_param("!").unwrap().parse::<i32>().unwrap();
+		let is_tracked = read_jobs(|j| j.query(JobID::Pid(Pid::from_raw(pid))).is_some()).unwrap();
+		assert!(!is_tracked);
+	}
+
+	#[test]
+	fn test_bg_compound() {
+		let mut slash = Slash::new();
+		// The whole loop runs in one background job, so the condition is not backgrounded on its own
+		execute::dispatch::exec_input("while false; do echo no; done &".to_string(), &mut slash).unwrap();
+		execute::dispatch::exec_input("wait $!".to_string(), &mut slash).unwrap();
+		assert_eq!(slash.get_status(), 0);
+
+		execute::dispatch::exec_input("{ sh -c 'exit 4'; } &".to_string(), &mut slash).unwrap();
+		execute::dispatch::exec_input("wait $!".to_string(), &mut slash).unwrap();
+		assert_eq!(slash.get_status(), 4);
+	}
+
+	#[test]
+	fn test_kill_job() {
+		let mut slash = Slash::new();
+		execute::dispatch::exec_input("sleep 7.31 &".to_string(), &mut slash).unwrap();
+		execute::dispatch::exec_input("kill %?7.31".to_string(), &mut slash).unwrap();
+		assert_eq!(slash.get_status(), 0);
+		execute::dispatch::exec_input("wait $!".to_string(), &mut slash).unwrap();
+		assert_eq!(slash.get_status(), 143);
+
+		execute::dispatch::exec_input("sleep 7.32 &".to_string(), &mut slash).unwrap();
+		execute::dispatch::exec_input("kill -s KILL $!; wait $!".to_string(), &mut slash).unwrap();
+		assert_eq!(slash.get_status(), 137);
+
+		execute::dispatch::exec_input("kill %?no_such_job_7.33".to_string(), &mut slash).unwrap();
+		assert_eq!(slash.get_status(), 1);
+		assert!(execute::dispatch::exec_input("kill -NOSUCHSIG 1".to_string(), &mut slash).is_err());
+
+		assert_eq!(slash.exec_captured("kill -l 143 INT").unwrap(), "TERM\n2\n");
+	}
+
+	#[test]
+	fn test_disown() {
+		let mut slash = Slash::new();
+		execute::dispatch::exec_input("sleep 7.34 &".to_string(), &mut slash).unwrap();
+		let pid = Pid::from_raw(slash.vars().get_param("!").unwrap().parse::<i32>().unwrap());
+
+		execute::dispatch::exec_input("disown -h %?7.34".to_string(), &mut slash).unwrap();
+		assert!(read_jobs(|j| j.query(JobID::Pid(pid)).is_some_and(|job| job.is_nohup())).unwrap());
+
+		execute::dispatch::exec_input("disown %?7.34".to_string(), &mut slash).unwrap();
+		assert!(read_jobs(|j| j.query(JobID::Pid(pid)).is_none()).unwrap());
+		assert!(execute::dispatch::exec_input("disown %?7.34".to_string(), &mut slash).is_err());
+		nix::sys::signal::kill(pid, Signal::SIGKILL).unwrap();
 	}
 }
@@ -13,7 +13,173 @@ pub mod control;
 pub mod job;
 pub mod cmd_override;
 pub mod exec;
+pub mod unset;
+pub mod set;
+pub mod enable;
+pub mod env;
+pub mod theme;
+pub mod printf;
+pub mod arith;
+pub mod read;
+pub mod hash;
+pub mod sandbox;
+#[cfg(feature = "namespaces")]
+pub mod run;
+pub mod cmd_type;
+pub mod trap;
+pub mod declare;
+pub mod history;
+pub mod complete;
+pub mod getopts;
 
-pub const BUILTINS: [&str; 43] = [
-	"try", "except", "return", "break", "continue", "exit", "command", "pushd", "popd", "setopt", "getopt", "type", "string", "int", "bool", "arr", "float", "dict", "expr", "echo", "jobs", "unset", "fg", "bg", "set", "builtin", "test", "[", "shift", "unalias", "alias", "export", "cd", "readonly", "declare", "local", "unset", "trap", "node", "exec", "source", "read_func", "wait",
+pub mod help;
+
+use crate::{prelude::*, prompt::comp::CompType};
+
+/// The function that runs a builtin, given the whole command
+pub type BuiltinFn = for<'a> fn(Pair<'a,Rule>, &mut Slash) -> SlashResult<()>;
+
+/// Everything the shell knows about a builtin
+pub struct BuiltinSpec {
+	pub name: &'static str,
+	pub func: BuiltinFn,
+	/// How the builtin is called, as `help` shows it
+	pub usage: &'static str,
+	/// What the builtin's arguments are completed with, when no spec has been registered with `complete`
+	pub comp: Option<CompType>,
+	/// Special builtins as POSIX defines them. In POSIX mode, an error in one ends a non-interactive shell
+	pub special: bool,
+	/// Builtins that set their own exit status. Others leave a status of 0 when they succeed
+	pub sets_status: bool,
+	/// Builtins that run other commands or take over the shell's process or jobs. A pipeline of builtins only runs without forking if none of them do
+	pub runs_cmds: bool
+}
+
+impl BuiltinSpec {
+	const fn new(name: &'static str, func: BuiltinFn, usage: &'static str) -> Self {
+		Self { name, func, usage, comp: None, special: false, sets_status: false, runs_cmds: false }
+	}
+	const fn comp(mut self, comp: CompType) -> Self {
+		self.comp = Some(comp);
+		self
+	}
+	const fn special(mut self) -> Self {
+		self.special = true;
+		self
+	}
+	const fn sets_status(mut self) -> Self {
+		self.sets_status = true;
+		self
+	}
+	const fn runs_cmds(mut self) -> Self {
+		self.runs_cmds = true;
+		self
+	}
+}
+
+/// Every builtin that is compiled in. Builtins loaded with `enable -f` and plugin builtins are looked up separately
+pub static BUILTINS: &[BuiltinSpec] = &[
+	BuiltinSpec::new("[", test::execute, "[ expr ]").sets_status(),
+	BuiltinSpec::new("alias", alias::execute, "alias [name[=value]...]").comp(CompType::Aliases),
+	BuiltinSpec::new("arr", assign::execute, "arr name=value"),
+	BuiltinSpec::new("bg", bg, "bg [job]").runs_cmds(),
+	BuiltinSpec::new("bool", assign::execute, "bool name=value"),
+	BuiltinSpec::new("break", control::loop_break, "break [n]").special(),
+	BuiltinSpec::new("builtin", builtin, "builtin name [args...]").comp(CompType::Builtins).runs_cmds(),
+	BuiltinSpec::new("cd", cd::execute, "cd [-L|-P] [dir]").comp(CompType::Directories),
+	BuiltinSpec::new("command", command, "command [-p] name [args...]").comp(CompType::Commands).sets_status().runs_cmds(),
+	BuiltinSpec::new("compopt", complete::compopt, "compopt [-o option] [+o option] [name...]"),
+	BuiltinSpec::new("complete", complete::execute, "complete [-p] [-r] [-F func] [-o option] [name...]").comp(CompType::Commands),
+	BuiltinSpec::new("continue", continue_loop, "continue").special(),
+	BuiltinSpec::new("declare", declare::execute, "declare [-aAfFgilnrtux] [-p] [name[=value]...]").comp(CompType::Variables),
+	BuiltinSpec::new("dict", not_implemented, "dict name=value"),
+	BuiltinSpec::new("dirs", dir_stack::dirs, "dirs [-clpv] [+N | -N]"),
+	BuiltinSpec::new("disown", job::disown, "disown [-ahr] [job...]"),
+	BuiltinSpec::new("echo", echo::execute, "echo [-neE] [args...]"),
+	BuiltinSpec::new("enable", enable::execute, "enable [-f library name...] [-d name...]").comp(CompType::Builtins),
+	BuiltinSpec::new("env", env::execute, "env allow|deny [path]").sets_status(),
+	BuiltinSpec::new("except", not_implemented, "except"),
+	BuiltinSpec::new("exec", exec::run_exec, "exec [command [args...]] [redirections]").comp(CompType::Commands).special().runs_cmds(),
+	BuiltinSpec::new("exit", control::exit, "exit [n]").special(),
+	BuiltinSpec::new("export", export::execute, "export [-n] [name[=value]...]").comp(CompType::Variables).special(),
+	BuiltinSpec::new("expr", not_implemented, "expr"),
+	BuiltinSpec::new("fg", fg, "fg [job]").runs_cmds(),
+	BuiltinSpec::new("float", assign::execute, "float name=value"),
+	BuiltinSpec::new("getopt", opts::getopt, "getopt name"),
+	BuiltinSpec::new("getopts", getopts::execute, "getopts optstring name [arg...]").sets_status(),
+	BuiltinSpec::new("hash", hash::execute, "hash [-r] [-p path] [name...]").comp(CompType::Commands).sets_status(),
+	BuiltinSpec::new("help", help::execute, "help [name...]").comp(CompType::Builtins).sets_status(),
+	BuiltinSpec::new("history", history::execute, "history [n]"),
+	BuiltinSpec::new("int", assign::execute, "int name=value"),
+	BuiltinSpec::new("jobs", job::jobs, "jobs [-lpnrs] [job...]"),
+	BuiltinSpec::new("kill", job::kill, "kill [-s sig | -sig] pid|%job... | kill -l [sig]").sets_status(),
+	BuiltinSpec::new("let", arith::execute, "let expr [expr...]").sets_status(),
+	BuiltinSpec::new("local", not_implemented, "local name[=value]..."),
+	BuiltinSpec::new("mapfile", read::mapfile, "mapfile [-t] [-n count] [-s count] [array]"),
+	BuiltinSpec::new("node", not_implemented, "node"),
+	BuiltinSpec::new("popd", dir_stack::popd, "popd [n]"),
+	BuiltinSpec::new("printf", printf::execute, "printf [-v var] format [args...]").sets_status(),
+	BuiltinSpec::new("pushd", dir_stack::pushd, "pushd [dir | +N | -N]").comp(CompType::Directories),
+	BuiltinSpec::new("pwd", pwd::execute, "pwd [-LP]"),
+	BuiltinSpec::new("read", read::execute, "read [-r] [-p prompt] [-d delim] [-n count] [-t timeout] [name...]").sets_status(),
+	BuiltinSpec::new("read_func", not_implemented, "read_func"),
+	BuiltinSpec::new("readarray", read::mapfile, "readarray [-t] [-n count] [-s count] [array]"),
+	BuiltinSpec::new("readonly", declare::readonly, "readonly [-p] [name[=value]...]").comp(CompType::Variables).special(),
+	BuiltinSpec::new("return", control::func_return, "return [n]").special(),
+	#[cfg(feature = "namespaces")]
+	BuiltinSpec::new("run", run::execute, "run [--user] [--mount] [--net] [--] command [args...]").comp(CompType::Commands).sets_status().runs_cmds(),
+	BuiltinSpec::new("sandbox", sandbox::execute, "sandbox [--clear-env] [--allow names] [--unset name] [--chdir dir] [--] command [args...]").comp(CompType::Commands).sets_status().runs_cmds(),
+	BuiltinSpec::new("set", set::execute, "set [-abefhmnuvxCEHPT] [-o option] [--] [args...]").special(),
+	BuiltinSpec::new("setopt", opts::setopt, "setopt name=value"),
+	BuiltinSpec::new("shift", not_implemented, "shift [n]").special(),
+	BuiltinSpec::new("source", source::execute, "source file [args...]").special().runs_cmds(),
+	BuiltinSpec::new("string", assign::execute, "string name=value"),
+	BuiltinSpec::new("test", test::execute, "test expr").sets_status(),
+	BuiltinSpec::new("theme", theme::execute, "theme [style [code]]"),
+	BuiltinSpec::new("trap", trap::execute, "trap [-lp] [action] [signal...]").special(),
+	BuiltinSpec::new("try", not_implemented, "try"),
+	BuiltinSpec::new("type", cmd_type::execute, "type [-afptP] name...").comp(CompType::Commands).sets_status(),
+	BuiltinSpec::new("unalias", alias::unalias, "unalias [-a] name...").comp(CompType::Aliases),
+	BuiltinSpec::new("unset", unset::execute, "unset [-fv] name...").comp(CompType::Variables).special(),
+	BuiltinSpec::new("wait", job::wait, "wait [-n] [id...]").sets_status().runs_cmds(),
 ];
+
+/// Finds a builtin that is compiled in
+pub fn lookup(name: &str) -> Option<&'static BuiltinSpec> {
+	BUILTINS.iter().find(|spec| spec.name == name)
+}
+
+/// Whether a command name refers to a builtin, including ones loaded with `enable -f`
+pub fn is_builtin(name: &str) -> bool {
+	#[cfg(feature = "wasm-plugins")]
+	if crate::plugin::has_builtin(name) {
+		return true
+	}
+	lookup(name).is_some() || enable::is_loaded(name)
+}
+
+fn fg<'a>(fg_call: Pair<'a,Rule>, slash: &mut Slash) -> SlashResult<()> {
+	job::continue_job(fg_call, slash, true)
+}
+
+fn bg<'a>(bg_call: Pair<'a,Rule>, slash: &mut Slash) -> SlashResult<()> {
+	job::continue_job(bg_call, slash, false)
+}
+
+fn builtin<'a>(call: Pair<'a,Rule>, slash: &mut Slash) -> SlashResult<()> {
+	cmd_override::execute(call, slash, true)
+}
+
+fn command<'a>(call: Pair<'a,Rule>, slash: &mut Slash) -> SlashResult<()> {
+	cmd_override::execute(call, slash, false)
+}
+
+fn continue_loop<'a>(_call: Pair<'a,Rule>, _slash: &mut Slash) -> SlashResult<()> {
+	control::loop_continue()
+}
+
+/// For names that are reserved as builtins but don't do anything yet
+fn not_implemented<'a>(call: Pair<'a,Rule>, _slash: &mut Slash) -> SlashResult<()> {
+	let name = call.clone().into_inner().find(|pair| pair.as_rule() == Rule::cmd_name).map(|pair| pair.as_str()).unwrap_or_default().to_string();
+	Err(High(SlashErrHigh::exec_err(format!("Have not implemented support for builtin `{}` yet",name),call)))
+}
@@ -24,7 +24,7 @@ pub fn setopt<'a>(setopt_call: Pair<'a,Rule>, slash: &mut Slash) -> SlashResult<
 
 pub fn getopt<'a>(getopt_call: Pair<'a,Rule>, slash: &mut Slash) -> SlashResult<()> {
 	let mut argv = getopt_call.filter(&ARG_RULES[..]);
-	let redirs = helper::prepare_redirs(getopt_call)?;
+	let redirs = helper::prepare_redirs(getopt_call,slash)?;
 	slash.consume_redirs(redirs)?;
 	let mut stdout = SmartFD::new(1)?;
 	while let Some(arg) = argv.pop_front() {
@@ -0,0 +1,467 @@
+use chrono::{Local, TimeZone};
+
+use crate::{helper, prelude::*, shellenv::SlashVal, utils};
+
+/// Prints arguments according to a format string
+/// `printf [-v var] [-u fd] format [args...]` supports the usual `%d %i %u %o %x %X %c %s %f %e %g %%` conversions with flags,
+/// width and precision, along with `%b` (expand escapes in the argument), `%q` (quote the argument for reuse as shell input),
+/// and `%(fmt)T`, which formats the argument as a unix timestamp using strftime syntax. `-1` or a missing argument means now
+/// The format is reused until every argument has been consumed. With `-v`, the output is assigned to `var` instead of printed,
+/// and with `-u fd` it is written to an open descriptor instead of stdout
+pub fn execute<'a>(printf_call: Pair<'a,Rule>, slash: &mut Slash) -> SlashResult<()> {
+	let blame = printf_call.clone();
+	let redirs = helper::prepare_redirs(printf_call.clone(),slash)?;
+	let mut argv = helper::prepare_argv(printf_call,slash)?;
+	argv.pop_front();
+
+	let mut target_var = None;
+	let mut target_fd = None;
+	while let Some(opt) = argv.front().filter(|arg| matches!(arg.as_str(), "-v" | "-u")).cloned() {
+		argv.pop_front();
+		if opt == "-u" {
+			target_fd = Some(helper::user_fd(argv.pop_front(), "printf", blame.clone())?);
+			continue
+		}
+		let Some(var) = argv.pop_front() else {
+			return Err(High(SlashErrHigh::syntax_err("printf: -v requires a variable name", blame)))
+		};
+		helper::check_var_name(&var, "printf", blame.clone())?;
+		target_var = Some(var);
+	}
+	let Some(format) = argv.pop_front() else {
+		return Err(High(SlashErrHigh::syntax_err("printf: usage: printf [-v var] [-u fd] format [arguments]", blame)))
+	};
+
+	let mut formatter = Formatter::new(argv);
+	let output = formatter.run(&format).blame(blame)?;
+	match target_var {
+		Some(var) => slash.vars_mut().set_var(&var, SlashVal::String(output)),
+		None => {
+			slash.consume_redirs(redirs)?;
+			let mut stdout = match target_fd {
+				Some(fd) => fd,
+				None => utils::SmartFD::new(STDOUT_FILENO)?
+			};
+			write!(stdout,"{}",output)?;
+		}
+	}
+	slash.set_code(if formatter.failed { 1 } else { 0 });
+	Ok(())
+}
+
+#[derive(Default,Debug)]
+struct Spec {
+	left: bool,
+	plus: bool,
+	space: bool,
+	alt: bool,
+	zero: bool,
+	width: Option<usize>,
+	precision: Option<usize>
+}
+
+struct Formatter {
+	args: VecDeque<String>,
+	/// Set when an argument couldn't be converted, which makes printf return 1
+	failed: bool,
+	/// Set by `\c` in a `%b` argument, which stops all further output
+	stopped: bool
+}
+
+impl Formatter {
+	fn new(args: VecDeque<String>) -> Self {
+		Self { args, failed: false, stopped: false }
+	}
+
+	fn run(&mut self, format: &str) -> SlashResult<String> {
+		let mut output = String::new();
+		loop {
+			let remaining = self.args.len();
+			self.format_once(format, &mut output)?;
+			// The format is reused only while it keeps consuming arguments
+			if self.stopped || self.args.is_empty() || self.args.len() == remaining {
+				break
+			}
+		}
+		Ok(output)
+	}
+
+	fn next_arg(&mut self) -> String {
+		self.args.pop_front().unwrap_or_default()
+	}
+
+	fn format_once(&mut self, format: &str, output: &mut String) -> SlashResult<()> {
+		let mut chars = format.chars().peekable();
+		while let Some(ch) = chars.next() {
+			match ch {
+				'\\' => {
+					let (expanded, _) = expand_escape(&mut chars, false);
+					output.push_str(&expanded);
+				}
+				'%' => {
+					if chars.peek() == Some(&'%') {
+						chars.next();
+						output.push('%');
+						continue
+					}
+					self.conversion(&mut chars, output)?;
+					if self.stopped {
+						return Ok(())
+					}
+				}
+				_ => output.push(ch)
+			}
+		}
+		Ok(())
+	}
+
+	fn conversion(&mut self, chars: &mut std::iter::Peekable<std::str::Chars>, output: &mut String) -> SlashResult<()> {
+		let mut spec = Spec::default();
+		while let Some(&ch) = chars.peek() {
+			match ch {
+				'-' => spec.left = true,
+				'+' => spec.plus = true,
+				' ' => spec.space = true,
+				'#' => spec.alt = true,
+				'0' => spec.zero = true,
+				_ => break
+			}
+			chars.next();
+		}
+		spec.width = self.read_number(chars);
+		if chars.peek() == Some(&'.') {
+			chars.next();
+			spec.precision = Some(self.read_number(chars).unwrap_or(0));
+		}
+
+		let Some(conv) = chars.next() else {
+			return Err(Low(SlashErrLow::ExecFailed("printf: missing conversion specifier".into())))
+		};
+		let formatted = match conv {
+			'd' | 'i' => {
+				let num = self.int_arg();
+				let sign = if num < 0 { "-" } else if spec.plus { "+" } else if spec.space { " " } else { "" };
+				pad_number(sign, "", &num.unsigned_abs().to_string(), &spec)
+			}
+			'u' => pad_number("", "", &(self.int_arg() as u64).to_string(), &spec),
+			'o' => {
+				let prefix = if spec.alt { "0" } else { "" };
+				pad_number("", prefix, &format!("{:o}",self.int_arg() as u64), &spec)
+			}
+			'x' | 'X' => {
+				let num = self.int_arg() as u64;
+				let (prefix, digits) = if conv == 'x' { ("0x", format!("{num:x}")) } else { ("0X", format!("{num:X}")) };
+				pad_number("", if spec.alt && num != 0 { prefix } else { "" }, &digits, &spec)
+			}
+			'f' | 'F' | 'e' | 'E' | 'g' | 'G' => {
+				let num = self.float_arg();
+				let sign = if num.is_sign_negative() { "-" } else if spec.plus { "+" } else if spec.space { " " } else { "" };
+				let body = format_float(num.abs(), conv, spec.precision.unwrap_or(6), spec.alt);
+				let spec = Spec { precision: None, ..spec };
+				pad_number(sign, "", &body, &spec)
+			}
+			'c' => pad(&self.next_arg().chars().next().map(String::from).unwrap_or_default(), &spec),
+			's' => {
+				let arg = self.next_arg();
+				let arg = match spec.precision {
+					Some(prec) => arg.chars().take(prec).collect(),
+					None => arg
+				};
+				pad(&arg, &spec)
+			}
+			'b' => {
+				let arg = self.next_arg();
+				let mut arg_chars = arg.chars().peekable();
+				let mut expanded = String::new();
+				while let Some(ch) = arg_chars.next() {
+					if ch != '\\' {
+						expanded.push(ch);
+						continue
+					}
+					let (escape, stop) = expand_escape(&mut arg_chars, true);
+					if stop {
+						self.stopped = true;
+						break
+					}
+					expanded.push_str(&escape);
+				}
+				pad(&expanded, &spec)
+			}
+			'q' => pad(&helper::shell_quote(&self.next_arg()), &spec),
+			'(' => {
+				let mut time_fmt = String::new();
+				loop {
+					match chars.next() {
+						Some(')') => break,
+						Some(ch) => time_fmt.push(ch),
+						None => return Err(Low(SlashErrLow::ExecFailed("printf: unterminated %(...)T conversion".into())))
+					}
+				}
+				if chars.next() != Some('T') {
+					return Err(Low(SlashErrLow::ExecFailed("printf: expected `T' after %(...)".into())))
+				}
+				let time = self.format_time(&time_fmt)?;
+				pad(&time, &spec)
+			}
+			_ => return Err(Low(SlashErrLow::ExecFailed(format!("printf: invalid conversion specifier: %{}",conv))))
+		};
+		output.push_str(&formatted);
+		Ok(())
+	}
+
+	/// Reads a width or precision, where `*` takes the value from the next argument
+	fn read_number(&mut self, chars: &mut std::iter::Peekable<std::str::Chars>) -> Option<usize> {
+		if chars.peek() == Some(&'*') {
+			chars.next();
+			return Some(self.int_arg().max(0) as usize)
+		}
+		let mut digits = String::new();
+		while let Some(&ch) = chars.peek().filter(|ch| ch.is_ascii_digit()) {
+			digits.push(ch);
+			chars.next();
+		}
+		digits.parse().ok()
+	}
+
+	fn int_arg(&mut self) -> i64 {
+		let arg = self.next_arg();
+		match parse_int(&arg) {
+			Some(num) => num,
+			None => {
+				eprintln!("printf: {}: invalid number",arg);
+				self.failed = true;
+				0
+			}
+		}
+	}
+
+	fn float_arg(&mut self) -> f64 {
+		let arg = self.next_arg();
+		if arg.is_empty() {
+			return 0.0
+		}
+		match arg.trim().parse::<f64>().ok().or_else(|| parse_int(&arg).map(|num| num as f64)) {
+			Some(num) => num,
+			None => {
+				eprintln!("printf: {}: invalid number",arg);
+				self.failed = true;
+				0.0
+			}
+		}
+	}
+
+	fn format_time(&mut self, time_fmt: &str) -> SlashResult<String> {
+		let arg = self.next_arg();
+		let secs = if arg.is_empty() || arg == "-1" {
+			Local::now().timestamp()
+		} else {
+			self.args.push_front(arg);
+			self.int_arg()
+		};
+		let time = Local.timestamp_opt(secs, 0)
+			.single()
+			.ok_or_else(|| Low(SlashErrLow::ExecFailed(format!("printf: timestamp out of range: {}",secs))))?;
+		// chrono reports bad format strings through fmt::Error rather than panicking if we write the output ourselves
+		let time_fmt = if time_fmt.is_empty() { "%X" } else { time_fmt };
+		let mut formatted = String::new();
+		fmt::Write::write_fmt(&mut formatted, format_args!("{}",time.format(time_fmt)))
+			.map_err(|_| Low(SlashErrLow::ExecFailed(format!("printf: invalid time format: {}",time_fmt))))?;
+		Ok(formatted)
+	}
+}
+
+/// Parses an integer argument, which can be decimal, hex (`0x`), octal (leading `0`),
+/// or a quote followed by a character, which gives the character's value
+fn parse_int(arg: &str) -> Option<i64> {
+	let arg = arg.trim();
+	if arg.is_empty() {
+		return Some(0)
+	}
+	if let Some(rest) = arg.strip_prefix(['\'','"']) {
+		return rest.chars().next().map(|ch| ch as i64)
+	}
+	let (negative, digits) = match arg.strip_prefix('-') {
+		Some(digits) => (true, digits),
+		None => (false, arg.strip_prefix('+').unwrap_or(arg))
+	};
+	let num = if let Some(hex) = digits.strip_prefix("0x").or_else(|| digits.strip_prefix("0X")) {
+		i64::from_str_radix(hex, 16).ok()?
+	} else if digits.len() > 1 && digits.starts_with('0') {
+		i64::from_str_radix(&digits[1..], 8).ok()?
+	} else {
+		digits.parse::<i64>().ok()?
+	};
+	Some(if negative { -num } else { num })
+}
+
+/// Expands the escape sequence following a backslash
+/// In `%b` arguments, octal escapes are written `\0NNN`, and `\c` stops output, which is reported by the returned bool
+fn expand_escape(chars: &mut std::iter::Peekable<std::str::Chars>, in_arg: bool) -> (String, bool) {
+	let Some(ch) = chars.next() else {
+		return ("\\".into(), false)
+	};
+	let escaped = match ch {
+		'n' => '\n',
+		't' => '\t',
+		'r' => '\r',
+		'a' => '\x07',
+		'b' => '\x08',
+		'e' | 'E' => '\x1b',
+		'f' => '\x0c',
+		'v' => '\x0b',
+		'\\' => '\\',
+		'"' => '"',
+		'\'' => '\'',
+		'c' if in_arg => return (String::new(), true),
+		'0'..='7' => {
+			// Up to three digits, not counting the leading 0 of a `%b` escape
+			let mut digits = if in_arg && ch == '0' { String::new() } else { ch.to_string() };
+			while digits.len() < 3 {
+				match chars.peek() {
+					Some(&digit) if digit.is_digit(8) => {
+						digits.push(digit);
+						chars.next();
+					}
+					_ => break
+				}
+			}
+			let value = u32::from_str_radix(&digits, 8).unwrap_or(0);
+			char::from_u32(value & 0xff).unwrap_or('\0')
+		}
+		'x' => {
+			let mut digits = String::new();
+			while digits.len() < 2 {
+				match chars.peek() {
+					Some(&digit) if digit.is_ascii_hexdigit() => {
+						digits.push(digit);
+						chars.next();
+					}
+					_ => break
+				}
+			}
+			if digits.is_empty() {
+				return ("\\x".into(), false)
+			}
+			char::from_u32(u32::from_str_radix(&digits, 16).unwrap_or(0)).unwrap_or('\0')
+		}
+		_ => return (format!("\\{ch}"), false)
+	};
+	(escaped.to_string(), false)
+}
+
+/// Expands every backslash escape in a string the way the format string's escapes are, as `${var@E}` does
+pub fn expand_escapes(text: &str) -> String {
+	let mut chars = text.chars().peekable();
+	let mut expanded = String::new();
+	while let Some(ch) = chars.next() {
+		if ch == '\\' {
+			expanded.push_str(&expand_escape(&mut chars, false).0);
+		} else {
+			expanded.push(ch);
+		}
+	}
+	expanded
+}
+
+fn pad(text: &str, spec: &Spec) -> String {
+	let width = spec.width.unwrap_or(0);
+	let len = text.chars().count();
+	if len >= width {
+		return text.to_string()
+	}
+	let fill = " ".repeat(width - len);
+	if spec.left { format!("{text}{fill}") } else { format!("{fill}{text}") }
+}
+
+/// Pads a number, putting zero padding between the sign or prefix and the digits
+fn pad_number(sign: &str, prefix: &str, digits: &str, spec: &Spec) -> String {
+	let digits = match spec.precision {
+		Some(prec) if digits.len() < prec => format!("{}{digits}","0".repeat(prec - digits.len())),
+		_ => digits.to_string()
+	};
+	let len = sign.len() + prefix.len() + digits.len();
+	let width = spec.width.unwrap_or(0);
+	if spec.zero && !spec.left && spec.precision.is_none() && len < width {
+		return format!("{sign}{prefix}{}{digits}","0".repeat(width - len))
+	}
+	pad(&format!("{sign}{prefix}{digits}"), spec)
+}
+
+fn format_float(num: f64, conv: char, precision: usize, alt: bool) -> String {
+	if !num.is_finite() {
+		let text = if num.is_nan() { "nan" } else { "inf" };
+		return if conv.is_ascii_uppercase() { text.to_uppercase() } else { text.to_string() }
+	}
+	let formatted = match conv.to_ascii_lowercase() {
+		'f' => format!("{num:.precision$}"),
+		'e' => format_exp(num, precision),
+		_ => {
+			// %g uses %e if the exponent is less than -4 or at least the precision, and %f otherwise
+			let precision = precision.max(1);
+			let exponent = if num == 0.0 { 0 } else { format!("{num:e}").split_once('e').and_then(|(_,exp)| exp.parse::<i32>().ok()).unwrap_or(0) };
+			let mut formatted = if exponent < -4 || exponent >= precision as i32 {
+				format_exp(num, precision - 1)
+			} else {
+				format!("{num:.*}",(precision as i32 - 1 - exponent).max(0) as usize)
+			};
+			if !alt {
+				formatted = strip_trailing_zeros(&formatted);
+			}
+			formatted
+		}
+	};
+	if conv.is_ascii_uppercase() { formatted.to_uppercase() } else { formatted }
+}
+
+/// Formats a number in C's exponent notation, e.g. `1.500000e+02`
+fn format_exp(num: f64, precision: usize) -> String {
+	let formatted = format!("{num:.precision$e}");
+	let (mantissa, exponent) = formatted.split_once('e').unwrap_or((&formatted, "0"));
+	let exponent = exponent.parse::<i32>().unwrap_or(0);
+	let sign = if exponent < 0 { '-' } else { '+' };
+	format!("{mantissa}e{sign}{:02}",exponent.abs())
+}
+
+fn strip_trailing_zeros(num: &str) -> String {
+	let (mantissa, exponent) = match num.split_once('e') {
+		Some((mantissa, exponent)) => (mantissa, Some(exponent)),
+		None => (num, None)
+	};
+	let mantissa = if mantissa.contains('.') { mantissa.trim_end_matches('0').trim_end_matches('.') } else { mantissa };
+	match exponent {
+		Some(exponent) => format!("{mantissa}e{exponent}"),
+		None => mantissa.to_string()
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn printf(format: &str, args: &[&str]) -> String {
+		let args = args.iter().map(|arg| arg.to_string()).collect();
+		Formatter::new(args).run(format).unwrap()
+	}
+
+	#[test]
+	fn test_printf_formats() {
+		assert_eq!(printf("%s-%s\\n", &["a","b","c"]), "a-b\nc-\n");
+		assert_eq!(printf("[%5s][%-5s][%.2s]", &["ab","cd","xyz"]), "[   ab][cd   ][xy]");
+		assert_eq!(printf("%d %+d %05d %x %#X %o %u", &["42","7","-42","255","255","8","-1"]), "42 +7 -0042 ff 0XFF 10 18446744073709551615");
+		assert_eq!(printf("%.3f %e %g %g", &["3.14159","1500","0.0001","100000000"]), "3.142 1.500000e+03 0.0001 1e+08");
+		assert_eq!(printf("%c%c %d", &["hello","w","'A"]), "hw 65");
+		assert_eq!(printf("%b|%q", &["a\\tb\\c ignored","it's"]), "a\tb");
+		assert_eq!(printf("%q", &["it's"]), "'it'\\''s'");
+		assert_eq!(printf("%*d|%%", &["4","1"]), "   1|%");
+		assert_eq!(printf("\\x41\\101", &[]), "AA");
+
+		assert_eq!(printf("%(%Y-%m-%d)T", &["0"]), Local.timestamp_opt(0,0).unwrap().format("%Y-%m-%d").to_string());
+		assert_eq!(printf("%(%s)T", &["86400"]), "86400");
+		assert!(Formatter::new(VecDeque::new()).run("%(%Y").is_err());
+		assert!(Formatter::new(VecDeque::new()).run("%z").is_err());
+
+		let mut formatter = Formatter::new(VecDeque::from(["abc".to_string()]));
+		assert_eq!(formatter.run("%d").unwrap(), "0");
+		assert!(formatter.failed);
+	}
+}
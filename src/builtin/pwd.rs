@@ -4,7 +4,7 @@ use crate::{error::{SlashErr::*, SlashErrHigh}, helper::{self}, shellenv::Slash,
 
 pub fn execute<'a>(pwd_call: Pair<'a,Rule>, slash: &mut Slash) -> SlashResult<()> {
 	let blame = pwd_call.clone();
-	let redirs = helper::prepare_redirs(pwd_call)?;
+	let redirs = helper::prepare_redirs(pwd_call,slash)?;
 
 	slash.ctx_mut().extend_redirs(redirs);
 
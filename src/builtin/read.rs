@@ -0,0 +1,290 @@
+use nix::{errno::Errno, unistd::isatty};
+
+use crate::{helper, prelude::*, prompt::rl_init, shellenv::{SlashVal, DEFAULT_IFS}, signal, utils};
+
+/// The options shared by `read` and `mapfile`
+struct ReadOpts {
+	raw: bool,
+	fd: Option<utils::SmartFD>,
+	prompt: Option<String>,
+	delim: u8,
+	array: Option<String>,
+	count: usize,
+	skip: usize,
+	trim: bool
+}
+
+impl ReadOpts {
+	fn new() -> Self {
+		Self { raw: false, fd: None, prompt: None, delim: b'\n', array: None, count: 0, skip: 0, trim: false }
+	}
+}
+
+/// Parses the leading options of a `read` or `mapfile` call
+/// `with_arg` lists the options that take an argument, which can be attached (`-u3`) or given as the next word (`-u 3`)
+fn parse_opts<'a>(argv: &mut VecDeque<String>, builtin: &str, with_arg: &str, blame: Pair<'a,Rule>) -> SlashResult<ReadOpts> {
+	let mut opts = ReadOpts::new();
+	while let Some(arg) = argv.front() {
+		if arg == "--" {
+			argv.pop_front();
+			break
+		}
+		if arg.len() < 2 || !arg.starts_with('-') {
+			break
+		}
+		let arg = argv.pop_front().unwrap();
+		let mut chars = arg[1..].chars();
+		while let Some(opt) = chars.next() {
+			let value = if with_arg.contains(opt) {
+				let attached = chars.as_str();
+				let value = if attached.is_empty() { argv.pop_front() } else { Some(attached.to_string()) };
+				chars = "".chars();
+				value
+			} else {
+				None
+			};
+			let bad_number = |value: &str| High(SlashErrHigh::syntax_err(format!("{}: {}: invalid number", builtin, value), blame.clone()));
+			match (opt, value) {
+				('r', _) if builtin == "read" => opts.raw = true,
+				('t', _) if builtin != "read" => opts.trim = true,
+				('u', value) => opts.fd = Some(helper::user_fd(value, builtin, blame.clone())?),
+				('p', Some(value)) => opts.prompt = Some(value),
+				// An empty delimiter means NUL, so `-d ''` reads NUL separated records
+				('d', Some(value)) => opts.delim = value.bytes().next().unwrap_or(b'\0'),
+				('a', Some(value)) => opts.array = Some(value),
+				('n', Some(value)) => opts.count = value.parse().map_err(|_| bad_number(&value))?,
+				('s', Some(value)) => opts.skip = value.parse().map_err(|_| bad_number(&value))?,
+				(opt, None) if with_arg.contains(opt) => {
+					return Err(High(SlashErrHigh::syntax_err(format!("{}: -{} requires an argument", builtin, opt), blame)))
+				}
+				(opt, _) => {
+					return Err(High(SlashErrHigh::syntax_err(format!("{}: -{}: invalid option", builtin, opt), blame)))
+				}
+			}
+		}
+	}
+	Ok(opts)
+}
+
+/// Reads one record from `fd`, up to and excluding `delim`
+/// Input is read a byte at a time so that nothing past the delimiter is consumed, leaving the rest for the next command that reads from the same descriptor.
+/// Unless `raw` is set, a backslash followed by a newline is removed and the record continues on the next line.
+/// Returns the record and whether the delimiter was found before end of file
+pub(crate) fn read_record(fd: RawFd, delim: u8, raw: bool) -> SlashResult<(Vec<u8>,bool)> {
+	let mut record = vec![];
+	let mut escaped = false;
+	let mut byte = [0u8;1];
+	loop {
+		match nix::unistd::read(fd, &mut byte) {
+			Ok(0) => return Ok((record,false)),
+			Ok(_) => {}
+			Err(Errno::EINTR) => {
+				signal::check_interrupt()?;
+				continue
+			}
+			Err(_) => return Err(Low(SlashErrLow::from_io()))
+		}
+		let byte = byte[0];
+		if escaped {
+			escaped = false;
+			if byte == b'\n' {
+				record.pop();
+				continue
+			}
+		} else if byte == delim {
+			return Ok((record,true))
+		} else if byte == b'\\' && !raw {
+			escaped = true;
+		}
+		record.push(byte);
+	}
+}
+
+/// Splits a line into at most `max` fields on the characters in `ifs`, like `read` does
+/// Escaped characters never delimit a field. When there are more fields than `max`, the last field holds the rest of the line, with only its trailing IFS whitespace removed
+fn split_line(line: &[(char,bool)], ifs: &str, max: usize) -> Vec<String> {
+	let is_space = |&(ch,escaped): &(char,bool)| !escaped && ifs.contains(ch) && ch.is_whitespace();
+	let is_delim = |&(ch,escaped): &(char,bool)| !escaped && ifs.contains(ch);
+	let mut fields = vec![];
+	let mut i = 0;
+	while i < line.len() && is_space(&line[i]) {
+		i += 1;
+	}
+	while i < line.len() {
+		if fields.len() + 1 == max {
+			let mut end = line.len();
+			while end > i && is_space(&line[end - 1]) {
+				end -= 1;
+			}
+			fields.push(line[i..end].iter().map(|(ch,_)| ch).collect());
+			break
+		}
+		let start = i;
+		while i < line.len() && !is_delim(&line[i]) {
+			i += 1;
+		}
+		fields.push(line[start..i].iter().map(|(ch,_)| ch).collect());
+		// Whitespace around a non-whitespace delimiter belongs to the same delimiter
+		while i < line.len() && is_space(&line[i]) {
+			i += 1;
+		}
+		if i < line.len() && is_delim(&line[i]) {
+			i += 1;
+			while i < line.len() && is_space(&line[i]) {
+				i += 1;
+			}
+		}
+	}
+	fields
+}
+
+/// The `read` builtin
+/// `read [-r] [-u fd] [-p prompt] [-d delim] [-a array] [name...]` reads a line and splits it into fields using IFS.
+/// Each field is assigned to the next name, and the last name receives the rest of the line. With no names, the whole line is stored in `REPLY`.
+/// With `-a`, every field is stored in an array instead. `-u` reads from an open descriptor, like one opened with `exec 3< file`.
+/// Unless `-r` is given, backslashes escape the next character and a trailing backslash continues the line.
+/// A line typed at the terminal is read with the line editor, without history.
+/// When reading from a terminal with `TMOUT` set, the shell logs out if nothing is typed in time.
+/// The exit status is 1 if end of file is reached
+pub fn execute<'a>(read_call: Pair<'a,Rule>, slash: &mut Slash) -> SlashResult<()> {
+	let blame = read_call.clone();
+	let redirs = helper::prepare_redirs(read_call.clone(),slash)?;
+	let mut argv = helper::prepare_argv(read_call,slash)?;
+	argv.pop_front();
+	let opts = parse_opts(&mut argv, "read", "upda", blame.clone())?;
+	for name in argv.iter().chain(opts.array.iter()) {
+		helper::check_var_name(name, "read", blame.clone())?;
+	}
+	slash.consume_redirs(redirs)?;
+
+	let input = opts.fd.as_ref().map(|fd| fd.as_raw_fd()).unwrap_or(STDIN_FILENO);
+	let from_tty = isatty(input).unwrap_or(false);
+	// A line typed at the terminal is read with the line editor, so that it can be edited like a command
+	let use_editor = from_tty && input == STDIN_FILENO && opts.delim == b'\n';
+	if let Some(prompt) = opts.prompt.as_ref().filter(|_| from_tty && !use_editor) {
+		let mut stderr = utils::SmartFD::new(STDERR_FILENO)?;
+		write!(stderr,"{}",prompt)?;
+	}
+	// TMOUT also limits how long a read from a terminal waits for its first byte
+	if let Some(timeout) = helper::input_timeout(slash).filter(|_| from_tty) {
+		if !helper::wait_for_input(input, timeout).blame(blame.clone())? {
+			eprintln!("\n{}",helper::AUTO_LOGOUT_MSG);
+			return Err(Low(SlashErrLow::CleanExit(slash.get_status())))
+		}
+	}
+	let (record,found_delim) = if use_editor {
+		let prompt = opts.prompt.as_deref().unwrap_or_default();
+		match rl_init::read_line(slash.meta(), prompt).blame(blame)? {
+			Some(line) => (line.into_bytes(),true),
+			None => (vec![],false)
+		}
+	} else {
+		read_record(input, opts.delim, opts.raw).blame(blame)?
+	};
+
+	let text = String::from_utf8_lossy(&record);
+	let mut line = vec![];
+	let mut chars = text.chars();
+	while let Some(ch) = chars.next() {
+		match ch {
+			'\\' if !opts.raw => {
+				if let Some(esc_ch) = chars.next() {
+					line.push((esc_ch,true))
+				}
+			}
+			_ => line.push((ch,false))
+		}
+	}
+
+	let ifs = slash.vars().get_var("IFS").map(|ifs| ifs.to_string()).unwrap_or(DEFAULT_IFS.into());
+	if let Some(array) = opts.array {
+		let fields = split_line(&line, &ifs, usize::MAX);
+		slash.vars_mut().set_var(&array, SlashVal::Array(fields.into_iter().map(SlashVal::String).collect()));
+	} else if argv.is_empty() {
+		// REPLY gets the line as it was read, without trimming IFS whitespace
+		let reply = line.into_iter().map(|(ch,_)| ch).collect();
+		slash.vars_mut().set_var("REPLY", SlashVal::String(reply));
+	} else {
+		let mut fields = VecDeque::from(split_line(&line, &ifs, argv.len()));
+		for name in argv {
+			let field = fields.pop_front().unwrap_or_default();
+			slash.vars_mut().set_var(&name, SlashVal::String(field));
+		}
+	}
+
+	let reached_eof = !found_delim;
+	slash.set_code(if reached_eof { 1 } else { 0 });
+	Ok(())
+}
+
+/// The `mapfile` builtin, also called `readarray`
+/// `mapfile [-t] [-n count] [-s skip] [-d delim] [-u fd] [array]` reads lines into an array, `MAPFILE` by default.
+/// `-t` removes the delimiter from each line, `-n` stops after `count` lines, and `-s` discards the first `skip` lines
+pub fn mapfile<'a>(mapfile_call: Pair<'a,Rule>, slash: &mut Slash) -> SlashResult<()> {
+	let blame = mapfile_call.clone();
+	let redirs = helper::prepare_redirs(mapfile_call.clone(),slash)?;
+	let mut argv = helper::prepare_argv(mapfile_call,slash)?;
+	let builtin = argv.pop_front().unwrap_or("mapfile".into());
+	let opts = parse_opts(&mut argv, &builtin, "udns", blame.clone())?;
+	let array = argv.pop_front().unwrap_or("MAPFILE".into());
+	helper::check_var_name(&array, &builtin, blame.clone())?;
+	slash.consume_redirs(redirs)?;
+
+	let input = opts.fd.as_ref().map(|fd| fd.as_raw_fd()).unwrap_or(STDIN_FILENO);
+	let mut lines = vec![];
+	let mut skipped = 0;
+	while opts.count == 0 || lines.len() < opts.count {
+		let (mut record,found_delim) = read_record(input, opts.delim, true).blame(blame.clone())?;
+		if !found_delim && record.is_empty() {
+			break
+		}
+		if skipped < opts.skip {
+			skipped += 1;
+			continue
+		}
+		if found_delim && !opts.trim {
+			record.push(opts.delim);
+		}
+		lines.push(SlashVal::String(String::from_utf8_lossy(&record).into_owned()));
+		if !found_delim {
+			break
+		}
+	}
+
+	slash.vars_mut().set_var(&array, SlashVal::Array(lines));
+	Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+	use crate::execute::dispatch::exec_input;
+
+	use super::*;
+
+	#[test]
+	fn test_read_fd() {
+		let path = env::temp_dir().join(format!("slash_read_test_{}", std::process::id()));
+		std::fs::write(&path, "first line\n  a  b  c  \nx\\\ny\none\ntwo\nthree\n").unwrap();
+		let mut slash = Slash::new();
+		let script = format!("exec 7< {}; read -u 7 line; read -u 7 a b; read -u 7 joined; mapfile -t -n 2 -u 7 rest", path.display());
+		exec_input(script, &mut slash).unwrap();
+		let var = |slash: &Slash, name: &str| slash.vars().get_var(name).map(|val| val.to_string()).unwrap_or_default();
+		assert_eq!(var(&slash, "REPLY"), "");
+		assert_eq!(var(&slash, "line"), "first line");
+		assert_eq!(var(&slash, "a"), "a");
+		assert_eq!(var(&slash, "b"), "b  c");
+		assert_eq!(var(&slash, "joined"), "xy");
+		assert!(matches!(slash.vars().get_var("rest"), Some(SlashVal::Array(lines)) if lines.len() == 2));
+
+		exec_input("read -u 7 last; read -u 7 gone".into(), &mut slash).unwrap();
+		assert_eq!(var(&slash, "last"), "three");
+		assert_eq!(slash.get_status(), 1);
+		exec_input("exec 7<&-".into(), &mut slash).unwrap();
+		assert!(exec_input("read -u 7 closed".into(), &mut slash).is_err());
+		std::fs::remove_file(&path).unwrap();
+
+		let line = "a:b::c".chars().map(|ch| (ch,false)).collect::<Vec<_>>();
+		assert_eq!(split_line(&line, ":", usize::MAX), vec!["a", "b", "", "c"]);
+		assert_eq!(split_line(&line, ":", 2), vec!["a", "b::c"]);
+	}
+}
@@ -0,0 +1,88 @@
+//! The `run` builtin, enabled with the `namespaces` feature
+
+use nix::{mount::{mount, MsFlags}, sched::{unshare, CloneFlags}, unistd::{getgid, geteuid, getuid}};
+
+use crate::{execute::command, helper, prelude::*};
+
+/// The `run` builtin, which runs an external command in new Linux namespaces
+/// `--user` (`-U`) maps the current user to root in a new user namespace, `--mount` (`-m`) gives the command its own mounts,
+/// and `--net` (`-n`) leaves it with nothing but an unconfigured loopback device.
+/// A user namespace is always added for users other than root, since the others can't be created without one.
+/// The command is started like any other, so it can be stopped, backgrounded, and waited on as a job
+pub fn execute<'a>(run_call: Pair<'a,Rule>, slash: &mut Slash) -> SlashResult<()> {
+	let blame = run_call.clone();
+	let redirs = helper::prepare_redirs(run_call.clone(),slash)?;
+	let mut argv = helper::prepare_argv(run_call,slash)?;
+	argv.pop_front();
+
+	let mut namespaces = CloneFlags::empty();
+	while let Some(arg) = argv.front().filter(|arg| arg.starts_with('-')).cloned() {
+		argv.pop_front();
+		if arg == "--" {
+			break
+		}
+		let Some(flag) = namespace_flag(&arg) else {
+			return Err(High(SlashErrHigh::syntax_err(format!("run: {}: invalid option", arg), blame)))
+		};
+		namespaces |= flag;
+	}
+	if argv.is_empty() {
+		return Err(High(SlashErrHigh::syntax_err("run: expected a command", blame)))
+	}
+	if !namespaces.is_empty() && !geteuid().is_root() {
+		namespaces |= CloneFlags::CLONE_NEWUSER;
+	}
+
+	let envp = slash.child_envp();
+	let mut setup = command::ChildSetup::new(None);
+	setup.namespaces = namespaces;
+	slash.ctx_mut().extend_redirs(redirs);
+	command::exec_external_argv(argv, envp, setup, slash, blame)
+}
+
+fn namespace_flag(arg: &str) -> Option<CloneFlags> {
+	match arg {
+		"--user" | "-U" => Some(CloneFlags::CLONE_NEWUSER),
+		"--mount" | "-m" => Some(CloneFlags::CLONE_NEWNS),
+		"--net" | "-n" => Some(CloneFlags::CLONE_NEWNET),
+		_ => None
+	}
+}
+
+/// Moves the calling process into new namespaces. Only ever called in a freshly forked child,
+/// since a process with more than one thread can't create a user namespace
+pub fn enter_namespaces(namespaces: CloneFlags) -> nix::Result<()> {
+	if namespaces.is_empty() {
+		return Ok(())
+	}
+	let (uid, gid) = (getuid(), getgid());
+	unshare(namespaces)?;
+	if namespaces.contains(CloneFlags::CLONE_NEWUSER) {
+		// The group map can only be written by an unprivileged user once setgroups() is disabled
+		let write = |path: &str, content: String| std::fs::write(path, content).map_err(|e| Errno::from_raw(e.raw_os_error().unwrap_or(0)));
+		if Path::new("/proc/self/setgroups").exists() {
+			write("/proc/self/setgroups", "deny".into())?;
+		}
+		write("/proc/self/uid_map", format!("0 {} 1", uid))?;
+		write("/proc/self/gid_map", format!("0 {} 1", gid))?;
+	}
+	if namespaces.contains(CloneFlags::CLONE_NEWNS) {
+		// Mounts made by the command would otherwise propagate back to the shared mounts outside
+		mount(None::<&str>, "/", None::<&str>, MsFlags::MS_REC | MsFlags::MS_PRIVATE, None::<&str>)?;
+	}
+	Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn test_namespace_flags() {
+		assert_eq!(namespace_flag("-U"), Some(CloneFlags::CLONE_NEWUSER));
+		assert_eq!(namespace_flag("--mount"), Some(CloneFlags::CLONE_NEWNS));
+		assert_eq!(namespace_flag("--net"), Some(CloneFlags::CLONE_NEWNET));
+		assert_eq!(namespace_flag("--pid"), None);
+		assert_eq!(enter_namespaces(CloneFlags::empty()), Ok(()));
+	}
+}
@@ -0,0 +1,73 @@
+use crate::{execute::command, helper, prelude::*};
+
+/// The `sandbox` builtin, which runs an external command with a reduced environment
+/// `--clear-env` starts the command with no environment variables except the ones named with `--allow`, which takes a comma separated list.
+/// `--unset NAME` removes a single variable, and `--chdir DIR` runs the command in another directory without moving the shell.
+/// The command follows the options, optionally after `--`, and is still looked up in the shell's `PATH`
+pub fn execute<'a>(sandbox_call: Pair<'a,Rule>, slash: &mut Slash) -> SlashResult<()> {
+	let blame = sandbox_call.clone();
+	let redirs = helper::prepare_redirs(sandbox_call.clone(),slash)?;
+	let mut argv = helper::prepare_argv(sandbox_call,slash)?;
+	argv.pop_front();
+
+	let mut clear_env = false;
+	let mut allowed = HashSet::new();
+	let mut unset = HashSet::new();
+	let mut cwd = None;
+	while let Some(arg) = argv.front().cloned() {
+		if !arg.starts_with('-') {
+			break
+		}
+		argv.pop_front();
+		let mut value = |name: &str| {
+			argv.pop_front().ok_or_else(|| High(SlashErrHigh::syntax_err(format!("sandbox: {} expects an argument", name), blame.clone())))
+		};
+		match arg.as_str() {
+			"--" => break,
+			"--clear-env" | "-i" => clear_env = true,
+			"--allow" => allowed.extend(value(arg.as_str())?.split(',').map(String::from)),
+			"--unset" | "-u" => { unset.insert(value(arg.as_str())?); }
+			"--chdir" | "-C" => cwd = Some(PathBuf::from(value(arg.as_str())?)),
+			_ => return Err(High(SlashErrHigh::syntax_err(format!("sandbox: {}: invalid option", arg), blame)))
+		}
+	}
+	if argv.is_empty() {
+		return Err(High(SlashErrHigh::syntax_err("sandbox: expected a command", blame)))
+	}
+	if let Some(dir) = cwd.as_ref().filter(|dir| !dir.is_dir()) {
+		return Err(High(SlashErrHigh::exec_err(format!("sandbox: {}: not a directory", dir.display()), blame)))
+	}
+
+	let envp = sandbox_env(slash.vars().evars().into_iter(), clear_env, &allowed, &unset);
+	slash.ctx_mut().extend_redirs(redirs);
+	command::exec_external_argv(argv, envp, command::ChildSetup::new(cwd.as_deref()), slash, blame)
+}
+
+/// The environment given to a sandboxed command, as `NAME=value` strings
+fn sandbox_env(vars: impl Iterator<Item = (String,String)>, clear_env: bool, allowed: &HashSet<String>, unset: &HashSet<String>) -> Vec<CString> {
+	vars.filter(|(key,_)| !clear_env || allowed.contains(key))
+		.filter(|(key,_)| !unset.contains(key))
+		.map(|(key,val)| CString::new(format!("{}={}",key,val)).unwrap())
+		.collect()
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn test_sandbox() {
+		let vars = || [("PATH", "/bin"), ("HOME", "/home/user"), ("TOKEN", "secret")].into_iter().map(|(key,val)| (key.to_string(), val.to_string()));
+		let names = |names: &[&str]| names.iter().map(|name| name.to_string()).collect::<HashSet<_>>();
+		let env_strs = |envp: Vec<CString>| envp.into_iter().map(|var| var.into_string().unwrap()).collect::<Vec<_>>();
+		assert_eq!(env_strs(sandbox_env(vars(), true, &names(&["PATH", "HOME"]), &names(&[]))), ["PATH=/bin", "HOME=/home/user"]);
+		assert_eq!(env_strs(sandbox_env(vars(), false, &names(&[]), &names(&["TOKEN"]))), ["PATH=/bin", "HOME=/home/user"]);
+
+		let mut slash = Slash::new();
+		slash.vars_mut().export_var("SLASH_SANDBOX_SECRET", "hidden");
+		let dir = env::temp_dir();
+		let output = slash.exec_captured(&format!("sandbox --clear-env --allow PATH --chdir {} -- /bin/sh -c 'echo \"$SLASH_SANDBOX_SECRET:$PWD\"'", dir.display())).unwrap();
+		assert_eq!(output, format!(":{}\n", dir.canonicalize().unwrap().display()));
+		assert_eq!(slash.exec_captured("sandbox /bin/sh -c 'echo $SLASH_SANDBOX_SECRET'").unwrap(), "hidden\n");
+	}
+}
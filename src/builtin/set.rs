@@ -0,0 +1,161 @@
+use crate::prelude::*;
+
+use crate::{helper, shellenv::{EnvFlags, Slash}, utils, SlashResult};
+
+/// Sets or clears shell options
+/// `set -x` enables an option and `set +x` disables it. Multiple options can be combined, e.g. `set -tv`
+/// Options can also be given by name with `set -o name` and `set +o name`, and `set -o` lists them by name
+/// `set -- args...` replaces the positional parameters, as does the first argument that isn't an option
+/// With no arguments, every shell variable is printed
+/// `set --json` prints the enabled options and every shell variable as a JSON object,
+/// and `set --porcelain` prints them as tab separated `option`/`var` records
+pub fn execute<'a>(set_call: Pair<'a,Rule>, slash: &mut Slash) -> SlashResult<()> {
+	let blame = set_call.clone();
+	let redirs = helper::prepare_redirs(set_call.clone(),slash)?;
+	let mut argv = helper::prepare_argv(set_call,slash)?;
+	argv.pop_front();
+
+	if argv.is_empty() {
+		slash.consume_redirs(redirs)?;
+		return print_vars(slash)
+	}
+	if argv.len() == 1 && matches!(argv[0].as_str(), "--json" | "--porcelain") {
+		slash.consume_redirs(redirs)?;
+		return if argv[0] == "--json" { print_json(slash) } else { print_porcelain(slash) }
+	}
+
+	if argv.len() == 1 && matches!(argv[0].as_str(), "-o" | "+o") {
+		slash.consume_redirs(redirs)?;
+		return print_long_opts(slash)
+	}
+
+	while let Some(arg) = argv.pop_front() {
+		if arg == "--" {
+			slash.vars_mut().set_pos_params(argv);
+			break
+		}
+		if !arg.starts_with(['-','+']) {
+			argv.push_front(arg);
+			slash.vars_mut().set_pos_params(argv);
+			break
+		}
+		if matches!(arg.as_str(), "-o" | "+o") {
+			let Some(name) = argv.pop_front() else {
+				let msg = format!("set: {} requires an option name",arg);
+				return Err(High(SlashErrHigh::syntax_err(msg, blame)))
+			};
+			let Some(flag) = EnvFlags::from_long_opt(&name) else {
+				let msg = format!("Invalid option name in set call: {}",name);
+				return Err(High(SlashErrHigh::syntax_err(msg, blame)))
+			};
+			slash.meta_mut().mod_flags(|f| f.set(flag, arg == "-o"));
+			continue
+		}
+		let enable = arg.starts_with('-');
+		for opt in arg.chars().skip(1) {
+			let Some(flag) = EnvFlags::from_set_opt(opt) else {
+				let msg = format!("Invalid flag in set call: {}{}",if enable { '-' } else { '+' },opt);
+				return Err(High(SlashErrHigh::syntax_err(msg, blame)))
+			};
+			slash.meta_mut().mod_flags(|f| f.set(flag, enable));
+		}
+	}
+	Ok(())
+}
+
+/// Prints every shell variable as `name=value`, sorted by name
+fn print_vars(slash: &Slash) -> SlashResult<()> {
+	let mut vars = slash.vars().vars().iter().collect::<Vec<_>>();
+	vars.sort_by(|a,b| a.0.cmp(b.0));
+	let mut stdout = utils::SmartFD::new(STDOUT_FILENO)?;
+	for (key,val) in vars {
+		writeln!(stdout,"{}={}",key,val)?;
+	}
+	Ok(())
+}
+
+fn print_long_opts(slash: &Slash) -> SlashResult<()> {
+	let mut stdout = utils::SmartFD::new(STDOUT_FILENO)?;
+	for (name,flag) in EnvFlags::LONG_OPTS {
+		let state = if slash.meta().flags().contains(flag) { "on" } else { "off" };
+		writeln!(stdout,"{:<16}{}",name,state)?;
+	}
+	Ok(())
+}
+
+fn enabled_opts(slash: &Slash) -> Vec<char> {
+	EnvFlags::SET_OPTS.chars()
+		.filter(|opt| EnvFlags::from_set_opt(*opt).is_some_and(|flag| slash.meta().flags().contains(flag)))
+		.collect()
+}
+
+fn print_json(slash: &Slash) -> SlashResult<()> {
+	let vars = slash.vars().vars().iter()
+		.map(|(key,val)| (key.clone(), serde_json::Value::String(val.to_string())))
+		.collect::<serde_json::Map<_,_>>();
+	let opts = enabled_opts(slash).into_iter().map(|opt| opt.to_string()).collect::<Vec<_>>();
+	let json = serde_json::json!({ "options": opts, "variables": vars });
+	let mut stdout = utils::SmartFD::new(STDOUT_FILENO)?;
+	writeln!(stdout,"{}",json)?;
+	Ok(())
+}
+
+fn print_porcelain(slash: &Slash) -> SlashResult<()> {
+	let mut vars = slash.vars().vars().iter().collect::<Vec<_>>();
+	vars.sort_by(|a,b| a.0.cmp(b.0));
+	let mut stdout = utils::SmartFD::new(STDOUT_FILENO)?;
+	for opt in enabled_opts(slash) {
+		writeln!(stdout,"option\t{}",opt)?;
+	}
+	for (key,val) in vars {
+		writeln!(stdout,"var\t{}\t{}",key,utils::escape_porcelain(&val.to_string()))?;
+	}
+	Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+	use crate::execute;
+
+use super::*;
+
+	#[test]
+	fn test_set_flags() {
+		let mut slash = Slash::new();
+		execute::dispatch::exec_input("set -tv".to_string(), &mut slash).unwrap();
+		assert!(slash.meta().flags().contains(EnvFlags::EXIT_AFTER_EXEC | EnvFlags::PRINT_INPUT));
+
+		execute::dispatch::exec_input("set +v".to_string(), &mut slash).unwrap();
+		assert!(slash.meta().flags().contains(EnvFlags::EXIT_AFTER_EXEC));
+		assert!(!slash.meta().flags().contains(EnvFlags::PRINT_INPUT));
+
+		assert!(execute::dispatch::exec_input("set -Q".to_string(), &mut slash).is_err());
+
+		execute::dispatch::exec_input("set -o posix -o nounset".to_string(), &mut slash).unwrap();
+		assert!(slash.meta().flags().contains(EnvFlags::POSIX | EnvFlags::UNSET_IS_ERROR));
+		execute::dispatch::exec_input("set +o posix".to_string(), &mut slash).unwrap();
+		assert!(!slash.meta().flags().contains(EnvFlags::POSIX));
+		assert!(execute::dispatch::exec_input("set -o bogus".to_string(), &mut slash).is_err());
+
+		// Whatever follows `--` or the options becomes the positional parameters
+		assert_eq!(slash.exec_captured("set -- -a 'b c'; echo $# $1 $2").unwrap(), "2 -a b c
+");
+		assert_eq!(slash.exec_captured("set +v x y; echo $# $2; set --; echo $#").unwrap(), "2 y
+0
+");
+	}
+
+	#[test]
+	fn test_set_json() {
+		let mut slash = Slash::new();
+		execute::dispatch::exec_input("set -v; foo=\"a b\"".to_string(), &mut slash).unwrap();
+		let output = slash.exec_captured("set --json").unwrap();
+		let json: serde_json::Value = serde_json::from_str(&output).unwrap();
+		assert_eq!(json["options"], serde_json::json!(["v"]));
+		assert_eq!(json["variables"]["foo"], "a b");
+
+		let output = slash.exec_captured("set --porcelain").unwrap();
+		assert!(output.lines().any(|line| line == "option\tv"));
+		assert!(output.lines().any(|line| line == "var\tfoo\ta b"));
+	}
+}
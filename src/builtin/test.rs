@@ -1,3 +1,4 @@
+use std::cmp::Ordering;
 use std::fs;
 use std::os::unix::fs::{FileTypeExt, MetadataExt};
 
@@ -5,7 +6,7 @@ use nix::unistd::{access, getegid, geteuid, AccessFlags};
 
 use crate::prelude::*;
 
-use crate::{error::{SlashErr::*, SlashErrLow}, shellenv::Slash, SlashResult};
+use crate::{error::{SlashErr::*, SlashErrLow}, locale, shellenv::Slash, SlashResult};
 
 pub fn run_test<T,F1,F2>(arg: Option<String>,alter: F1,check_property: F2) -> SlashResult<bool>
 where F1: FnOnce(&str) -> SlashResult<T>, F2: FnOnce(&T) -> bool {
@@ -42,6 +43,16 @@ fn do_log_op<'a>(args: &mut VecDeque<String>, result: bool, operator: &str, slas
 	}
 }
 
+/// The `test` and `[` builtins, which set the exit status to 0 if the expression is true and 1 if it isn't
+pub fn execute<'a>(test_call: Pair<'a,Rule>, slash: &mut Slash) -> SlashResult<()> {
+	let blame = test_call.clone();
+	let mut argv = crate::helper::prepare_argv(test_call,slash)?;
+	argv.pop_front(); // Ignore the command name
+	let result = test(&mut argv, slash).blame(blame)?;
+	slash.set_code(if result { 0 } else { 1 });
+	Ok(())
+}
+
 /// The test function is a special snowflake and takes a mutable reference to an already prepared arg vector
 /// instead of a raw pair like the other builtins. This is to make recursion with -a/-o flags easier
 pub fn test<'a>(test_call: &mut VecDeque<String>, slash: &mut Slash) -> SlashResult<bool> {
@@ -126,6 +137,12 @@ pub fn test<'a>(test_call: &mut VecDeque<String>, slash: &mut Slash) -> SlashRes
 					match cmp.as_str() {
 						"=" => do_cmp(arg.as_str(), test_call.pop_front(), str_no_op, |lhs, rhs| lhs == rhs)?,
 						"!=" => do_cmp(arg.as_str(), test_call.pop_front(), str_no_op, |lhs, rhs| lhs != rhs)?,
+						// String ordering follows the collation order of the current locale
+						"<" | ">" => {
+							locale::sync(slash);
+							let ordering = if cmp == "<" { Ordering::Less } else { Ordering::Greater };
+							do_cmp(arg.as_str(), test_call.pop_front(), str_no_op, |lhs, rhs| locale::collate(lhs, rhs) == ordering)?
+						}
 						_ => {
 							if cmp.as_str() == "==" {
 								return Err(Low(SlashErrLow::InvalidSyntax("'==' is not a valid comparison operator for test calls. Use '=' instead.".into())));
@@ -163,4 +180,16 @@ mod tests {
 
 use super::*;
 
+	#[test]
+	fn test_string_ordering() {
+		let mut slash = Slash::new();
+		let mut check = |input: &str| {
+			let mut argv = input.split_whitespace().map(|arg| arg.to_string()).collect::<VecDeque<_>>();
+			test(&mut argv, &mut slash).unwrap()
+		};
+		assert!(check("apple < banana"));
+		assert!(check("banana > apple"));
+		assert!(!check("apple > apple"));
+		assert!(check("a < b -a b > a"));
+	}
 }
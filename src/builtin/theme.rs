@@ -0,0 +1,100 @@
+use crate::{helper, prelude::*, term::{self, Style}, utils};
+
+/// Manages the colors used by the shell
+/// `theme` lists every style, `theme set name spec` changes a style, `theme reset [name]` restores defaults,
+/// and `theme load file` reads `name = spec` lines from a theme file
+pub fn execute<'a>(theme_call: Pair<'a,Rule>, slash: &mut Slash) -> SlashResult<()> {
+	let blame = theme_call.clone();
+	let redirs = helper::prepare_redirs(theme_call.clone(),slash)?;
+	let mut argv = helper::prepare_argv(theme_call,slash)?;
+	argv.pop_front();
+
+	match argv.pop_front().as_deref() {
+		None => {
+			slash.consume_redirs(redirs)?;
+			list_styles()
+		}
+		Some("set") => {
+			let (Some(name), spec) = (argv.pop_front(), argv.into_iter().collect::<Vec<_>>().join(" ")) else {
+				return Err(High(SlashErrHigh::syntax_err("theme: usage: theme set <style> <spec>", blame)))
+			};
+			let style = find_style(&name).blame(blame.clone())?;
+			term::set_style(style, &spec).blame(blame)
+		}
+		Some("reset") => {
+			match argv.pop_front() {
+				Some(name) => term::reset_style(Some(find_style(&name).blame(blame)?)),
+				None => term::reset_style(None)
+			}
+			Ok(())
+		}
+		Some("load") => {
+			let Some(path) = argv.pop_front() else {
+				return Err(High(SlashErrHigh::syntax_err("theme: load requires a file path", blame)))
+			};
+			load_theme(&path).blame(blame)
+		}
+		Some(arg) => {
+			let msg = format!("theme: invalid subcommand: {}",arg);
+			Err(High(SlashErrHigh::syntax_err(msg, blame)))
+		}
+	}
+}
+
+fn find_style(name: &str) -> SlashResult<Style> {
+	Style::from_name(name).ok_or_else(|| Low(SlashErrLow::ExecFailed(format!("theme: unknown style: {}",name))))
+}
+
+/// Theme files hold one `name = spec` pair per line. Blank lines and lines starting with `#` are ignored
+fn load_theme(path: &str) -> SlashResult<()> {
+	let content = std::fs::read_to_string(path)
+		.map_err(|e| Low(SlashErrLow::ExecFailed(format!("theme: cannot read {}: {}",path,e))))?;
+	for (i,line) in content.lines().enumerate() {
+		let line = line.trim();
+		if line.is_empty() || line.starts_with('#') {
+			continue
+		}
+		let Some((name,spec)) = line.split_once('=') else {
+			return Err(Low(SlashErrLow::ExecFailed(format!("theme: {}:{}: expected `name = spec`",path,i + 1))))
+		};
+		let spec = spec.trim().trim_matches(|ch| ch == '"' || ch == '\'');
+		term::set_style(find_style(name.trim())?, spec)?;
+	}
+	Ok(())
+}
+
+fn list_styles() -> SlashResult<()> {
+	let mut stdout = utils::SmartFD::new(STDOUT_FILENO)?;
+	for style in Style::ALL {
+		let spec = style.spec().unwrap_or_else(|| "default".into());
+		writeln!(stdout,"{:<16}{}",style.name(),term::paint(style, &spec))?;
+	}
+	Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+	use crate::execute;
+
+use super::*;
+
+	#[test]
+	fn test_theme() {
+		let mut slash = Slash::new();
+		execute::dispatch::exec_input("theme set hl.comment 'bold #102030'".to_string(), &mut slash).unwrap();
+		assert_eq!(Style::HlComment.code(), "\x1b[1;38;2;16;32;48m");
+		assert!(execute::dispatch::exec_input("theme set hl.nonexistent red".to_string(), &mut slash).is_err());
+		assert!(execute::dispatch::exec_input("theme set hl.comment mauve".to_string(), &mut slash).is_err());
+
+		let file = env::temp_dir().join(format!("slash_theme_{}",std::process::id()));
+		std::fs::write(&file, "# comments are skipped\nhl.number = \"bright-green\"\n").unwrap();
+		execute::dispatch::exec_input(format!("theme load {}",file.display()), &mut slash).unwrap();
+		assert_eq!(Style::HlNumber.code(), "\x1b[92m");
+		std::fs::remove_file(file).ok();
+
+		execute::dispatch::exec_input("theme reset hl.comment".to_string(), &mut slash).unwrap();
+		execute::dispatch::exec_input("theme reset hl.number".to_string(), &mut slash).unwrap();
+		assert_eq!(Style::HlComment.code(), Style::HlComment.default_code());
+		assert_eq!(Style::HlNumber.code(), Style::HlNumber.default_code());
+	}
+}
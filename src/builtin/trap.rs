@@ -0,0 +1,158 @@
+use crate::{helper, prelude::*, signal, utils};
+
+/// The conditions that can be trapped besides signals
+const CONDITIONS: [&str;3] = ["EXIT", "ERR", "RETURN"];
+
+/// Maps a condition given to `trap` to the name that it is stored under
+/// Signals can be given by name, with or without the `SIG` prefix, or by number, and are stored under their full name like `SIGINT`
+fn condition_name(arg: &str) -> Option<&'static str> {
+	let name = arg.strip_prefix("SIG").unwrap_or(arg);
+	if name == "0" {
+		return Some("EXIT")
+	}
+	if let Some(cond) = CONDITIONS.iter().find(|cond| **cond == name) {
+		return Some(*cond)
+	}
+	let sig = match name.parse::<i32>() {
+		Ok(num) => Signal::try_from(num).ok()?,
+		Err(_) => format!("SIG{}",name).parse::<Signal>().ok()?
+	};
+	Some(sig.as_str())
+}
+
+/// The `trap` builtin
+/// `trap action condition...` sets the command to run when each condition is reached, and `trap - condition...` removes it.
+/// An empty action makes the condition do nothing. With no arguments or `-p`, the traps that are set are printed in a form that can be reused as input.
+/// The `EXIT` condition, which can also be given as `0`, runs when the shell exits. `ERR` runs whenever a command fails where `set -e` would exit,
+/// and is only inherited by functions, command substitutions, and subshells when `set -E` is on. `RETURN` runs when a function or a sourced file finishes,
+/// and is only inherited with `set -T`, or by functions given the trace attribute with `declare -ft`.
+/// A trap on a signal runs between commands once the signal arrives, in place of what the signal would normally do. An empty action ignores the signal,
+/// and subshells keep ignoring it, while other signal traps are reset in subshells. `trap -l` lists the signal names
+pub fn execute<'a>(trap_call: Pair<'a,Rule>, slash: &mut Slash) -> SlashResult<()> {
+	let blame = trap_call.clone();
+	let redirs = helper::prepare_redirs(trap_call.clone(),slash)?;
+	let mut argv = helper::prepare_argv(trap_call,slash)?;
+	argv.pop_front();
+	slash.consume_redirs(redirs)?;
+
+	if argv.front().is_some_and(|arg| arg == "-l") {
+		let mut stdout = utils::SmartFD::new(STDOUT_FILENO)?;
+		writeln!(stdout,"{}",signal::signal_names().join(" "))?;
+		return Ok(())
+	}
+	let print = argv.front().is_some_and(|arg| arg == "-p");
+	if print {
+		argv.pop_front();
+	}
+	if argv.front().is_some_and(|arg| arg == "--") {
+		argv.pop_front();
+	}
+	if print || argv.is_empty() {
+		let mut stdout = utils::SmartFD::new(STDOUT_FILENO)?;
+		for (condition,action) in slash.logic().borrow_traps() {
+			if argv.is_empty() || argv.iter().any(|arg| condition_name(arg) == Some(condition.as_str())) {
+				writeln!(stdout,"trap -- '{}' {}",action.replace('\'', "'\\''"),condition)?;
+			}
+		}
+		return Ok(())
+	}
+
+	// A lone condition resets it, the same as `trap - condition`
+	let action = if argv.len() == 1 { "-".into() } else { argv.pop_front().unwrap() };
+	for arg in argv {
+		let Some(condition) = condition_name(&arg) else {
+			return Err(High(SlashErrHigh::exec_err(format!("trap: {}: invalid condition", arg), blame)))
+		};
+		if let Ok(sig) = condition.parse::<Signal>() {
+			let handler = if action == "-" { None } else { Some(action.as_str()) };
+			signal::set_signal_trap(sig, handler).map_err(|_| High(SlashErrHigh::exec_err(format!("trap: {}: cannot be trapped", arg), blame.clone())))?;
+		}
+		if action == "-" {
+			slash.logic_mut().take_trap(condition);
+		} else {
+			slash.logic_mut().set_trap(condition, &action);
+		}
+	}
+	Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+	use crate::execute::dispatch::exec_input;
+
+	use super::*;
+
+	#[test]
+	fn test_exit_trap() {
+		let mut slash = Slash::new();
+		exec_input("trap \"trapped=\\$?; echo 'bye'\" 0".into(), &mut slash).unwrap();
+		assert_eq!(slash.exec_captured("trap").unwrap(), "trap -- 'trapped=$?; echo '\\''bye'\\''' EXIT\n");
+		exec_input("false".into(), &mut slash).unwrap();
+		slash.run_exit_trap();
+		assert_eq!(slash.vars().get_var("trapped").map(|val| val.to_string()).as_deref(), Some("1"));
+		assert_eq!(slash.get_status(), 1);
+		// The trap only runs once
+		assert!(slash.logic().get_trap("EXIT").is_none());
+
+		exec_input("trap 'exit 7' EXIT".into(), &mut slash).unwrap();
+		slash.run_exit_trap();
+		assert_eq!(slash.get_status(), 7);
+		exec_input("trap 'echo bye' EXIT; trap - EXIT".into(), &mut slash).unwrap();
+		assert!(slash.logic().borrow_traps().is_empty());
+		assert!(exec_input("trap 'echo bye' NOSUCHSIG".into(), &mut slash).is_err());
+	}
+
+	#[test]
+	fn test_err_trap() {
+		let mut slash = Slash::new();
+		let errs = |slash: &Slash| slash.vars().get_var("errs").map(|val| val.to_string()).unwrap_or_default();
+		exec_input("trap 'errs=${errs}$?' ERR; f() { false; true; }; g() { return 3; }".into(), &mut slash).unwrap();
+		// Conditions and commands followed by && or || are allowed to fail
+		exec_input("false; true && false; false || true; if false; then :; fi; while false; do :; done".into(), &mut slash).unwrap();
+		assert_eq!(errs(&slash), "11");
+		assert_eq!(slash.get_status(), 0);
+
+		// Functions only inherit the trap with set -E, but a function that fails still triggers it
+		exec_input("errs=; f; g".into(), &mut slash).unwrap();
+		assert_eq!(errs(&slash), "3");
+		// A function's changes to variables don't outlive it, so output shows where the trap ran
+		exec_input("set -E".into(), &mut slash).unwrap();
+		let output = slash.exec_captured("trap 'echo err $?' ERR; f; (false)").unwrap();
+		assert_eq!(output, "err 1\nerr 1\nerr 1\n");
+
+		// set -e exits after the trap runs
+		assert!(exec_input("set -e; false; errs=unreachable".into(), &mut slash).is_err());
+		assert_eq!(errs(&slash), "31");
+	}
+
+	#[test]
+	fn test_return_trap() {
+		let path = env::temp_dir().join(format!("slash_return_trap_{}",std::process::id()));
+		std::fs::write(&path, "sourced=1").unwrap();
+		let mut slash = Slash::new();
+		exec_input("f() { return 3; }; g() { trap 'echo inner $?' RETURN; true; }".into(), &mut slash).unwrap();
+		let script = format!("trap 'echo ret $?' RETURN; f; g; source {}", path.display());
+		assert_eq!(slash.exec_captured(&script).unwrap(), "inner 0\nret 0\n");
+		assert_eq!(slash.exec_captured(&format!("declare -ft f; {}", script)).unwrap(), "ret 3\ninner 0\nret 0\n");
+		exec_input("set -T".into(), &mut slash).unwrap();
+		assert_eq!(slash.exec_captured("trap 'echo ret $?' RETURN; f; echo $?").unwrap(), "ret 3\n3\n");
+		std::fs::remove_file(path).ok();
+	}
+
+	#[test]
+	fn test_signal_trap() {
+		let mut slash = Slash::new();
+		// The trap runs in a forked child, which is the process that the signal is sent to
+		let output = slash.exec_captured("trap 'echo caught $?' USR1; sh -c 'kill -USR1 $PPID; exit 3'; echo after").unwrap();
+		assert_eq!(output, "caught 3\nafter\n");
+		let output = slash.exec_captured("trap '' USR1; sh -c 'kill -USR1 $PPID'; trap; echo after").unwrap();
+		assert_eq!(output, "trap -- '' SIGUSR1\nafter\n");
+		assert_eq!(slash.exec_captured("trap 'echo $1' 10 SIGUSR2; trap -p USR1 12").unwrap(), "trap -- 'echo $1' SIGUSR1\ntrap -- 'echo $1' SIGUSR2\n");
+		assert!(slash.exec_captured("trap -l").unwrap().starts_with("HUP INT QUIT"));
+		assert!(exec_input("trap 'echo no' KILL".into(), &mut slash).is_err());
+		// Signal handlers belong to the whole process, so they're given back for the tests that run after this one
+		for sig in [Signal::SIGUSR1, Signal::SIGUSR2] {
+			signal::set_signal_trap(sig, None).unwrap();
+		}
+	}
+}
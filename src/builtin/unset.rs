@@ -0,0 +1,101 @@
+use crate::prelude::*;
+
+use crate::{helper, shellenv::Slash, SlashResult};
+
+bitflags! {
+	#[derive(Debug,Clone,Copy)]
+	pub struct UnsetFlags: u8 {
+		const FUNCS = 0b00001; // -f
+		const VARS  = 0b00010; // -v
+	}
+}
+
+/// Removes variables and functions
+/// `unset -f` only considers functions, `unset -v` only considers variables,
+/// and a bare `unset` tries a variable first before falling back to a function.
+/// `unset arr[n]` removes a single element from an array.
+pub fn execute<'a>(unset_call: Pair<'a,Rule>, slash: &mut Slash) -> SlashResult<()> {
+	let blame = unset_call.clone();
+	let mut argv = helper::prepare_argv(unset_call, slash)?;
+	argv.pop_front();
+	let mut flags = UnsetFlags::empty();
+
+	while argv.front().is_some_and(|arg| arg.starts_with('-')) {
+		let arg = argv.pop_front().unwrap();
+		for opt in arg.chars().skip(1) {
+			match opt {
+				'f' => flags |= UnsetFlags::FUNCS,
+				'v' => flags |= UnsetFlags::VARS,
+				_ => return Err(High(SlashErrHigh::syntax_err(format!("Invalid flag in unset call: -{}",opt), blame)))
+			}
+		}
+	}
+	if flags.contains(UnsetFlags::FUNCS | UnsetFlags::VARS) {
+		return Err(High(SlashErrHigh::syntax_err("unset: cannot simultaneously unset a function and a variable", blame)))
+	}
+
+	while let Some(arg) = argv.pop_front() {
+		if flags.contains(UnsetFlags::FUNCS) {
+			slash.unset_func(&arg).blame(blame.clone())?;
+			continue
+		}
+		if let Some((name,index)) = split_arr_index(&arg) {
+			let index = index.parse::<usize>().map_err(|_| {
+				High(SlashErrHigh::syntax_err(format!("unset: `{}' is not a valid array index",index), blame.clone()))
+			})?;
+			slash.vars_mut().unset_arr_index(&name, index).blame(blame.clone())?;
+			continue
+		}
+		let is_var = slash.vars().get_var(&arg).is_some();
+		if is_var || flags.contains(UnsetFlags::VARS) {
+			helper::unset_var_conflicts(slash, &arg)?;
+		} else if slash.is_func(&arg)? {
+			slash.unset_func(&arg).blame(blame.clone())?;
+		}
+	}
+	Ok(())
+}
+
+/// Splits `name[index]` into its name and index
+fn split_arr_index(arg: &str) -> Option<(String,String)> {
+	let (name,rest) = arg.split_once('[')?;
+	let index = rest.strip_suffix(']')?;
+	if name.is_empty() || index.is_empty() {
+		return None
+	}
+	Some((name.to_string(),index.to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+	use crate::{execute, shellenv::SlashVal};
+
+use super::*;
+
+	#[test]
+	fn test_unset_variants() {
+		let mut slash = Slash::new();
+		let input = "foo() { echo foo; }; string foo=bar; unset foo";
+		execute::dispatch::exec_input(input.to_string(), &mut slash).unwrap();
+		assert!(slash.vars().get_var("foo").is_none());
+		assert!(slash.logic().get_func("foo").is_some());
+
+		execute::dispatch::exec_input("unset foo".to_string(), &mut slash).unwrap();
+		assert!(slash.logic().get_func("foo").is_none());
+
+		let input = "bar() { echo bar; }; unset -v bar";
+		execute::dispatch::exec_input(input.to_string(), &mut slash).unwrap();
+		assert!(slash.logic().get_func("bar").is_some());
+
+		execute::dispatch::exec_input("unset -f bar".to_string(), &mut slash).unwrap();
+		assert!(slash.logic().get_func("bar").is_none());
+	}
+
+	#[test]
+	fn test_unset_arr_index() {
+		let mut slash = Slash::new();
+		let input = "arr list=[1,2,3]; unset list[1]";
+		execute::dispatch::exec_input(input.to_string(), &mut slash).unwrap();
+		assert_eq!(slash.vars().get_var("list"), Some(SlashVal::Array(vec![SlashVal::Int(1),SlashVal::Int(3)])));
+	}
+}
@@ -2,7 +2,7 @@ use crate::prelude::*;
 
 use nix::errno::Errno;
 
-use crate::helper;
+use crate::{helper, term};
 
 pub type SlashResult<T> = Result<T,SlashErr>;
 
@@ -51,7 +51,7 @@ impl<T> SlashErrExt<T> for Result<T,SlashErr> {
 	fn catch(self) -> Option<T> {
 		match self {
 			Err(err) => {
-				eprintln!("{}",err);
+				eprintln!("{}",crate::term::paint(crate::term::Style::Error, &err.to_string()));
 				None
 			}
 			Ok(thing) => Some(thing)
@@ -80,6 +80,61 @@ impl From<pest::error::Error<Rule>> for SlashErr {
 	}
 }
 
+impl SlashErr {
+	/// The simple error underneath, which is the error itself for `Low` errors
+	pub fn low(&self) -> &SlashErrLow {
+		match self {
+			SlashErr::Low(low) => low,
+			SlashErr::High(high) => high.get_err()
+		}
+	}
+	pub fn category(&self) -> Option<ErrCategory> {
+		self.low().category()
+	}
+	/// The line that the error points to, if it was blamed on part of the input
+	pub fn line(&self) -> Option<usize> {
+		let SlashErr::High(high) = self else {
+			return None
+		};
+		let (_,position) = high.pest_err.split_once("--> ")?;
+		// The position is `line:col`, or `path:line:col` once the error has been attributed to a file
+		position.lines().next()?.rsplitn(3, ':').nth(1)?.parse().ok()
+	}
+	/// Names the file that the error came from, so that its line and column refer to that file
+	/// Input that isn't a file can be named too, like `-c string` for the command given to `slash -c`.
+	/// Errors that already name a file, like ones from a file sourced by the file, are left alone
+	pub fn attribute_to(self, path: &str) -> Self {
+		match self {
+			SlashErr::High(mut high) => {
+				high.pest_err = attribute_source(&high.pest_err, path);
+				SlashErr::High(high)
+			}
+			SlashErr::Low(SlashErrLow::Parse(msg)) => SlashErr::Low(SlashErrLow::Parse(attribute_source(&msg, path))),
+			low => low
+		}
+	}
+}
+
+/// Inserts a file path into the position line of a pest error, turning `--> 2:5` into `--> path:2:5`
+/// Paths in the home directory are shortened to start with `~`
+fn attribute_source(err: &str, path: &str) -> String {
+	let path = shorten_home(path, &env::var("HOME").unwrap_or_default());
+	match err.split_once("--> ") {
+		Some((head,tail)) if tail.starts_with(|ch: char| ch.is_ascii_digit()) => format!("{}--> {}:{}",head,path,tail),
+		_ => err.to_string()
+	}
+}
+
+/// Replaces `home` at the start of a path with `~`
+/// A home of `/` is left alone, since every absolute path would start with it
+fn shorten_home(path: &str, home: &str) -> String {
+	let home = home.trim_end_matches('/');
+	match path.strip_prefix(home) {
+		Some(rest) if !home.is_empty() && rest.starts_with('/') => format!("~{}",rest),
+		_ => path.to_string()
+	}
+}
+
 impl Display for SlashErr {
 	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
 		match self {
@@ -89,6 +144,65 @@ impl Display for SlashErr {
 	}
 }
 
+/// The kinds of error the shell can run into, which decide the exit status of a failure and what happens after it
+#[derive(Debug,Clone,Copy,PartialEq,Eq)]
+pub enum ErrCategory {
+	/// Input that couldn't be parsed, or a builtin that was called with bad arguments
+	Parse,
+	/// A word that couldn't be expanded, like a bad arithmetic expression or an array index out of range
+	Expansion,
+	/// A redirection that couldn't be set up
+	Redir,
+	/// A command that ran and failed
+	Exec,
+	/// Something that should never happen, and is a bug in the shell itself
+	InternalBug,
+}
+
+/// What the shell does once an error has been reported
+#[derive(Debug,Clone,Copy,PartialEq,Eq)]
+pub enum Recovery {
+	/// Move on to the next command, without treating the error as a failed command
+	Continue,
+	/// Treat the error as a failed command, so the ERR trap runs and `set -e` exits
+	ErrExit,
+	/// Skip the rest of the input. A script ends, and an interactive shell goes back to the prompt
+	Abort,
+}
+
+#[derive(Debug,Clone,Copy,PartialEq,Eq)]
+pub struct ErrPolicy {
+	pub code: i32,
+	pub interactive: Recovery,
+	pub script: Recovery,
+}
+
+impl ErrPolicy {
+	const fn new(code: i32, interactive: Recovery, script: Recovery) -> Self {
+		Self { code, interactive, script }
+	}
+	pub fn recovery(&self, interactive: bool) -> Recovery {
+		if interactive { self.interactive } else { self.script }
+	}
+}
+
+/// The exit status and recovery for each category
+/// Syntax errors only end a script. Expansion errors throw out the rest of the input like POSIX asks for,
+/// while failed redirections and commands are ordinary failures
+const ERR_POLICIES: [(ErrCategory,ErrPolicy);5] = [
+	(ErrCategory::Parse,       ErrPolicy::new(2,  Recovery::Continue, Recovery::Abort)),
+	(ErrCategory::Expansion,   ErrPolicy::new(3,  Recovery::Abort,    Recovery::Abort)),
+	(ErrCategory::Redir,       ErrPolicy::new(4,  Recovery::ErrExit,  Recovery::ErrExit)),
+	(ErrCategory::Exec,        ErrPolicy::new(1,  Recovery::ErrExit,  Recovery::ErrExit)),
+	(ErrCategory::InternalBug, ErrPolicy::new(70, Recovery::Abort,    Recovery::Abort)),
+];
+
+impl ErrCategory {
+	pub fn policy(self) -> ErrPolicy {
+		ERR_POLICIES.iter().find(|(category,_)| *category == self).map(|(_,policy)| *policy).unwrap()
+	}
+}
+
 /// Simple errors
 #[derive(Debug,Clone)]
 pub enum SlashErrLow {
@@ -101,6 +215,8 @@ pub enum SlashErrLow {
 	InvalidSyntax(String),
 	InternalErr(String),
 	IndexErr(String),
+	ExpansionErr(String),
+	RedirErr(String),
 	ExecFailed(String),
 
 	// Not actual errors, used to propagate logic from commands like `exit` and `return`
@@ -108,12 +224,35 @@ pub enum SlashErrLow {
 	FuncReturn(i32),
 	LoopCont,
 	LoopBreak(i32),
+	Interrupted,
 }
 
 impl SlashErrLow {
 	pub fn from_io() -> Self {
 		Self::IoError(std::io::Error::last_os_error().to_string())
 	}
+	/// Errors that only carry control flow, like `exit`, `return`, `break`, and interrupts, have no category
+	pub fn category(&self) -> Option<ErrCategory> {
+		match self {
+			SlashErrLow::Parse(_) |
+			SlashErrLow::InvalidSyntax(_) => Some(ErrCategory::Parse),
+			SlashErrLow::IndexErr(_) |
+			SlashErrLow::ExpansionErr(_) => Some(ErrCategory::Expansion),
+			SlashErrLow::BadFD(_) |
+			SlashErrLow::RedirErr(_) => Some(ErrCategory::Redir),
+			SlashErrLow::IoError(_) |
+			SlashErrLow::ErrNo(_) |
+			SlashErrLow::CmdNotFound(_) |
+			SlashErrLow::BadPermission(_) |
+			SlashErrLow::ExecFailed(_) => Some(ErrCategory::Exec),
+			SlashErrLow::InternalErr(_) => Some(ErrCategory::InternalBug),
+			SlashErrLow::FuncReturn(_) |
+			SlashErrLow::LoopCont |
+			SlashErrLow::LoopBreak(_) |
+			SlashErrLow::CleanExit(_) |
+			SlashErrLow::Interrupted => None,
+		}
+	}
 }
 
 impl Display for SlashErrLow {
@@ -126,6 +265,8 @@ impl Display for SlashErrLow {
 			SlashErrLow::InvalidSyntax(msg) => write!(f,"Syntax Error: {}",msg),
 			SlashErrLow::InternalErr(msg) => write!(f,"Internal Error: {}",msg),
 			SlashErrLow::IndexErr(msg) => write!(f,"Index Error: {}",msg),
+			SlashErrLow::ExpansionErr(msg) => write!(f,"Expansion Error: {}",msg),
+			SlashErrLow::RedirErr(msg) => write!(f,"Redirection Error: {}",msg),
 			SlashErrLow::ExecFailed(msg) => write!(f,"Execution Failed: {}",msg),
 			SlashErrLow::CmdNotFound(name) => write!(f,"Command not found: {}",name),
 			SlashErrLow::BadPermission(name) => write!(f,"Permission denied: {}",name),
@@ -133,6 +274,7 @@ impl Display for SlashErrLow {
 			SlashErrLow::LoopCont => write!(f, "Found continue outside of loop"),
 			SlashErrLow::LoopBreak(_) => write!(f, "Found break outside of loop"),
 			SlashErrLow::CleanExit(_) => write!(f, ""),
+			SlashErrLow::Interrupted => write!(f, ""),
 		}
 	}
 }
@@ -184,6 +326,14 @@ impl SlashErrHigh {
 		Self::blame(pair, SlashErrLow::InternalErr(msg.into()))
 	}
 
+	pub fn expansion_err(msg: impl Into<String>, pair: Pair<Rule>) -> Self {
+		Self::blame(pair, SlashErrLow::ExpansionErr(msg.into()))
+	}
+
+	pub fn redir_err(msg: impl Into<String>, pair: Pair<Rule>) -> Self {
+		Self::blame(pair, SlashErrLow::RedirErr(msg.into()))
+	}
+
 	pub fn exec_err(msg: impl Into<String>, pair: Pair<Rule>) -> Self {
 		Self::blame(pair, SlashErrLow::ExecFailed(msg.into()))
 	}
@@ -193,8 +343,94 @@ impl SlashErrHigh {
 	}
 }
 
+/// Expands the tabs in the source lines of a pest diagnostic to `tab_stop` columns, and moves the underline to match
+/// Pest copies the tabs into the underline and counts every other character as one column, which only lines up
+/// when the terminal's tab stops happen to agree with it and the line has no wide characters
+pub fn expand_diagnostic_tabs(err: &str, tab_stop: usize) -> String {
+	// The underline's columns refer to the first source line, even when the blamed span continues onto another one
+	let mut source: Option<&str> = None;
+	let mut lines = vec![];
+	for line in err.lines() {
+		match line.split_once(" | ") {
+			Some((gutter,text)) if !gutter.trim().is_empty() && gutter.trim().chars().all(|ch| ch.is_ascii_digit()) => {
+				source.get_or_insert(text);
+				lines.push(format!("{} | {}",gutter,term::expand_tabs(text, tab_stop)));
+			}
+			Some((gutter,marker)) if gutter.trim().is_empty() && marker.contains('^') => {
+				lines.push(format!("{} | {}",gutter,realign_underline(source.unwrap_or_default(), marker, tab_stop)));
+			}
+			_ => lines.push(line.to_string())
+		}
+	}
+	lines.join("\n")
+}
+
+/// Rebuilds an underline like `\t ^---` or `  ^----^` so that it sits under the same characters once tabs are expanded
+fn realign_underline(source: &str, marker: &str, tab_stop: usize) -> String {
+	let offset = marker.chars().take_while(|ch| matches!(ch, ' ' | '\t')).count();
+	let marker = marker.trim_start_matches([' ','\t']);
+	let start = term::column_of(source, offset, tab_stop);
+	let span = marker.chars().count();
+	let end = offset + span;
+	let marker = if marker.len() > 1 && marker.ends_with('^') && end <= source.chars().count() {
+		let width = term::column_of(source, end, tab_stop) - start;
+		format!("^{}^","-".repeat(width.saturating_sub(2)))
+	} else {
+		marker.to_string()
+	};
+	format!("{}{}"," ".repeat(start),marker)
+}
+
 pub fn infer_parse_err(input: &str, err: pest::error::Error<Rule>) -> String {
 	let mut err_msg = err.to_string();
 
 	err_msg
 }
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn test_expand_diagnostic_tabs() {
+		let err = [
+			" --> 1:3",
+			"  |",
+			"1 | \t\tfoo | bar",
+			"  | \t\t^-^",
+			"  |",
+			"  = Command not found: foo"
+		].join("\n");
+		let expected = [
+			" --> 1:3",
+			"  |",
+			"1 |         foo | bar",
+			"  |         ^-^",
+			"  |",
+			"  = Command not found: foo"
+		].join("\n");
+		assert_eq!(expand_diagnostic_tabs(&err, 4), expected);
+
+		// Wide characters take two columns, so the underline has to move past both of them
+		let err = "1 | 日\tx\n  |  \t^---";
+		assert_eq!(expand_diagnostic_tabs(err, 8), "1 | 日      x\n  |         ^---");
+	}
+
+	#[test]
+	fn test_attribute_to() {
+		let err = SlashErr::Low(SlashErrLow::Parse(" --> 1:5\n  |\n1 | if true\n  |     ^---".into()));
+		let err = err.attribute_to("-c string");
+		assert!(err.to_string().contains(" --> -c string:1:5\n"));
+		// Already attributed errors keep the name they were given first
+		assert!(err.attribute_to("/other").to_string().contains(" --> -c string:1:5\n"));
+
+		if let Ok(home) = env::var("HOME") {
+			let err = SlashErr::Low(SlashErrLow::Parse(" --> 12:1".into()));
+			let path = format!("{}/.slashrc", home.trim_end_matches('/'));
+			assert!(err.attribute_to(&path).to_string().ends_with(" --> ~/.slashrc:12:1"));
+		}
+		assert_eq!(shorten_home("/home/user/.slashrc", "/home/user/"), "~/.slashrc");
+		assert_eq!(shorten_home("/home/username", "/home/user"), "/home/username");
+		assert_eq!(shorten_home("/etc/profile", "/"), "/etc/profile");
+	}
+}
@@ -5,7 +5,7 @@ use crate::utils;
 pub fn exec_cmd<'a>(cmd: Pair<Rule>, slash: &mut Slash) -> SlashResult<()> {
 	let blame = cmd.clone();
 	let mut argv = helper::prepare_argv(cmd.clone(),slash)?;
-	let mut redirs = helper::prepare_redirs(cmd)?;
+	let mut redirs = helper::prepare_redirs(cmd,slash)?;
 	slash.ctx_mut().extend_redirs(redirs);
 	argv.retain(|arg| !arg.is_empty() && arg != "\"\"" && arg != "''");
 
@@ -15,6 +15,48 @@ pub fn exec_cmd<'a>(cmd: Pair<Rule>, slash: &mut Slash) -> SlashResult<()> {
 		return slash.change_dir(&dir)
 	}
 
+	let envp = slash.child_envp();
+	exec_external_argv(argv, envp, ChildSetup::new(None), slash, blame)
+}
+
+/// Changes made in the child just before an external command is executed, which leave the shell itself untouched
+#[derive(Debug)]
+pub struct ChildSetup<'a> {
+	/// The directory the command runs in
+	pub cwd: Option<&'a Path>,
+	/// The namespaces the command is moved into, from the `run` builtin
+	#[cfg(feature = "namespaces")]
+	pub namespaces: nix::sched::CloneFlags
+}
+
+impl<'a> ChildSetup<'a> {
+	pub fn new(cwd: Option<&'a Path>) -> Self {
+		Self {
+			cwd,
+			#[cfg(feature = "namespaces")]
+			namespaces: nix::sched::CloneFlags::empty()
+		}
+	}
+	/// Applies the setup in the child, exiting with status 1 if any of it fails
+	fn apply(&self) {
+		#[cfg(feature = "namespaces")]
+		if let Err(e) = crate::builtin::run::enter_namespaces(self.namespaces) {
+			eprintln!("slash: failed to create namespaces: {}",e.desc());
+			std::process::exit(1)
+		}
+		if let Some(cwd) = self.cwd {
+			if let Err(e) = nix::unistd::chdir(cwd) {
+				eprintln!("slash: {}: {}",cwd.display(),e.desc());
+				std::process::exit(1)
+			}
+		}
+	}
+}
+
+/// Runs an external command with the given environment, after making the changes in `setup` in the child
+pub fn exec_external_argv<'a>(argv: VecDeque<String>, envp: Vec<CString>, setup: ChildSetup, slash: &mut Slash, blame: Pair<'a,Rule>) -> SlashResult<()> {
+	// Jobs are shown and looked up by the whole command line, not just the program
+	let cmd_line = argv.iter().cloned().collect::<Vec<_>>().join(" ");
 	let argv = argv.into_iter().map(|arg| CString::new(arg).unwrap()).collect::<Vec<_>>();
 
 
@@ -24,22 +66,32 @@ pub fn exec_cmd<'a>(cmd: Pair<Rule>, slash: &mut Slash) -> SlashResult<()> {
 	if utils::SHELL_CMDS.contains(&command.to_str().unwrap()) {
 		return Err(High(SlashErrHigh::exec_err(format!("This shell command appears malformed"), blame)))
 	}
-
-	let env_vars = env::vars().into_iter().collect::<Vec<(String,String)>>();
-	let envp = env_vars.iter().map(|var| CString::new(format!("{}={}",var.0,var.1)).unwrap()).collect::<Vec<_>>();
+	// Commands are looked up in the shell's PATH, which the process environment may not reflect
+	// If nothing executable matches, a match that can't be run is used instead, so that executing it reports "Permission denied"
+	let name = command.to_str().unwrap();
+	let resolved = helper::which(slash, name)
+		.or_else(|| helper::find_unrunnable(slash, name).map(|path| path.to_string_lossy().to_string()));
+	let command = match resolved {
+		Some(path) => CString::new(path).unwrap(),
+		None => command
+	};
 
 	slash.ctx_mut().activate_redirs()?;
 
 	if slash.ctx_mut().flags().contains(utils::ExecFlags::NO_FORK) {
+		setup.apply();
 		utils::exec_external(command, argv, envp, blame);
 	}
 
 	match unsafe { fork() } {
 		Ok(ForkResult::Child) => {
+			// The parent sets the group too, but the command may already be running by then, and it can't be moved after that
+			setpgid(Pid::from_raw(0), Pid::from_raw(0)).ok();
+			setup.apply();
 			utils::exec_external(command, argv, envp, blame);
 		}
 		Ok(ForkResult::Parent { child }) => {
-			utils::handle_parent_process(child, command.to_str().unwrap().to_string(),slash)?;
+			utils::handle_parent_process(child, cmd_line, slash)?;
 		}
 		Err(_) => todo!()
 	}
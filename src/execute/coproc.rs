@@ -0,0 +1,85 @@
+use crate::{helper, prelude::*, shellenv::{ChildProc, JobBuilder, SlashVal}, utils::{self, ExecFlags}};
+
+use super::dispatch;
+
+/// Runs a command asynchronously, with its stdin and stdout connected to the shell through a pair of pipes
+/// The pipe file descriptors are stored in an array named `COPROC`, or the name given in `coproc NAME { ... }`
+/// `NAME[0]` reads from the coprocess's stdout, and `NAME[1]` writes to its stdin. The pid is stored in `NAME_PID`
+pub fn exec_coproc<'a>(coproc: Pair<'a,Rule>, slash: &mut Slash) -> SlashResult<()> {
+	let blame = coproc.clone();
+	let name = coproc.scry(Rule::coproc_name).map(|name| name.as_str().to_string()).unwrap_or("COPROC".into());
+	let cmd = coproc.into_inner().find(|pair| matches!(pair.as_rule(), Rule::shell_cmd | Rule::simple_cmd)).unpack()?;
+	let cmd_name = helper::get_pipeline_cmd(cmd.clone())?;
+
+	// Redirections given to the coproc command itself are applied on top of the pipes
+	let redirs = slash.ctx_mut().take_redirs();
+	let (mut to_r, mut to_w) = utils::SmartFD::pipe()?;
+	let (mut from_r, mut from_w) = utils::SmartFD::pipe()?;
+
+	match unsafe { fork() } {
+		Ok(ForkResult::Child) => {
+			to_w.close()?;
+			from_r.close()?;
+			slash.ctx_mut().push_redir(utils::Redir::from_raw(0, to_r.as_raw_fd()));
+			slash.ctx_mut().push_redir(utils::Redir::from_raw(1, from_w.as_raw_fd()));
+			slash.ctx_mut().extend_redirs(redirs);
+			slash.enter_child_env();
+			*slash.ctx_mut().flags_mut() |= ExecFlags::NO_FORK;
+
+			match cmd.clone().step(1).filter(|inner| inner.as_rule() == Rule::brace_grp) {
+				Some(brace_grp) => {
+					slash.ctx_mut().activate_redirs()?;
+					let body = brace_grp.as_str().trim_matches(['{','}']).trim();
+					dispatch::exec_input(body.to_string(), slash)?;
+				}
+				None => dispatch::dispatch_exec(cmd, slash)?
+			}
+			std::process::exit(slash.get_status())
+		}
+		Ok(ForkResult::Parent { child }) => {
+			to_r.close()?;
+			from_w.close()?;
+			let fds = vec![
+				SlashVal::Int(from_r.into_raw_fd()),
+				SlashVal::Int(to_w.into_raw_fd())
+			];
+			slash.vars_mut().set_var(&name, SlashVal::Array(fds));
+			slash.vars_mut().set_var(&format!("{}_PID",name), SlashVal::Int(child.as_raw()));
+
+			let children = vec![
+				ChildProc::new(child, Some(&cmd_name), None)?
+			];
+			let job = JobBuilder::new()
+				.with_pgid(child)
+				.with_children(children)
+				.build();
+			helper::handle_bg(slash,job)?;
+		}
+		Err(_) => return Err(High(SlashErrHigh::exec_err("Failed to fork coprocess", blame)))
+	}
+	Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+	use crate::execute;
+
+use super::*;
+
+	#[test]
+	fn test_coproc_pipes() {
+		let mut slash = Slash::new();
+		execute::dispatch::exec_input("coproc tr a-z A-Z".to_string(), &mut slash).unwrap();
+		let fds = slash.vars().get_var("COPROC").unwrap();
+		let fds = fds.as_array().unwrap();
+		let mut read_fd = utils::SmartFD::new(fds[0].as_int().unwrap()).unwrap();
+		let mut write_fd = utils::SmartFD::new(fds[1].as_int().unwrap()).unwrap();
+		assert!(slash.vars().get_var("COPROC_PID").is_some());
+
+		write!(write_fd, "hello coproc").unwrap();
+		write_fd.close().unwrap();
+		let mut output = String::new();
+		read_fd.read_to_string(&mut output).unwrap();
+		assert_eq!(output, "HELLO COPROC");
+	}
+}
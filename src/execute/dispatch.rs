@@ -1,37 +1,59 @@
-use crate::{builtin::{self, BUILTINS}, error::SlashErrExt, expand, helper, prelude::*, script, utils::{ExecFlags, Redir}};
+use crate::{builtin, error::{Recovery, SlashErr, SlashErrExt}, expand, helper, prelude::*, script, shellenv, signal, utils::{self, ExecFlags}};
 
 use super::{pipeline, command, func};
 
+/// Runs a single command
+/// Temporary files made for the command, like process substitution fifos, are removed once it is done, even if it failed.
+/// A backgrounded command may still be using them after this returns, so its files are left for the shell to remove when it exits
 pub fn dispatch_exec<'a>(node: Pair<'a,Rule>, slash: &mut Slash) -> SlashResult<()> {
+	let mark = slash.meta().temp_files().len();
+	let result = dispatch_node(node, slash);
+	if !slash.ctx().flags().contains(ExecFlags::BACKGROUND) {
+		slash.meta_mut().remove_temp_files(mark);
+	}
+	result
+}
+
+fn dispatch_node<'a>(node: Pair<'a,Rule>, slash: &mut Slash) -> SlashResult<()> {
 		match node.as_rule() {
 			Rule::simple_cmd => {
 				let command_name = node.clone().into_inner().find(|pair| pair.as_rule() == Rule::cmd_name).unpack()?.as_str();
-				if !slash.ctx().flags().contains(ExecFlags::IGN_FUNC) && slash.is_func(command_name)? {
-					func::exec_func(node,slash)?;
-				} else if BUILTINS.contains(&command_name) {
-					exec_builtin(node,command_name,slash)?;
+				// Redirections are applied to the shell itself before the command runs, so they are undone before the next one
+				if node.clone().into_inner().any(|pair| pair.as_rule() == Rule::redir) {
+					utils::with_saved_fds(slash, |slash| exec_simple_cmd(node, command_name, slash))?;
 				} else {
-					command::exec_cmd(node, slash)?;
+					exec_simple_cmd(node, command_name, slash)?;
 				}
 			}
 			Rule::shell_cmd => {
+				if slash.ctx().flags().contains(ExecFlags::BACKGROUND) && forks_in_bg(&node) {
+					return exec_bg_compound(node, slash)
+				}
+				// Only simple commands can replace the current process
+				// Compound commands have to fork for each command they run, even inside of a pipeline
+				let in_fork = slash.ctx().flags().contains(ExecFlags::NO_FORK);
+				slash.ctx_mut().flags_mut().remove(ExecFlags::NO_FORK);
 				let mut shell_cmd_inner = node.to_deque();
 				let shell_cmd = shell_cmd_inner.pop_front().unpack()?;
+				let has_redirs = shell_cmd_inner.front().is_some_and(|pair| pair.as_rule() == Rule::redir);
 				while shell_cmd_inner.front().is_some_and(|pair| pair.as_rule() == Rule::redir) {
-					let redir = Redir::from_pair(shell_cmd_inner.pop_front().unpack()?)?;
+					let redir = helper::prepare_redir(shell_cmd_inner.pop_front().unpack()?, slash)?;
 					slash.ctx_mut().push_redir(redir);
 				}
-				match shell_cmd.as_rule() {
-					Rule::for_cmd => script::fordo::exec_for_cmd(shell_cmd, slash)?,
-					Rule::match_cmd => script::matchdo::exec_match_cmd(shell_cmd, slash)?,
-					Rule::loop_cmd => script::loopdo::exec_loop_cmd(shell_cmd, slash)?,
-					Rule::if_cmd => script::ifthen::exec_if_cmd(shell_cmd, slash)?,
-					Rule::subshell => super::subshell::exec_subshell(shell_cmd, slash)?,
-					Rule::brace_grp => todo!(),
-					Rule::assignment => super::assignment::exec_assignment(shell_cmd, slash)?,
-					Rule::func_def => super::func::exec_func_def(shell_cmd, slash)?,
-					_ => unreachable!()
-				};
+				if in_fork {
+					// This process only exists to run this command, so the redirections are applied once for all of it
+					shellenv::stop_reaping();
+					slash.ctx_mut().activate_redirs()?;
+					exec_shell_cmd(shell_cmd, slash)?;
+				} else if has_redirs {
+					// The redirections cover every command inside, and are undone once the compound command is done
+					utils::with_saved_fds(slash, |slash| {
+						slash.ctx_mut().activate_redirs()?;
+						exec_shell_cmd(shell_cmd, slash)
+					})?;
+				} else {
+					exec_shell_cmd(shell_cmd, slash)?;
+				}
 			}
 			Rule::pipeline => { pipeline::exec_pipeline(node, slash)?; },
 			Rule::EOI => { /* Do nothing */ }
@@ -40,50 +62,155 @@ pub fn dispatch_exec<'a>(node: Pair<'a,Rule>, slash: &mut Slash) -> SlashResult<
 		Ok(())
 }
 
-pub fn descend(mut node_stack: VecDeque<Pair<Rule>>, slash: &mut Slash) -> SlashResult<()> {
-	slash.ctx_mut().descend()?; // Increment depth counter
+fn exec_simple_cmd<'a>(cmd: Pair<'a,Rule>, command_name: &str, slash: &mut Slash) -> SlashResult<()> {
+	if !slash.ctx().flags().contains(ExecFlags::IGN_FUNC) && slash.is_func(command_name)? {
+		func::exec_func(cmd,slash)
+	} else if builtin::is_builtin(command_name) {
+		exec_builtin(cmd,command_name,slash)
+	} else {
+		command::exec_cmd(cmd, slash)
+	}
+}
+
+fn exec_shell_cmd<'a>(shell_cmd: Pair<'a,Rule>, slash: &mut Slash) -> SlashResult<()> {
+	match shell_cmd.as_rule() {
+		Rule::for_cmd => script::fordo::exec_for_cmd(shell_cmd, slash)?,
+		Rule::select_cmd => script::select::exec_select_cmd(shell_cmd, slash)?,
+		Rule::match_cmd => script::matchdo::exec_match_cmd(shell_cmd, slash)?,
+		Rule::loop_cmd => script::loopdo::exec_loop_cmd(shell_cmd, slash)?,
+		Rule::if_cmd => script::ifthen::exec_if_cmd(shell_cmd, slash)?,
+		Rule::arith_cmd => {
+			let body = shell_cmd.scry(Rule::arith_body).map(|body| body.as_str()).unwrap_or_default();
+			let value = expand::arithmetic::eval(body, slash).blame(shell_cmd)?;
+			slash.set_code(if value != 0 { 0 } else { 1 });
+		}
+		Rule::subshell => super::subshell::exec_subshell(shell_cmd, slash)?,
+		Rule::coproc_cmd => super::coproc::exec_coproc(shell_cmd, slash)?,
+		Rule::brace_grp => {
+			let body = shell_cmd.as_str().trim_matches(['{','}']).trim();
+			slash.exec_as_body(body)?;
+		}
+		Rule::assignment => super::assignment::exec_assignment(shell_cmd, slash)?,
+		Rule::func_def => super::func::exec_func_def(shell_cmd, slash)?,
+		_ => unreachable!()
+	};
+	Ok(())
+}
+
+/// Whether or not a shell command has to be run in a forked child when it is backgrounded
+/// Assignments, arithmetic commands, and function definitions always happen in the current shell
+fn forks_in_bg(shell_cmd: &Pair<Rule>) -> bool {
+	shell_cmd.clone().step(1).is_some_and(|inner| !matches!(inner.as_rule(), Rule::assignment | Rule::arith_cmd | Rule::func_def | Rule::coproc_cmd))
+}
+
+/// Runs an entire compound command like a loop or brace group in a background child with its own process group
+/// The compound itself runs in the foreground of that child, so the commands inside of it are not backgrounded individually
+fn exec_bg_compound<'a>(shell_cmd: Pair<'a,Rule>, slash: &mut Slash) -> SlashResult<()> {
+	let blame = shell_cmd.clone();
+	let cmd_name = helper::get_pipeline_cmd(shell_cmd.clone())?;
+	match unsafe { fork() } {
+		Ok(ForkResult::Child) => {
+			setpgid(Pid::from_raw(0), Pid::from_raw(0)).ok();
+			shellenv::revoke_tty_access();
+			slash.enter_child_env();
+			let flags = slash.ctx_mut().flags_mut();
+			flags.remove(ExecFlags::DISOWN);
+			*flags |= ExecFlags::NO_FORK;
+			if let Err(e) = dispatch_exec(shell_cmd, slash) {
+				if matches!(e, Low(SlashErrLow::Interrupted)) {
+					signal::exit_interrupted()
+				}
+				eprintln!("{}",e);
+				std::process::exit(1)
+			}
+			std::process::exit(slash.get_status())
+		}
+		Ok(ForkResult::Parent { child }) => {
+			utils::handle_parent_process(child, cmd_name, slash)
+		}
+		Err(_) => Err(High(SlashErrHigh::exec_err("Failed to fork background command", blame)))
+	}
+}
+
+pub fn descend(node_stack: VecDeque<Pair<Rule>>, slash: &mut Slash) -> SlashResult<()> {
+	let max_depth = slash.meta().borrow_shopts().core.max_recurse_depth;
+	slash.ctx_mut().descend(max_depth)?; // Increment depth counter
+	let result = exec_nodes(node_stack, slash);
+	slash.ctx_mut().ascend()?; // Decrement depth counter, even if execution failed
+	result
+}
+
+fn exec_nodes(mut node_stack: VecDeque<Pair<Rule>>, slash: &mut Slash) -> SlashResult<()> {
 	while let Some(node) = node_stack.pop_front() {
+		slash.run_signal_traps()?;
+		signal::check_interrupt()?;
 		match node.as_rule() {
 			Rule::main | Rule::cmd_list => {
 				let inner = node.to_deque();
 				node_stack.extend(inner);
 			}
 			Rule::op => {
-				let option = node.step(1);
-				if let Some(op) = option {
-					match op.as_rule() {
-						Rule::and => {
-							let is_success = slash.get_status() == 0;
-							if !is_success {
-								break
-							}
-						}
-						Rule::or => {
-							let is_success = slash.get_status() == 0;
-							if is_success {
-								break
-							}
-						}
-						_ => unreachable!()
-					}
+				if !runs_after(node, slash)? {
+					node_stack.pop_front();
 				}
 			}
 			Rule::bg_cmd => {
+				let disown = node.clone().into_inner().any(|pair| pair.as_rule() == Rule::disown_op);
 				if let Some(cmd) = node.step(1) {
 					let flags = slash.ctx_mut().flags_mut();
 					*flags |= ExecFlags::BACKGROUND;
-					dispatch_exec(cmd, slash)?
+					let result = if disown {
+						exec_disowned(cmd, slash)
+					} else {
+						dispatch_exec(cmd, slash)
+					};
+					slash.ctx_mut().flags_mut().remove(ExecFlags::BACKGROUND | ExecFlags::DISOWN);
+					result?
 				}
 			}
 			_ => dispatch_exec(node, slash)?
 		}
 	}
-	slash.ctx_mut().ascend()?; // Decrement depth counter
 	Ok(())
 }
 
-pub fn exec_input(mut input: String, slash: &mut Slash) -> SlashResult<()> {
-	input = expand::dispatch::expand_aliases(input, 0, vec![],slash)?;
+/// Executes a command started with `&!` or `&|`
+/// The command's stdio is detached from the terminal, and it is never added to the job table
+/// so it is not sent SIGHUP when the shell exits. Redirections given to the command still apply
+fn exec_disowned<'a>(cmd: Pair<'a,Rule>, slash: &mut Slash) -> SlashResult<()> {
+	*slash.ctx_mut().flags_mut() |= ExecFlags::DISOWN;
+	let saved_fds = utils::save_fds()?;
+	let mut dev_null = utils::SmartFD::std_open(Path::new("/dev/null"))?;
+	for fd in [STDIN_FILENO, STDOUT_FILENO, STDERR_FILENO] {
+		dev_null.dup2(&fd)?;
+	}
+	dev_null.close()?;
+	let result = dispatch_exec(cmd, slash);
+	utils::restore_fds(saved_fds, slash)?;
+	result
+}
+
+pub fn exec_input(input: String, slash: &mut Slash) -> SlashResult<()> {
+	exec_lists(input, slash, false)
+}
+
+/// Runs input at the top level of a script or an interactive session
+/// Unlike `exec_input()`, an error doesn't always end the input. Each one is handled by the policy of its category, see `error::ErrCategory`
+pub fn exec_toplevel(input: String, slash: &mut Slash) -> SlashResult<()> {
+	exec_lists(input, slash, true)
+}
+
+fn exec_lists(mut input: String, slash: &mut Slash, recover: bool) -> SlashResult<()> {
+	input = helper::inline_heredocs(&input).ok_or_else(|| Low(SlashErrLow::Parse("A heredoc is missing its closing delimiter".into())))?;
+	input = helper::join_continued_lines(&input);
+	if slash.aliases_enabled() {
+		input = expand::dispatch::expand_aliases(input, 0, vec![],slash)?;
+	}
+	let max_depth = slash.meta().borrow_shopts().core.max_recurse_depth;
+	if helper::nesting_depth(&input) > max_depth {
+		let msg = format!("Input is nested more than {} levels deep. The limit can be raised with `setopt core.max_recurse_depth=N`",max_depth);
+		return Err(Low(SlashErrLow::Parse(msg)))
+	}
 	let mut lists = SlashParse::parse(Rule::main, &input).map_err(|e| Low(SlashErrLow::Parse(e.to_string())))?.next().unwrap().into_inner().collect::<VecDeque<_>>();
 	lists.pop_back();
 	// Chew through the input one list at a time
@@ -91,72 +218,100 @@ pub fn exec_input(mut input: String, slash: &mut Slash) -> SlashResult<()> {
 		let mut cmds = list.into_inner();
 		while let Some(cmd) = cmds.next() {
 			if cmd.as_rule() == Rule::op {
-				let op = cmd.scry(&[Rule::and,Rule::or][..]).unpack()?;
-				match op.as_rule() {
-					Rule::and => {
-						if slash.get_status() != 0 {
-							break
-						} else {
-							continue
-						}
-					}
-					Rule::or => {
-						if slash.get_status() == 0 {
-							break
-						} else {
-							continue
-						}
-					}
-					_ => unreachable!()
+				if !runs_after(cmd, slash)? {
+					cmds.next();
 				}
+				continue
 			}
 			let blame = cmd.clone();
+			// A command that is followed by `&&` or `||` is allowed to fail
+			let followed_by_op = cmds.peek().is_some_and(|next| next.as_rule() == Rule::op);
 			let node_stack = VecDeque::from([cmd]);
-			descend(node_stack, slash).blame_no_overwrite(blame)?;
+			if let Err(err) = descend(node_stack, slash).blame_no_overwrite(blame.clone()) {
+				if !recover {
+					return Err(err)
+				}
+				if helper::recover_err(slash, err)? == Recovery::Continue {
+					continue
+				}
+			}
+			if !followed_by_op && slash.get_status() != 0 {
+				helper::handle_cmd_failure(slash).blame_no_overwrite(blame)?;
+			}
 		}
 	}
 	Ok(())
 }
 
+/// Whether the command after a `&&` or `||` runs, which depends on the status left by everything before it
+/// Chains are evaluated left to right with equal precedence, so `false && a || b` skips `a` and runs `b`
+fn runs_after(op: Pair<Rule>, slash: &Slash) -> SlashResult<bool> {
+	let op = op.scry(&[Rule::and,Rule::or][..]).unpack()?;
+	Ok((op.as_rule() == Rule::and) == (slash.get_status() == 0))
+}
+
 pub fn exec_builtin(cmd: Pair<Rule>, name: &str, slash: &mut Slash) -> SlashResult<()> {
 	let blame = cmd.clone();
-	match name {
-		"test" | "[" => {
-			let mut argv = helper::prepare_argv(cmd,slash)?;
-			argv.pop_front(); // Ignore the command name
-			let result = builtin::test::test(&mut argv, slash).blame(blame)?;
-			if result {
+	let Some(spec) = builtin::lookup(name) else {
+		if builtin::enable::is_loaded(name) {
+			// Loaded builtins report their own exit status
+			return builtin::enable::exec_loaded(cmd, slash)
+		}
+		#[cfg(feature = "wasm-plugins")]
+		if crate::plugin::has_builtin(name) {
+			return crate::plugin::exec_builtin(cmd, slash)
+		}
+		return Err(High(SlashErrHigh::exec_err(format!("Have not implemented support for builtin `{}` yet",name),blame)))
+	};
+	match (spec.func)(cmd, slash) {
+		Ok(()) => {
+			if !spec.sets_status {
 				slash.set_code(0);
-				return Ok(())
-			} else {
-				slash.set_code(1);
-				return Ok(())
 			}
+			Ok(())
 		}
-		"string" | "float" | "int" | "arr" | "bool" => builtin::assign::execute(cmd, slash)?,
-		"exec" => builtin::exec::run_exec(cmd, slash)?,
-		"fg" => builtin::job::continue_job(cmd, slash, true)?,
-		"bg" => builtin::job::continue_job(cmd, slash, false)?,
-		"jobs" => builtin::job::jobs(cmd, slash)?,
-		"return" => builtin::control::func_return(cmd, slash)?,
-		"break" => builtin::control::loop_break(cmd, slash)?,
-		"continue" => builtin::control::loop_continue()?,
-		"pushd" => builtin::dir_stack::pushd(cmd, slash)?,
-		"source" => builtin::source::execute(cmd, slash)?,
-		"popd" => builtin::dir_stack::popd(cmd, slash)?,
-		"setopt" => builtin::opts::setopt(cmd, slash)?,
-		"getopt" => builtin::opts::getopt(cmd, slash)?,
-		"exit" => builtin::control::exit(cmd, slash)?,
-		"cd" => builtin::cd::execute(cmd, slash)?,
-		"alias" => builtin::alias::execute(cmd, slash)?,
-		"unalias" => builtin::alias::unalias(cmd, slash)?,
-		"pwd" => builtin::pwd::execute(cmd, slash)?,
-		"export" => builtin::export::execute(cmd, slash)?,
-		"echo" => builtin::echo::execute(cmd, slash)?,
-		"builtin" => builtin::cmd_override::execute(cmd, slash, true)?,
-		"command" => builtin::cmd_override::execute(cmd, slash, false)?,
-		_ => return Err(High(SlashErrHigh::exec_err(format!("Have not implemented support for builtin `{}` yet",name),blame)))
-	};
-	slash.set_code(0);
-	Ok(())
+		// In POSIX mode, a special builtin that fails ends a non-interactive shell
+		Err(err) if spec.special && slash.is_posix() && !slash.meta().flags().contains(shellenv::EnvFlags::INTERACTIVE) && err.category().is_some() => {
+			helper::report_err(slash, &err);
+			Err(Low(SlashErrLow::CleanExit(slash.get_status())))
+		}
+		Err(err) => Err(err)
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn test_chains() {
+		let mut slash = Slash::new();
+		let mut run = |input: &str| slash.exec_captured(input).unwrap();
+		assert_eq!(run("false && echo a || echo b && echo c"), "b\nc\n");
+		assert_eq!(run("true || echo a && echo b"), "b\n");
+		assert_eq!(run("false && echo a && echo b || echo c"), "c\n");
+		assert_eq!(run("false && echo a; echo $?"), "1\n");
+		assert_eq!(run("if false && true || true; then echo yes; fi"), "yes\n");
+	}
+
+	#[test]
+	fn test_compound_bodies() {
+		let mut slash = Slash::new();
+		let mut run = |input: &str| slash.exec_captured(input).unwrap();
+		assert_eq!(run("if false; then :; else echo a; echo b\necho c; fi"), "a\nb\nc\n");
+		assert_eq!(run("if true; then echo job & wait; fi"), "job\n");
+		assert_eq!(run("for i < 1 2; do echo $i & wait; done"), "1\n2\n");
+		assert_eq!(run("if true; then echo compound; fi & wait"), "compound\n");
+	}
+
+	#[test]
+	fn test_redir_scope() {
+		let mut slash = Slash::new();
+		let path = std::env::temp_dir().join(format!("slash_redir_scope_{}",std::process::id()));
+		// Each command's redirections are undone before the next one, and a compound command's cover everything inside of it
+		let input = format!("echo a > {0}; echo b; {{ echo c; /bin/echo d; }} >> {0}; echo e; cat {0}",path.display());
+		let output = slash.exec_captured(&input).unwrap();
+		std::fs::remove_file(&path).ok();
+		assert_eq!(output, "b\ne\na\nc\nd\n");
+	}
 }
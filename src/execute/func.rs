@@ -1,4 +1,4 @@
-use crate::{helper, prelude::*};
+use crate::{helper, prelude::*, shellenv::FuncAttrs};
 
 use super::dispatch;
 
@@ -10,7 +10,7 @@ pub fn exec_func_def<'a>(func_def: Pair<'a,Rule>, slash: &mut Slash) -> SlashRes
 		slash,
 		func_name.as_str().trim_end_matches("()"),
 		body.as_str().trim_matches(['{','}']).trim()
-	)?;
+	).blame(blame)?;
 	slash.set_code(0);
 	Ok(())
 }
@@ -20,22 +20,42 @@ pub fn exec_func(cmd: Pair<Rule>,slash: &mut Slash) -> SlashResult<()> {
 	let mut argv = helper::prepare_argv(cmd,slash)?;
 	let func_name = argv.pop_front().unwrap();
 	let body = slash.logic().get_func(&func_name).unwrap();
+	let depth = slash.meta().func_depth();
+	let max_depth = slash.meta().borrow_shopts().core.max_func_depth;
+	if depth >= max_depth {
+		let msg = format!("{}: maximum function nesting level of {} exceeded. The limit can be raised with `setopt core.max_func_depth=N`",func_name,max_depth);
+		return Err(High(SlashErrHigh::exec_err(msg, blame)))
+	}
 	let mut var_table = slash.vars().clone();
 	let snapshot = slash.clone();
+	// The snapshot restores the depth when the function returns
+	slash.meta_mut().set_func_depth(depth + 1);
 
 	var_table.reset_params();
+	let source = slash.logic().borrow_functions().get(&func_name).map(|func| func.source().to_string()).unwrap_or_default();
+	var_table.push_call_frame(&func_name, &source, blame.as_span().start_pos().line_col().0);
 	for arg in argv {
 		var_table.pos_param_pushback(&arg);
 	}
 	*slash.vars_mut() = var_table;
+	let traced = slash.logic().func_attrs(&func_name).contains(FuncAttrs::TRACE);
+	slash.enter_trap_scope(traced);
 	let result = dispatch::exec_input(body, slash);
-	*slash = snapshot;
 
-	let code = helper::extract_return(&result);
-	if let Ok(code) = code {
-		slash.set_code(code);
-		Ok(())
-	} else {
-		result
-	}
+	// Without a `return`, the function's status is the status of the last command it ran
+	let code = match result {
+		Ok(_) => Ok(slash.get_status()),
+		Err(_) => helper::extract_return(&result)
+	};
+	let Ok(code) = code else {
+		*slash = snapshot;
+		return result
+	};
+	// The RETURN trap runs before the function's scope is thrown away
+	slash.set_code(code);
+	let trap_result = slash.run_trap("RETURN");
+	*slash = snapshot;
+	trap_result?;
+	slash.set_code(code);
+	Ok(())
 }
@@ -2,5 +2,6 @@ pub mod assignment;
 pub mod command;
 pub mod func;
 pub mod subshell;
+pub mod coproc;
 pub mod dispatch;
 pub mod pipeline;
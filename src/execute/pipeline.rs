@@ -1,8 +1,11 @@
-use crate::{helper, utils, prelude::*, shellenv::{ChildProc, JobBuilder}};
+use crate::{builtin, helper, utils, prelude::*, shellenv::{ChildProc, JobBuilder, SlashVal}};
 
 use super::dispatch;
 
 pub fn exec_pipeline<'a>(pipeline: Pair<'a,Rule>, slash: &mut Slash) -> SlashResult<()> {
+	if !slash.ctx().flags().contains(utils::ExecFlags::BACKGROUND) && only_builtins(&pipeline, slash) {
+		return exec_builtin_pipeline(pipeline, slash)
+	}
 	let blame = pipeline.clone();
 	let (in_redirs,out_redirs) = slash.ctx_mut().sort_redirs();
 	let _ = slash.ctx_mut().take_redirs();
@@ -36,6 +39,7 @@ pub fn exec_pipeline<'a>(pipeline: Pair<'a,Rule>, slash: &mut Slash) -> SlashRes
 				let _ = w_pipe.as_ref()
 					.map(|w| utils::Redir::from_raw(1, w.as_raw_fd()))
 					.and_then(|redir| Some(slash.ctx_mut().push_redir(redir)));
+				slash.enter_child_env();
 				*slash.ctx_mut().flags_mut() |= utils::ExecFlags::NO_FORK;
 				// These two if statements handle the case of existing i/o for the pipeline
 				// Stuff like shell functions in the middle of pipelines
@@ -48,8 +52,21 @@ pub fn exec_pipeline<'a>(pipeline: Pair<'a,Rule>, slash: &mut Slash) -> SlashRes
 					slash.ctx_mut().extend_redirs(out_redirs.into());
 				}
 
-				dispatch::dispatch_exec(node, slash)?;
-				std::process::exit(1)
+				if let Err(e) = dispatch::dispatch_exec(node, slash) {
+					let low = match &e {
+						Low(low) => low,
+						High(high) => high.get_err()
+					};
+					// `exit` in a pipeline only ends its own stage, with the status it was given
+					match low {
+						SlashErrLow::CleanExit(code) => std::process::exit(*code),
+						SlashErrLow::Interrupted => crate::signal::exit_interrupted(),
+						_ => {}
+					}
+					eprintln!("{}",e);
+					std::process::exit(1)
+				}
+				std::process::exit(slash.get_status())
 			}
 			Ok(ForkResult::Parent { child }) => {
 				if let Some(mut pipe) = w_pipe {
@@ -73,7 +90,11 @@ pub fn exec_pipeline<'a>(pipeline: Pair<'a,Rule>, slash: &mut Slash) -> SlashRes
 						.with_children(children)
 						.build();
 
-					helper::handle_fg(slash,job)?;
+					if slash.ctx().flags().contains(utils::ExecFlags::BACKGROUND) {
+						helper::handle_bg(slash,job)?;
+					} else {
+						helper::handle_fg(slash,job)?;
+					}
 				}
 			}
 			Err(e) => return Err(High(SlashErrHigh::exec_err("Command in pipeline failed", blame)))
@@ -82,6 +103,136 @@ pub fn exec_pipeline<'a>(pipeline: Pair<'a,Rule>, slash: &mut Slash) -> SlashRes
 			first = false;
 		}
 	}
-	slash.set_code(0);
 	Ok(())
 }
+
+/// Whether every command in a pipeline is a builtin that can run inside of the shell
+/// Functions shadow builtins, so a name that is also a function doesn't count
+fn only_builtins(pipeline: &Pair<Rule>, slash: &Slash) -> bool {
+	pipeline.clone().into_inner().all(|node| {
+		if node.as_rule() != Rule::simple_cmd {
+			return false
+		}
+		let Some(name) = node.into_inner().find(|pair| pair.as_rule() == Rule::cmd_name).map(|pair| pair.as_str()) else {
+			return false
+		};
+		slash.logic().get_func(name).is_none() && builtin::lookup(name).is_some_and(|spec| !spec.runs_cmds)
+	})
+}
+
+/// Runs a pipeline made only of builtins without forking for each of them
+/// The builtins run one after another, each writing into an in-memory file that the next one reads from.
+/// Each one runs in a copy of the shell that is thrown away afterwards, like the forked stages of other pipelines,
+/// except for the last one when `core.lastpipe` is set, which runs in the current shell
+fn exec_builtin_pipeline<'a>(pipeline: Pair<'a,Rule>, slash: &mut Slash) -> SlashResult<()> {
+	// Builtins that see NO_FORK exit when they are done, which would take the shell with them
+	// The flag is put back however the stages end, including when one of them fails to be set up
+	let no_fork = slash.ctx().flags().contains(utils::ExecFlags::NO_FORK);
+	slash.ctx_mut().flags_mut().remove(utils::ExecFlags::NO_FORK);
+	let result = run_builtin_stages(pipeline, slash);
+	if no_fork {
+		*slash.ctx_mut().flags_mut() |= utils::ExecFlags::NO_FORK;
+	}
+	result
+}
+
+/// Runs the stages of a builtin pipeline, setting `PIPESTATUS` and the exit status from them
+fn run_builtin_stages<'a>(pipeline: Pair<'a,Rule>, slash: &mut Slash) -> SlashResult<()> {
+	let (in_redirs,out_redirs) = slash.ctx_mut().sort_redirs();
+	let _ = slash.ctx_mut().take_redirs();
+	let lastpipe = slash.meta().borrow_shopts().core.lastpipe;
+
+	let mut stages = pipeline.into_inner().peekable();
+	let mut input: Option<utils::SmartFD> = None;
+	let mut statuses = vec![];
+	let mut first = true;
+	let mut result = Ok(());
+	while let Some(node) = stages.next() {
+		let last = stages.peek().is_none();
+		let output = if last { None } else { Some(utils::SmartFD::new_memfd("pipeline", false)?) };
+		// Redirections of the whole pipeline apply to its first and last stages
+		let mut redirs = VecDeque::new();
+		if first {
+			redirs.extend(in_redirs.iter().cloned());
+		}
+		if last {
+			redirs.extend(out_redirs.iter().cloned());
+		}
+		let run_stage = |slash: &mut Slash| {
+			slash.ctx_mut().extend_redirs(redirs);
+			dispatch::dispatch_exec(node, slash)
+		};
+
+		// The stage's own result is kept apart from failures to set up its stdio, which end the whole pipeline
+		let stage_result = utils::with_saved_fds(slash, |slash| {
+			if let Some(mut input) = input.take() {
+				nix::unistd::lseek(input.as_raw_fd(), 0, nix::unistd::Whence::SeekSet).map_err(|_| Low(SlashErrLow::from_io()))?;
+				input.dup2(&STDIN_FILENO)?;
+				input.close()?;
+			}
+			if let Some(output) = &output {
+				output.dup2(&STDOUT_FILENO)?;
+			}
+			Ok(if last && lastpipe {
+				run_stage(slash)
+			} else {
+				slash.in_child_env(run_stage)
+			})
+		})?;
+		let status = match stage_result {
+			Ok(()) => slash.get_status(),
+			// An error in the last stage belongs to the current shell when it runs there
+			Err(e) if last && lastpipe => {
+				result = Err(e);
+				slash.get_status()
+			}
+			Err(e) if matches!(e.low(), SlashErrLow::Interrupted) => return Err(e),
+			Err(e) => match e.low() {
+				// `exit` only ends its own stage, with the status it was given
+				SlashErrLow::CleanExit(code) => *code,
+				_ => {
+					eprintln!("{}",e);
+					1
+				}
+			}
+		};
+		statuses.push(SlashVal::Int(status));
+		input = output;
+		first = false;
+	}
+
+	let code = match statuses.last() {
+		Some(SlashVal::Int(code)) => *code,
+		_ => 0
+	};
+	slash.vars_mut().set_var("PIPESTATUS", SlashVal::Array(statuses));
+	slash.set_code(code);
+	result
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn test_pipestatus() {
+		let mut slash = Slash::new();
+		dispatch::exec_input("false | (exit 3) | true".into(), &mut slash).unwrap();
+		assert_eq!(slash.get_status(), 0);
+		assert_eq!(slash.vars().get_var("PIPESTATUS"), Some(SlashVal::Array(vec![SlashVal::Int(1), SlashVal::Int(3), SlashVal::Int(0)])));
+		assert_eq!(slash.exec_captured("true | false; echo $? $PIPESTATUS[0] \"$PIPESTATUS[1]\"").unwrap(), "1 0 1\n");
+	}
+
+	#[test]
+	fn test_builtin_pipeline() {
+		let mut slash = Slash::new();
+		// Without lastpipe, `read` runs in a copy of the shell and its variables are lost
+		assert_eq!(slash.exec_captured("x=old; echo foo bar | read x y; echo $x").unwrap(), "old\n");
+		assert_eq!(slash.exec_captured("setopt core.lastpipe=true; echo foo bar | read x y; echo $x-$y").unwrap(), "foo-bar\n");
+		assert_eq!(slash.exec_captured("setopt core.lastpipe=true; printf '%s\\n' one two three | mapfile -t lines; echo $lines[2]").unwrap(), "three\n");
+		// Stages before the last never change the current shell
+		assert_eq!(slash.exec_captured("z=1; export z=2 | read w; echo $z").unwrap(), "1\n");
+
+		assert_eq!(slash.exec_captured("read a < /dev/null | echo; echo $PIPESTATUS[0] $PIPESTATUS[1]").unwrap(), "\n1 0\n");
+	}
+}
@@ -1,3 +1,4 @@
+use crate::builtin::{alias, declare};
 use crate::expand;
 use crate::helper;
 use crate::prelude::*;
@@ -17,23 +18,62 @@ pub fn exec_subshell<'a>(subsh: Pair<'a,Rule>, slash: &mut Slash) -> SlashResult
 	}
 
 	let argv = helper::prepare_argv(subsh.clone(),slash)?;
-	let redirs = helper::prepare_redirs(subsh)?;
+	let redirs = helper::prepare_redirs(subsh,slash)?;
 
 	slash.ctx_mut().extend_redirs(redirs);
 	if let Some(shebang) = shebang {
-		let script = format!("{}{}",shebang,body);
+		// A slash subshell runs in a new process, so it is given the parent's aliases and functions first
+		let definitions = if runs_slash(&shebang) { definitions(slash) } else { String::new() };
+		let script = format!("{}{}{}",shebang,definitions,body);
 		handle_external_subshell(script,argv,slash)?;
 	} else {
 		handle_internal_subshell(body.to_string(),argv,slash)?;
 	}
-
-	slash.set_code(0);
 	Ok(())
 }
 
+/// Whether a subshell's interpreter is slash itself, either directly or through `env`
+fn runs_slash(shebang: &str) -> bool {
+	let file_name = |word: &str| Path::new(word).file_name().map(|name| name.to_string_lossy().to_string());
+	let mut words = shebang.trim_start_matches("#!").split_whitespace();
+	let Some(mut interpreter) = words.next().and_then(file_name) else {
+		return false
+	};
+	if interpreter == "env" {
+		match words.find(|word| !word.starts_with('-')).and_then(file_name) {
+			Some(name) => interpreter = name,
+			None => return false
+		}
+	}
+	let current = env::current_exe().ok().and_then(|exe| exe.file_name().map(|name| name.to_string_lossy().to_string()));
+	interpreter == "slash" || current.is_some_and(|current| current == interpreter)
+}
+
+/// The shell's aliases and functions as a script that defines them again, sorted by name
+fn definitions(slash: &Slash) -> String {
+	let mut script = String::new();
+	let mut aliases = slash.logic().borrow_aliases().iter().collect::<Vec<_>>();
+	aliases.sort();
+	for (name,body) in aliases {
+		script.push_str(&alias::format_alias(name, body));
+		script.push('\n');
+	}
+	let mut names = slash.logic().borrow_functions().keys().cloned().collect::<Vec<_>>();
+	names.sort();
+	for name in names {
+		let body = slash.logic().get_func(&name).unwrap_or_default();
+		script.push_str(&format!("{}() {{ {} }}\n",name,body));
+		let attrs = slash.logic().func_attrs(&name);
+		if !attrs.is_empty() {
+			script.push_str(&format!("declare {} {}\n",declare::attr_flags(attrs),name));
+		}
+	}
+	script
+}
+
 fn handle_external_subshell(script: String, argv: VecDeque<String>, slash: &mut Slash) -> SlashResult<()> {
 	let argv = argv.into_iter().map(|arg| CString::new(arg).unwrap()).collect::<Vec<_>>();
-	let envp = slash.get_cstring_evars()?;
+	let envp = slash.child_envp();
 	let mut memfd = utils::SmartFD::new_memfd("anonymous_subshell", true)?;
 	write!(memfd,"{}",script)?;
 
@@ -66,14 +106,77 @@ fn handle_external_subshell(script: String, argv: VecDeque<String>, slash: &mut
 	Ok(())
 }
 
+/// Runs a slash subshell without forking, in a copy of the shell that is thrown away afterwards
+/// `exit` only ends the subshell, and its status becomes the subshell's status
 fn handle_internal_subshell(body: String, argv: VecDeque<String>, slash: &mut Slash) -> SlashResult<()> {
-	let snapshot = slash.clone();
-	slash.ctx_mut().activate_redirs()?;
-	slash.vars_mut().reset_params();
-	for arg in argv {
-		slash.vars_mut().pos_param_pushback(&arg);
+	let result = slash.in_child_env(|slash| {
+		slash.ctx_mut().activate_redirs()?;
+		slash.vars_mut().reset_params();
+		for arg in argv {
+			slash.vars_mut().pos_param_pushback(&arg);
+		}
+		dispatch::exec_input(body.consume_escapes(), slash)
+	});
+	match result {
+		Err(e) => match e.low() {
+			SlashErrLow::CleanExit(code) => {
+				slash.set_code(*code);
+				Ok(())
+			}
+			_ => Err(e)
+		}
+		Ok(()) => Ok(())
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn test_child_env() {
+		let mut slash = Slash::new();
+		let get = |slash: &Slash, name: &str| slash.vars().get_var(name).map(|val| val.to_string());
+		dispatch::exec_input("x=1; (x=2; export SLASH_SUBSHELL_LEAK=yes; unset x); y=$(x=3; echo $x)".into(), &mut slash).unwrap();
+		assert_eq!(get(&slash, "x").as_deref(), Some("1"));
+		assert_eq!(get(&slash, "y").as_deref(), Some("3"));
+		assert!(slash.vars().get_evar("SLASH_SUBSHELL_LEAK").is_none());
+		assert!(env::var("SLASH_SUBSHELL_LEAK").is_err());
+
+		// Variables that the subshell never exported are left alone, like ones set by another thread while it ran
+		slash.in_child_env(|_| {
+			env::set_var("SLASH_SUBSHELL_OTHER", "kept");
+			Ok(())
+		}).unwrap();
+		assert_eq!(env::var("SLASH_SUBSHELL_OTHER").as_deref(), Ok("kept"));
+		env::remove_var("SLASH_SUBSHELL_OTHER");
+
+		// `exit` only ends the subshell
+		dispatch::exec_input("(exit 3)".into(), &mut slash).unwrap();
+		assert_eq!(slash.get_status(), 3);
+
+		// Exported variables reach external commands, shell variables don't
+		let output = slash.exec_captured("export SLASH_SUBSHELL_EXPORT=yes; hidden=no; /bin/sh -c 'echo \"$SLASH_SUBSHELL_EXPORT [$hidden]\"'").unwrap();
+		assert_eq!(output, "yes []\n");
+	}
+
+	#[test]
+	fn test_subshell_definitions() {
+		assert!(runs_slash("#!/usr/bin/slash\n"));
+		assert!(runs_slash("#!/usr/bin/env slash\n"));
+		assert!(!runs_slash("#!/usr/bin/python3\n"));
+		assert!(!runs_slash("#!/usr/bin/env -S python3\n"));
+
+		let mut slash = Slash::new();
+		dispatch::exec_input("alias ll='ls -l'; greet() { echo \"hi $1\"; }; declare -ft greet".into(), &mut slash).unwrap();
+		let script = definitions(&slash);
+		assert!(script.contains("alias ll='ls -l'\n"));
+
+		// The definitions recreate the same aliases and functions in a new shell
+		let mut child = Slash::new();
+		dispatch::exec_input(script, &mut child).unwrap();
+		assert_eq!(child.logic().get_alias("ll").as_deref(), Some("ls -l"));
+		assert_eq!(child.logic().get_func("greet"), slash.logic().get_func("greet"));
+		assert_eq!(child.logic().func_attrs("greet"), slash.logic().func_attrs("greet"));
 	}
-	dispatch::exec_input(body.consume_escapes(), slash)?;
-	*slash = snapshot;
-	Ok(())
 }
@@ -0,0 +1,491 @@
+//! Arithmetic evaluation for `$(( ))`, `(( ))`, and `let`
+//!
+//! Values are 64 bit signed integers. Literals can be decimal, hexadecimal (`0x1f`), octal (`017`),
+//! or `base#value` with a base from 2 to 64. Variables can be named without a `$`, and assignment operators
+//! like `+=` and `<<=` update shell variables in place
+
+use crate::{error::SlashErr, prelude::*, shellenv::SlashVal};
+
+/// How many times a variable's value can itself be evaluated as an expression
+const MAX_EVAL_DEPTH: usize = 64;
+
+/// Longer operators come first, so that they are matched before their prefixes
+const OPERATORS: [&str;39] = [
+	"<<=", ">>=", "**", "++", "--", "<<", ">>", "<=", ">=", "==", "!=", "&&", "||",
+	"*=", "/=", "%=", "+=", "-=", "&=", "^=", "|=",
+	"+", "-", "*", "/", "%", "<", ">", "&", "^", "|", "!", "~", "=", "?", ":", ",", "(", ")"
+];
+
+const ASSIGN_OPS: [&str;11] = ["=", "*=", "/=", "%=", "+=", "-=", "<<=", ">>=", "&=", "^=", "|="];
+
+#[derive(Debug,Clone,PartialEq)]
+enum Token {
+	Num(String),
+	Ident(String),
+	Op(&'static str)
+}
+
+/// Expands an arith_sub pair, i.e. `$(( expr ))`
+pub fn expand_arith(pair: Pair<Rule>, slash: &mut Slash) -> SlashResult<String> {
+	let body = pair.as_str();
+	let body = &body[3..body.len() - 2]; // From '$((this))' to 'this'
+	Ok(eval(body, slash)?.to_string())
+}
+
+/// Evaluates an arithmetic expression. `$` expansions in the expression are performed first
+pub fn eval(expr: &str, slash: &mut Slash) -> SlashResult<i64> {
+	let expanded = expand_dollars(expr, slash)?;
+	eval_expanded(&expanded, slash, 0)
+}
+
+fn eval_expanded(expr: &str, slash: &mut Slash, depth: usize) -> SlashResult<i64> {
+	if depth > MAX_EVAL_DEPTH {
+		return Err(arith_err(expr, "expression recursion level exceeded"))
+	}
+	let tokens = tokenize(expr)?;
+	eval_tokens(expr, &tokens, slash, depth)
+}
+
+fn eval_tokens(expr: &str, tokens: &[Token], slash: &mut Slash, depth: usize) -> SlashResult<i64> {
+	if tokens.is_empty() {
+		return Ok(0)
+	}
+	let mut parser = ArithParser { expr, tokens, pos: 0, slash, depth };
+	let value = parser.comma(false)?;
+	if let Some(token) = parser.peek() {
+		let msg = format!("syntax error: invalid arithmetic operator (error token is \"{}\")",token_str(&token));
+		return Err(arith_err(expr, &msg))
+	}
+	Ok(value)
+}
+
+/// An expression that is tokenized once and evaluated many times, like the condition of `while (( i < n ))`
+/// Expressions containing `$` are expanded and tokenized again on every evaluation, since their tokens can change between them
+pub struct CompiledArith {
+	expr: String,
+	tokens: Option<Vec<Token>>
+}
+
+impl CompiledArith {
+	pub fn new(expr: &str) -> Self {
+		let tokens = if expr.contains('$') { None } else { tokenize(expr).ok() };
+		Self { expr: expr.to_string(), tokens }
+	}
+	pub fn eval(&self, slash: &mut Slash) -> SlashResult<i64> {
+		match &self.tokens {
+			Some(tokens) => eval_tokens(&self.expr, tokens, slash, 0),
+			None => eval(&self.expr, slash)
+		}
+	}
+}
+
+fn arith_err(expr: &str, msg: &str) -> SlashErr {
+	Low(SlashErrLow::ExpansionErr(format!("{}: {}",expr.trim(),msg)))
+}
+
+fn token_str(token: &Token) -> String {
+	match token {
+		Token::Num(num) => num.clone(),
+		Token::Ident(name) => name.clone(),
+		Token::Op(op) => op.to_string()
+	}
+}
+
+/// Expands `$var`, `${var}`, positional parameters, and command substitutions inside of an expression
+fn expand_dollars(expr: &str, slash: &mut Slash) -> SlashResult<String> {
+	let mut result = String::new();
+	let mut chars = expr.chars().peekable();
+	while let Some(ch) = chars.next() {
+		if ch != '$' {
+			result.push(ch);
+			continue
+		}
+		match chars.peek() {
+			Some('(') => {
+				chars.next();
+				let mut body = String::new();
+				let mut depth = 1;
+				for ch in chars.by_ref() {
+					match ch {
+						'(' => depth += 1,
+						')' => {
+							depth -= 1;
+							if depth == 0 {
+								break
+							}
+						}
+						_ => { /* Do nothing */ }
+					}
+					body.push(ch);
+				}
+				if body.starts_with('(') && body.ends_with(')') {
+					result.push_str(&eval(&body[1..body.len() - 1], slash)?.to_string());
+				} else {
+					result.push_str(slash.exec_captured(&body)?.trim());
+				}
+			}
+			Some('{') => {
+				chars.next();
+				let name = chars.by_ref().take_while(|&ch| ch != '}').collect::<String>();
+				result.push_str(&slash.vars().get_var(&name).unwrap_or_default().to_string());
+			}
+			Some(&ch) if ch.is_ascii_alphabetic() || ch == '_' => {
+				let mut name = String::new();
+				while let Some(&ch) = chars.peek() {
+					if !(ch.is_ascii_alphanumeric() || ch == '_') {
+						break
+					}
+					name.push(ch);
+					chars.next();
+				}
+				result.push_str(&slash.vars().get_var(&name).unwrap_or_default().to_string());
+			}
+			Some(&ch) if ch.is_ascii_digit() || matches!(ch, '#' | '?' | '$' | '!' | '@' | '*' | '-') => {
+				chars.next();
+				result.push_str(&slash.vars().get_param(&ch.to_string()).unwrap_or_default());
+			}
+			_ => result.push('$')
+		}
+	}
+	Ok(result)
+}
+
+fn tokenize(expr: &str) -> SlashResult<Vec<Token>> {
+	let mut tokens = vec![];
+	let mut rest = expr.trim_start();
+	while let Some(ch) = rest.chars().next() {
+		if ch.is_ascii_digit() {
+			// Literals can contain letters and `#`, `@`, and `_` for bases higher than ten
+			let end = rest.find(|ch: char| !(ch.is_ascii_alphanumeric() || matches!(ch, '#' | '@' | '_'))).unwrap_or(rest.len());
+			tokens.push(Token::Num(rest[..end].to_string()));
+			rest = &rest[end..];
+		} else if ch.is_ascii_alphabetic() || ch == '_' {
+			let end = rest.find(|ch: char| !(ch.is_ascii_alphanumeric() || ch == '_')).unwrap_or(rest.len());
+			tokens.push(Token::Ident(rest[..end].to_string()));
+			rest = &rest[end..];
+		} else if let Some(op) = OPERATORS.iter().find(|op| rest.starts_with(*op)) {
+			tokens.push(Token::Op(op));
+			rest = &rest[op.len()..];
+		} else {
+			let msg = format!("syntax error: operand expected (error token is \"{}\")",rest.trim());
+			return Err(arith_err(expr, &msg))
+		}
+		rest = rest.trim_start();
+	}
+	Ok(tokens)
+}
+
+/// Parses a numeric literal
+/// Leading `0x` means hexadecimal, a leading `0` means octal, and `base#digits` works for any base from 2 to 64.
+/// Bases above 36 use lowercase letters, then uppercase letters, then `@` and `_` as digits
+pub fn parse_literal(lit: &str) -> Option<i64> {
+	let (base, digits) = if let Some((base,digits)) = lit.split_once('#') {
+		(base.parse::<u32>().ok().filter(|base| (2..=64).contains(base))?, digits)
+	} else if let Some(hex) = lit.strip_prefix("0x").or_else(|| lit.strip_prefix("0X")) {
+		(16, hex)
+	} else if lit.len() > 1 && lit.starts_with('0') {
+		(8, &lit[1..])
+	} else {
+		(10, lit)
+	};
+	if digits.is_empty() {
+		return None
+	}
+	let mut value: i64 = 0;
+	for ch in digits.chars() {
+		let digit = match ch {
+			'0'..='9' => ch as u32 - '0' as u32,
+			'a'..='z' => ch as u32 - 'a' as u32 + 10,
+			'A'..='Z' if base <= 36 => ch as u32 - 'A' as u32 + 10,
+			'A'..='Z' => ch as u32 - 'A' as u32 + 36,
+			'@' => 62,
+			'_' => 63,
+			_ => return None
+		};
+		if digit >= base {
+			return None
+		}
+		value = value.wrapping_mul(base as i64).wrapping_add(digit as i64);
+	}
+	Some(value)
+}
+
+/// A recursive descent parser that evaluates as it goes
+/// When `skip` is set, the parser is walking through the side of a `&&`, `||`, or `?:` that isn't taken,
+/// so it checks syntax without assigning variables or failing on division by zero
+struct ArithParser<'a,'s> {
+	expr: &'a str,
+	tokens: &'a [Token],
+	pos: usize,
+	slash: &'s mut Slash,
+	depth: usize
+}
+
+impl<'a,'s> ArithParser<'a,'s> {
+	fn peek(&self) -> Option<Token> {
+		self.tokens.get(self.pos).cloned()
+	}
+	fn peek_op(&self) -> Option<&'static str> {
+		match self.tokens.get(self.pos) {
+			Some(Token::Op(op)) => Some(op),
+			_ => None
+		}
+	}
+	fn eat(&mut self, ops: &[&str]) -> Option<&'static str> {
+		let op = self.peek_op().filter(|op| ops.contains(op))?;
+		self.pos += 1;
+		Some(op)
+	}
+	fn expect(&mut self, op: &str) -> SlashResult<()> {
+		if self.eat(&[op]).is_none() {
+			let found = self.peek().map(|token| token_str(&token)).unwrap_or_default();
+			let msg = format!("syntax error: `{}' expected (error token is \"{}\")",op,found);
+			return Err(arith_err(self.expr, &msg))
+		}
+		Ok(())
+	}
+	fn err(&self, msg: &str) -> SlashErr {
+		arith_err(self.expr, msg)
+	}
+
+	fn get_var(&mut self, name: &str) -> SlashResult<i64> {
+		let value = self.slash.vars().get_var(name).map(|val| val.to_string()).unwrap_or_default();
+		let value = value.trim();
+		if value.is_empty() {
+			return Ok(0)
+		}
+		// The most negative value has no positive counterpart, so it can't be read as a negated literal
+		if value == i64::MIN.to_string() {
+			return Ok(i64::MIN)
+		}
+		if let Some(int) = parse_literal(value.trim_start_matches('-')) {
+			return Ok(if value.starts_with('-') { -int } else { int })
+		}
+		eval_expanded(value, self.slash, self.depth + 1)
+	}
+	fn set_var(&mut self, name: &str, value: i64) {
+		let value = match i32::try_from(value) {
+			Ok(int) => SlashVal::Int(int),
+			Err(_) => SlashVal::String(value.to_string())
+		};
+		self.slash.vars_mut().set_var(name, value);
+	}
+
+	fn comma(&mut self, skip: bool) -> SlashResult<i64> {
+		let mut value = self.assign(skip)?;
+		while self.eat(&[","]).is_some() {
+			value = self.assign(skip)?;
+		}
+		Ok(value)
+	}
+
+	fn assign(&mut self, skip: bool) -> SlashResult<i64> {
+		if let (Some(Token::Ident(name)), Some(Token::Op(op))) = (self.peek(), self.tokens.get(self.pos + 1).cloned()) {
+			if ASSIGN_OPS.contains(&op) {
+				self.pos += 2;
+				let rhs = self.assign(skip)?;
+				if skip {
+					return Ok(0)
+				}
+				let value = if op == "=" {
+					rhs
+				} else {
+					let lhs = self.get_var(&name)?;
+					self.apply(&op[..op.len() - 1], lhs, rhs)?
+				};
+				self.set_var(&name, value);
+				return Ok(value)
+			}
+		}
+		self.ternary(skip)
+	}
+
+	fn ternary(&mut self, skip: bool) -> SlashResult<i64> {
+		let cond = self.binary(0, skip)?;
+		if self.eat(&["?"]).is_none() {
+			return Ok(cond)
+		}
+		let if_true = self.comma(skip || cond == 0)?;
+		self.expect(":")?;
+		let if_false = self.assign(skip || cond != 0)?;
+		Ok(if cond != 0 { if_true } else { if_false })
+	}
+
+	/// Binary operators from lowest to highest precedence
+	const LEVELS: [&'static [&'static str];10] = [
+		&["||"],
+		&["&&"],
+		&["|"],
+		&["^"],
+		&["&"],
+		&["==", "!="],
+		&["<", ">", "<=", ">="],
+		&["<<", ">>"],
+		&["+", "-"],
+		&["*", "/", "%"],
+	];
+
+	fn binary(&mut self, level: usize, skip: bool) -> SlashResult<i64> {
+		let Some(ops) = Self::LEVELS.get(level) else {
+			return self.power(skip)
+		};
+		let mut lhs = self.binary(level + 1, skip)?;
+		while let Some(op) = self.eat(ops) {
+			let rhs = match op {
+				"&&" => self.binary(level + 1, skip || lhs == 0)?,
+				"||" => self.binary(level + 1, skip || lhs != 0)?,
+				_ => self.binary(level + 1, skip)?
+			};
+			lhs = if skip { 0 } else { self.apply(op, lhs, rhs)? };
+		}
+		Ok(lhs)
+	}
+
+	fn power(&mut self, skip: bool) -> SlashResult<i64> {
+		let base = self.unary(skip)?;
+		if self.eat(&["**"]).is_none() {
+			return Ok(base)
+		}
+		let exp = self.power(skip)?;
+		if skip { Ok(0) } else { self.apply("**", base, exp) }
+	}
+
+	fn unary(&mut self, skip: bool) -> SlashResult<i64> {
+		if let Some(op) = self.eat(&["++", "--"]) {
+			let Some(Token::Ident(name)) = self.peek() else {
+				return Err(self.err(&format!("syntax error: `{}' requires a variable",op)))
+			};
+			self.pos += 1;
+			if skip {
+				return Ok(0)
+			}
+			let value = self.get_var(&name)?;
+			let value = if op == "++" { value.wrapping_add(1) } else { value.wrapping_sub(1) };
+			self.set_var(&name, value);
+			return Ok(value)
+		}
+		match self.eat(&["!", "~", "-", "+"]) {
+			Some("!") => Ok((self.unary(skip)? == 0) as i64),
+			Some("~") => Ok(!self.unary(skip)?),
+			Some("-") => Ok(self.unary(skip)?.wrapping_neg()),
+			Some(_) => self.unary(skip),
+			None => self.postfix(skip)
+		}
+	}
+
+	fn postfix(&mut self, skip: bool) -> SlashResult<i64> {
+		match self.peek() {
+			Some(Token::Num(lit)) => {
+				self.pos += 1;
+				parse_literal(&lit).ok_or_else(|| self.err(&format!("value too great for base (error token is \"{}\")",lit)))
+			}
+			Some(Token::Ident(name)) => {
+				self.pos += 1;
+				if let Some(op) = self.eat(&["++", "--"]) {
+					if skip {
+						return Ok(0)
+					}
+					let value = self.get_var(&name)?;
+					self.set_var(&name, if op == "++" { value.wrapping_add(1) } else { value.wrapping_sub(1) });
+					return Ok(value)
+				}
+				if skip { Ok(0) } else { self.get_var(&name) }
+			}
+			Some(Token::Op("(")) => {
+				self.pos += 1;
+				let value = self.comma(skip)?;
+				self.expect(")")?;
+				Ok(value)
+			}
+			Some(token) => Err(self.err(&format!("syntax error: operand expected (error token is \"{}\")",token_str(&token)))),
+			None => Err(self.err("syntax error: operand expected"))
+		}
+	}
+
+	fn apply(&self, op: &str, lhs: i64, rhs: i64) -> SlashResult<i64> {
+		Ok(match op {
+			"+" => lhs.wrapping_add(rhs),
+			"-" => lhs.wrapping_sub(rhs),
+			"*" => lhs.wrapping_mul(rhs),
+			"/" | "%" if rhs == 0 => return Err(self.err("division by 0")),
+			"/" => lhs.wrapping_div(rhs),
+			"%" => lhs.wrapping_rem(rhs),
+			"**" if rhs < 0 => return Err(self.err("exponent less than 0")),
+			"**" => lhs.wrapping_pow(rhs.min(u32::MAX as i64) as u32),
+			"<<" => lhs.wrapping_shl(rhs as u32),
+			">>" => lhs.wrapping_shr(rhs as u32),
+			"&" => lhs & rhs,
+			"^" => lhs ^ rhs,
+			"|" => lhs | rhs,
+			"&&" => (lhs != 0 && rhs != 0) as i64,
+			"||" => (lhs != 0 || rhs != 0) as i64,
+			"==" => (lhs == rhs) as i64,
+			"!=" => (lhs != rhs) as i64,
+			"<" => (lhs < rhs) as i64,
+			">" => (lhs > rhs) as i64,
+			"<=" => (lhs <= rhs) as i64,
+			">=" => (lhs >= rhs) as i64,
+			_ => unreachable!("Unhandled arithmetic operator: {}",op)
+		})
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use crate::execute;
+
+use super::*;
+
+	#[test]
+	fn test_arithmetic() {
+		let mut slash = Slash::new();
+		let mut eval_str = |expr: &str| eval(expr, &mut slash).unwrap();
+		assert_eq!(eval_str("1 + 2 * 3"), 7);
+		assert_eq!(eval_str("(1 + 2) * 3"), 9);
+		assert_eq!(eval_str("2 ** 3 ** 2"), 512);
+		assert_eq!(eval_str("0x1f + 010 + 2#101 + 36#z + 64#_"), 31 + 8 + 5 + 35 + 63);
+		assert_eq!(eval_str("-7 / 2, -7 % 2"), -1);
+		assert_eq!(eval_str("1 << 4 | 1"), 17);
+		assert_eq!(eval_str("!0 && ~0 == -1"), 1);
+		assert_eq!(eval_str("3 > 2 ? 10 : 20"), 10);
+
+		assert_eq!(eval_str("i = 5, i += 2, i <<= 1"), 14);
+		assert_eq!(eval_str("i++ + ++i"), 14 + 16);
+		assert_eq!(eval_str("0 && (j = 1), j"), 0);
+		assert_eq!(eval_str("m = 9223372036854775807, ++m"), i64::MIN);
+		assert_eq!(eval_str("m--, m"), i64::MAX);
+		assert!(eval("1 / 0", &mut slash).is_err());
+		assert!(eval("0 && 1 / 0", &mut slash).is_ok());
+		assert!(eval("2#3", &mut slash).is_err());
+		assert!(eval("1 +", &mut slash).is_err());
+
+		execute::dispatch::exec_input("((i += 4)); ((n = i * 2))".to_string(), &mut slash).unwrap();
+		assert_eq!(slash.vars().get_var("i"), Some(SlashVal::Int(20)));
+		assert_eq!(slash.vars().get_var("n"), Some(SlashVal::Int(40)));
+		execute::dispatch::exec_input("((i - 20))".to_string(), &mut slash).unwrap();
+		assert_eq!(slash.get_status(), 1);
+		let output = slash.exec_captured("echo $((n / 3)) \"$(( (n + 2) % 5 ))\"").unwrap();
+		assert_eq!(output.trim(), "13 2");
+		execute::dispatch::exec_input("let 'x = 2#11' x*=x".to_string(), &mut slash).unwrap();
+		assert_eq!(slash.vars().get_var("x"), Some(SlashVal::Int(9)));
+
+		// Compiled expressions see new values on every evaluation, including ones written with `$`
+		let compiled = CompiledArith::new("x < 12 ? ++x : 0");
+		assert!(compiled.tokens.is_some());
+		assert_eq!(compiled.eval(&mut slash).unwrap(), 10);
+		assert_eq!(compiled.eval(&mut slash).unwrap(), 11);
+		assert_eq!(compiled.eval(&mut slash).unwrap(), 12);
+		assert_eq!(compiled.eval(&mut slash).unwrap(), 0);
+		assert_eq!(CompiledArith::new("$x * 2").eval(&mut slash).unwrap(), 24);
+		execute::dispatch::exec_input("k=0; total=0; while (( k < 1000 )); do ((total += k, k++)); done".to_string(), &mut slash).unwrap();
+		assert_eq!(slash.vars().get_var("total"), Some(SlashVal::Int(499500)));
+		execute::dispatch::exec_input("until ((k == 0)); do ((k -= 250)); done".to_string(), &mut slash).unwrap();
+		assert_eq!(slash.vars().get_var("k"), Some(SlashVal::Int(0)));
+
+		// Substitutions work in assignments and as arguments to other commands
+		execute::dispatch::exec_input("c=0; c=$((c+1)); c=$((c+1))".to_string(), &mut slash).unwrap();
+		assert_eq!(slash.vars().get_var("c").map(|val| val.to_string()).as_deref(), Some("2"));
+		let output = slash.exec_captured("x=6; if [ $((x*2)) -gt 10 ]; then echo big; fi").unwrap();
+		assert_eq!(output, "big\n");
+	}
+}
@@ -1,5 +1,241 @@
-use crate::prelude::*;
+use crate::{helper::StrExtension, prelude::*};
 
-pub fn expand_brace(pair: Pair<Rule>) -> String {
-	todo!()
+/// Expands a brace_word pair in place, joining the resulting words with spaces
+pub fn expand_brace<'a>(pair: Pair<'a,Rule>, slash: &mut Slash) -> SlashResult<String> {
+	if slash.is_posix() {
+		return Ok(pair.as_str().to_string())
+	}
+	let limit = slash.meta().borrow_shopts().core.max_brace_expand;
+	Ok(expand_braces(pair.as_str(), limit)?.join(" "))
+}
+
+/// Expands every brace group in a word, e.g. `a{b,c}d{e,f}` -> `abde abdf acde acdf`
+/// Nested groups like `{a,{b,c}}` and ranges like `{1..10..2}` are expanded recursively
+/// `limit` caps the number of words that a single word can produce
+pub fn expand_braces(word: &str, limit: usize) -> SlashResult<Vec<String>> {
+	let (prefix, body, suffix) = match find_brace_group(word) {
+		Some(parts) => parts,
+		None => return Ok(vec![word.to_string()])
+	};
+
+	let alternatives = match expand_range(&body, limit)? {
+		Some(range) => range,
+		None => {
+			let mut alternatives = vec![];
+			for alt in split_brace_list(&body) {
+				alternatives.extend(expand_braces(&alt, limit)?);
+				check_limit(alternatives.len(), limit)?;
+			}
+			alternatives
+		}
+	};
+	let suffixes = expand_braces(&suffix, limit)?;
+	check_limit(alternatives.len().saturating_mul(suffixes.len()), limit)?;
+
+	let mut product = Vec::with_capacity(alternatives.len() * suffixes.len());
+	for alt in &alternatives {
+		for suffix in &suffixes {
+			product.push(format!("{prefix}{alt}{suffix}"));
+		}
+	}
+	Ok(product)
+}
+
+fn check_limit(count: usize, limit: usize) -> SlashResult<()> {
+	if count > limit {
+		let msg = format!("Brace expansion would produce more than {} words (see core.max_brace_expand)",limit);
+		Err(Low(SlashErrLow::ExpansionErr(msg)))
+	} else {
+		Ok(())
+	}
+}
+
+/// Finds the first brace group that is actually expandable, and splits the word around it
+/// Groups without a top level comma or a valid range, like `{foo}`, are left alone
+/// Quoted or escaped braces are never treated as a group
+/// Returns (prefix, body, suffix)
+fn find_brace_group(word: &str) -> Option<(String,String,String)> {
+	let chars = word.chars().collect::<Vec<char>>();
+	let mask = word.literal_mask();
+	let mut i = 0;
+	while i < chars.len() {
+		if chars[i] == '{' && !mask[i] {
+			if i > 0 && chars[i - 1] == '$' && !mask[i - 1] {
+				// This is a parameter expansion, skip past it
+				if let Some(close) = find_closing_brace(&chars, &mask, i) {
+					i = close;
+				}
+			} else if let Some(close) = find_closing_brace(&chars, &mask, i) {
+				let body = chars[i + 1..close].iter().collect::<String>();
+				if split_brace_list(&body).len() > 1 || parse_range(&body).is_some() {
+					let prefix = chars[..i].iter().collect::<String>();
+					let suffix = chars[close + 1..].iter().collect::<String>();
+					return Some((prefix, body, suffix))
+				}
+			}
+		}
+		i += 1;
+	}
+	None
+}
+
+fn find_closing_brace(chars: &[char], mask: &[bool], open: usize) -> Option<usize> {
+	let mut depth = 0;
+	for i in open..chars.len() {
+		if mask[i] {
+			continue
+		}
+		match chars[i] {
+			'{' => depth += 1,
+			'}' => {
+				depth -= 1;
+				if depth == 0 {
+					return Some(i)
+				}
+			}
+			_ => { /* Do nothing */ }
+		}
+	}
+	None
+}
+
+/// Splits a brace body on unquoted commas that are not nested inside of another brace group
+fn split_brace_list(body: &str) -> Vec<String> {
+	let mask = body.literal_mask();
+	let mut items = vec![];
+	let mut current = String::new();
+	let mut depth = 0;
+	for (ch,literal) in body.chars().zip(mask) {
+		match ch {
+			_ if literal => current.push(ch),
+			'{' => {
+				depth += 1;
+				current.push(ch);
+			}
+			'}' => {
+				depth -= 1;
+				current.push(ch);
+			}
+			',' if depth == 0 => items.push(std::mem::take(&mut current)),
+			_ => current.push(ch)
+		}
+	}
+	items.push(current);
+	items
+}
+
+enum BraceRange {
+	Num { start: i64, end: i64, step: i64, width: usize },
+	Alpha { start: char, end: char, step: i64 }
+}
+
+/// Parses ranges like `1..10`, `-5..5..2`, `01..10`, and `a..z`
+fn parse_range(body: &str) -> Option<BraceRange> {
+	let parts = body.split("..").collect::<Vec<&str>>();
+	if !(2..=3).contains(&parts.len()) {
+		return None
+	}
+	let step = match parts.get(2) {
+		Some(step) => step.parse::<i64>().ok()?.checked_abs()?.max(1),
+		None => 1
+	};
+	if let (Ok(start), Ok(end)) = (parts[0].parse::<i64>(), parts[1].parse::<i64>()) {
+		// Zero padded ranges like {01..10} pad every result to the widest bound
+		let is_padded = |s: &str| {
+			let digits = s.trim_start_matches('-');
+			digits.len() > 1 && digits.starts_with('0')
+		};
+		let width = if is_padded(parts[0]) || is_padded(parts[1]) {
+			parts[0].len().max(parts[1].len())
+		} else {
+			0
+		};
+		return Some(BraceRange::Num { start, end, step, width })
+	}
+	let mut start = parts[0].chars();
+	let mut end = parts[1].chars();
+	match (start.next(), start.next(), end.next(), end.next()) {
+		(Some(start), None, Some(end), None) if start.is_ascii_alphabetic() && end.is_ascii_alphabetic() => {
+			Some(BraceRange::Alpha { start, end, step })
+		}
+		_ => None
+	}
+}
+
+fn expand_range(body: &str, limit: usize) -> SlashResult<Option<Vec<String>>> {
+	let range = match parse_range(body) {
+		Some(range) => range,
+		None => return Ok(None)
+	};
+	let (start, end, step) = match range {
+		BraceRange::Num { start, end, step, .. } => (start, end, step),
+		BraceRange::Alpha { start, end, step } => (start as i64, end as i64, step)
+	};
+	let count = (start.abs_diff(end) / step as u64).saturating_add(1);
+	check_limit(count.try_into().unwrap_or(usize::MAX), limit)?;
+
+	let step = if start <= end { step } else { -step };
+	let mut results = vec![];
+	let mut cur = Some(start);
+	// A range that ends near the edge of i64 stops once the next step would overflow
+	while let Some(cur_val) = cur.filter(|cur| (step > 0 && *cur <= end) || (step < 0 && *cur >= end)) {
+		let item = match range {
+			BraceRange::Num { width, .. } => {
+				if cur_val < 0 {
+					format!("-{:0>width$}", cur_val.unsigned_abs(), width = width.saturating_sub(1))
+				} else {
+					format!("{:0>width$}", cur_val, width = width)
+				}
+			}
+			BraceRange::Alpha { .. } => (cur_val as u8 as char).to_string()
+		};
+		results.push(item);
+		cur = cur_val.checked_add(step);
+	}
+	Ok(Some(results))
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn expand(word: &str) -> Vec<String> {
+		expand_braces(word, 10000).unwrap()
+	}
+
+	#[test]
+	fn test_brace_combined() {
+		assert_eq!(expand("a{b,c}d{e,f}"), vec!["abde","abdf","acde","acdf"]);
+		assert_eq!(expand("{a,{b,c}}"), vec!["a","b","c"]);
+		assert_eq!(expand("x{1..3}{a,b}"), vec!["x1a","x1b","x2a","x2b","x3a","x3b"]);
+		assert_eq!(expand("{foo}"), vec!["{foo}"]);
+	}
+
+	#[test]
+	fn test_brace_quoting() {
+		assert_eq!(expand("\\{a,b\\}"), vec!["\\{a,b\\}"]);
+		assert_eq!(expand("\"{a,b}\""), vec!["\"{a,b}\""]);
+		assert_eq!(expand("'{a,b}'{c,d}"), vec!["'{a,b}'c","'{a,b}'d"]);
+		assert_eq!(expand("{a,\"b,c\"}"), vec!["a","\"b,c\""]);
+		let mut slash = Slash::new();
+		assert_eq!(slash.exec_captured("echo \\{a,b\\} x\\*y '{c,d}' x'y'\\'z").unwrap(), "{a,b} x*y {c,d} xy'z\n");
+	}
+
+	#[test]
+	fn test_brace_ranges() {
+		assert_eq!(expand("{-2..2}"), vec!["-2","-1","0","1","2"]);
+		assert_eq!(expand("{10..1..3}"), vec!["10","7","4","1"]);
+		assert_eq!(expand("{08..10}"), vec!["08","09","10"]);
+		assert_eq!(expand("{c..a}"), vec!["c","b","a"]);
+	}
+
+	#[test]
+	fn test_brace_limit() {
+		assert!(expand_braces("{1..100}{1..100}", 1000).is_err());
+		assert!(expand_braces("{1..100000000000}", 1000).is_err());
+		assert_eq!(expand("{9223372036854775806..9223372036854775807}"), vec!["9223372036854775806","9223372036854775807"]);
+		assert_eq!(expand("{-9223372036854775807..-9223372036854775808}"), vec!["-9223372036854775807","-9223372036854775808"]);
+		let mut slash = Slash::new();
+		assert!(slash.meta_mut().set_shopt("core.max_brace_expand", "-1").is_err());
+	}
 }
@@ -1,4 +1,11 @@
-use crate::{execute, prelude::*, utils};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use nix::unistd::mkfifo;
+
+use crate::{execute::dispatch, prelude::*, signal, utils};
+
+/// Counts the process substitutions made by this shell, so that each one gets its own fifo
+static PROC_SUB_COUNT: AtomicUsize = AtomicUsize::new(0);
 
 pub fn expand_cmd_sub(mut pair: Pair<Rule>,slash: &mut Slash) -> SlashResult<String> {
 	if pair.as_rule() == Rule::word {
@@ -9,65 +16,87 @@ pub fn expand_cmd_sub(mut pair: Pair<Rule>,slash: &mut Slash) -> SlashResult<Str
 	let body = pair.as_str();
 	let body = &body[2..body.len() - 1]; // From '$(this)' to 'this'
 
-	let (mut r_pipe, mut w_pipe) = utils::SmartFD::pipe()?;
-	let redir = utils::Redir::from_raw(1,w_pipe.as_raw_fd());
-	let mut sub_slash = slash.clone();
-	let flags = sub_slash.ctx_mut().flags_mut();
-	*flags |= utils::ExecFlags::NO_FORK; // Tell the child proc to not fork since it's already in a fork
-	sub_slash.ctx_mut().push_redir(redir);
-
-	match unsafe { fork() } {
-		Ok(ForkResult::Child) => {
-			r_pipe.close()?;
-			// Execute the subshell body with the ctx payload
-			execute::dispatch::exec_input(body.consume_escapes(), &mut sub_slash)?;
-			std::process::exit(1);
-		}
-		Ok(ForkResult::Parent { child: _ }) => {
-			w_pipe.close()?;
-		}
-		Err(_) => panic!()
-	}
-
-	let mut buffer = String::new();
-	r_pipe.read_to_string(&mut buffer)?;
-	r_pipe.close()?;
-
-	Ok(buffer.trim().to_string())
+	let output = slash.exec_captured(&body.consume_escapes())?;
+	Ok(output.trim().to_string())
 }
 
 /// Used in tests
 pub fn cmd_sub_from_str(input: &str,slash: &mut Slash) -> SlashResult<String> {
-	// Get the subshell token
-	let body = input;
+	let output = slash.exec_captured(&input.consume_escapes())?;
+	Ok(output.trim().to_string())
+}
+
+/// Expands `<(cmd)` or `>(cmd)` into the path of a fifo that is connected to `cmd`
+/// The command runs in a forked child, which writes its output into the fifo for `<(cmd)`, or reads its input from it for `>(cmd)`.
+/// The fifo is registered as a temporary file, so it is removed once the command that it was given to is done
+pub fn expand_proc_sub(mut pair: Pair<Rule>, slash: &mut Slash) -> SlashResult<String> {
+	if pair.as_rule() != Rule::proc_sub {
+		pair = pair.scry(Rule::proc_sub).unpack()?;
+	}
+	let blame = pair.clone();
+	let reads_output = pair.scry(Rule::r#in).is_some();
+	// The body is run as it was written, so its quotes and escapes are left for the command inside to interpret
+	let body = pair.scry(Rule::subsh_body).unpack()?.as_str().to_string();
 
-	let (mut r_pipe, mut w_pipe) = utils::SmartFD::pipe()?;
-	let redir = utils::Redir::from_raw(1,w_pipe.as_raw_fd());
-	let mut sub_slash = slash.clone();
-	let flags = sub_slash.ctx_mut().flags_mut();
-	*flags |= utils::ExecFlags::NO_FORK; // Tell the child proc to not fork since it's already in a fork
-	sub_slash.ctx_mut().push_redir(redir);
+	let id = PROC_SUB_COUNT.fetch_add(1, Ordering::Relaxed);
+	let path = env::temp_dir().join(format!("slash_proc_sub_{}_{}",std::process::id(),id));
+	mkfifo(&path, Mode::S_IRUSR | Mode::S_IWUSR).map_err(|_| Low(SlashErrLow::from_io()))?;
+	slash.meta_mut().track_temp_file(path.clone());
 
 	match unsafe { fork() } {
 		Ok(ForkResult::Child) => {
-			r_pipe.close()?;
-			// Execute the subshell body with the ctx payload
-			execute::dispatch::exec_input(body.consume_escapes(), &mut sub_slash)?;
-			std::process::exit(1);
+			slash.enter_child_env();
+			// Opening the fifo waits until the command that was given its path opens the other end
+			let (flags,target) = if reads_output { (OFlag::O_WRONLY,STDOUT_FILENO) } else { (OFlag::O_RDONLY,STDIN_FILENO) };
+			let Ok(mut fifo) = utils::SmartFD::open(&path, flags, Mode::empty()) else {
+				std::process::exit(1)
+			};
+			fifo.dup2(&target)?;
+			fifo.close()?;
+			if let Err(e) = dispatch::exec_input(body, slash) {
+				match e.low() {
+					SlashErrLow::CleanExit(code) => std::process::exit(*code),
+					SlashErrLow::Interrupted => signal::exit_interrupted(),
+					_ => {
+						eprintln!("{}",e);
+						std::process::exit(1)
+					}
+				}
+			}
+			std::process::exit(slash.get_status())
 		}
-		Ok(ForkResult::Parent { child: _ }) => {
-			w_pipe.close()?;
-		}
-		Err(_) => panic!()
+		// The child is not waited on. It is reaped by the SIGCHLD handler once it finishes
+		Ok(ForkResult::Parent { .. }) => Ok(path.to_string_lossy().to_string()),
+		Err(_) => Err(High(SlashErrHigh::exec_err("Failed to fork process substitution", blame)))
 	}
+}
 
-	let mut buffer = String::new();
-	r_pipe.read_to_string(&mut buffer)?;
-	r_pipe.close()?;
+#[cfg(test)]
+mod tests {
+	use super::*;
 
-	Ok(buffer.trim().to_string())
-}
+	#[test]
+	fn test_proc_sub() {
+		let mut slash = Slash::new();
+		dispatch::exec_input("read line < <(echo from a proc sub)".into(), &mut slash).unwrap();
+		assert_eq!(slash.vars().get_var("line").map(|val| val.to_string()).as_deref(), Some("from a proc sub"));
+		assert_eq!(slash.exec_captured("cat < <(printf 'a\\nb\\n')").unwrap(), "a\nb\n");
 
-pub fn expand_proc_sub(pair: Pair<Rule>) -> String {
-	todo!()
+		// The fifo is removed once the command that it was given to is done
+		assert!(slash.meta().temp_files().is_empty());
+		let prefix = format!("slash_proc_sub_{}_",std::process::id());
+		let leftover = std::fs::read_dir(env::temp_dir()).unwrap()
+			.flatten()
+			.any(|entry| entry.file_name().to_string_lossy().starts_with(&prefix));
+		assert!(!leftover);
+
+		// Anything still registered is removed when the shell exits, after the EXIT trap has had a chance to use it
+		let path = env::temp_dir().join(format!("slash_temp_file_{}",std::process::id()));
+		std::fs::write(&path, "still here").unwrap();
+		slash.meta_mut().track_temp_file(path.clone());
+		dispatch::exec_input(format!("trap 'read kept < {}' EXIT", path.display()), &mut slash).unwrap();
+		slash.run_exit_trap();
+		assert_eq!(slash.vars().get_var("kept").map(|val| val.to_string()).as_deref(), Some("still here"));
+		assert!(!path.exists());
+	}
 }
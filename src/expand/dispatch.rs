@@ -71,13 +71,16 @@ pub fn expand_cmd<'a>(cmd: Pair<'a,Rule>, slash: &mut Slash) -> SlashResult<Stri
 		Rule::param_sub,
 		Rule::glob_word,
 		Rule::dquoted,
+		Rule::arith_sub,
 		Rule::cmd_sub,
 		Rule::arr_index,
 		Rule::proc_sub,
 		Rule::brace_word,
 		Rule::tilde_sub
 	];
-	buffer = alias_pass(buffer,slash)?;
+	if slash.aliases_enabled() {
+		buffer = alias_pass(buffer,slash)?;
+	}
 	for rule in expand_rules {
 		// Expand each rule in order
 		buffer = rule_pass(rule,buffer,slash)?;
@@ -99,9 +102,13 @@ pub fn alias_pass<'a>(buffer: String, slash: &mut Slash) -> SlashResult<String>
 	Ok(result)
 }
 
+/// Expands aliases in command position, repeating until nothing changes
+/// Each alias body is only substituted once, so self-referential aliases like `alias ls='ls -l'` terminate
 pub fn expand_aliases(input: String, depth: usize, mut cached: Vec<String>, slash: &mut Slash) -> SlashResult<String> {
-	if depth > 10 {
-		return Ok(input)
+	let max_depth = slash.meta().borrow_shopts().core.max_alias_depth;
+	if depth > max_depth {
+		let msg = format!("Alias expansion went more than {} levels deep. The limit can be raised with `setopt core.max_alias_depth=N`",max_depth);
+		return Err(Low(SlashErrLow::ExpansionErr(msg)))
 	}
 	let mut result = input.clone();
 	let mut alias_pass = SlashParse::parse(Rule::main, &input)?;
@@ -146,19 +153,18 @@ pub fn rule_pass<'a>(rule: Rule, buffer: String, slash: &mut Slash) -> SlashResu
 		if word.contains_rules(rule) {
 			let span = word.as_span();
 			let expanded = match rule {
-				Rule::var_sub => {
-					slash.vars().get_var(&word.as_str()[1..]).unwrap_or_default().to_string()
-				}
+				Rule::var_sub => expand::misc::expand_var(word,slash)?,
 				Rule::param_sub => {
 					let param = slash.vars().get_param(&word.as_str()[1..]).unwrap_or_default().to_string();
 					param
 				}
 				Rule::dquoted => expand::string::expand_string(word,slash)?,
 				Rule::arr_index => expand::index::expand_index(word,slash)?,
-				Rule::glob_word => expand::glob::expand_glob(word),
-				Rule::brace_word => expand::brace::expand_brace(word),
+				Rule::glob_word => expand::glob::expand_glob(word,slash),
+				Rule::brace_word => expand::brace::expand_brace(word,slash)?,
+				Rule::arith_sub => expand::arithmetic::expand_arith(word,slash)?,
 				Rule::cmd_sub => expand::cmdsub::expand_cmd_sub(word,slash)?,
-				Rule::proc_sub => expand::cmdsub::expand_proc_sub(word),
+				Rule::proc_sub => expand::cmdsub::expand_proc_sub(word,slash)?,
 				Rule::tilde_sub => expand::misc::expand_tilde(word)?,
 				_ => unreachable!()
 			};
@@ -171,15 +177,23 @@ pub fn rule_pass<'a>(rule: Rule, buffer: String, slash: &mut Slash) -> SlashResu
 
 pub fn rule_queue() -> Vec<Rule> {
 	vec![
+		Rule::arith_sub,
 		Rule::cmd_sub,
 		Rule::param_sub,
 		Rule::var_sub,
+		Rule::arr_index,
 		Rule::dquoted
 	]
 }
 
 pub fn expand_word<'a>(pair: Pair<'a,Rule>, slash: &mut Slash) -> SlashResult<String> {
 	let word = pair.as_str();
+	// Double quoted words are handed off whole, so that their escapes are consumed along with the expansions
+	if let Some(dquoted) = pair.scry(Rule::dquoted) {
+		if dquoted.as_str() == word {
+			return expand::string::expand_string(dquoted,slash)
+		}
+	}
 	let mut rule_queue = rule_queue();
 	let expansions = match SlashParse::parse(Rule::expand_word_loud, word) {
 		Ok(mut parsed) => parsed.next().unwrap(),
@@ -192,17 +206,15 @@ pub fn expand_word<'a>(pair: Pair<'a,Rule>, slash: &mut Slash) -> SlashResult<St
 		while let Some(pair) = matches.pop_front() {
 			let span = pair.as_span();
 			let expanded = match rule {
+				Rule::arith_sub => expand::arithmetic::expand_arith(pair,slash)?,
 				Rule::cmd_sub => expand::cmdsub::expand_cmd_sub(pair,slash)?,
 				Rule::param_sub => {
 					let param_name = &pair.as_str()[1..];
 					let param = slash.vars().get_param(param_name).unwrap_or_default().to_string();
 					param
 				}
-				Rule::var_sub => {
-					let var_name = &pair.as_str()[1..];
-					let result = slash.vars().get_var(var_name).unwrap_or_default().to_string();
-					result
-				}
+				Rule::var_sub => expand::misc::expand_var(pair,slash)?,
+				Rule::arr_index => expand::index::expand_index(pair,slash)?,
 				Rule::dquoted => expand::string::expand_string(pair,slash)?,
 				_ => unreachable!()
 			};
@@ -215,3 +227,199 @@ pub fn expand_word<'a>(pair: Pair<'a,Rule>, slash: &mut Slash) -> SlashResult<St
 
 	Ok(result)
 }
+
+#[cfg(test)]
+mod tests {
+	use crate::{execute, helper, shellenv::SlashVal};
+
+use super::*;
+
+	#[test]
+	fn test_expand_adjacent_vars() {
+		let mut slash = Slash::new();
+		let input = "string A=foo; string B=bar; string C=${A}_${B}.txt; string D=\"$A-$B\"";
+		execute::dispatch::exec_input(input.to_string(), &mut slash).unwrap();
+		assert_eq!(slash.vars().get_var("C"), Some(SlashVal::String("foo_bar.txt".into())));
+		assert_eq!(slash.vars().get_var("D"), Some(SlashVal::String("foo-bar".into())));
+	}
+
+	#[test]
+	fn test_expand_dquote_escapes() {
+		let mut slash = Slash::new();
+		let input = "string A=foo; string B=\"\\$A\"; string C=\"\\$A $A\"; string D='$A'";
+		execute::dispatch::exec_input(input.to_string(), &mut slash).unwrap();
+		assert_eq!(slash.vars().get_var("B"), Some(SlashVal::String("$A".into())));
+		assert_eq!(slash.vars().get_var("C"), Some(SlashVal::String("$A foo".into())));
+		assert_eq!(slash.vars().get_var("D"), Some(SlashVal::String("$A".into())));
+	}
+
+	#[test]
+	fn test_cmd_sub_capture() {
+		let mut slash = Slash::new();
+		let input = "string A=\"$(echo foo; /bin/echo bar)\"";
+		execute::dispatch::exec_input(input.to_string(), &mut slash).unwrap();
+		assert_eq!(slash.vars().get_var("A"), Some(SlashVal::String("foo\nbar".into())));
+
+		let output = slash.exec_captured("sh -c 'echo out; exit 3'").unwrap();
+		assert_eq!(output, "out\n");
+		assert_eq!(slash.get_status(), 3);
+	}
+
+	#[test]
+	fn test_posix_mode() {
+		let mut slash = Slash::new();
+		execute::dispatch::exec_input("set -o posix; x=\"a  b\"".to_string(), &mut slash).unwrap();
+		assert_eq!(slash.exec_captured("printf '[%s]' $x").unwrap(), "[a][b]");
+		assert_eq!(slash.exec_captured("printf '[%s]' \"$x\"").unwrap(), "[a  b]");
+		assert_eq!(slash.exec_captured("printf '[%s]' {a,b}").unwrap(), "[{a,b}]");
+		assert_eq!(slash.exec_captured("echo -n -e foo").unwrap(), "-e foo");
+		assert_eq!(crate::helper::split_fields(" a:b::c ", " :"), vec!["a","b","","c"]);
+	}
+
+	#[test]
+	fn test_nesting_limits() {
+		let mut slash = Slash::new();
+		let input = "setopt core.max_func_depth=20; setopt core.max_alias_depth=2; setopt core.max_recurse_depth=50";
+		execute::dispatch::exec_input(input.to_string(), &mut slash).unwrap();
+
+		execute::dispatch::exec_input("recurse() { recurse; }".to_string(), &mut slash).unwrap();
+		let err = execute::dispatch::exec_input("recurse".to_string(), &mut slash).unwrap_err();
+		assert!(err.to_string().contains("maximum function nesting level of 20"));
+
+		execute::dispatch::exec_input("alias one=two; alias two=three; alias three=four; alias four=true".to_string(), &mut slash).unwrap();
+		assert!(execute::dispatch::exec_input("one".to_string(), &mut slash).is_err());
+
+		let nested = format!("{}true{}","( ".repeat(60)," )".repeat(60));
+		assert!(execute::dispatch::exec_input(nested, &mut slash).is_err());
+		assert_eq!(crate::helper::nesting_depth("{ (a) \"((\" '{' \\( }"), 2);
+
+		// The shell is still usable once a limit has been hit
+		execute::dispatch::exec_input("string after=ok".to_string(), &mut slash).unwrap();
+		assert_eq!(slash.vars().get_var("after"), Some(SlashVal::String("ok".into())));
+	}
+
+	#[test]
+	fn test_redir_targets() {
+		let dir = env::temp_dir().join(format!("slash_redir_targets_{}",std::process::id()));
+		std::fs::create_dir_all(&dir).unwrap();
+		let mut slash = Slash::new();
+		let input = format!("DIR={}; NAME=\"a b\"; touch $DIR/log1", dir.display());
+		execute::dispatch::exec_input(input, &mut slash).unwrap();
+
+		slash.exec_captured("echo vars > $DIR/$NAME; echo braces > $DIR/{x}.txt; echo globbed > $DIR/log*").unwrap();
+		assert_eq!(std::fs::read_to_string(dir.join("a b")).unwrap(), "vars\n");
+		assert_eq!(std::fs::read_to_string(dir.join("{x}.txt")).unwrap(), "braces\n");
+		assert_eq!(std::fs::read_to_string(dir.join("log1")).unwrap(), "globbed\n");
+
+		let err = execute::dispatch::exec_input("echo no > $DIR/{y,z}".to_string(), &mut slash).unwrap_err();
+		assert!(err.to_string().contains("ambiguous redirect"));
+		std::fs::remove_dir_all(dir).ok();
+	}
+
+	#[test]
+	fn test_heredocs() {
+		let mut slash = Slash::new();
+		execute::dispatch::exec_input("name=world; show() { cat <<EOF\nin $1\nEOF\n}".to_string(), &mut slash).unwrap();
+		let input = "cat <<EOF | tr a-z A-Z\nit's $name, $((1 << 3)) \\$name\nEOF\ncat <<'EOF'; show func\nraw $name 100%\nEOF\n";
+		assert_eq!(slash.exec_captured(input).unwrap(), "IT'S WORLD, 8 $NAME\nraw $name 100%\nin func\n");
+		assert_eq!(slash.exec_captured("cat <<-END\n\t\ttabs\n\tEND\ncat <<< \"$name\"").unwrap(), "tabs\nworld\n");
+
+		// Input with an open heredoc is incomplete, which keeps the line editor reading
+		assert!(helper::inline_heredocs("cat <<EOF\nbody").is_none());
+		assert!(helper::inline_heredocs("echo $((1 << 2)) # <<EOF").is_some());
+		assert!(execute::dispatch::exec_input("cat <<EOF\nbody".to_string(), &mut slash).is_err());
+	}
+
+	#[test]
+	fn test_timing_vars() {
+		let mut slash = Slash::new();
+		execute::dispatch::exec_input("SECONDS=100".to_string(), &mut slash).unwrap();
+		assert_eq!(slash.vars().get_var("SECONDS").unwrap().to_string(), "100");
+
+		slash.start_timer();
+		slash.stop_timer().unwrap();
+		// Fast commands don't show a duration unless the threshold is lowered
+		assert_eq!(helper::escseq_cmdtime(&slash).unwrap(), "");
+		execute::dispatch::exec_input("setopt prompt.cmd_time_threshold=0".to_string(), &mut slash).unwrap();
+		assert!(helper::escseq_cmdtime(&slash).unwrap().ends_with("ms"));
+		assert_eq!(helper::format_cmd_runtime(std::time::Duration::from_millis(3200)), "3.2s");
+	}
+
+	#[test]
+	fn test_prompt_status() {
+		let mut slash = Slash::new();
+		slash.set_code(2);
+		assert_eq!(expand::misc::expand_prompt(Some("[\\?]"), &mut slash).unwrap(), "[2]");
+		// While the prompt is up, it reports the last command line's status even if a hook changes $?
+		slash.meta_mut().enter_prompt(1);
+		slash.set_code(0);
+		assert_eq!(expand::misc::expand_prompt(Some("[\\?]"), &mut slash).unwrap(), "[1]");
+		assert_eq!(helper::escseq_success(&mut slash).unwrap(), "");
+		slash.meta_mut().leave_prompt();
+		assert_eq!(slash.prompt_status(), 0);
+
+		execute::dispatch::exec_input("setopt prompt.exit_status.color=true".to_string(), &mut slash).unwrap();
+		assert!(slash.meta().borrow_shopts().prompt.exit_status.color);
+	}
+
+	#[test]
+	fn test_var_transforms() {
+		let mut slash = Slash::new();
+		slash.vars_mut().set_var("msg", SlashVal::String("it's a \\test".into()));
+		slash.vars_mut().set_var("esc", SlashVal::String("a\\tb\\x41".into()));
+		slash.vars_mut().set_var("num", SlashVal::Int(5));
+		slash.vars_mut().set_var("list", SlashVal::Array(vec![SlashVal::String("a b".into()), SlashVal::String("c".into())]));
+		slash.vars_mut().export_var("SLASH_TRANSFORM_VAR", "x y");
+		let mut expand = |word: &str| expand::misc::expand_var(SlashParse::parse(Rule::var_sub, word).unwrap().next().unwrap(), &mut slash).unwrap();
+
+		assert_eq!(expand("${msg@Q}"), "'it'\\''s a '\\\\'test'");
+		assert_eq!(expand("${list@Q}"), "'a b' c");
+		assert_eq!(expand("${esc@E}"), "a\tbA");
+		assert_eq!(expand("${msg@A}"), "msg='it'\\''s a '\\\\'test'");
+		assert_eq!(expand("${num@A}"), "int num=5");
+		assert_eq!(expand("${list@A}"), "arr list=['a b',c]");
+		assert_eq!(expand("${SLASH_TRANSFORM_VAR@A}"), "export SLASH_TRANSFORM_VAR='x y'");
+		assert_eq!(expand("${num@a}"), "i");
+		assert_eq!(expand("${list@a}"), "a");
+		assert_eq!(expand("${SLASH_TRANSFORM_VAR@a}"), "x");
+		assert_eq!(expand("${msg@a}"), "");
+		assert_eq!(expand("${missing@Q}"), "");
+		assert_eq!(helper::shell_quote("plain-word"), "plain-word");
+		assert_eq!(helper::shell_quote(""), "''");
+		assert_eq!(helper::shell_quote("a\\"), "'a'\\\\");
+	}
+
+	#[test]
+	fn test_escape_comments() {
+		assert_eq!(helper::escape_comments("echo hi # note"), "echo hi \\# note");
+		assert_eq!(helper::escape_comments("#start;echo a#b '# q' \"# d\" \\# $# ${#x}"), "\\#start;echo a#b '# q' \"# d\" \\# $# ${#x}");
+		assert_eq!(helper::escape_comments("(#!python\nprint(1)\n)"), "(#!python\nprint(1)\n)");
+
+		let mut slash = Slash::new();
+		let output = slash.exec_captured(&helper::escape_comments("echo hi # not a comment")).unwrap();
+		assert_eq!(output, "hi # not a comment\n");
+		let output = slash.exec_captured(&helper::escape_comments("echo #b c#d; printf '%s\\n' x #y")).unwrap();
+		assert_eq!(output, "#b c#d\nx\n#y\n");
+		let output = slash.exec_captured("echo hi # a comment").unwrap();
+		assert_eq!(output, "hi\n");
+		// A `#` inside of a word never starts a comment
+		let output = slash.exec_captured("echo c#d #e").unwrap();
+		assert_eq!(output, "c#d\n");
+	}
+
+	#[test]
+	fn test_line_continuation() {
+		assert_eq!(helper::join_continued_lines("echo a \\\n  b\necho c\n"), "echo a   b\n\necho c\n");
+		assert_eq!(helper::join_continued_lines("echo \"a\\\nb\" \\\\\n"), "echo \"ab\" \\\\\n\n");
+		for kept in ["echo 'a\\\nb'\n", "# note \\\necho x\n"] {
+			assert_eq!(helper::join_continued_lines(kept), kept);
+		}
+		assert!(helper::ends_in_continuation("ls \\"));
+		assert!(!helper::ends_in_continuation("ls \\\\"));
+		assert!(!helper::ends_in_continuation("echo 'a \\"));
+
+		let mut slash = Slash::new();
+		let output = slash.exec_captured("echo one \\\n  two | \\\n  tr a-z A-Z").unwrap();
+		assert_eq!(output, "ONE TWO\n");
+	}
+}
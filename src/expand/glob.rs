@@ -1,12 +1,119 @@
-use crate::prelude::*;
-
-pub fn expand_glob(pair: Pair<Rule>) -> String {
-	let word = pair.as_str();
-	let mut result = String::new();
-	for entry in glob::glob(word).unwrap() {
-		if let Ok(path) = entry {
-			result = format!("{} {}",result,path.to_str().unwrap());
+use crate::{locale, prelude::*};
+
+use super::pattern::ShellPattern;
+
+pub fn expand_glob(pair: Pair<Rule>, slash: &Slash) -> String {
+	glob_paths(pair.as_str(), slash).join(" ")
+}
+
+/// Returns every path that matches the given pattern
+/// Respects `core.dotglob`, and filters out anything matched by the colon separated patterns in `GLOBIGNORE`
+/// Like in bash, setting `GLOBIGNORE` also allows dotfiles to be matched
+/// Bracket expressions are matched by `ShellPattern`, so that they follow the current locale
+/// Matches are sorted according to `core.glob_sort`, and a path that the pattern reaches more than once is only given once
+pub fn glob_paths(pattern: &str, slash: &Slash) -> Vec<String> {
+	locale::sync(slash);
+	let components = path_components(pattern).map(ShellPattern::new).collect::<Vec<_>>();
+	let brackets = components.iter().any(|comp| comp.has_brackets());
+	let pattern = ShellPattern::new(pattern);
+	let ignore = slash.vars().get_var("GLOBIGNORE").map(|val| val.to_string()).unwrap_or_default();
+	let ignore_pats = ignore.split(':')
+		.filter(|pat| !pat.is_empty())
+		.map(ShellPattern::new)
+		.collect::<Vec<_>>();
+	let dotglob = slash.meta().borrow_shopts().core.dotglob || !ignore_pats.is_empty();
+
+	let options = glob::MatchOptions {
+		require_literal_leading_dot: !dotglob,
+		..Default::default()
+	};
+	let mut paths = vec![];
+	if let Ok(entries) = glob::glob_with(&pattern.to_glob(), options) {
+		for entry in entries.flatten() {
+			let path = entry.to_str().unwrap().to_string();
+			let name = entry.file_name().and_then(|name| name.to_str()).unwrap_or_default();
+			if matches!(name, "." | "..") || ignore_pats.iter().any(|pat| pat.matches(&path)) {
+				continue
+			}
+			if brackets && !matches_components(&components, &pattern, &path) {
+				continue
+			}
+			paths.push(path);
+		}
+	}
+	let mut seen = HashSet::new();
+	paths.retain(|path| seen.insert(path.clone()));
+	if slash.meta().borrow_shopts().core.glob_sort == "bytes" {
+		paths.sort();
+	} else {
+		// Strings that collate the same are put in byte order, so the result never depends on the order they were found in
+		paths.sort_by(|a,b| locale::collate(a,b).then_with(|| a.cmp(b)));
+	}
+	paths
+}
+
+/// Splits a path into its components, ignoring `.` and repeated slashes, since the glob crate normalizes those away
+fn path_components(path: &str) -> impl Iterator<Item = &str> {
+	path.split('/').filter(|comp| !comp.is_empty() && *comp != ".")
+}
+
+/// Checks a path found by the glob crate against the bracket expressions in the pattern
+/// Each component is matched on its own, unless a `**` makes the component counts differ
+fn matches_components(components: &[ShellPattern], pattern: &ShellPattern, path: &str) -> bool {
+	let path_comps = path_components(path).collect::<Vec<_>>();
+	if path_comps.len() == components.len() {
+		components.iter().zip(path_comps).all(|(comp,path_comp)| comp.matches(path_comp))
+	} else {
+		pattern.matches(path)
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use std::fs;
+
+	use crate::execute;
+
+use super::*;
+
+	#[test]
+	fn test_globignore() {
+		let dir = env::temp_dir().join(format!("slash_globignore_{}",std::process::id()));
+		fs::create_dir_all(&dir).unwrap();
+		for file in ["a.rs", "b.o", ".hidden.rs"] {
+			fs::write(dir.join(file), "").unwrap();
 		}
+		let mut slash = Slash::new();
+		let pattern = format!("{}/*",dir.display());
+
+		execute::dispatch::exec_input("string GLOBIGNORE=\"*.o\"".to_string(), &mut slash).unwrap();
+		let paths = glob_paths(&pattern, &slash);
+		let expected = vec![
+			dir.join(".hidden.rs").display().to_string(),
+			dir.join("a.rs").display().to_string()
+		];
+		fs::remove_dir_all(&dir).unwrap();
+		assert_eq!(paths, expected);
+	}
+
+	#[test]
+	fn test_glob_sort() {
+		let Some(_isolated) = crate::utils::in_own_process() else { return };
+		let dir = env::temp_dir().join(format!("slash_glob_sort_{}",std::process::id()));
+		fs::create_dir_all(dir.join("sub")).unwrap();
+		for file in ["b", "B", "a", "C", "sub/a"] {
+			fs::write(dir.join(file), "").unwrap();
+		}
+		let mut slash = Slash::new();
+		let names = |paths: Vec<String>| paths.iter().map(|path| path.strip_prefix(&format!("{}/",dir.display())).unwrap().to_string()).collect::<Vec<_>>();
+
+		execute::dispatch::exec_input("LC_ALL=C; setopt core.glob_sort=bytes".to_string(), &mut slash).unwrap();
+		assert_eq!(names(glob_paths(&format!("{}/?",dir.display()), &slash)), ["B", "C", "a", "b"]);
+		execute::dispatch::exec_input("setopt core.glob_sort=locale".to_string(), &mut slash).unwrap();
+		assert_eq!(names(glob_paths(&format!("{}/?",dir.display()), &slash)), ["B", "C", "a", "b"]);
+
+		// Overlapping parts of a pattern don't produce the same path twice
+		assert_eq!(names(glob_paths(&format!("{}/**/**/a",dir.display()), &slash)), ["a", "sub/a"]);
+		fs::remove_dir_all(&dir).unwrap();
 	}
-	result.trim().to_string()
 }
@@ -0,0 +1,396 @@
+//! History expansion, which lets a line typed at the prompt refer to earlier commands with `!`
+//!
+//! An event designator picks a command from the history: `!!` is the previous one, `!n` is entry `n`, `!-n` is the one `n` commands back,
+//! `!str` is the latest one that starts with `str`, and `!?str?` is the latest one that contains `str`.
+//! A word designator after a `:` picks words out of that command, counting the command name as word 0: `:n`, `:^`, `:$`, `:x-y`, `:x*`, `:x-`, `:*`,
+//! and `:%` for the word that a `!?str?` search matched. `!$`, `!^`, `!*` and `!:n` are short for the same designators on the previous command.
+//! Modifiers after that edit the result: `:h` and `:t` keep the head or the tail of a path, `:r` and `:e` drop or keep a `.suffix`,
+//! `:s/old/new/` replaces the first `old` (`&` in `new` stands for `old`), `:gs/old/new/` replaces all of them, `:&` repeats the last substitution,
+//! `:q` quotes the result, and `:p` prints the line without running it.
+//! Nothing is expanded inside single quotes or after a backslash, and a `!` followed by a space, `=`, `(`, or the end of the line is left alone
+
+use crate::{error::SlashErr, prelude::*, shellenv::CmdHistory};
+
+/// A line after history expansion
+pub struct HistExpansion {
+	pub line: String,
+	/// Whether a `:p` modifier asked for the line to be printed instead of run
+	pub print_only: bool
+}
+
+/// Expands the history references in a line
+/// Returns None if the line doesn't contain any, so that it can be run as it was typed
+pub fn expand_history(line: &str, history: &CmdHistory) -> SlashResult<Option<HistExpansion>> {
+	if !line.contains('!') {
+		return Ok(None)
+	}
+	let mut expander = Expander {
+		chars: line.chars().collect(),
+		pos: 0,
+		entries: history.entries().collect(),
+		last_subst: None,
+		print_only: false
+	};
+	let mut result = String::new();
+	let mut changed = false;
+	let (mut in_squote, mut in_dquote) = (false,false);
+	while let Some(ch) = expander.next_char() {
+		match ch {
+			'\\' if !in_squote => {
+				result.push(ch);
+				if let Some(escaped) = expander.next_char() {
+					result.push(escaped);
+				}
+			}
+			'\'' if !in_dquote => {
+				in_squote = !in_squote;
+				result.push(ch);
+			}
+			'"' if !in_squote => {
+				in_dquote = !in_dquote;
+				result.push(ch);
+			}
+			'!' if !in_squote && expander.starts_reference(&result) => {
+				result.push_str(&expander.expand_reference()?);
+				changed = true;
+			}
+			_ => result.push(ch)
+		}
+	}
+	Ok(changed.then_some(HistExpansion { line: result, print_only: expander.print_only }))
+}
+
+struct Expander<'a> {
+	chars: Vec<char>,
+	pos: usize,
+	entries: Vec<&'a str>,
+	/// The last `:s` substitution, which `:&` and an empty `old` reuse
+	last_subst: Option<(String,String)>,
+	print_only: bool
+}
+
+impl<'a> Expander<'a> {
+	fn next_char(&mut self) -> Option<char> {
+		let ch = self.chars.get(self.pos).copied();
+		if ch.is_some() {
+			self.pos += 1;
+		}
+		ch
+	}
+	fn peek_at(&self, offset: usize) -> Option<char> {
+		self.chars.get(self.pos + offset).copied()
+	}
+	fn peek(&self) -> Option<char> {
+		self.peek_at(0)
+	}
+	fn text_from(&self, start: usize) -> String {
+		self.chars[start..self.pos].iter().collect()
+	}
+	fn take_number(&mut self) -> Option<usize> {
+		let start = self.pos;
+		while self.peek().is_some_and(|ch| ch.is_ascii_digit()) {
+			self.pos += 1;
+		}
+		self.text_from(start).parse().ok()
+	}
+	/// Whether the `!` that was just read starts a history reference
+	/// `$!` and `${!name}` are parameters, and `!(...)` is a pattern
+	fn starts_reference(&self, before: &str) -> bool {
+		if before.ends_with('$') || before.ends_with("${") {
+			return false
+		}
+		self.peek().is_some_and(|ch| !ch.is_whitespace() && !matches!(ch, '=' | '(' | '"'))
+	}
+	fn event_not_found(&self, start: usize) -> SlashErr {
+		Low(SlashErrLow::ExpansionErr(format!("{}: event not found", self.text_from(start))))
+	}
+	fn bad_word(&self, start: usize) -> SlashErr {
+		Low(SlashErrLow::ExpansionErr(format!("{}: bad word specifier", self.text_from(start))))
+	}
+	/// Expands the reference that starts at the `!` that was just read
+	fn expand_reference(&mut self) -> SlashResult<String> {
+		let start = self.pos - 1;
+		let mut search = None;
+		let event = match self.peek() {
+			Some('!') => {
+				self.pos += 1;
+				self.entries.last().copied()
+			}
+			Some('$' | '^' | '*' | '%' | ':') => self.entries.last().copied(),
+			Some('-') if self.peek_at(1).is_some_and(|ch| ch.is_ascii_digit()) => {
+				self.pos += 1;
+				let back = self.take_number().unwrap_or(0);
+				self.entries.len().checked_sub(back).and_then(|i| self.entries.get(i)).copied()
+			}
+			Some(ch) if ch.is_ascii_digit() => {
+				let number = self.take_number().unwrap_or(0);
+				number.checked_sub(1).and_then(|i| self.entries.get(i)).copied()
+			}
+			Some('?') => {
+				self.pos += 1;
+				let text_start = self.pos;
+				while self.peek().is_some_and(|ch| ch != '?' && ch != '\n') {
+					self.pos += 1;
+				}
+				let text = self.text_from(text_start);
+				if self.peek() == Some('?') {
+					self.pos += 1;
+				}
+				search = Some(text.clone());
+				self.entries.iter().rev().find(|entry| !text.is_empty() && entry.contains(&text)).copied()
+			}
+			_ => {
+				let text_start = self.pos;
+				while self.peek().is_some_and(|ch| !ch.is_whitespace() && !matches!(ch, ':' | ';' | '&' | '|' | '<' | '>' | '(' | ')' | '\'' | '"')) {
+					self.pos += 1;
+				}
+				let text = self.text_from(text_start);
+				self.entries.iter().rev().find(|entry| entry.starts_with(&text)).copied()
+			}
+		};
+		let Some(event) = event else {
+			return Err(self.event_not_found(start))
+		};
+
+		let designator = match (self.peek(), self.peek_at(1)) {
+			(Some(':'), Some(ch)) if ch.is_ascii_digit() || matches!(ch, '^' | '$' | '*' | '-' | '%') => {
+				self.pos += 1;
+				true
+			}
+			(Some('^' | '$' | '*' | '%'), _) => true,
+			_ => false
+		};
+		let mut text = if designator {
+			let words = split_words(event);
+			let matched = search.and_then(|text| words.iter().position(|word| word.contains(&text)));
+			let (first,last) = self.word_range(words.len(), matched, start)?;
+			words.get(first..last).map(|words| words.join(" ")).unwrap_or_default()
+		} else {
+			event.to_string()
+		};
+
+		while self.peek() == Some(':') {
+			let (global,modifier) = match (self.peek_at(1), self.peek_at(2)) {
+				(Some('g' | 'a'), Some(ch @ ('s' | '&'))) => (true, ch),
+				(Some(ch @ ('h' | 't' | 'r' | 'e' | 'p' | 'q' | 's' | '&')), _) => (false, ch),
+				_ => break
+			};
+			self.pos += if global { 3 } else { 2 };
+			text = match modifier {
+				'h' => match text.rfind('/') {
+					Some(slash) => text[..slash].to_string(),
+					None => text
+				}
+				't' => match text.rfind('/') {
+					Some(slash) => text[slash + 1..].to_string(),
+					None => text
+				}
+				'r' => match suffix_start(&text) {
+					Some(dot) => text[..dot].to_string(),
+					None => text
+				}
+				'e' => match suffix_start(&text) {
+					Some(dot) => text[dot..].to_string(),
+					None => text
+				}
+				'p' => {
+					self.print_only = true;
+					text
+				}
+				'q' => format!("'{}'", text.replace('\'', "'\\''")),
+				's' => {
+					let (old,new) = self.take_subst(start)?;
+					substitute(&text, &old, &new, global)
+				}
+				'&' => match self.last_subst.clone() {
+					Some((old,new)) => substitute(&text, &old, &new, global),
+					None => return Err(Low(SlashErrLow::ExpansionErr(format!("{}: no previous substitution", self.text_from(start)))))
+				}
+				_ => unreachable!()
+			};
+		}
+		Ok(text)
+	}
+	/// Reads a word designator, which is given as the range `first..last` over the event's words
+	fn word_range(&mut self, count: usize, matched: Option<usize>, start: usize) -> SlashResult<(usize,usize)> {
+		let last_word = count.saturating_sub(1);
+		let first = match self.peek() {
+			Some('^') => { self.pos += 1; 1 }
+			Some('$') => { self.pos += 1; last_word }
+			Some('%') => {
+				self.pos += 1;
+				matched.ok_or_else(|| self.bad_word(start))?
+			}
+			Some('*') => {
+				self.pos += 1;
+				return Ok((1, count.max(1)))
+			}
+			Some('-') => 0,
+			_ => self.take_number().unwrap_or(0)
+		};
+		let range = match self.peek() {
+			Some('*') => {
+				self.pos += 1;
+				(first, count.max(first))
+			}
+			Some('-') => {
+				self.pos += 1;
+				match self.peek() {
+					Some('$') => {
+						self.pos += 1;
+						(first, count)
+					}
+					Some(ch) if ch.is_ascii_digit() => {
+						let last = self.take_number().unwrap_or(0);
+						(first, last + 1)
+					}
+					// `x-` stops before the last word
+					_ => (first, last_word)
+				}
+			}
+			_ => (first, first + 1)
+		};
+		if range.0 >= count || range.1 > count || range.0 > range.1 {
+			return Err(self.bad_word(start))
+		}
+		Ok(range)
+	}
+	/// Reads the `/old/new/` part of a substitution, where `/` can be any character
+	/// The closing delimiter can be left off at the end of the line
+	fn take_subst(&mut self, start: usize) -> SlashResult<(String,String)> {
+		let Some(delim) = self.next_char() else {
+			return Err(Low(SlashErrLow::ExpansionErr(format!("{}: bad substitution", self.text_from(start)))))
+		};
+		let take_part = |expander: &mut Self| {
+			let mut part = String::new();
+			while let Some(ch) = expander.next_char() {
+				match ch {
+					'\\' if expander.peek() == Some(delim) => part.push(expander.next_char().unwrap()),
+					_ if ch == delim => break,
+					_ => part.push(ch)
+				}
+			}
+			part
+		};
+		let mut old = take_part(self);
+		let new = take_part(self);
+		if old.is_empty() {
+			match &self.last_subst {
+				Some((last_old,_)) => old = last_old.clone(),
+				None => return Err(Low(SlashErrLow::ExpansionErr(format!("{}: no previous substitution", self.text_from(start)))))
+			}
+		}
+		self.last_subst = Some((old.clone(), new.clone()));
+		Ok((old,new))
+	}
+}
+
+/// Where the `.suffix` of the last path component starts, if it has one
+fn suffix_start(text: &str) -> Option<usize> {
+	let dot = text.rfind('.')?;
+	text.rfind('/').is_none_or(|slash| dot > slash).then_some(dot)
+}
+
+/// Replaces `old` with `new` once, or everywhere if `global` is set. A `&` in `new` stands for `old`, and `\&` for a literal `&`
+fn substitute(text: &str, old: &str, new: &str, global: bool) -> String {
+	let mut replacement = String::new();
+	let mut chars = new.chars().peekable();
+	while let Some(ch) = chars.next() {
+		match ch {
+			'\\' if chars.peek() == Some(&'&') => replacement.push(chars.next().unwrap()),
+			'&' => replacement.push_str(old),
+			_ => replacement.push(ch)
+		}
+	}
+	if global {
+		text.replace(old, &replacement)
+	} else {
+		text.replacen(old, &replacement, 1)
+	}
+}
+
+/// Splits a command into words the way word designators count them
+/// Quoted text stays in one word, and runs of `;`, `&`, `|`, `<`, `>`, `(` and `)` are words of their own
+fn split_words(line: &str) -> Vec<String> {
+	let mut words = vec![];
+	let mut word = String::new();
+	let mut chars = line.chars().peekable();
+	let is_operator = |ch: char| matches!(ch, ';' | '&' | '|' | '<' | '>' | '(' | ')');
+	while let Some(ch) = chars.next() {
+		match ch {
+			'\\' => {
+				word.push(ch);
+				if let Some(escaped) = chars.next() {
+					word.push(escaped);
+				}
+			}
+			'\'' | '"' => {
+				word.push(ch);
+				for quoted in chars.by_ref() {
+					word.push(quoted);
+					if quoted == ch {
+						break
+					}
+				}
+			}
+			_ if ch.is_whitespace() => {
+				if !word.is_empty() {
+					words.push(std::mem::take(&mut word));
+				}
+			}
+			_ if is_operator(ch) => {
+				if !word.is_empty() {
+					words.push(std::mem::take(&mut word));
+				}
+				let mut operator = String::from(ch);
+				while let Some(next) = chars.next_if(|next| is_operator(*next)) {
+					operator.push(next);
+				}
+				words.push(operator);
+			}
+			_ => word.push(ch)
+		}
+	}
+	if !word.is_empty() {
+		words.push(word);
+	}
+	words
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn test_history_expansion() {
+		let mut history = CmdHistory::default();
+		for line in ["cd /usr/local/lib", "tar xf archive.tar.gz 'my file'", "echo one two three"] {
+			history.push(line, true, 1000);
+		}
+		let expand = |line: &str| expand_history(line, &history).unwrap().map(|expanded| expanded.line);
+
+		assert_eq!(expand("echo hi"), None);
+		assert_eq!(expand("sudo !!").as_deref(), Some("sudo echo one two three"));
+		assert_eq!(expand("ls !$ !^").as_deref(), Some("ls three one"));
+		assert_eq!(expand("printf !*").as_deref(), Some("printf one two three"));
+		assert_eq!(expand("!!:2-$").as_deref(), Some("two three"));
+		assert_eq!(expand("!!:0-").as_deref(), Some("echo one two"));
+		assert_eq!(expand("!-2:2").as_deref(), Some("archive.tar.gz"));
+		assert_eq!(expand("!tar:$").as_deref(), Some("'my file'"));
+		assert_eq!(expand("!1:1:h !1:1:t").as_deref(), Some("/usr/local lib"));
+		assert_eq!(expand("!2:2:r !2:2:e").as_deref(), Some("archive.tar .gz"));
+		assert_eq!(expand("!?arch?:%").as_deref(), Some("archive.tar.gz"));
+		assert_eq!(expand("!!:s/one/1/").as_deref(), Some("echo 1 two three"));
+		assert_eq!(expand("!!:gs/o/0").as_deref(), Some("ech0 0ne tw0 three"));
+		assert_eq!(expand("!!:s/two/[&]/").as_deref(), Some("echo one [two] three"));
+
+		// Quoting, parameters, and a `!` on its own are left alone
+		assert_eq!(expand("echo '!!' \\!! $! ${!ref} [ a != b ] ! true"), None);
+		assert_eq!(expand("echo \"!!\"").as_deref(), Some("echo \"echo one two three\""));
+
+		let printed = expand_history("!!:p", &history).unwrap().unwrap();
+		assert!(printed.print_only);
+		assert!(expand_history("!nothing", &history).is_err());
+		assert!(expand_history("!!:9", &history).is_err());
+	}
+}
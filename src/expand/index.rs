@@ -1,19 +1,34 @@
 use crate::{prelude::*, shellenv::SlashVal};
 
+/// Expands an indexed variable like `$arr[1]`, `$arr[0..2]`, or `$dict["key"]`
+/// Subscripts can be chained to reach into nested values. Anything that doesn't exist expands to an empty string
 pub fn expand_index(pair: Pair<Rule>,slash: &mut Slash) -> SlashResult<String> {
-	let mut inner = pair.step(1).unpack()?.into_inner().peekable();
-	let arr_name = inner.next().unpack()?;
-
-	let array = slash.vars().get_var(arr_name.as_str());
-	let mut cur_val = match array {
-		Some(SlashVal::Array(vec)) => Some(SlashVal::Array(vec)),
-		_ => return Ok(String::new()), // If not an array, return empty
+	// arr_index is atomic, so the subscripts are read from the text rather than from inner pairs
+	let body = &pair.as_str()[1..];
+	let Some((arr_name,mut subscripts)) = body.split_once('[') else {
+		return Ok(String::new())
 	};
+	let arr_name = arr_name.trim_start_matches('{').trim_end_matches('}');
 
-	while let Some(index) = inner.next() {
-		let idx = index.as_str().parse::<usize>().map_err(|_| Low(SlashErrLow::IndexErr(format!("Index '{}' out of range for array '{}'",index,arr_name.as_str()))))?;
+	let mut cur_val = slash.vars().get_var(arr_name);
+	while let Some((subscript,rest)) = subscripts.split_once(']') {
+		subscripts = rest.strip_prefix('[').unwrap_or(rest);
+		let out_of_range = || Low(SlashErrLow::IndexErr(format!("Index '{}' out of range for array '{}'",subscript,arr_name)));
 		cur_val = match cur_val {
-			Some(SlashVal::Array(vec)) => vec.get(idx).cloned(),
+			Some(SlashVal::Array(vec)) => {
+				if let Some((start,end)) = subscript.split_once("..") {
+					let start = start.parse::<usize>().map_err(|_| out_of_range())?;
+					let end = end.parse::<usize>().map_err(|_| out_of_range())?.min(vec.len());
+					Some(SlashVal::Array(vec.get(start..end).unwrap_or_default().to_vec()))
+				} else {
+					let idx = subscript.parse::<usize>().map_err(|_| out_of_range())?;
+					vec.get(idx).cloned()
+				}
+			}
+			Some(SlashVal::Dict(map)) => {
+				let key = subscript.trim_matches(|ch| ch == '"' || ch == '\'');
+				map.get(key).cloned()
+			}
 			_ => return Ok(String::new()), // Invalid nesting (e.g., indexing a non-array)
 		};
 	}
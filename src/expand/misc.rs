@@ -1,4 +1,6 @@
-use crate::{helper, prelude::*};
+use crate::{builtin, helper, prelude::*, shellenv::SlashVal};
+
+use super::param;
 
 pub fn expand_tilde(pair: Pair<Rule>) -> SlashResult<String> {
 	debug_assert!(pair.as_rule() == Rule::tilde_sub, "Found this: {:?}",pair.as_rule());
@@ -20,6 +22,64 @@ pub fn expand_tilde(pair: Pair<Rule>) -> SlashResult<String> {
 	Ok(word.replacen("~", &home, 1))
 }
 
+/// Expands a var_sub pair, i.e. `$var`, `${var}`, `${#var}`, `${var@op}`, or `${var<op>word}`
+/// `@Q` quotes the value for reuse as shell input, `@E` expands the backslash escapes in it,
+/// `@A` gives a command that recreates the variable, and `@a` gives its attributes as flags.
+/// The other operators, like `:-` and `%%`, are applied by `expand::param`
+pub fn expand_var(pair: Pair<Rule>, slash: &mut Slash) -> SlashResult<String> {
+	let text = &pair.as_str()[1..];
+	let body = text.strip_prefix('{').and_then(|body| body.strip_suffix('}')).unwrap_or(text);
+	if let Some(name) = body.strip_prefix('#').filter(|name| !name.is_empty()) {
+		if matches!(name, "@" | "*") {
+			return Ok(slash.vars().borrow_pos_params().len().to_string())
+		}
+		return Ok(param::length(slash.vars().get_var(name)))
+	}
+	let (var_name, rest) = body.split_at(param::name_len(body));
+	let val = slash.vars().get_var(var_name);
+	if let Some((op,word)) = param::split_op(rest) {
+		return param::apply_op(var_name, val, op, word, slash)
+	}
+	let Some(val) = val else {
+		return Ok(String::new())
+	};
+	let exported = slash.vars().is_exported(var_name);
+	let expanded = match rest.strip_prefix('@').and_then(|op| op.chars().next()) {
+		Some('Q') => match &val {
+			SlashVal::Array(elems) => elems.iter().map(|elem| helper::shell_quote(&elem.to_string())).collect::<Vec<_>>().join(" "),
+			_ => helper::shell_quote(&val.to_string())
+		}
+		Some('E') => builtin::printf::expand_escapes(&val.to_string()),
+		Some('A') => match &val {
+			SlashVal::Int(_) | SlashVal::Float(_) | SlashVal::Bool(_) => format!("{} {}={}",val.fmt_type(),var_name,val),
+			SlashVal::Array(elems) => {
+				let elems = elems.iter().map(|elem| helper::shell_quote(&elem.to_string())).collect::<Vec<_>>();
+				format!("arr {}=[{}]",var_name,elems.join(","))
+			}
+			_ if exported => format!("export {}={}",var_name,helper::shell_quote(&val.to_string())),
+			_ => format!("{}={}",var_name,helper::shell_quote(&val.to_string()))
+		}
+		Some('a') => var_attrs(&val, exported),
+		_ => val.to_string()
+	};
+	Ok(expanded)
+}
+
+/// The attributes of a variable as `declare` would write them: `a` for arrays, `A` for dicts, `i` for integers, and `x` if it is exported
+fn var_attrs(val: &SlashVal, exported: bool) -> String {
+	let mut attrs = String::new();
+	match val {
+		SlashVal::Array(_) => attrs.push('a'),
+		SlashVal::Dict(_) => attrs.push('A'),
+		SlashVal::Int(_) => attrs.push('i'),
+		_ => {}
+	}
+	if exported {
+		attrs.push('x');
+	}
+	attrs
+}
+
 pub fn expand_shebang(slash: &mut Slash,shebang: &str) -> String {
 	let mut command = shebang.trim_start_matches("#!").trim().to_string();
 	if command.has_unescaped("/") {
@@ -32,8 +92,12 @@ pub fn expand_shebang(slash: &mut Slash,shebang: &str) -> String {
 	}
 }
 
+/// Expands the escape sequences in a prompt string, which is `PS1` unless another one is given
 pub fn expand_prompt(input: Option<&str>,slash: &mut Slash) -> SlashResult<String> {
-	let mut prompt = slash.vars().get_evar("PS1").unwrap_or_default();
+	let mut prompt = match input {
+		Some(input) => input.to_string(),
+		None => slash.vars().get_var("PS1").map(|ps1| ps1.to_string()).unwrap_or_default()
+	};
 	prompt = prompt.replace("\n", "");
 	let mut result = prompt.clone();
 	let mut prompt_parse = SlashParse::parse(Rule::prompt, &prompt)
@@ -66,6 +130,7 @@ pub fn expand_esc<'a>(slash: &mut Slash,pair: Pair<'a,Rule>) -> SlashResult<Stri
 		Rule::esc_return => "\r".into(),
 		Rule::esc_newline => "\n".into(),
 		Rule::esc_vis_grp => helper::handle_prompt_visgroup(slash,pair)?,
+		Rule::esc_hide_grp => helper::handle_prompt_hidegroup(slash,pair)?,
 		Rule::esc_user_seq => {
 			let query = pair.step(1).unpack()?.as_str();
 			helper::escseq_custom(slash,query)?
@@ -84,7 +149,7 @@ pub fn expand_esc<'a>(slash: &mut Slash,pair: Pair<'a,Rule>) -> SlashResult<Stri
 		}
 		Rule::esc_12hour_short => expand_time("%I:%M %p"),
 		Rule::esc_24hour_short => expand_time("%H:%M"),
-		Rule::esc_runtime => helper::escseq_cmdtime()?,
+		Rule::esc_runtime => helper::escseq_cmdtime(slash)?,
 		Rule::esc_12hour => expand_time("%I:%M:%S"),
 		Rule::esc_24hour => expand_time("%H:%M:%S"),
 		Rule::esc_weekday => expand_time("%a %b %d"),
@@ -98,6 +163,7 @@ pub fn expand_esc<'a>(slash: &mut Slash,pair: Pair<'a,Rule>) -> SlashResult<Stri
 		Rule::esc_exit_code => helper::escseq_exitcode(slash)?,
 		Rule::esc_success_symbol => helper::escseq_success(slash)?,
 		Rule::esc_failure_symbol => helper::escseq_fail(slash)?,
+		Rule::esc_dir_depth => helper::escseq_dir_depth(slash),
 		_ => unreachable!("Got this rule in prompt expansion: {:?}",pair.as_rule())
 	})
 }
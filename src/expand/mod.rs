@@ -1,7 +1,11 @@
+pub mod arithmetic;
 pub mod brace;
 pub mod cmdsub;
 pub mod dispatch;
 pub mod glob;
+pub mod history;
 pub mod index;
 pub mod misc;
+pub mod param;
+pub mod pattern;
 pub mod string;
@@ -0,0 +1,304 @@
+//! The operators of `${name<op>word}` parameter expansions
+//!
+//! `-`, `=`, `?` and `+` check whether the parameter is set: `${x-word}` gives `word` if `x` is unset, `${x=word}` assigns it to `x` as well,
+//! `${x?word}` fails with `word` as the message, and `${x+word}` gives `word` only if `x` is set. With a colon in front, like `:-`, an empty value counts as unset.
+//! `#` and `##` remove the shortest and the longest prefix that matches a pattern, and `%` and `%%` do the same with suffixes.
+//! `/pat/rep` replaces the longest match of a pattern, `//` replaces every match, and `/#` and `/%` only match at the start or the end.
+//! `:offset` and `:offset:length` take a substring. Both are arithmetic, and negative numbers count back from the end.
+//! The word is only expanded if it is used, and the quoted parts of a pattern match literally
+
+use crate::{helper, locale, prelude::*, shellenv::{SlashVal, VarTable}};
+
+use super::pattern::ShellPattern;
+
+/// Longer operators come first, so that they are matched before their prefixes
+const OPERATORS: [&str;17] = [":-", ":=", ":?", ":+", "##", "%%", "//", "/#", "/%", "-", "=", "?", "+", "#", "%", "/", ":"];
+
+/// The length of the parameter name at the start of a `${...}` body
+/// Names of variables start with a letter, positional parameters are numbers, and special parameters are a single character
+pub fn name_len(body: &str) -> usize {
+	match body.chars().next() {
+		Some(ch) if ch.is_ascii_alphabetic() => body.find(|ch: char| !ch.is_ascii_alphanumeric() && ch != '_').unwrap_or(body.len()),
+		Some(ch) if ch.is_ascii_digit() => body.find(|ch: char| !ch.is_ascii_digit()).unwrap_or(body.len()),
+		Some(ch) => ch.len_utf8(),
+		None => 0
+	}
+}
+
+/// Splits what follows a parameter name into its operator and word
+pub fn split_op(rest: &str) -> Option<(&'static str,&str)> {
+	OPERATORS.iter().find_map(|op| rest.strip_prefix(op).map(|word| (*op,word)))
+}
+
+/// The length of a value for `${#name}`, in characters. Arrays give their number of elements
+pub fn length(val: Option<SlashVal>) -> String {
+	match val {
+		Some(SlashVal::Array(elems)) => elems.len().to_string(),
+		Some(val) => val.to_string().chars().count().to_string(),
+		None => "0".into()
+	}
+}
+
+/// Applies an operator to the value of the parameter `name`, which is None if it is unset
+pub fn apply_op(name: &str, val: Option<SlashVal>, op: &str, word: &str, slash: &mut Slash) -> SlashResult<String> {
+	let text = val.map(|val| val.to_string());
+	// With a colon, an empty value is treated like an unset one
+	let is_set = match op.starts_with(':') {
+		true => text.as_ref().is_some_and(|text| !text.is_empty()),
+		false => text.is_some()
+	};
+	let text = text.unwrap_or_default();
+	match op {
+		"-" | ":-" => match is_set {
+			true => Ok(text),
+			false => expand_word(word, slash, false)
+		}
+		"=" | ":=" => {
+			if is_set {
+				return Ok(text)
+			}
+			if VarTable::is_special_param(name) {
+				return Err(Low(SlashErrLow::ExpansionErr(format!("${}: cannot assign in this way", name))))
+			}
+			let value = expand_word(word, slash, false)?;
+			slash.vars_mut().set_var(name, SlashVal::String(value.clone()));
+			Ok(value)
+		}
+		"?" | ":?" => {
+			if is_set {
+				return Ok(text)
+			}
+			let msg = expand_word(word, slash, false)?;
+			let msg = if msg.is_empty() { "parameter null or not set".to_string() } else { msg };
+			Err(Low(SlashErrLow::ExpansionErr(format!("{}: {}", name, msg))))
+		}
+		"+" | ":+" => match is_set {
+			true => expand_word(word, slash, false),
+			false => Ok(String::new())
+		}
+		"#" | "##" | "%" | "%%" => {
+			let pattern = ShellPattern::new(&expand_word(word, slash, true)?);
+			locale::sync(slash);
+			Ok(remove_affix(&text, &pattern, op))
+		}
+		"/" | "//" | "/#" | "/%" => {
+			let (pattern,replacement) = split_replacement(word);
+			let pattern = expand_word(pattern, slash, true)?;
+			let replacement = expand_word(replacement, slash, false)?;
+			if pattern.is_empty() {
+				return Ok(text)
+			}
+			locale::sync(slash);
+			Ok(replace(&text, &ShellPattern::new(&pattern), &replacement, op))
+		}
+		":" => substring(&text, word, slash),
+		_ => Ok(text)
+	}
+}
+
+/// The byte offsets of every character boundary in a string, including its end
+fn boundaries(text: &str) -> Vec<usize> {
+	text.char_indices().map(|(i,_)| i).chain([text.len()]).collect()
+}
+
+/// Removes the shortest (`#`, `%`) or the longest (`##`, `%%`) prefix or suffix that matches a pattern
+fn remove_affix(text: &str, pattern: &ShellPattern, op: &str) -> String {
+	let bounds = boundaries(text);
+	let found = match op {
+		"#" => bounds.iter().find(|&&end| pattern.matches(&text[..end])).map(|&end| &text[end..]),
+		"##" => bounds.iter().rev().find(|&&end| pattern.matches(&text[..end])).map(|&end| &text[end..]),
+		"%" => bounds.iter().rev().find(|&&start| pattern.matches(&text[start..])).map(|&start| &text[..start]),
+		_ => bounds.iter().find(|&&start| pattern.matches(&text[start..])).map(|&start| &text[..start])
+	};
+	found.unwrap_or(text).to_string()
+}
+
+/// Splits the word of a replacement at its first unescaped `/`. A missing replacement is empty
+fn split_replacement(word: &str) -> (&str,&str) {
+	let mut chars = word.char_indices();
+	while let Some((i,ch)) = chars.next() {
+		match ch {
+			'\\' => { chars.next(); }
+			'/' => return (&word[..i], &word[i + 1..]),
+			_ => {}
+		}
+	}
+	(word,"")
+}
+
+/// Replaces the longest matches of a pattern, either the first one, every one (`//`), or only one at the start (`/#`) or the end (`/%`)
+fn replace(text: &str, pattern: &ShellPattern, replacement: &str, op: &str) -> String {
+	let bounds = boundaries(text);
+	match op {
+		"/#" => match bounds.iter().rev().find(|&&end| pattern.matches(&text[..end])) {
+			Some(&end) => format!("{}{}", replacement, &text[end..]),
+			None => text.to_string()
+		}
+		"/%" => match bounds.iter().find(|&&start| pattern.matches(&text[start..])) {
+			Some(&start) => format!("{}{}", &text[..start], replacement),
+			None => text.to_string()
+		}
+		_ => {
+			let global = op == "//";
+			let mut result = String::new();
+			let mut i = 0;
+			while i < bounds.len() - 1 {
+				let start = bounds[i];
+				// Empty matches are skipped, so that a pattern like `x*` doesn't match between every character
+				let end = bounds[i + 1..].iter().rposition(|&end| pattern.matches(&text[start..end]));
+				match end {
+					Some(offset) => {
+						result.push_str(replacement);
+						i += offset + 1;
+						if !global {
+							result.push_str(&text[bounds[i]..]);
+							return result
+						}
+					}
+					None => {
+						result.push_str(&text[start..bounds[i + 1]]);
+						i += 1;
+					}
+				}
+			}
+			result
+		}
+	}
+}
+
+/// Takes the substring for `${name:offset}` or `${name:offset:length}`
+fn substring(text: &str, word: &str, slash: &mut Slash) -> SlashResult<String> {
+	let chars = text.chars().collect::<Vec<_>>();
+	let len = chars.len() as i64;
+	let (offset,length) = match word.split_once(':') {
+		Some((offset,length)) => (offset, Some(length)),
+		None => (word, None)
+	};
+	let offset = super::arithmetic::eval(offset, slash)?;
+	let start = if offset < 0 { len + offset } else { offset };
+	if start < 0 || start > len {
+		return Ok(String::new())
+	}
+	let end = match length {
+		Some(length) => {
+			let length = super::arithmetic::eval(length, slash)?;
+			if length < 0 { len + length } else { (start + length).min(len) }
+		}
+		None => len
+	};
+	if end < start {
+		return Err(Low(SlashErrLow::ExpansionErr(format!("{}: substring expression < 0", word))))
+	}
+	Ok(chars[start as usize..end as usize].iter().collect())
+}
+
+/// Makes text match itself literally when it is used as a pattern
+fn escape_pattern(text: &str) -> String {
+	let mut escaped = String::new();
+	for ch in text.chars() {
+		match ch {
+			'*' | '?' | '[' => escaped.push_str(&format!("[{}]", ch)),
+			_ => escaped.push(ch)
+		}
+	}
+	escaped
+}
+
+/// Expands the word of an operator, and removes its quotes
+/// If the word is a pattern, the parts of it that were quoted or escaped are made to match literally
+fn expand_word(word: &str, slash: &mut Slash, pattern: bool) -> SlashResult<String> {
+	let literal = |text: &str| if pattern { escape_pattern(text) } else { text.to_string() };
+	let mut result = String::new();
+	let mut rest = word;
+	if word.starts_with('~') {
+		let end = word.find('/').unwrap_or(word.len());
+		if let Some(dir) = helper::expand_tilde_prefix(slash, &word[..end]) {
+			result.push_str(&literal(&dir));
+			rest = &word[end..];
+		}
+	}
+	// Unquoted text is gathered up and expanded all at once
+	let mut unquoted = String::new();
+	let mut chars = rest.chars().peekable();
+	while let Some(ch) = chars.next() {
+		match ch {
+			'\\' | '\'' | '"' => {
+				result.push_str(&super::string::expand_heredoc(&std::mem::take(&mut unquoted), slash)?);
+				match ch {
+					'\\' => if let Some(escaped) = chars.next() {
+						result.push_str(&literal(&escaped.to_string()))
+					}
+					'\'' => {
+						let quoted = chars.by_ref().take_while(|ch| *ch != '\'').collect::<String>();
+						result.push_str(&literal(&quoted));
+					}
+					_ => {
+						let mut quoted = String::new();
+						while let Some(ch) = chars.next() {
+							match ch {
+								'\\' if chars.peek() == Some(&'"') => quoted.push(chars.next().unwrap()),
+								'\\' => {
+									quoted.push(ch);
+									quoted.extend(chars.next());
+								}
+								'"' => break,
+								_ => quoted.push(ch)
+							}
+						}
+						result.push_str(&literal(&super::string::expand_heredoc(&quoted, slash)?));
+					}
+				}
+			}
+			// Substitutions are kept whole, since they can have quotes of their own
+			'$' if matches!(chars.peek(), Some('(' | '{')) => {
+				let open = chars.next().unwrap();
+				let close = if open == '(' { ')' } else { '}' };
+				unquoted.push(ch);
+				unquoted.push(open);
+				let mut depth = 1;
+				for ch in chars.by_ref() {
+					unquoted.push(ch);
+					if ch == open {
+						depth += 1;
+					} else if ch == close {
+						depth -= 1;
+						if depth == 0 {
+							break
+						}
+					}
+				}
+			}
+			_ => unquoted.push(ch)
+		}
+	}
+	result.push_str(&super::string::expand_heredoc(&unquoted, slash)?);
+	Ok(result)
+}
+
+#[cfg(test)]
+mod tests {
+	use crate::execute::dispatch;
+
+	use super::*;
+
+	#[test]
+	fn test_param_ops() {
+		let mut slash = Slash::new();
+		dispatch::exec_input("path=/usr/local/lib/libfoo.so.1; empty=; greeting='hello world'".into(), &mut slash).unwrap();
+		let mut expand = |word: &str| slash.exec_captured(&format!("echo \"{}\"", word)).unwrap().trim_end_matches('\n').to_string();
+
+		assert_eq!(expand("${unset_var:-fallback} ${empty:-fallback} ${empty-fallback}"), "fallback fallback ");
+		assert_eq!(expand("${greeting:+set} ${empty:+set} ${empty+set}"), "set  set");
+		assert_eq!(expand("${#greeting} ${#unset_var}"), "11 0");
+		assert_eq!(expand("${path#*/} ${path##*/}"), "usr/local/lib/libfoo.so.1 libfoo.so.1");
+		assert_eq!(expand("${path%.*} ${path%%.*}"), "/usr/local/lib/libfoo.so /usr/local/lib/libfoo");
+		assert_eq!(expand("${greeting/o/0} ${greeting//o/0} ${greeting/#hello/bye} ${greeting/%world/there}"), "hell0 world hell0 w0rld bye world hello there");
+		assert_eq!(expand("${greeting:6} ${greeting:0:5} ${greeting: -5:3} ${greeting:2:-2}"), "world hello wor llo wor");
+		assert_eq!(expand("${greeting%'o'*} ${greeting#\\*}"), "hello w hello world");
+		assert_eq!(expand("${unset_var:-$HOME/x} ${unset_var:-${greeting%% *}}"), format!("{}/x hello", env::var("HOME").unwrap_or_default()));
+
+		dispatch::exec_input("echo ${assigned:=value}".into(), &mut slash).unwrap();
+		assert_eq!(slash.vars().get_var("assigned").map(|val| val.to_string()).as_deref(), Some("value"));
+		assert!(dispatch::exec_input("echo ${unset_var:?needs a value}".into(), &mut slash).is_err());
+	}
+}
@@ -0,0 +1,182 @@
+//! Shell pattern matching
+//!
+//! The glob crate compares bracket ranges by code point and doesn't know about character classes,
+//! so bracket expressions are matched here instead. Ranges like `[a-f]` follow the collation order of `LC_COLLATE`,
+//! and classes like `[[:alpha:]]` are checked against `LC_CTYPE`. Call `locale::sync()` before matching
+
+use crate::locale;
+
+#[derive(Debug,Clone,PartialEq)]
+enum Token {
+	Literal(char),
+	AnyChar,
+	AnyString,
+	Bracket { negated: bool, items: Vec<BracketItem> }
+}
+
+#[derive(Debug,Clone,PartialEq)]
+enum BracketItem {
+	Char(char),
+	Range(char,char),
+	Class(String)
+}
+
+impl Token {
+	fn matches(&self, ch: char) -> bool {
+		match self {
+			Token::Literal(lit) => *lit == ch,
+			Token::AnyChar => true,
+			Token::AnyString => false,
+			Token::Bracket { negated, items } => {
+				let found = items.iter().any(|item| match item {
+					BracketItem::Char(item_ch) => *item_ch == ch,
+					BracketItem::Range(start,end) => locale::in_range(ch, *start, *end),
+					BracketItem::Class(class) => locale::is_class(ch, class)
+				});
+				found != *negated
+			}
+		}
+	}
+}
+
+/// A compiled shell pattern
+/// Backslashes are literal, like they are in glob crate patterns. Special characters are escaped by wrapping them in brackets
+#[derive(Debug,Clone)]
+pub struct ShellPattern {
+	tokens: Vec<Token>
+}
+
+impl ShellPattern {
+	pub fn new(pattern: &str) -> Self {
+		let chars = pattern.chars().collect::<Vec<_>>();
+		let mut tokens = vec![];
+		let mut i = 0;
+		while i < chars.len() {
+			match chars[i] {
+				'*' => tokens.push(Token::AnyString),
+				'?' => tokens.push(Token::AnyChar),
+				'[' => match parse_bracket(&chars, i + 1) {
+					Some((bracket,end)) => {
+						tokens.push(bracket);
+						i = end;
+					}
+					// An unclosed bracket is just a character
+					None => tokens.push(Token::Literal('['))
+				}
+				ch => tokens.push(Token::Literal(ch))
+			}
+			i += 1;
+		}
+		Self { tokens }
+	}
+
+	pub fn has_brackets(&self) -> bool {
+		self.tokens.iter().any(|token| matches!(token, Token::Bracket {..}))
+	}
+
+	/// Renders the pattern for the glob crate, with every bracket expression loosened to `?`
+	/// The paths that this finds are a superset of the real matches, and should be filtered with `matches()`
+	pub fn to_glob(&self) -> String {
+		self.tokens.iter().map(|token| match token {
+			Token::Literal(ch) => glob::Pattern::escape(&ch.to_string()),
+			Token::AnyChar | Token::Bracket {..} => "?".into(),
+			Token::AnyString => "*".into(),
+		}).collect()
+	}
+
+	pub fn matches(&self, text: &str) -> bool {
+		let text = text.chars().collect::<Vec<_>>();
+		let (mut pat_idx, mut text_idx) = (0,0);
+		// Where to resume from when a match fails after a `*`
+		let mut backtrack: Option<(usize,usize)> = None;
+		while text_idx < text.len() {
+			match self.tokens.get(pat_idx) {
+				Some(Token::AnyString) => {
+					backtrack = Some((pat_idx, text_idx));
+					pat_idx += 1;
+					continue
+				}
+				Some(token) if token.matches(text[text_idx]) => {
+					pat_idx += 1;
+					text_idx += 1;
+					continue
+				}
+				_ => { /* Fall through to backtracking */ }
+			}
+			let Some((star_idx,star_text_idx)) = backtrack else {
+				return false
+			};
+			// Let the last `*` consume one more character, and try again from there
+			backtrack = Some((star_idx, star_text_idx + 1));
+			pat_idx = star_idx + 1;
+			text_idx = star_text_idx + 1;
+		}
+		self.tokens[pat_idx..].iter().all(|token| *token == Token::AnyString)
+	}
+}
+
+/// Parses a bracket expression starting just after the opening `[`
+/// Returns the token and the index of the closing `]`, or None if the bracket is never closed
+fn parse_bracket(chars: &[char], mut i: usize) -> Option<(Token,usize)> {
+	let negated = matches!(chars.get(i), Some('!' | '^'));
+	if negated {
+		i += 1;
+	}
+	let first = i;
+	let mut items = vec![];
+	while let Some(&ch) = chars.get(i) {
+		match ch {
+			// A `]` right after the opening bracket is part of the set
+			']' if i > first => return Some((Token::Bracket { negated, items }, i)),
+			'[' if chars.get(i + 1) == Some(&':') => {
+				let name_start = i + 2;
+				let name_end = (name_start..chars.len().saturating_sub(1)).find(|&j| chars[j] == ':' && chars[j + 1] == ']')?;
+				items.push(BracketItem::Class(chars[name_start..name_end].iter().collect()));
+				i = name_end + 2;
+				continue
+			}
+			_ => {
+				if chars.get(i + 1) == Some(&'-') && chars.get(i + 2).is_some_and(|&end| end != ']') {
+					items.push(BracketItem::Range(ch, chars[i + 2]));
+					i += 3;
+					continue
+				}
+				items.push(BracketItem::Char(ch));
+			}
+		}
+		i += 1;
+	}
+	None
+}
+
+#[cfg(test)]
+mod tests {
+	use crate::shellenv::Slash;
+
+use super::*;
+
+	#[test]
+	fn test_bracket_patterns() {
+		let Some(_isolated) = crate::utils::in_own_process() else { return };
+		let mut slash = Slash::new();
+		slash.vars_mut().set_var("LC_ALL", crate::shellenv::SlashVal::String("C.UTF-8".into()));
+		locale::sync(&slash);
+
+		let pattern = ShellPattern::new("[[:alpha:]]*[[:digit:]]");
+		assert!(pattern.matches("file1"));
+		assert!(pattern.matches("été2"));
+		assert!(!pattern.matches("1file"));
+		assert!(!pattern.matches("file"));
+
+		assert!(ShellPattern::new("[!a-c]?").matches("dx"));
+		assert!(!ShellPattern::new("[!a-c]?").matches("bx"));
+		assert!(ShellPattern::new("[]x]").matches("]"));
+		assert!(ShellPattern::new("[*]").matches("*"));
+		assert!(!ShellPattern::new("[*]").matches("a"));
+		assert!(ShellPattern::new("a[b").matches("a[b"));
+		assert!(ShellPattern::new("*.[ch]").matches("src/main.c"));
+		assert!(!ShellPattern::new("[[:upper:]]").matches("a"));
+		assert!(!ShellPattern::new("[[:bogus:]]").matches("a"));
+		assert_eq!(ShellPattern::new("dir/[[:alpha:]]*.rs").to_glob(), "dir/?*.rs");
+	}
+}
@@ -8,33 +8,106 @@ pub fn expand_string(pair: Pair<Rule>, slash: &mut Slash) -> SlashResult<String>
 	let body = body.unwrap();
 	// TODO: this is three unwraps in a row. One is guaranteed to work, but still
 	let mut sub_expansions = SlashParse::parse(Rule::find_expansions, body.as_str())?.next().unwrap().to_vec();
-	let mut result = body.as_str().to_string();
+	let body_str = body.as_str();
+	let mut expansions = vec![];
 	while let Some(word) = sub_expansions.pop() {
-		let span = word.as_span();
-		let mut inner = word.clone().into_inner();
-		if word.clone().into_inner().count() == 0 {
-			continue
-		} else {
-			let sub_type = inner.next().unpack()?;
-			let expanded = match sub_type.as_rule() {
-				Rule::var_sub => {
-					slash.vars().get_var(&word.as_str()[1..]).unwrap_or_default().to_string()
-				}
-				Rule::param_sub => {
-					let param = slash.vars().get_param(&word.as_str()[1..]).unwrap_or_default().to_string();
-					param
-				}
-				Rule::cmd_sub => {
-					let result = super::cmdsub::expand_cmd_sub(word,slash)?;
-					result
-				}
-				Rule::arr_index => super::index::expand_index(word,slash)?,
-				Rule::proc_sub => super::cmdsub::expand_proc_sub(word),
-				_ => continue
-			};
-			result.replace_span(span, &expanded);
+		let offset = word.as_span().start();
+		let subs = match SlashParse::parse(Rule::expand_word_loud, word.as_str()) {
+			Ok(mut parsed) => parsed.next().unpack()?.seek_all(&[
+				Rule::var_sub,
+				Rule::param_sub,
+				Rule::arith_sub,
+				Rule::cmd_sub,
+				Rule::arr_index,
+				Rule::proc_sub
+			][..]),
+			Err(_) => continue
+		};
+		for sub in subs {
+			let span = sub.as_span();
+			let expanded = expand_sub(sub,slash)?;
+			expansions.push((offset + span.start(),offset + span.end(),expanded));
 		}
 	}
+	expansions.sort_by(|a,b| b.0.cmp(&a.0));
+
+	// Escapes are only consumed in the literal parts of the string, expanded values are left untouched
+	let mut result = String::new();
+	let mut last_end = 0;
+	while let Some((start,end,expanded)) = expansions.pop() {
+		result.push_str(&unescape_dquote(&body_str[last_end..start]));
+		result.push_str(&expanded);
+		last_end = end;
+	}
+	result.push_str(&unescape_dquote(&body_str[last_end..]));
 	result = format!("\"{}\"",result);
 	Ok(result)
 }
+
+fn expand_sub(sub: Pair<Rule>, slash: &mut Slash) -> SlashResult<String> {
+	let expanded = match sub.as_rule() {
+		Rule::var_sub => super::misc::expand_var(sub,slash)?,
+		Rule::param_sub => slash.vars().get_param(&sub.as_str()[1..]).unwrap_or_default().to_string(),
+		Rule::arith_sub => super::arithmetic::expand_arith(sub,slash)?,
+		Rule::cmd_sub => super::cmdsub::expand_cmd_sub(sub,slash)?,
+		Rule::arr_index => super::index::expand_index(sub,slash)?,
+		Rule::proc_sub => super::cmdsub::expand_proc_sub(sub,slash)?,
+		_ => unreachable!()
+	};
+	Ok(expanded)
+}
+
+/// Expands the body of a heredoc whose delimiter wasn't quoted
+/// Quotes mean nothing in a heredoc, so the body is searched for substitutions directly instead of being parsed as words.
+/// As in double quotes, a backslash only escapes `$`, backticks, and another backslash
+pub fn expand_heredoc(body: &str, slash: &mut Slash) -> SlashResult<String> {
+	const SUB_RULES: [Rule;5] = [Rule::arith_sub, Rule::cmd_sub, Rule::arr_index, Rule::var_sub, Rule::param_sub];
+	let mut result = String::new();
+	let mut literal_start = 0;
+	let mut pos = 0;
+	while let Some(offset) = body[pos..].find(['$','\\']) {
+		pos += offset;
+		let rest = &body[pos..];
+		let next = rest[1..].chars().next();
+		if rest.starts_with('\\') {
+			pos += 1 + next.map_or(0, char::len_utf8);
+			continue
+		}
+		// A lone `$` at the end of a line or before a space is left as it is
+		let sub = next.filter(|next| !next.is_whitespace())
+			.and_then(|_| SUB_RULES.iter().find_map(|rule| SlashParse::parse(*rule, rest).ok().and_then(|mut pairs| pairs.next())));
+		let Some(sub) = sub else {
+			pos += 1;
+			continue
+		};
+		let len = sub.as_str().len();
+		result.push_str(&unescape_dquote(&body[literal_start..pos]));
+		result.push_str(&expand_sub(sub,slash)?);
+		pos += len;
+		literal_start = pos;
+	}
+	result.push_str(&unescape_dquote(&body[literal_start..]));
+	Ok(result)
+}
+
+/// Consumes the escapes for `$`, backticks, and backslashes
+fn unescape_dquote(literal: &str) -> String {
+	let mut result = String::new();
+	let mut chars = literal.chars().peekable();
+	while let Some(ch) = chars.next() {
+		match ch {
+			'\\' if chars.peek().is_some_and(|next| matches!(next, '$' | '`' | '\\')) => {
+				result.push(chars.next().unwrap());
+			}
+			'\\' => {
+				// Keep other escapes intact, they are handled further down the line
+				result.push(ch);
+				if let Some(next) = chars.next() {
+					result.push(next);
+				}
+			}
+			_ => result.push(ch)
+		}
+	}
+	result
+}
@@ -4,8 +4,8 @@ use std::os::unix::fs::PermissionsExt;
 use io::Read;
 use nix::unistd::getpgrp;
 
-use crate::{expand, prelude::*, utils};
-use crate::{utils::REGEX, error::{SlashErr, SlashErrHigh, SlashErrLow}, shellenv::{self, attach_tty, disable_reaping, enable_reaping, write_jobs, DisplayWaitStatus, HashFloat, Job, Slash, SlashVal}, SlashResult};
+use crate::{expand, path_cache, prelude::*, term::{self, Style}, utils};
+use crate::{utils::REGEX, error::{self, Recovery, SlashErr, SlashErrHigh, SlashErrLow}, shellenv::{self, attach_tty, disable_reaping, enable_reaping, write_jobs, DisplayWaitStatus, HashFloat, Job, Slash, SlashVal}, SlashResult};
 
 
 #[macro_export]
@@ -83,6 +83,10 @@ pub trait StrExtension {
 	fn split_twice(&self,left: &str, right: &str) -> Option<(String,String,String)>;
 	fn expand_globs(&self) -> Vec<String>;
 	fn consume_escapes(&self) -> String;
+	fn escape_dquote(&self) -> String;
+	fn dequote(&self) -> String;
+	fn literal_mask(&self) -> Vec<bool>;
+	fn to_glob_pattern(&self) -> Option<String>;
 }
 
 impl StrExtension for str {
@@ -126,8 +130,9 @@ impl StrExtension for str {
 	fn fill_from(&self, other: &str) -> String {
 		let mut result = self.to_string();
 		if self.len() < other.len() {
-			let clipped = other[self.len()..].to_string();
-			result = format!("{}{}",self,clipped);
+			// The byte offset can land inside a multi-byte character, so move forward to the next boundary
+			let start = (self.len()..other.len()).find(|&i| other.is_char_boundary(i)).unwrap_or(other.len());
+			result.push_str(&other[start..]);
 		}
 		result
 	}
@@ -189,6 +194,118 @@ impl StrExtension for str {
 		}
 		product
 	}
+	/// Escapes the characters that keep their meaning inside of double quotes
+	fn escape_dquote(&self) -> String {
+		let mut product = String::new();
+		for ch in self.chars() {
+			if matches!(ch, '\\' | '"' | '$' | '`') {
+				product.push('\\');
+			}
+			product.push(ch);
+		}
+		product
+	}
+	/// Removes one layer of quoting, respecting escapes
+	/// Unlike `trim_quotes`, escaped quotes are kept as literal characters
+	fn dequote(&self) -> String {
+		let mut result = String::new();
+		let mut chars = self.chars().peekable();
+		let mut opening_quote: Option<char> = None;
+
+		while let Some(ch) = chars.next() {
+			match ch {
+				'\\' => {
+					if let Some(next) = chars.peek().copied() {
+						let escapable = match opening_quote {
+							Some('\'') => next == '\'',
+							Some(_) => matches!(next, '\\' | '"' | '$' | '`'),
+							None => true
+						};
+						if escapable {
+							chars.next();
+							result.push(next);
+							continue
+						}
+					}
+					result.push(ch);
+				}
+				'\'' | '"' if opening_quote.is_none() => opening_quote = Some(ch),
+				'\'' | '"' if opening_quote == Some(ch) => opening_quote = None,
+				_ => result.push(ch)
+			}
+		}
+		result
+	}
+	/// Returns a mask with one entry per character of the string
+	/// A character is marked `true` if it is quoted, escaped, or is itself a quote or escape character
+	/// Expansions should treat marked characters literally
+	fn literal_mask(&self) -> Vec<bool> {
+		let mut mask = Vec::with_capacity(self.len());
+		let mut chars = self.chars();
+		let mut opening_quote: Option<char> = None;
+
+		while let Some(ch) = chars.next() {
+			match ch {
+				'\\' => {
+					mask.push(true);
+					if chars.next().is_some() {
+						mask.push(true);
+					}
+				}
+				'\'' | '"' if opening_quote.is_none() => {
+					opening_quote = Some(ch);
+					mask.push(true);
+				}
+				'\'' | '"' if opening_quote == Some(ch) => {
+					opening_quote = None;
+					mask.push(true);
+				}
+				_ => mask.push(opening_quote.is_some())
+			}
+		}
+		mask
+	}
+	/// Builds a glob pattern from a word, removing quotes and escaping any literal characters
+	/// Returns None if the word does not contain any unquoted glob characters
+	fn to_glob_pattern(&self) -> Option<String> {
+		let mut pattern = String::new();
+		let mut chars = self.chars().peekable();
+		let mut opening_quote: Option<char> = None;
+		let mut open_bracket = false;
+		let mut is_glob = false;
+
+		while let Some(ch) = chars.next() {
+			match ch {
+				'\\' => {
+					let esc_ch = chars.peek().copied();
+					let escapable = match opening_quote {
+						Some('\'') => esc_ch == Some('\''),
+						Some(_) => esc_ch.is_some_and(|esc| matches!(esc, '\\' | '"' | '$' | '`')),
+						None => esc_ch.is_some()
+					};
+					if escapable {
+						chars.next();
+						pattern.push_str(&glob::Pattern::escape(&esc_ch.unwrap().to_string()));
+					} else {
+						pattern.push_str(&glob::Pattern::escape("\\"));
+					}
+				}
+				'\'' | '"' if opening_quote.is_none() => opening_quote = Some(ch),
+				'\'' | '"' if opening_quote == Some(ch) => opening_quote = None,
+				_ if opening_quote.is_some() => pattern.push_str(&glob::Pattern::escape(&ch.to_string())),
+				_ => {
+					match ch {
+						'*' | '?' => is_glob = true,
+						'[' => open_bracket = true,
+						']' if open_bracket => is_glob = true,
+						_ => { /* Do nothing */ }
+					}
+					pattern.push(ch);
+				}
+			}
+		}
+		is_glob.then_some(pattern)
+	}
 	fn expand_globs(&self) -> Vec<String> {
 		let result = match glob::glob(self) {
 			Ok(paths) => {
@@ -394,6 +511,9 @@ impl StrExtension for str {
 }
 
 pub fn validate_autocd(slash: &mut Slash,argv: &VecDeque<String>) -> SlashResult<bool> {
+	if slash.is_posix() {
+		return Ok(false)
+	}
 	if slash.meta().get_shopt("core.autocd").is_ok_and(|opt| opt.parse::<bool>().unwrap()) && argv.len() == 1 {
 		let candidate = argv.front().unwrap();
 		Ok(Path::new(candidate).is_dir())
@@ -410,24 +530,28 @@ pub fn try_expansion<'a>(slash: &mut Slash,pair: Pair<'a,Rule>) -> SlashResult<S
 	}
 }
 
-pub fn try_glob(words: VecDeque<String>) -> VecDeque<String> {
+pub fn try_glob(slash: &Slash, words: VecDeque<String>) -> VecDeque<String> {
 	let mut globs = VecDeque::new();
-	for word in &words {
-		if !word.has_unescaped("*") && !word.has_unescaped("?") && !REGEX["glob_braces"].is_match(&word) {
-			return words
-		}
-		if let Ok(results) = glob::glob(&word) {
-			for entry in results {
-				if let Ok(path) = entry {
-					globs.push_back(path.to_str().unwrap().to_string());
-				}
+	for word in words {
+		// Quoted and escaped glob characters are matched literally
+		let pattern = match word.to_glob_pattern() {
+			Some(pattern) => pattern,
+			None => {
+				globs.push_back(word);
+				continue
 			}
+		};
+		let matches = expand::glob::glob_paths(&pattern, slash);
+		if matches.is_empty() {
+			globs.push_back(word);
+		} else {
+			globs.extend(matches);
 		}
 	}
 	globs
 }
 
-pub fn try_tilde(words: VecDeque<String>) -> VecDeque<String> {
+pub fn try_tilde(slash: &Slash, words: VecDeque<String>) -> VecDeque<String> {
 	let mut expanded = VecDeque::new();
 	for word in &words {
 		if !word.starts_with('~') {
@@ -439,35 +563,371 @@ pub fn try_tilde(words: VecDeque<String>) -> VecDeque<String> {
 			} else {
 				return words
 			}
+			let home = env::var("HOME").unwrap_or_default();
+			expanded.push_back(word.replacen("~", &home, 1).to_string());
+			continue
 		}
-		let home = env::var("HOME").unwrap_or_default();
-		expanded.push_back(word.replacen("~", &home, 1).to_string());
+		expanded.push_back(expand_tilde_prefix(slash, word).unwrap_or_else(|| word.to_string()));
 	}
 	expanded
 }
 
-pub fn try_brace(word: &str) -> VecDeque<String> {
-	// TODO: implement this
+/// The deepest level of bracket nesting in a piece of input, ignoring quoted and escaped brackets
+/// Used to reject pathological input before it reaches the parser, which recurses once per level
+pub fn nesting_depth(input: &str) -> usize {
+	let mut depth = 0usize;
+	let mut max_depth = 0;
+	let mut quote = None;
+	let mut chars = input.chars();
+	while let Some(ch) = chars.next() {
+		match (quote, ch) {
+			(Some('\''), '\'') => quote = None,
+			(Some('\''), _) => {}
+			(_, '\\') => { chars.next(); }
+			(Some('"'), '"') => quote = None,
+			(Some(_), _) => {}
+			(None, '\'' | '"') => quote = Some(ch),
+			(None, '(' | '{' | '[') => {
+				depth += 1;
+				max_depth = max_depth.max(depth);
+			}
+			(None, ')' | '}' | ']') => depth = depth.saturating_sub(1),
+			_ => {}
+		}
+	}
+	max_depth
+}
+
+/// Starts the word that holds an inlined heredoc body. Delimiters can't contain it, so heredocs that were already inlined are recognized and left alone
+const INLINED_HEREDOC: char = '\u{1}';
+
+/// A heredoc operator found on a line of input, along with the delimiter that ends its body
+struct HeredocStart {
+	/// Where the operator and its delimiter are in the line
+	span: std::ops::Range<usize>,
+	delim: String,
+	quoted: bool,
+	strip_tabs: bool
+}
+
+/// Tracks quotes and arithmetic across lines while looking for heredocs, so that `<<` is only taken as an operator where it is one
+#[derive(Default)]
+struct HeredocScan {
+	quote: Option<char>,
+	/// How many parentheses deep the scan is inside of `((`, where `<<` is a shift
+	arith_depth: usize
+}
+
+impl HeredocScan {
+	fn find_heredocs(&mut self, line: &str) -> Vec<HeredocStart> {
+		let mut found = vec![];
+		let mut chars = line.char_indices().peekable();
+		while let Some((i,ch)) = chars.next() {
+			match (self.quote, ch) {
+				(Some('\''), '\'') => self.quote = None,
+				(Some('\''), _) => {}
+				(_, '\\') => { chars.next(); }
+				(Some('"'), '"') => self.quote = None,
+				(Some(_), _) => {}
+				(None, '\'' | '"') => self.quote = Some(ch),
+				(None, '#') if i == 0 || line[..i].ends_with([' ','\t',';']) => break,
+				(None, '(') if self.arith_depth > 0 => self.arith_depth += 1,
+				(None, '(') if line[i..].starts_with("((") => {
+					chars.next();
+					self.arith_depth = 2;
+				}
+				(None, ')') => self.arith_depth = self.arith_depth.saturating_sub(1),
+				(None, '<') if self.arith_depth > 0 => {}
+				(None, '<') if line[i..].starts_with("<<<") => {
+					chars.next();
+					chars.next();
+				}
+				(None, '<') if line[i..].starts_with("<<") => {
+					chars.next();
+					let strip_tabs = chars.next_if(|(_,ch)| *ch == '-').is_some();
+					while chars.next_if(|(_,ch)| *ch == ' ' || *ch == '\t').is_some() {}
+					let start = chars.peek().map_or(line.len(), |(j,_)| *j);
+					let mut word_quote = None;
+					while let Some(&(_,ch)) = chars.peek() {
+						match (word_quote, ch) {
+							(None, ' ' | '\t' | '\n' | ';' | '|' | '&' | '<' | '>' | '(' | ')') => break,
+							(None, '\'' | '"') => word_quote = Some(ch),
+							(Some(quote), _) if quote == ch => word_quote = None,
+							_ => {}
+						}
+						chars.next();
+					}
+					let end = chars.peek().map_or(line.len(), |(j,_)| *j);
+					let raw = &line[start..end];
+					if raw.is_empty() || raw.strip_prefix('\'').is_some_and(|raw| raw.starts_with(INLINED_HEREDOC)) {
+						continue
+					}
+					found.push(HeredocStart {
+						span: i..end,
+						delim: raw.replace(['\'','"','\\'], ""),
+						quoted: raw.contains(['\'','"','\\']),
+						strip_tabs
+					});
+				}
+				_ => {}
+			}
+		}
+		found
+	}
+}
+
+/// Moves the body of each heredoc into the redirection that reads it, so that the parser never sees the body as commands
+/// `cat <<EOF`, the lines after it, and the closing `EOF` become a single quoted word holding the lines, which `heredoc_text()` reads back.
+/// Blank lines take the place of the closing delimiters, so the lines after a heredoc keep their line numbers.
+/// Returns None if a heredoc is missing its closing delimiter
+pub fn inline_heredocs(input: &str) -> Option<String> {
+	if !input.contains("<<") {
+		return Some(input.to_string())
+	}
+	let mut scan = HeredocScan::default();
+	let mut output = String::new();
+	let mut lines = input.split_inclusive('\n');
+	while let Some(line) = lines.next() {
+		let mut rest = 0;
+		let mut closing_lines = 0;
+		for heredoc in scan.find_heredocs(line) {
+			let mut body = String::new();
+			loop {
+				let body_line = lines.next()?;
+				let body_line = if heredoc.strip_tabs { body_line.trim_start_matches('\t') } else { body_line };
+				if body_line.trim_end_matches('\n') == heredoc.delim {
+					break
+				}
+				body.push_str(body_line);
+			}
+			closing_lines += 1;
+			// The body is stored in single quotes, so the characters that would end them are escaped
+			let encoded = body.replace('%', "%25").replace('\'', "%27");
+			let mode = if heredoc.quoted { 'q' } else { 'e' };
+			output.push_str(&line[rest..heredoc.span.start]);
+			output.push_str(&format!("<<'{}{}{}'",INLINED_HEREDOC,mode,encoded));
+			rest = heredoc.span.end;
+		}
+		output.push_str(&line[rest..]);
+		output.push_str(&"\n".repeat(closing_lines));
+	}
+	Some(output)
+}
+
+/// Reads the text of a heredoc back out of the word that `inline_heredocs()` stored it in
+/// The text is expanded unless the heredoc's delimiter was quoted
+pub fn heredoc_text(word: &str, slash: &mut Slash) -> SlashResult<String> {
+	let encoded = word.strip_prefix('\'').and_then(|word| word.strip_suffix('\'')).unwrap_or(word);
+	let mut chars = encoded.trim_start_matches(INLINED_HEREDOC).chars();
+	let mode = chars.next();
+	let mut text = String::new();
+	while let Some(ch) = chars.next() {
+		if ch != '%' {
+			text.push(ch);
+			continue
+		}
+		let code = chars.by_ref().take(2).collect::<String>();
+		match code.as_str() {
+			"27" => text.push('\''),
+			_ => text.push('%')
+		}
+	}
+	match mode {
+		Some('e') => expand::string::expand_heredoc(&text, slash),
+		_ => Ok(text)
+	}
+}
+
+/// In POSIX mode, the results of unquoted expansions are split into fields using IFS
+pub fn try_split(slash: &Slash, raw: &str, words: VecDeque<String>) -> VecDeque<String> {
+	let unquoted_expansion = !raw.contains(['"','\'']) && raw.contains(['$','`']);
+	if !slash.is_posix() || !unquoted_expansion {
+		return words
+	}
+	let ifs = slash.vars().get_var("IFS").map(|ifs| ifs.to_string()).unwrap_or(" \t\n".into());
+	if ifs.is_empty() {
+		return words
+	}
+	words.into_iter().flat_map(|word| split_fields(&word, &ifs)).collect()
+}
+
+/// Splits a word on the characters in `ifs`
+/// Runs of IFS whitespace count as a single delimiter, while every other IFS character delimits a field on its own
+pub fn split_fields(word: &str, ifs: &str) -> Vec<String> {
+	let mut fields = vec![];
+	let mut field = String::new();
+	let mut in_field = false;
+	let mut after_space = false;
+	for ch in word.chars() {
+		if !ifs.contains(ch) {
+			field.push(ch);
+			in_field = true;
+			after_space = false;
+		} else if ch.is_whitespace() {
+			if in_field {
+				fields.push(std::mem::take(&mut field));
+				in_field = false;
+				after_space = true;
+			}
+		} else {
+			// Whitespace next to a non-whitespace delimiter is part of the same delimiter
+			if in_field || !after_space {
+				fields.push(std::mem::take(&mut field));
+			}
+			in_field = false;
+			after_space = false;
+		}
+	}
+	if in_field {
+		fields.push(field);
+	}
+	fields
+}
+
+/// Quotes a word so that it can be reused as shell input, as `printf %q` and `${var@Q}` do
+/// Words made only of characters without special meaning are left alone. Otherwise the word is single quoted,
+/// with quotes and backslashes escaped outside of the quotes, since a backslash inside of them could escape the closing quote
+pub fn shell_quote(word: &str) -> String {
+	if word.is_empty() {
+		return "''".into()
+	}
+	if word.chars().all(|ch| ch.is_ascii_alphanumeric() || "_-./=:,+@%".contains(ch)) {
+		return word.to_string()
+	}
+	let mut quoted = String::new();
+	let mut in_quote = false;
+	for ch in word.chars() {
+		if matches!(ch, '\'' | '\\') {
+			if in_quote {
+				quoted.push('\'');
+				in_quote = false;
+			}
+			quoted.push('\\');
+		} else if !in_quote {
+			quoted.push('\'');
+			in_quote = true;
+		}
+		quoted.push(ch);
+	}
+	if in_quote {
+		quoted.push('\'');
+	}
+	quoted
+}
+
+/// Escapes each `#` that would start a comment, so that it is read as an ordinary character
+/// This is how `core.int_comments` is turned off for lines typed at the prompt, since the grammar always skips comments.
+/// A `#` only starts a comment at the start of a word, outside of quotes. `#!` is left alone, since it starts a subshell's shebang
+pub fn escape_comments(input: &str) -> String {
+	let mut output = String::with_capacity(input.len());
+	let mut chars = input.chars().peekable();
+	let (mut in_squote, mut in_dquote) = (false,false);
+	let mut word_start = true;
+	while let Some(ch) = chars.next() {
+		match ch {
+			'\\' if !in_squote => {
+				output.push(ch);
+				if let Some(escaped) = chars.next() {
+					output.push(escaped);
+				}
+				word_start = false;
+				continue
+			}
+			'\'' if !in_dquote => in_squote = !in_squote,
+			'"' if !in_squote => in_dquote = !in_dquote,
+			'#' if word_start && !in_squote && !in_dquote && chars.peek() != Some(&'!') => output.push('\\'),
+			_ => {}
+		}
+		output.push(ch);
+		word_start = !in_squote && !in_dquote && (ch.is_whitespace() || matches!(ch, ';' | '&' | '|' | '(' | ')' | '<' | '>'));
+	}
+	output
+}
+
+/// Removes each backslash that ends a line, which joins the line to the next one, so that long commands can be split across lines
+/// Backslashes in single quotes and comments are left alone. The newlines that were removed are put back after the end of the joined line,
+/// so the lines that follow it keep their line numbers
+pub fn join_continued_lines(input: &str) -> String {
+	if !input.contains("\\\n") {
+		return input.to_string()
+	}
+	let mut output = String::with_capacity(input.len());
+	let mut chars = input.chars();
+	let (mut in_squote, mut in_dquote, mut in_comment) = (false,false,false);
+	let mut word_start = true;
+	let mut joined = 0;
+	while let Some(ch) = chars.next() {
+		match ch {
+			'\\' if !in_squote && !in_comment => {
+				match chars.next() {
+					Some('\n') => joined += 1,
+					Some(escaped) => {
+						output.push(ch);
+						output.push(escaped);
+						word_start = false;
+					}
+					None => output.push(ch)
+				}
+				continue
+			}
+			'\n' if !in_squote && !in_dquote => {
+				in_comment = false;
+				output.push(ch);
+				output.push_str(&"\n".repeat(joined));
+				joined = 0;
+				word_start = true;
+				continue
+			}
+			'\'' if !in_dquote && !in_comment => in_squote = !in_squote,
+			'"' if !in_squote && !in_comment => in_dquote = !in_dquote,
+			'#' if word_start && !in_squote && !in_dquote => in_comment = true,
+			_ => {}
+		}
+		output.push(ch);
+		word_start = !in_squote && !in_dquote && (ch.is_whitespace() || matches!(ch, ';' | '&' | '|' | '(' | ')' | '<' | '>'));
+	}
+	output
+}
+
+/// Whether the last line of some input ends in a backslash that continues it on the next line
+pub fn ends_in_continuation(input: &str) -> bool {
+	// The newline after a continuation is removed, and only put back once another line ends
+	!join_continued_lines(&format!("{}\n",input)).ends_with('\n')
+}
+
+pub fn try_brace(slash: &mut Slash, words: VecDeque<String>) -> SlashResult<VecDeque<String>> {
+	if slash.is_posix() {
+		return Ok(words)
+	}
+	let limit = slash.meta().borrow_shopts().core.max_brace_expand;
 	let mut unpacked = VecDeque::new();
-	unpacked
+	for word in words {
+		if !word.contains('{') {
+			unpacked.push_back(word);
+			continue
+		}
+		unpacked.extend(expand::brace::expand_braces(&word, limit)?);
+	}
+	Ok(unpacked)
 }
 
 pub fn prepare_argv<'a>(pair: Pair<'a,Rule>,slash: &mut Slash) -> SlashResult<VecDeque<String>> {
 	let mut args = VecDeque::new();
 	let mut inner = pair.into_inner().filter(|pr| matches!(pr.as_rule(), Rule::cmd_name | Rule::arg_assign | Rule::word));
 	while let Some(pair) = inner.next() {
-		let word = pair.as_str().trim_quotes().to_string();
+		let raw = pair.as_str().to_string();
+		// A word without expansions is still the text that was typed, so its quotes and escapes can be removed properly
+		let has_expansions = pair.contains_rules(&[Rule::expand_word,Rule::dquoted][..]);
+		let remove_quotes = |word: String| if has_expansions { word.trim_quotes() } else { word.dequote() };
 		let expanded = VecDeque::from(vec![try_expansion(slash,pair)?]);
-		let expanded_ext = try_glob(expanded.clone());
-		let expanded_ext = try_tilde(expanded_ext);
+		let expanded = try_split(slash, &raw, expanded);
+		let expanded = try_brace(slash, expanded)?;
+		let expanded_ext = try_glob(slash, expanded.clone());
+		let expanded_ext = try_tilde(slash, expanded_ext);
 		if !expanded_ext.is_empty() {
-			for word in expanded_ext {
-				args.push_back(word.trim_quotes());
-			}
+			args.extend(expanded_ext.into_iter().map(remove_quotes));
 		} else {
-			for word in expanded {
-				args.push_back(word.trim_quotes());
-			}
+			args.extend(expanded.into_iter().map(remove_quotes));
 		}
 	}
 	Ok(args)
@@ -484,6 +944,7 @@ pub fn get_pipeline_cmd<'a>(pair: Pair<'a,Rule>) -> SlashResult<String> {
 			let shell_cmd = pair.step(1).unpack()?;
 			match shell_cmd.as_rule() {
 				Rule::for_cmd => "for".into(),
+				Rule::select_cmd => "select".into(),
 				Rule::if_cmd => "if".into(),
 				Rule::match_cmd => "match".into(),
 				Rule::loop_cmd => {
@@ -492,6 +953,8 @@ pub fn get_pipeline_cmd<'a>(pair: Pair<'a,Rule>) -> SlashResult<String> {
 					loop_kind.into()
 				}
 				Rule::subshell => "anonymous subshell".into(),
+				Rule::coproc_cmd => "coproc".into(),
+				Rule::brace_grp => "brace group".into(),
 				_ => todo!("shell cmd kind '{:?}'", shell_cmd.as_rule())
 			}
 		}
@@ -499,16 +962,75 @@ pub fn get_pipeline_cmd<'a>(pair: Pair<'a,Rule>) -> SlashResult<String> {
 	})
 }
 
-pub fn prepare_redirs<'a>(pair: Pair<'a,Rule>) -> SlashResult<VecDeque<utils::Redir>> {
-	let mut results = pair.filter(Rule::redir).into_iter().map(|pr| utils::Redir::from_pair(pr)).collect::<VecDeque<_>>();
+pub fn prepare_redirs<'a>(pair: Pair<'a,Rule>, slash: &mut Slash) -> SlashResult<VecDeque<utils::Redir>> {
 	let mut redirs = VecDeque::new();
-	while let Some(result) = results.pop_front() {
-		let extracted = result?;
-		redirs.push_back(extracted);
+	for redir in pair.filter(Rule::redir) {
+		redirs.push_back(prepare_redir(redir, slash)?);
 	}
 	Ok(redirs)
 }
 
+/// Builds a redirection, expanding its filename
+/// The filename goes through the same expansions as an argument, except that it is never split into several words.
+/// Brace expansion and globbing have to produce exactly one word, or the redirection is ambiguous
+pub fn prepare_redir<'a>(pair: Pair<'a,Rule>, slash: &mut Slash) -> SlashResult<utils::Redir> {
+	let mut redir = utils::Redir::from_pair(pair.clone())?;
+	let Some(file) = pair.scry(Rule::file) else {
+		return Ok(redir)
+	};
+	let Some(word) = file.into_inner().next() else {
+		return Ok(redir)
+	};
+	if word.as_rule() == Rule::proc_sub {
+		// The command is given the fifo that the substituted command reads from or writes to
+		redir.set_file_target(PathBuf::from(expand::cmdsub::expand_proc_sub(word, slash)?));
+		return Ok(redir)
+	}
+	if redir.redir_type() == Rule::heredoc {
+		redir.set_file_target(PathBuf::from(heredoc_text(word.as_str(), slash)?));
+		return Ok(redir)
+	}
+	let raw = word.as_str().to_string();
+	let expanded = VecDeque::from(vec![try_expansion(slash, word.clone())?]);
+	let expanded = try_brace(slash, expanded)?;
+	if expanded.len() != 1 {
+		return Err(High(SlashErrHigh::redir_err(format!("{}: ambiguous redirect", raw), word)))
+	}
+	let globbed = if slash.is_posix() { expanded.clone() } else { try_glob(slash, expanded.clone()) };
+	let target = match globbed.len() {
+		1 => globbed,
+		_ => expanded
+	};
+	let target = try_tilde(slash, target).pop_front().unwrap_or_default();
+	redir.set_file_target(PathBuf::from(target.trim_quotes()));
+	Ok(redir)
+}
+
+/// Checks a variable name given to a builtin as an argument, like the names passed to `read`
+/// This keeps builtins from assigning to special parameters like `?` and `#`
+pub fn check_var_name<'a>(name: &str, builtin: &str, blame: Pair<'a,Rule>) -> SlashResult<()> {
+	let mut chars = name.chars();
+	let valid_start = chars.next().is_some_and(|ch| ch.is_ascii_alphabetic() || ch == '_');
+	if !valid_start || !chars.all(|ch| ch.is_ascii_alphanumeric() || ch == '_' || ch == '.') {
+		return Err(High(SlashErrHigh::syntax_err(format!("{}: `{}': not a valid identifier", builtin, name), blame)))
+	}
+	Ok(())
+}
+
+/// Resolves the descriptor given to a builtin's `-u` option, like `read -u 3`
+/// Returns a duplicate, so dropping it leaves the original descriptor open for later commands
+pub fn user_fd<'a>(arg: Option<String>, builtin: &str, blame: Pair<'a,Rule>) -> SlashResult<utils::SmartFD> {
+	let Some(arg) = arg else {
+		return Err(High(SlashErrHigh::syntax_err(format!("{}: -u requires a file descriptor", builtin), blame)))
+	};
+	let fd = arg.parse::<RawFd>().ok().filter(|fd| *fd >= 0);
+	let Some(fd) = fd.filter(|fd| fcntl(*fd, F_GETFD).is_ok()) else {
+		return Err(High(SlashErrHigh::exec_err(format!("{}: {}: invalid file descriptor", builtin, arg), blame)))
+	};
+	let fd = nix::unistd::dup(fd).map_err(|_| Low(SlashErrLow::from_io()))?;
+	utils::SmartFD::new(fd)
+}
+
 pub fn handle_nested(open: &str, close: &str, haystack: &mut VecDeque<char>) -> String {
 	let mut count = 1;
 	let mut result = String::new();
@@ -689,16 +1211,186 @@ pub fn slice_completion(line: &str, candidate: &str) -> String {
 	}
 }
 
-pub fn which(slash: &mut Slash,command: &str) -> Option<String> {
-	if let Some(env_path) = slash.vars().get_evar("PATH") {
-		for path in env::split_paths(&env_path) {
-			let full_path = path.join(command);
-			if full_path.is_file() && is_exec(&full_path) {
-				return Some(full_path.to_string_lossy().to_string())
+/// The shell's current `PATH`, which may differ from the process environment if it was assigned without being exported
+/// Falls back to the inherited `PATH` if the shell hasn't set one
+pub fn shell_path(slash: &Slash) -> String {
+	slash.vars().get_var("PATH")
+		.map(|path| path.to_string())
+		.or_else(|| env::var("PATH").ok())
+		.unwrap_or_default()
+}
+
+/// The history file, named by `HIST_FILE`, or `~/.slash_hist` if it isn't set
+pub fn hist_file(slash: &Slash) -> PathBuf {
+	slash.vars().get_var("HIST_FILE")
+		.map(|path| PathBuf::from(path.to_string()))
+		.filter(|path| !path.as_os_str().is_empty())
+		.unwrap_or_else(|| PathBuf::from(format!("{}/.slash_hist", env::var("HOME").unwrap_or_default())))
+}
+
+/// Finds the directory that `cd` or `pushd` should change to, searching `CDPATH` for relative paths that don't start with `.`
+/// Returns the directory and whether it was found through `CDPATH`
+pub fn cd_target(slash: &Slash, arg: &str) -> (PathBuf, bool) {
+	if arg.starts_with(['/', '.']) {
+		return (PathBuf::from(arg), false)
+	}
+	let cd_path = slash.vars().get_var("CDPATH").map(|val| val.to_string()).unwrap_or_default();
+	for dir in cd_path.split(':').filter(|dir| !dir.is_empty()) {
+		let candidate = Path::new(dir).join(arg);
+		if candidate.is_dir() {
+			return (candidate, true)
+		}
+	}
+	(PathBuf::from(arg), false)
+}
+
+/// The current directory as the shell sees it: `PWD`, as long as it is an absolute path to the directory the shell is actually in
+/// Otherwise the real path of the current directory
+pub fn logical_cwd(slash: &Slash) -> PathBuf {
+	use std::os::unix::fs::MetadataExt;
+	let same_file = |a: &Path, b: &Path| match (fs::metadata(a), fs::metadata(b)) {
+		(Ok(a), Ok(b)) => a.dev() == b.dev() && a.ino() == b.ino(),
+		_ => false
+	};
+	match slash.vars().get_var("PWD").map(|pwd| PathBuf::from(pwd.to_string())) {
+		Some(pwd) if pwd.is_absolute() && same_file(&pwd, Path::new(".")) => pwd,
+		_ => env::current_dir().unwrap_or_default()
+	}
+}
+
+/// Joins `path` onto `base` without touching the filesystem
+/// `.` is dropped and `..` removes the component before it, so symlinks are left as they were written
+pub fn logical_path(base: &Path, path: &Path) -> PathBuf {
+	use std::path::Component;
+	let mut logical = PathBuf::from("/");
+	for component in base.join(path).components() {
+		match component {
+			Component::ParentDir => {
+				logical.pop();
 			}
+			Component::Normal(name) => logical.push(name),
+			Component::RootDir | Component::CurDir | Component::Prefix(_) => {}
 		}
 	}
-	None
+	logical
+}
+
+/// The directory stack as `dirs` shows it: the current directory first, then the directories saved by `pushd`, most recent first
+pub fn dir_stack(slash: &Slash) -> Vec<PathBuf> {
+	let cwd = slash.vars().get_evar("PWD").map(PathBuf::from).or_else(|| env::current_dir().ok()).unwrap_or_default();
+	let mut stack = vec![cwd];
+	stack.extend(slash.meta().saved_dirs().iter().rev().cloned());
+	stack
+}
+
+/// The position in `dirs` of an entry given as `+N` or `-N`, counted from the top or from the bottom
+pub fn dir_stack_index(slash: &Slash, arg: &str) -> Option<usize> {
+	let len = slash.meta().saved_dirs().len() + 1;
+	let index = arg.get(1..)?.parse::<usize>().ok().filter(|index| *index < len)?;
+	match arg.chars().next()? {
+		'+' => Some(index),
+		'-' => Some(len - 1 - index),
+		_ => None
+	}
+}
+
+/// An entry of the directory stack given as `+N` or `-N`
+pub fn dir_stack_entry(slash: &Slash, arg: &str) -> Option<PathBuf> {
+	dir_stack(slash).into_iter().nth(dir_stack_index(slash, arg)?)
+}
+
+/// Expands the tilde prefix of a word, which runs up to the first slash
+/// `~` is `HOME` and `~user` is that user's home directory, `~+` and `~-` are `PWD` and `OLDPWD`,
+/// and `~+N` and `~-N` are entries of the directory stack. Returns `None` if the prefix doesn't name anything
+pub fn expand_tilde_prefix(slash: &Slash, word: &str) -> Option<String> {
+	let rest = word.strip_prefix('~')?;
+	let (prefix, path) = rest.split_at(rest.find('/').unwrap_or(rest.len()));
+	let dir = match prefix {
+		"" => env::var("HOME").ok()?,
+		"+" => slash.vars().get_evar("PWD")?,
+		"-" => slash.vars().get_evar("OLDPWD")?,
+		_ if prefix.starts_with(['+', '-']) => dir_stack_entry(slash, prefix)?.to_string_lossy().to_string(),
+		user => nix::unistd::User::from_name(user).ok()??.dir.to_string_lossy().to_string()
+	};
+	Some(format!("{}{}", dir, path))
+}
+
+/// Every path that a command name could refer to, in search order, whether or not it can be executed
+/// Names containing a slash are not searched for
+fn path_candidates(slash: &Slash, command: &str) -> Vec<PathBuf> {
+	if command.is_empty() {
+		return vec![]
+	}
+	if command.contains('/') {
+		return vec![PathBuf::from(command)]
+	}
+	env::split_paths(&shell_path(slash))
+		.map(|dir| dir.join(command))
+		.collect()
+}
+
+/// Searches the shell's `PATH` for an executable file, skipping anything that can't be executed
+/// Names containing a slash are not searched for, and are only checked for being executable
+/// The contents of each directory come from `path_cache`, so repeated lookups don't touch every directory
+pub fn search_path(slash: &Slash, command: &str) -> Option<PathBuf> {
+	if command.is_empty() {
+		return None
+	}
+	if command.contains('/') {
+		return Some(PathBuf::from(command)).filter(|path| is_exec(path))
+	}
+	path_cache::find(&shell_path(slash), command)
+}
+
+/// The `PATH` that finds every standard utility, as given by `confstr(_CS_PATH)`. Used by `command -p`
+pub fn default_path() -> String {
+	let fallback = || "/usr/bin:/bin".to_string();
+	let mut buf = vec![0u8; 256];
+	let len = unsafe { libc::confstr(libc::_CS_PATH, buf.as_mut_ptr() as *mut libc::c_char, buf.len()) };
+	// The length includes the terminating null byte, and is zero if there is no value
+	if len == 0 || len > buf.len() {
+		return fallback()
+	}
+	buf.truncate(len - 1);
+	String::from_utf8(buf).unwrap_or_else(|_| fallback())
+}
+
+/// Searches the given `PATH` instead of the shell's, without touching the hash table
+pub fn search_dirs(path: &str, command: &str) -> Option<PathBuf> {
+	path_cache::find(path, command)
+}
+
+/// Every executable file in the shell's `PATH` that a command name matches, like `type -a` shows
+pub fn search_path_all(slash: &Slash, command: &str) -> Vec<PathBuf> {
+	path_candidates(slash, command).into_iter().filter(|path| is_exec(path)).collect()
+}
+
+/// Finds something that a command name refers to but that can't be run, like a directory or a file without execute permission
+/// Used to report "Permission denied" instead of "command not found" when no executable matches
+pub fn find_unrunnable(slash: &Slash, command: &str) -> Option<PathBuf> {
+	path_candidates(slash, command).into_iter().find(|path| path.exists() && !is_exec(path))
+}
+
+/// Resolves a command name to the executable that would be run for it
+/// Results are remembered in the command hash table, which is cleared whenever `PATH` changes
+pub fn which(slash: &mut Slash,command: &str) -> Option<String> {
+	if command.contains('/') {
+		return search_path(slash, command).map(|path| path.to_string_lossy().to_string())
+	}
+	let path = shell_path(slash);
+	if slash.logic().hashed_path() != path {
+		slash.logic_mut().clear_hashed(&path);
+	}
+	// A path given with `hash -p` is used even if it can't be run, so that running it reports why
+	if slash.logic().is_pinned(command) {
+		return slash.logic().get_hashed(command).map(|pinned| pinned.to_string_lossy().to_string())
+	}
+	if let Some(hashed) = slash.logic().get_hashed(command).filter(|hashed| is_exec(hashed)) {
+		return Some(hashed.to_string_lossy().to_string())
+	}
+	let found = search_path(slash, command)?;
+	slash.logic_mut().hash_cmd(command, found.clone());
+	Some(found.to_string_lossy().to_string())
 }
 
 pub fn is_exec(path: &Path) -> bool {
@@ -708,7 +1400,8 @@ pub fn is_exec(path: &Path) -> bool {
 }
 
 pub fn write_alias(slash: &mut Slash,alias: &str, body: &str) -> SlashResult<()> {
-	if slash.logic().get_func(alias).is_some() {
+	// A readonly function stays defined underneath the alias
+	if slash.logic().get_func(alias).is_some() && !slash.logic().func_attrs(alias).contains(shellenv::FuncAttrs::READONLY) {
 		slash.logic_mut().remove_func(alias);
 	}
 	slash.logic_mut().new_alias(alias, body.into());
@@ -716,30 +1409,49 @@ pub fn write_alias(slash: &mut Slash,alias: &str, body: &str) -> SlashResult<()>
 }
 
 pub fn write_func(slash: &mut Slash,func: &str, body: &str) -> SlashResult<()> {
+	if slash.logic().func_attrs(func).contains(shellenv::FuncAttrs::READONLY) {
+		return Err(Low(SlashErrLow::ExecFailed(format!("{}: readonly function",func))))
+	}
 	if slash.logic().get_alias(func).is_some() {
 		slash.logic_mut().remove_alias(func);
 	}
-	slash.logic_mut().new_func(func, body);
+	let source = slash.vars().current_source();
+	slash.logic_mut().new_func(func, body, &source);
 	Ok(())
 }
 
 pub fn unset_var_conflicts(slash: &mut Slash,key: &str) -> SlashResult<()> {
-	if slash.vars().get_var(key).is_some() {
-		slash.vars_mut().unset_var(key)
-	}
-	if slash.vars().get_evar(key).is_some() {
-		std::env::remove_var(key);
-		slash.vars_mut().unset_evar(key);
-	}
-
+	slash.vars_mut().unset_var(key);
 	Ok(())
 }
 
 pub fn handle_fg(slash: &mut Slash, job: Job) -> SlashResult<()> {
 	let mut code = 0;
-	attach_tty(job.pgid())?;
+	let mut pipe_status = vec![];
+	let mut killed_by_sigint = false;
+	let pgid = job.pgid();
+	// The job may change the terminal's modes, like an editor switching to raw mode, so the shell's own are put back afterwards
+	let shell_modes = shellenv::get_tty_modes();
+	attach_tty(pgid)?;
 	disable_reaping();
-	let statuses = write_jobs(|j| j.new_fg(job))??;
+	let statuses = match write_jobs(|j| j.new_fg(job)).and_then(|result| result) {
+		Ok(statuses) => statuses,
+		Err(e) => {
+			// Waiting went wrong, but the shell still needs its terminal and its reaper back
+			attach_tty(getpgrp()).ok();
+			if let Some(modes) = &shell_modes {
+				shellenv::set_tty_modes(modes);
+			}
+			write_jobs(|j| j.reset_fg()).ok();
+			enable_reaping()?;
+			return Err(e)
+		}
+	};
+	// A stopped job keeps the modes it was using, so that resuming it with `fg` doesn't leave it with the shell's
+	let job_modes = statuses.iter()
+		.any(|status| matches!(status, WaitStatus::Stopped(..)))
+		.then(shellenv::get_tty_modes)
+		.flatten();
 	for status in statuses {
 		match status {
 			WaitStatus::Exited(_, exit_code) => {
@@ -750,21 +1462,54 @@ pub fn handle_fg(slash: &mut Slash, job: Job) -> SlashResult<()> {
 				code = utils::SIG_EXIT_OFFSET + sig as i32;
 			},
 			WaitStatus::Signaled(pid, sig, _) => {
+				killed_by_sigint |= sig == nix::sys::signal::Signal::SIGINT;
 				crate::signal::handle_child_signal(pid, sig)?;
 				code = utils::SIG_EXIT_OFFSET + sig as i32;
 			},
 			_ => { /* Do nothing */ }
 		}
+		pipe_status.push(SlashVal::Int(code));
+	}
+	crate::signal::settle_fg_interrupt(killed_by_sigint);
+	if job_modes.is_some() {
+		write_jobs(|j| {
+			if let Some(job) = j.query_mut(shellenv::JobID::Pgid(pgid)) {
+				job.set_tty_modes(job_modes);
+			}
+		})?;
 	}
 	attach_tty(getpgrp())?;
+	if let Some(modes) = &shell_modes {
+		shellenv::set_tty_modes(modes);
+	}
+	// Each process in the job gets an entry, so failures in the middle of a pipeline can be inspected
+	slash.vars_mut().set_var("PIPESTATUS", SlashVal::Array(pipe_status));
 	slash.set_code(code);
 	write_jobs(|j| {
-		j.update_job_statuses().unwrap();
+		j.update_job_statuses().ok();
 		j.reset_fg();
 	})?;
 	enable_reaping()
 }
 
+/// Registers a job that was started in the background
+/// The job is added to the job table and the pid of its last process is stored in `$!`
+/// Disowned jobs only set `$!`
+pub fn handle_bg(slash: &mut Slash, job: Job) -> SlashResult<()> {
+	if let Some(pid) = job.get_pids().last() {
+		slash.vars_mut().set_param("!", &pid.to_string());
+	}
+	if !slash.ctx().flags().contains(utils::ExecFlags::DISOWN) {
+		let child_max = slash.meta().borrow_shopts().core.child_max;
+		write_jobs(|j| {
+			j.retire_finished(child_max);
+			j.insert_job(job,false)
+		})??;
+	}
+	slash.set_code(0);
+	Ok(())
+}
+
 pub fn extract_return<T>(result: &SlashResult<T>) -> SlashResult<i32> {
 	match result {
 		Ok(_) => Ok(0),
@@ -895,8 +1640,18 @@ pub fn capture_ansi_escape(chars: &mut VecDeque<char>) -> String {
 	sequence
 }
 
-pub fn handle_prompt_hidegroup<'a>() -> SlashResult<String> {
-	todo!()
+/// Expands a `\[ ... \]` group, and wraps the result in markers so that it isn't counted towards the prompt width
+pub fn handle_prompt_hidegroup(slash: &mut Slash,pair: Pair<Rule>) -> SlashResult<String> {
+	let offset = pair.as_span().start();
+	let mut group = pair.as_str().to_string();
+	for esc in pair.into_inner().rev() {
+		let span = esc.as_span();
+		let expanded = expand::misc::expand_esc(slash,esc)?;
+		group.replace_range(span.start() - offset..span.end() - offset, &expanded);
+	}
+	// Slice off the \[ and \] delimiters
+	let inner = &group[2..group.len() - 2];
+	Ok(format!("{}{inner}{}",term::IGNORE_START,term::IGNORE_END))
 }
 
 pub fn format_cmd_runtime(dur: std::time::Duration) -> String {
@@ -919,6 +1674,9 @@ pub fn format_cmd_runtime(dur: std::time::Duration) -> String {
 		let millis = dur.as_millis();
 		return format!("{}ms",millis);
 	}
+	if seconds < 60 {
+		return format!("{:.1}s",dur.as_secs_f64());
+	}
 	if seconds >= 60 {
 		minutes = seconds / 60;
 		seconds %= 60;
@@ -1062,48 +1820,170 @@ pub fn format_cmd_runtime(dur: std::time::Duration) -> String {
 	result.join(" ")
 }
 
-pub fn escseq_cmdtime<'a>() -> SlashResult<String> {
-	Ok(env::var("OX_CMD_TIME").unwrap_or_default())
+/// Runs the ERR trap and applies `set -e` after a command fails
+/// Failures in the condition of an `if` or `while` don't count, and neither do failures of commands followed by `&&` or `||`
+pub fn handle_cmd_failure(slash: &mut Slash) -> SlashResult<()> {
+	if slash.ctx().flags().contains(utils::ExecFlags::IN_COND) {
+		return Ok(())
+	}
+	let status = slash.get_status();
+	slash.run_trap("ERR")?;
+	if slash.meta().flags().contains(shellenv::EnvFlags::EXIT_ON_ERROR) {
+		return Err(SlashErr::Low(SlashErrLow::CleanExit(status)))
+	}
+	Ok(())
+}
+
+/// Reports an error that stopped a command, and sets the exit status that the error's category calls for
+/// Errors that only carry control flow, like `exit`, are left alone
+pub fn report_err(slash: &mut Slash, err: &SlashErr) {
+	let Some(category) = err.category() else {
+		return
+	};
+	print_err(slash, err);
+	slash.set_code(category.policy().code);
+}
+
+fn print_err(slash: &Slash, err: &SlashErr) {
+	let tab_stop = slash.meta().borrow_shopts().prompt.tab_stop;
+	let msg = error::expand_diagnostic_tabs(&err.to_string(), tab_stop);
+	eprintln!("{}",term::paint(Style::Error, &msg));
+}
+
+/// Applies the recovery policy to an error raised by a top level command
+/// Errors that abort the input are handed back untouched. Anything else is reported, and the recovery is returned so the caller can carry on
+/// A `return`, `break`, or `continue` that gets this far had nothing to leave, so it is reported and the input carries on
+pub fn recover_err(slash: &mut Slash, err: SlashErr) -> SlashResult<Recovery> {
+	let interactive = slash.meta().flags().contains(shellenv::EnvFlags::INTERACTIVE);
+	let (code,recovery) = match err.category() {
+		Some(category) => (category.policy().code, category.policy().recovery(interactive)),
+		None if matches!(err.low(), SlashErrLow::FuncReturn(_) | SlashErrLow::LoopCont | SlashErrLow::LoopBreak(_)) => (1, Recovery::Continue),
+		None => return Err(err)
+	};
+	if recovery == Recovery::Abort {
+		return Err(err)
+	}
+	// Code that wasn't read from a file is named after where it came from instead, if it has a name
+	let source = slash.vars().current_source();
+	let source = if source.is_empty() { slash.meta().input_name().unwrap_or_default().to_string() } else { source };
+	let err = if source.is_empty() { err } else { err.attribute_to(&source) };
+	print_err(slash, &err);
+	slash.set_code(code);
+	Ok(recovery)
+}
+
+/// Takes a snapshot of the open descriptors before a command runs, if `core.fd_audit` is enabled
+pub fn fd_audit_start(slash: &Slash) -> Option<Vec<RawFd>> {
+	slash.meta().borrow_shopts().core.fd_audit.then(utils::list_open_fds)
+}
+
+/// Reports every descriptor that was opened since `fd_audit_start()` and is still open
+/// Descriptors that `exec` opened on purpose are left out. The leaked descriptors are returned
+pub fn fd_audit_finish(slash: &Slash, before: Option<Vec<RawFd>>) -> Vec<RawFd> {
+	let Some(before) = before else {
+		return vec![]
+	};
+	let leaked = utils::list_open_fds().into_iter()
+		.filter(|fd| !before.contains(fd) && !slash.meta().open_fds().contains_key(fd))
+		.collect::<Vec<_>>();
+	for fd in &leaked {
+		let target = fs::read_link(format!("/proc/self/fd/{}",fd)).map(|path| path.display().to_string()).unwrap_or_default();
+		eprintln!("{}",term::paint(Style::Warning, &format!("fd audit: descriptor {} was left open ({})",fd,target)));
+	}
+	leaked
+}
+
+/// The duration of the last command, or nothing if it finished faster than `prompt.cmd_time_threshold`
+/// Combined with a context group, e.g. `\(took \D\)`, slow commands can be called out without cluttering the prompt after fast ones
+pub fn escseq_cmdtime<'a>(slash: &Slash) -> SlashResult<String> {
+	let threshold = slash.meta().get_shopt("prompt.cmd_time_threshold")?.parse::<u128>().unwrap_or(0);
+	match slash.meta().get_cmd_duration() {
+		Some(dur) if dur.as_millis() >= threshold => Ok(format_cmd_runtime(dur)),
+		_ => Ok(String::new())
+	}
 }
 
 pub fn escseq_custom(slash: &mut Slash,query: &str) -> SlashResult<String> {
+	#[cfg(feature = "wasm-plugins")]
+	if let Some(segment) = query.strip_prefix("plugin.") {
+		return crate::plugin::prompt_segment(slash, segment)
+	}
+	if let Some(name) = query.strip_prefix("theme.") {
+		// Lets prompts use theme colors, e.g. `\{theme.prompt.path\}`
+		let style = Style::from_name(name)
+			.ok_or_else(|| SlashErr::Low(SlashErrLow::ExecFailed(format!("Unknown theme style: {name}"))))?;
+		return Ok(term::sgr(style))
+	}
 	let command = slash.meta().get_shopt(&format!("prompt.custom.{query}"))?;
-	let cmd_sub = format!("$({command})");
-	let parsed = SlashParse::parse(Rule::cmd_sub, &cmd_sub)
-		.map_err(|e| SlashErr::Low(SlashErrLow::Parse(e.to_string())))?
-		.into_iter()
-		.next()
-		.unpack()?;
-	expand::cmdsub::expand_cmd_sub(parsed,slash)
+	// A sequence that doesn't finish within prompt.hook_timeout expands to nothing
+	let timeout = hook_timeout(slash);
+	let status = slash.get_status();
+	let output = slash.exec_hook(&command.consume_escapes(), timeout)?;
+	slash.set_code(status);
+	Ok(output.map(|output| output.trim().to_string()).unwrap_or_default())
+}
+
+/// How long custom prompt escapes are given to finish, from `prompt.hook_timeout`
+pub fn hook_timeout(slash: &Slash) -> Option<std::time::Duration> {
+	match slash.meta().borrow_shopts().prompt.hook_timeout {
+		0 => None,
+		millis => Some(std::time::Duration::from_millis(millis as u64))
+	}
+}
+
+/// What an interactive shell prints when `TMOUT` runs out
+pub const AUTO_LOGOUT_MSG: &str = "timed out waiting for input: auto-logout";
+
+/// How long the shell waits for input before logging out, from `TMOUT`
+/// Leaving it unset, setting it to zero, or setting it to anything other than a whole number of seconds disables the limit
+pub fn input_timeout(slash: &Slash) -> Option<std::time::Duration> {
+	let secs = slash.vars().get_var("TMOUT")?.to_string().trim().parse::<u64>().ok()?;
+	(secs > 0).then(|| std::time::Duration::from_secs(secs))
+}
+
+/// Waits for `fd` to become readable, for at most `timeout`
+/// Returns false if the time ran out first
+pub fn wait_for_input(fd: RawFd, timeout: std::time::Duration) -> SlashResult<bool> {
+	let deadline = std::time::Instant::now() + timeout;
+	loop {
+		let remaining = deadline.saturating_duration_since(std::time::Instant::now()).as_millis().min(libc::c_int::MAX as u128) as libc::c_int;
+		let mut poll_fd = libc::pollfd { fd, events: libc::POLLIN, revents: 0 };
+		match unsafe { libc::poll(&mut poll_fd, 1, remaining) } {
+			// Timeouts too long for poll() are waited out in several calls
+			0 if std::time::Instant::now() < deadline => continue,
+			0 => return Ok(false),
+			ready if ready > 0 => return Ok(true),
+			_ if Errno::last() == Errno::EINTR => crate::signal::check_interrupt()?,
+			_ => return Err(Low(SlashErrLow::from_io()))
+		}
+	}
 }
 
 pub fn escseq_exitcode<'a>(slash: &mut Slash) -> SlashResult<String> {
-	Ok(slash.vars().get_param("?").unwrap_or_default())
+	Ok(slash.prompt_status().to_string())
+}
+
+/// How many directories `pushd` has saved, or nothing when the stack is empty
+pub fn escseq_dir_depth(slash: &Slash) -> String {
+	match slash.meta().saved_dirs().len() {
+		0 => String::new(),
+		depth => depth.to_string()
+	}
 }
 
 pub fn escseq_success<'a>(slash: &mut Slash) -> SlashResult<String> {
-	let code = slash.vars().get_param("?");
 	let success = slash.meta().get_shopt("prompt.exit_status.success")?.trim_matches('"').to_string();
-	if let Some(code) = code {
-		match code.as_str() {
-			"0" => Ok(success),
-			_ => Ok(String::new()),
-		}
-	} else {
-		Ok(success)
+	match slash.prompt_status() {
+		0 => Ok(term::paint(Style::PromptSuccess, &success)),
+		_ => Ok(String::new())
 	}
 }
 
 pub fn escseq_fail<'a>(slash: &mut Slash) -> SlashResult<String> {
-	let code = slash.vars().get_param("?");
 	let failure = slash.meta().get_shopt("prompt.exit_status.failure")?.trim_matches('"').to_string();
-	if let Some(code) = code {
-		match code.as_str() {
-			"0" => Ok(String::new()),
-			_ => Ok(failure),
-		}
-	} else {
-		Ok(String::new())
+	match slash.prompt_status() {
+		0 => Ok(String::new()),
+		_ => Ok(term::paint(Style::PromptFailure, &failure))
 	}
 }
 
@@ -1170,7 +2050,7 @@ pub fn escseq_working_directory<'a>(slash: &mut Slash) -> SlashResult<String> {
 		}
 		cwd = path.to_string_lossy().to_string();
 	}
-	Ok(cwd)
+	Ok(term::paint(Style::PromptPath, &cwd))
 }
 
 /// Handles the basename of the current working directory.
@@ -1297,11 +2177,6 @@ pub fn determine_job_symbol(id: usize, current: Option<&usize>, prev: Option<&us
 }
 
 pub fn format_command_status(i: usize, cmd: &String, job: &Job, init: bool, pids: bool) -> String {
-	const GREEN: &str = "\x1b[32m";
-	const RED: &str = "\x1b[31m";
-	const CYAN: &str = "\x1b[35m";
-	const RESET: &str = "\x1b[0m";
-
 	let pid = if pids || init {
 		let mut pid = job.get_pids().get(i).unwrap().to_string();
 		pid.push(' ');
@@ -1325,13 +2200,14 @@ pub fn format_command_status(i: usize, cmd: &String, job: &Job, init: bool, pids
 
 	let status1 = format!("{}{}", pid, status0);
 	let status2 = format!("{}\t{}", status1, cmd);
-	let status_final = if status0.starts_with("done") {
-		format!("{}{}{}", GREEN, status2, RESET)
-	} else if status0.starts_with("exit") || status0.starts_with("stopped") {
-		format!("{}{}{}", RED, status2, RESET)
+	let style = if status0.starts_with("done") {
+		Style::JobDone
+	} else if status0.starts_with("failed") || status0.starts_with("signaled") || status0.starts_with("stopped") {
+		Style::JobFailed
 	} else {
-		format!("{}{}{}", CYAN, status2, RESET)
+		Style::JobRunning
 	};
+	let status_final = term::paint(style, &status2);
 
 	if i != job.get_commands().len() - 1 {
 		format!("{} |", status_final)
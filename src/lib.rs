@@ -0,0 +1,23 @@
+pub mod prompt;
+pub mod execute;
+pub mod error;
+pub mod shellenv;
+pub mod shopt;
+pub mod helper;
+pub mod signal;
+pub mod expand;
+pub mod builtin;
+pub mod prelude;
+pub mod utils;
+pub mod script;
+pub mod pest_ext;
+pub mod shell;
+pub mod ast;
+pub mod term;
+pub mod locale;
+pub mod path_cache;
+#[cfg(feature = "wasm-plugins")]
+pub mod plugin;
+
+pub use error::SlashResult;
+pub use shell::{ExitStatus, Shell, ShellBuilder};
@@ -0,0 +1,90 @@
+//! Locale support for pattern matching and string comparison
+//!
+//! Glob bracket expressions and the `<` and `>` test operators follow `LC_COLLATE` and `LC_CTYPE`, instead of byte order.
+//! The locale is read from the shell's own variables, so assigning `LC_ALL=C` takes effect without exporting it.
+//! Locales that aren't installed fall back to `C.UTF-8`, and then to `C`
+
+use std::{cmp::Ordering, ffi::{c_char, c_int, c_uint, c_ulong}, sync::Mutex};
+
+use crate::prelude::*;
+
+extern "C" {
+	fn wctype(name: *const c_char) -> c_ulong;
+	fn iswctype(wc: c_uint, desc: c_ulong) -> c_int;
+}
+
+/// The character classes that can appear in bracket expressions, as in `[[:alpha:]]`
+pub const CHAR_CLASSES: [&str;12] = [
+	"alnum", "alpha", "blank", "cntrl", "digit", "graph", "lower", "print", "punct", "space", "upper", "xdigit"
+];
+
+/// The locale last applied to LC_COLLATE and LC_CTYPE, so that setlocale() is only called when it changes
+static APPLIED: Mutex<[Option<String>;2]> = Mutex::new([None,None]);
+
+/// The locale that applies to a category, checking `LC_ALL`, then the category itself, then `LANG`
+pub fn locale_for(slash: &Slash, category: &str) -> String {
+	["LC_ALL", category, "LANG"].iter()
+		.find_map(|name| {
+			slash.vars().get_var(name).map(|val| val.to_string())
+				.or_else(|| slash.vars().get_evar(name))
+				.filter(|val| !val.is_empty())
+		})
+		.unwrap_or_else(|| "C".into())
+}
+
+/// Lets the applied locale be locked again after a panic that happened while it was held
+pub fn clear_poison() {
+	APPLIED.clear_poison();
+}
+
+/// Applies the shell's locale variables to the C library
+/// Should be called before collating or classifying characters
+pub fn sync(slash: &Slash) {
+	let Ok(mut applied) = APPLIED.lock() else { return };
+	let categories = [(libc::LC_COLLATE, "LC_COLLATE"), (libc::LC_CTYPE, "LC_CTYPE")];
+	for (i,(category,name)) in categories.into_iter().enumerate() {
+		let locale = locale_for(slash, name);
+		if applied[i].as_deref() == Some(locale.as_str()) {
+			continue
+		}
+		let is_utf8 = locale.to_lowercase().replace('-', "").contains("utf8");
+		let applies = set_locale(category, &locale) || (is_utf8 && set_locale(category, "C.UTF-8"));
+		if !applies {
+			set_locale(category, "C");
+		}
+		applied[i] = Some(locale);
+	}
+}
+
+fn set_locale(category: c_int, locale: &str) -> bool {
+	let Ok(locale) = CString::new(locale) else { return false };
+	unsafe { !libc::setlocale(category, locale.as_ptr()).is_null() }
+}
+
+/// Compares two strings using the collation order of the current locale
+pub fn collate(lhs: &str, rhs: &str) -> Ordering {
+	match (CString::new(lhs), CString::new(rhs)) {
+		(Ok(c_lhs), Ok(c_rhs)) => unsafe { libc::strcoll(c_lhs.as_ptr(), c_rhs.as_ptr()) }.cmp(&0),
+		_ => lhs.cmp(rhs)
+	}
+}
+
+/// Checks whether a character falls between two others in the collation order of the current locale
+pub fn in_range(ch: char, start: char, end: char) -> bool {
+	let (mut ch_buf, mut start_buf, mut end_buf) = ([0;4], [0;4], [0;4]);
+	let ch = ch.encode_utf8(&mut ch_buf);
+	collate(start.encode_utf8(&mut start_buf), ch) != Ordering::Greater &&
+	collate(ch, end.encode_utf8(&mut end_buf)) != Ordering::Greater
+}
+
+/// Checks whether a character belongs to one of the classes in `CHAR_CLASSES`, according to the current locale
+pub fn is_class(ch: char, class: &str) -> bool {
+	if !CHAR_CLASSES.contains(&class) {
+		return false
+	}
+	let Ok(name) = CString::new(class) else { return false };
+	unsafe {
+		let desc = wctype(name.as_ptr());
+		desc != 0 && iswctype(ch as c_uint, desc) != 0
+	}
+}
@@ -1,25 +1,10 @@
-use std::{os::fd::AsRawFd, path::PathBuf};
+use std::{any::Any, io::Read, os::fd::AsRawFd, panic::{AssertUnwindSafe, PanicHookInfo}, path::PathBuf, sync::Mutex};
 
 use clap::{ArgAction, Parser as ClapParser};
-use error::{SlashErr, SlashErrExt, SlashErrLow, SlashResult};
-use execute::dispatch;
-use nix::{sys::termios::{self, LocalFlags, Termios}, unistd::isatty};
-use shellenv::Slash;
-
-pub mod prompt;
-pub mod execute;
-pub mod error;
-pub mod shellenv;
-pub mod shopt;
-pub mod helper;
-pub mod signal;
-pub mod expand;
-pub mod builtin;
-pub mod prelude;
-pub mod utils;
-pub mod script;
-pub mod pest_ext;
-
+use libc::{STDERR_FILENO, STDIN_FILENO, STDOUT_FILENO};
+use nix::{sys::termios::{self, LocalFlags, Termios}, unistd::{getpgrp, getpid, isatty, Pid}};
+use once_cell::sync::Lazy;
+use slash::{ast, builtin::enable, error::{SlashErr, SlashErrExt, SlashErrLow}, execute::dispatch, helper, locale, path_cache, prompt::{self, comp}, shellenv::{self, attach_tty, EnvFlags, ExecCtx, Slash}, signal, term::{self, Style}, utils::{self, SmartFD}, Shell, SlashResult};
 
 #[derive(Debug,ClapParser)]
 #[command(name = "slash")]
@@ -29,6 +14,9 @@ pub mod pest_ext;
 struct SlashArgs {
 	script: Option<PathBuf>,
 
+	#[arg(trailing_var_arg = true, help = "Positional parameters for the script or command")]
+	script_args: Vec<String>,
+
 	#[arg(long = "no-rc", action = ArgAction::SetTrue, help = "Run without executing .slashrc")]
 	no_rc: bool,
 
@@ -42,7 +30,35 @@ struct SlashArgs {
 	hist_path: Option<PathBuf>,
 
 	#[arg(short = 'c', value_name = "COMMAND", help = "Run a single command and then exit")]
-	command: Option<String>
+	command: Option<String>,
+
+	#[arg(long = "posix", action = ArgAction::SetTrue, help = "Disable ox extensions and behave like a POSIX sh")]
+	posix: bool,
+
+	#[arg(long = "emit-ast", value_name = "FORMAT", value_parser = ["json"], help = "Print the parse tree of the input instead of running it")]
+	emit_ast: Option<String>
+}
+
+/// Parses the script, the -c command, or stdin, and prints the resulting tree
+fn emit_ast(args: &SlashArgs) -> SlashResult<()> {
+	let input = if let Some(cmd) = &args.command {
+		cmd.clone()
+	} else if let Some(path) = &args.script {
+		std::fs::read_to_string(path).map_err(|_| SlashErr::Low(SlashErrLow::from_io()))?
+	} else {
+		let mut buffer = String::new();
+		std::io::stdin().read_to_string(&mut buffer).map_err(|_| SlashErr::Low(SlashErrLow::from_io()))?;
+		buffer
+	};
+	println!("{}",ast::parse(&input)?.to_json()?);
+	Ok(())
+}
+
+/// Invoking the shell as `sh` turns on POSIX mode
+fn invoked_as_sh() -> bool {
+	std::env::args().next()
+		.and_then(|arg0| PathBuf::from(arg0).file_name().map(|name| name.to_string_lossy().trim_start_matches('-') == "sh"))
+		.unwrap_or(false)
 }
 
 fn set_termios() -> Option<Termios> {
@@ -63,51 +79,237 @@ fn restore_termios(orig: &Option<Termios>) {
 	}
 }
 
+/// Leaves an interactive shell
+/// The EXIT trap runs first, then jobs are hung up if `core.huponexit` is set, and the terminal is put back the way it was found
+fn exit_shell(slash: &mut Slash, termios: &Option<Termios>, code: i32) -> ! {
+	slash.set_code(code);
+	slash.run_exit_trap();
+	if slash.meta().borrow_shopts().core.huponexit {
+		signal::hangup_jobs();
+	}
+	slash.meta_mut().close_open_fds();
+	restore_termios(termios);
+	std::process::exit(slash.get_status())
+}
+
+/// Reads and runs one line of input in an interactive shell
+fn run_line(slash: &mut Slash, termios: &Option<Termios>) {
+	if signal::take_winch() {
+		slash.update_term_size();
+	}
+	let input = match prompt::prompt::run_prompt(slash) {
+		Ok(input) => input,
+		Err(SlashErr::Low(SlashErrLow::CleanExit(code))) => exit_shell(slash, termios, code),
+		Err(e) => {
+			eprintln!("{}",term::paint(Style::Error, &e.to_string()));
+			String::new()
+		}
+	};
+	// A hangup while waiting for input usually surfaces as a read error, so the signal is checked here as well
+	if let Some(code) = signal::pending_exit() {
+		exit_shell(slash, termios, code)
+	}
+	if slash.meta().flags().contains(EnvFlags::PRINT_INPUT) {
+		eprintln!("{}",input);
+	}
+	// set -t exits after the command that follows it, not after the `set` call itself
+	let exit_after = slash.meta().flags().contains(EnvFlags::EXIT_AFTER_EXEC);
+
+	slash.start_timer();
+	slash.ctx_mut().push_state().catch();
+	let fds_before = helper::fd_audit_start(slash);
+	let saved_fds = utils::save_fds().unwrap();
+	signal::clear_interrupt();
+
+	// Scripts always have comments, but lines typed at the prompt only have them with core.int_comments
+	let input = if slash.meta().borrow_shopts().core.int_comments { input } else { helper::escape_comments(&input) };
+	let result = dispatch::exec_toplevel(input, slash);
+
+	utils::restore_fds(saved_fds,slash).catch();
+	slash.ctx_mut().pop_state().catch();
+	helper::fd_audit_finish(slash, fds_before);
+	// With core.checkwinsize, a resize made while a command was running shows up right away
+	if signal::take_winch() || slash.meta().borrow_shopts().core.checkwinsize {
+		slash.update_term_size();
+	}
+
+	// Signals that arrived during the last command of the line have their traps run before the next prompt
+	let result = result.and_then(|_| slash.run_signal_traps());
+	if let Err(e) = result {
+		match e.low() {
+			SlashErrLow::CleanExit(code) => exit_shell(slash, termios, *code),
+			SlashErrLow::Interrupted => slash.set_code(130),
+			_ => helper::report_err(slash, &e)
+		}
+	}
+	if exit_after {
+		let status = slash.get_status();
+		exit_shell(slash, termios, status)
+	}
+}
+
+/// Where the last panic happened, recorded by the panic hook so that it can be reported along with the message
+static PANIC_LOCATION: Mutex<Option<String>> = Mutex::new(None);
+
+fn record_panic(info: &PanicHookInfo) {
+	*PANIC_LOCATION.lock().unwrap_or_else(|e| e.into_inner()) = info.location().map(|loc| loc.to_string());
+}
+
+/// Puts an interactive shell back together after a panic, so that the session outlives it
+/// Stdio, the terminal settings, and the foreground process group are restored, and the panic is reported as an internal error.
+/// A forked child exits instead, since it would otherwise carry on as a second copy of the shell
+fn recover_from_panic(slash: &mut Slash, termios: &Option<Termios>, tty_fds: &(SmartFD,SmartFD,SmartFD), shell_pid: Pid, payload: Box<dyn Any + Send>) {
+	let msg = payload.downcast_ref::<&str>().map(|msg| msg.to_string())
+		.or_else(|| payload.downcast_ref::<String>().cloned())
+		.unwrap_or_else(|| "unknown cause".into());
+	let msg = match PANIC_LOCATION.lock().unwrap_or_else(|e| e.into_inner()).take() {
+		Some(location) => format!("the shell panicked at {}: {}",location,msg),
+		None => format!("the shell panicked: {}",msg)
+	};
+	let err = SlashErr::Low(SlashErrLow::InternalErr(msg));
+	clear_lock_poison();
+	if getpid() != shell_pid {
+		helper::report_err(slash, &err);
+		std::process::exit(slash.get_status())
+	}
+
+	for (saved,fd) in [(&tty_fds.0,STDIN_FILENO),(&tty_fds.1,STDOUT_FILENO),(&tty_fds.2,STDERR_FILENO)] {
+		saved.dup2(&fd).ok();
+	}
+	restore_termios(termios);
+	attach_tty(getpgrp()).ok();
+	shellenv::enable_reaping().ok();
+	*slash.ctx_mut() = ExecCtx::new();
+	helper::report_err(slash, &err);
+}
+
+/// Lets every global lock be taken again, since a panic while one was held leaves it poisoned,
+/// and the shell would otherwise fail on every later command that needs it
+fn clear_lock_poison() {
+	PANIC_LOCATION.clear_poison();
+	shellenv::clear_jobs_poison();
+	signal::clear_printer_poison();
+	path_cache::clear_poison();
+	locale::clear_poison();
+	term::clear_poison();
+	enable::clear_poison();
+	comp::clear_poison();
+	#[cfg(feature = "wasm-plugins")]
+	slash::plugin::clear_poison();
+}
+
+/// Runs `f`, putting the shell back together if it panics
+fn run_caught(slash: &mut Slash, termios: &Option<Termios>, tty_fds: &(SmartFD,SmartFD,SmartFD), shell_pid: Pid, f: impl FnOnce(&mut Slash)) {
+	let result = std::panic::catch_unwind(AssertUnwindSafe(|| f(slash)));
+	if let Err(payload) = result {
+		recover_from_panic(slash, termios, tty_fds, shell_pid, payload)
+	}
+}
+
 fn main() {
 
 	let mut slash = Slash::new(); // The shell environment
+	signal::sig_handler_setup();
+	Lazy::force(&utils::SHELL_EXE);
 
 	let args = SlashArgs::parse();
-	if args.no_rc {
-		slash.vars_mut().export_var("PS1", "$> ");
+	if args.emit_ast.is_some() {
+		if let Err(e) = emit_ast(&args) {
+			eprintln!("{}",term::paint(Style::Error, &e.to_string()));
+			std::process::exit(1)
+		}
+		std::process::exit(0)
+	}
+	let mut args = args;
+	if args.command.is_some() {
+		// With -c, the first operand is $0 rather than a script
+		if let Some(name) = args.script.take() {
+			slash.vars_mut().set_param("0".into(), &name.to_string_lossy());
+		}
+	}
+	let posix = args.posix || invoked_as_sh();
+	let interactive = args.command.is_none() && args.script.is_none() && isatty(std::io::stdin().as_raw_fd()).unwrap_or(false);
+	slash.meta_mut().mod_flags(|f| {
+		f.set(EnvFlags::POSIX, posix);
+		f.set(EnvFlags::INTERACTIVE, interactive);
+	});
+	for arg in &args.script_args {
+		slash.vars_mut().pos_param_pushback(arg);
+	}
+	if interactive {
+		signal::ignore_termination();
+	}
+	slash.update_term_size();
+
+	// POSIX shells don't read an rc file
+	if !args.no_rc && !posix {
+		slash.source_rc(args.rc_path.clone()).catch();
+	}
+	slash.source_env_file().catch();
+	#[cfg(feature = "wasm-plugins")]
+	slash::plugin::load_plugins(&mut slash).catch();
+
+	if args.command.is_some() || args.script.is_some() {
+		let mut shell = Shell::from(slash);
+		let status = match (&args.command, &args.script) {
+			(Some(cmd), _) => shell.run_named(cmd, "-c string"),
+			(None, Some(path)) => shell.run_script(path),
+			(None, None) => unreachable!()
+		};
+		shell.env_mut().run_exit_trap();
+		let status = status.map(|_| shell.last_status());
+		drop(shell);
+		match status {
+			Ok(status) => std::process::exit(status.code()),
+			Err(e) => {
+				eprintln!("{}",term::paint(Style::Error, &e.to_string()));
+				std::process::exit(127)
+			}
+		}
 	}
 
-	if !args.no_rc {
-		slash.source_rc(args.rc_path).catch();
+	if let Some(path) = &args.hist_path {
+		slash.vars_mut().export_var("HIST_FILE", &path.to_string_lossy());
+	}
+	if !args.no_hist {
+		let hist_path = helper::hist_file(&slash);
+		let max_len = slash.meta().borrow_shopts().core.max_hist;
+		slash.meta_mut().history_mut().read_file(&hist_path, max_len).catch();
 	}
 
 	let termios = set_termios();
+	// Copies of the terminal's stdio, for putting things back after a panic
+	let tty_fds = utils::save_fds().unwrap();
+	let shell_pid = getpid();
+	std::panic::set_hook(Box::new(record_panic));
 	loop {
-		let input = prompt::prompt::run_prompt(&mut slash).catch().unwrap_or_default();
+		run_caught(&mut slash, &termios, &tty_fds, shell_pid, |slash| run_line(slash, &termios));
+	}
+}
 
-		slash.start_timer();
-		slash.ctx_mut().push_state().catch();
-		let saved_fds = utils::save_fds().unwrap();
+#[cfg(test)]
+mod tests {
+	use super::*;
 
-		let result = dispatch::exec_input(input, &mut slash);
+	#[test]
+	fn test_panic_recovery() {
+		// The terminal is left alone, since this process doesn't own it
+		shellenv::revoke_tty_access();
+		let mut slash = Slash::new();
+		let tty_fds = utils::save_fds().unwrap();
+		let shell_pid = getpid();
 
-		utils::restore_fds(saved_fds,&mut slash).catch();
-		slash.ctx_mut().pop_state().catch();
+		// A panic while the job table is held poisons it
+		run_caught(&mut slash, &None, &tty_fds, shell_pid, |_| {
+			shellenv::write_jobs(|_| panic!("panicked while holding the job table")).ok();
+		});
+		assert_eq!(slash.get_status(), 70);
 
-		match result {
-			Ok(_) => continue,
-			Err(e) => {
-				match e {
-					SlashErr::Low(SlashErrLow::CleanExit(code)) => {
-						restore_termios(&termios);
-						std::process::exit(code)
-					}
-					SlashErr::High(ref high) => {
-						if let SlashErrLow::CleanExit(code) = high.get_err() {
-							restore_termios(&termios);
-							std::process::exit(*code)
-						} else {
-							eprintln!("{}",e)
-						}
-					}
-					_ => eprintln!("{}",e)
-				}
-			}
-		}
+		// The shell still works afterwards, including for commands that need the job table
+		assert!(shellenv::write_jobs(|_| ()).is_ok());
+		run_caught(&mut slash, &None, &tty_fds, shell_pid, |slash| {
+			dispatch::exec_input("sleep 0 & wait; recovered=yes".into(), slash).unwrap();
+		});
+		assert_eq!(slash.vars().get_var("recovered").map(|val| val.to_string()).as_deref(), Some("yes"));
 	}
 }
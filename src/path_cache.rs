@@ -0,0 +1,118 @@
+//! A cache of the executables found in each `PATH` directory
+//!
+//! Looking up a command used to stat a candidate file in every `PATH` directory, and the highlighter and completion do that on every keystroke.
+//! Each directory is read once instead, and read again only when its modification time changes, which happens whenever a file is added, removed, or renamed in it.
+//! A directory that changed very recently is read every time until its timestamp settles, since timestamps are too coarse to catch every change.
+//! Changing the permissions of a file doesn't touch the directory, so a file that was made executable is only picked up once something else in its directory changes.
+//! Relative `PATH` entries depend on the working directory, so they are always checked directly
+
+use std::{sync::Mutex, time::{Duration, SystemTime}};
+
+use once_cell::sync::Lazy;
+
+use crate::{helper, prelude::*};
+
+/// File timestamps are only as fine as the kernel's clock tick, so a directory read within this long of its last change could change again without its time moving
+const RACY_WINDOW: Duration = Duration::from_secs(1);
+
+/// The executables in one directory, as of the directory's modification time
+struct CachedDir {
+	mtime: SystemTime,
+	scanned: SystemTime,
+	names: HashSet<String>
+}
+
+impl CachedDir {
+	fn is_current(&self, mtime: SystemTime) -> bool {
+		self.mtime == mtime && self.scanned.duration_since(mtime).is_ok_and(|age| age >= RACY_WINDOW)
+	}
+}
+
+static CACHE: Lazy<Mutex<HashMap<PathBuf,CachedDir>>> = Lazy::new(|| Mutex::new(HashMap::new()));
+
+/// Lets the cache be locked again after a panic that happened while it was held
+pub fn clear_poison() {
+	CACHE.clear_poison();
+}
+
+/// Runs `f` on the names of the executables in `dir`, reading the directory again if it changed since it was cached
+/// Returns None if the directory can't be read
+fn with_dir<T>(dir: &Path, f: impl FnOnce(&HashSet<String>) -> T) -> Option<T> {
+	let mtime = std::fs::metadata(dir).and_then(|meta| meta.modified()).ok()?;
+	let mut cache = CACHE.lock().unwrap();
+	if !cache.get(dir).is_some_and(|cached| cached.is_current(mtime)) {
+		let scanned = SystemTime::now();
+		let names = std::fs::read_dir(dir).ok()?
+			.flatten()
+			.filter(|entry| helper::is_exec(&entry.path()))
+			.filter_map(|entry| entry.file_name().into_string().ok())
+			.collect();
+		cache.insert(dir.to_path_buf(), CachedDir { mtime, scanned, names });
+	}
+	cache.get(dir).map(|cached| f(&cached.names))
+}
+
+/// Finds the first executable called `command` in the directories of `path`
+pub fn find(path: &str, command: &str) -> Option<PathBuf> {
+	for dir in env::split_paths(path) {
+		let candidate = dir.join(command);
+		let found = if dir.is_absolute() {
+			// A file that lost its execute permission since the directory was read is skipped
+			with_dir(&dir, |names| names.contains(command)).unwrap_or(false) && helper::is_exec(&candidate)
+		} else {
+			helper::is_exec(&candidate)
+		};
+		if found {
+			return Some(candidate)
+		}
+	}
+	None
+}
+
+/// The names of every executable in the directories of `path`, for completion
+pub fn executables(path: &str) -> HashSet<String> {
+	let mut names = HashSet::new();
+	for dir in env::split_paths(path) {
+		if dir.is_absolute() {
+			with_dir(&dir, |found| names.extend(found.iter().cloned()));
+		} else if let Ok(entries) = std::fs::read_dir(&dir) {
+			names.extend(entries.flatten()
+				.filter(|entry| helper::is_exec(&entry.path()))
+				.filter_map(|entry| entry.file_name().into_string().ok()));
+		}
+	}
+	names
+}
+
+#[cfg(test)]
+mod tests {
+	use std::os::unix::fs::PermissionsExt;
+
+	use super::*;
+
+	#[test]
+	fn test_path_cache() {
+		let dir = env::temp_dir().join(format!("slash_path_cache_{}",std::process::id()));
+		std::fs::create_dir_all(&dir).unwrap();
+		let make_exec = |name: &str| {
+			let file = dir.join(name);
+			std::fs::write(&file, "#!/bin/sh\n").unwrap();
+			std::fs::set_permissions(&file, std::fs::Permissions::from_mode(0o755)).unwrap();
+		};
+		make_exec("first_tool");
+		std::fs::write(dir.join("notes"), "").unwrap();
+		let path = format!("/slash_no_such_dir:{}",dir.display());
+
+		assert_eq!(find(&path, "first_tool"), Some(dir.join("first_tool")));
+		assert_eq!(find(&path, "notes"), None);
+		assert!(executables(&path).contains("first_tool"));
+
+		// Adding and removing files changes the directory, so the cache is refreshed
+		make_exec("second_tool");
+		assert_eq!(find(&path, "second_tool"), Some(dir.join("second_tool")));
+		std::fs::remove_file(dir.join("first_tool")).unwrap();
+		assert_eq!(find(&path, "first_tool"), None);
+		assert!(!executables(&path).contains("first_tool"));
+		std::fs::remove_dir_all(dir).ok();
+	}
+}
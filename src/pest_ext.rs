@@ -1,4 +1,4 @@
-use crate::{prelude::*, utils};
+use crate::{helper, prelude::*};
 
 pub const ARG_RULES: [Rule; 2] = [Rule::arg_assign,Rule::word];
 
@@ -107,7 +107,10 @@ impl<'a> PairExt<'a> for Pair<'a,Rule> {
 		for arg in inner {
 			match arg.as_rule() {
 				Rule::word | Rule::cmd_name | Rule::arg_assign => argv.push(arg.as_str().trim_quotes()),
-				Rule::redir => slash.ctx_mut().push_redir(utils::Redir::from_pair(arg).unwrap()),
+				Rule::redir => {
+					let redir = helper::prepare_redir(arg, slash).unwrap();
+					slash.ctx_mut().push_redir(redir)
+				}
 				_ => unreachable!("Unexpected rule: {:?}",arg.as_rule())
 			}
 		}
@@ -130,23 +133,24 @@ impl<'a> PairExt<'a> for Pair<'a,Rule> {
 }
 
 #[derive(pest_derive::Parser)]
-#[grammar_inline = r##"
+#[grammar_inline = r###"
 // Helper rules
 WHITESPACE        = _{ " " | "\t" }
 COMMENT           = _{ !"#!" ~ "#" ~ (!(NEWLINE | "#") ~ ANY)* }
 number            =  { ASCII_DIGIT+ }
 parameter         =  { "#" | ASCII_DIGIT+ | "@" | "*" | "?" | "$" | "!" | "_" | "-" }
-glob_brackets     = @{ !"\\[" ~ "[" ~ (!("]" | "," | WHITESPACE | NEWLINE) ~ ANY)* ~ "]" }
+glob_class        = @{ "[:" ~ ASCII_ALPHA+ ~ ":]" }
+glob_brackets     = @{ !"\\[" ~ "[" ~ (glob_class | !("]" | "," | WHITESPACE | NEWLINE) ~ ANY)* ~ "]" }
 glob_pat          =  { (!"\\?" ~ "?") | (!"\\*" ~ "*") | glob_brackets }
 alpha_range_upper = @{ ASCII_ALPHA_UPPER ~ ".." ~ ASCII_ALPHA_UPPER }
 alpha_range_lower = @{ ASCII_ALPHA_LOWER ~ ".." ~ ASCII_ALPHA_LOWER }
-num_range         = @{ ASCII_DIGIT ~ ".." ~ ASCII_DIGIT }
+num_range         = @{ "-"? ~ ASCII_DIGIT+ ~ ".." ~ "-"? ~ ASCII_DIGIT+ ~ (".." ~ ASCII_DIGIT+)? }
 brace_list        = @{ !"," ~ (brace_word | ident) ~ ("," ~ !"," ~ (brace_word | ident))* }
 brace_expand      = @{ "{" ~ (alpha_range_upper | alpha_range_lower | num_range | brace_list) ~ "}" }
 path_seg          = @{ path_root | path_rel }
 path_root         =  { ("/" ~ ident)+ }
 path_rel          =  { (ident ~ "/")+ }
-reserved          =  @{ ("if" | "for" | "while" | "do" | "done" | "fi" | "in" | "select" | "match") ~ word_bound }
+reserved          =  @{ ("if" | "for" | "while" | "do" | "done" | "fi" | "in" | "select" | "match" | "coproc") ~ word_bound }
 
 // in case you need to explicitly mark where a word ends
 // necessary with shell constructs, for some reason
@@ -161,18 +165,32 @@ find_expansions =  { (WHITESPACE | NEWLINE | assignment | word | operator)* }
 glob_word       = @{ ident? ~ glob_pat+ ~ ident? }
 tilde_sub       = @{ pwd | prev_pwd | (home ~ path_seg?) }
 brace_word      = @{ ident? ~ brace_expand+ ~ ident? }
-var_sub         = @{ !"\\$" ~ "$" ~ var_ident }
+var_name        = @{ ASCII_ALPHA ~ (ASCII_ALPHANUMERIC | "_")* }
+var_transform   =  { "@" ~ ("Q" | "E" | "A" | "a") }
+param_name      = @{ var_name | ASCII_DIGIT+ | "@" | "*" | "#" | "?" | "$" | "!" | "-" | "_" }
+param_op        = @{ ":-" | ":=" | ":?" | ":+" | "##" | "%%" | "//" | "/#" | "/%" | "-" | "=" | "?" | "+" | "#" | "%" | "/" | ":" }
+param_word      = @{ (("\\" ~ ANY) | ("{" ~ param_word ~ "}") | (!"}" ~ ANY))* }
+var_sub         = @{
+	!"\\$" ~ "$" ~ (
+		("{" ~ (("#" ~ param_name) | (param_name ~ var_transform) | (param_name ~ param_op ~ param_word) | param_name) ~ "}")
+		| var_name
+	)
+}
 index           =  { ASCII_DIGIT+ }
 slice           = ${ index ~ ".." ~ index }
 key             =  { dquoted | squoted }
 arr_index       = @{ !"\\$" ~ "$" ~ var_ident ~ ("[" ~ (key | slice | index) ~ "]")+ }
 cmd_sub         = @{ !"\\$" ~ "$(" ~ subsh_body ~ ")" }
+arith_body      = @{ (arith_nested | (!"(" ~ !")" ~ ANY))* }
+arith_nested    = _{ "(" ~ arith_body ~ ")" }
+arith_sub       = @{ !"\\$" ~ "$((" ~ arith_body ~ "))" }
 param_sub       = @{ !"\\$" ~ "$" ~ parameter }
 expansion       =  {
     tilde_sub
   | brace_word
-  | var_sub
   | arr_index
+  | var_sub
+  | arith_sub
   | cmd_sub
   | param_sub
 }
@@ -197,14 +215,22 @@ var_ident_plain    = @{ NEWLINE* ~ !parameter ~ ASCII_ALPHA ~ (ASCII_ALPHANUMERI
 var_ident_brackets = @{ !"\\{" ~ "{" ~ var_ident_plain ~ !"\\}" ~ "}" }
 var_ident          =  { var_ident_brackets | var_ident_plain }
 ident              = _{
-	"[" |
-    "]" |
     (("\\" ~ ANY) | // 'out' and 'in' refer to redir operators '>' and '<'
-  	(!out ~ !in ~ ASCII_ALPHANUMERIC | "\"" | "'" | "[" | "]" | "*" | "?" | "_" | "-" | "!" | "%" | "+" | "=" | "\\" | "/" | "," | "." | ":" | "@"))+
+  	(!out ~ !in ~ ASCII_ALPHANUMERIC | "\"" | "'" | "[" | "]" | "*" | "?" | "_" | "-" | "!" | "%" | "+" | "=" | "\\" | "/" | "," | "." | ":" | "@" | "#"))+ |
+	"[" |
+    "]"
 }
+// Quoted and unquoted parts joined into one word, like 'it'\''s' or --name="a b"
+joined_part        = _{
+    squoted
+  | dquoted
+  | (("\\" ~ ANY) | (!(WHITESPACE | NEWLINE | "'" | "\"" | "\\" | "=>" | ";" | "&" | "|" | "<" | ">" | "(" | ")" | "$" | "`" | "{" | "}") ~ ANY))+
+}
+joined_word        = ${ joined_part ~ joined_part+ }
 cmd_name           = @{ word }
 word               = ${
-    dquoted
+    joined_word
+  | dquoted
   | squoted
   | expand_word
   | ident
@@ -218,19 +244,27 @@ increment          =  @{ var_ident ~ "++" ~ (!sep ~ WHITESPACE+ ~ cmd_list)? }
 decrement          =  @{ var_ident ~ "--" ~ (!sep ~ WHITESPACE+ ~ cmd_list)? }
 minus_assign       =  { var_ident ~ "-=" ~ word? ~ (!sep ~ cmd_list)? }
 assignment         =  { increment | decrement | std_assign | plus_assign | minus_assign }
-arg_assign         =  { var_ident ~ "=" ~ (array|word)? }
+arg_assign         =  { !NEWLINE ~ var_ident ~ "=" ~ (array|word)? }
 sep                = _{ (";" | NEWLINE)+ }
+// A list ends at a separator, or at a `&` that sends its last command to the background
+bg_ended           = _{ ((bg_cmd | expr) ~ op)* ~ bg_cmd }
+list_item          = _{ (cmd_list ~ sep) | (&bg_ended ~ cmd_list) }
 
 // Types of commands
 cmd_list   =  { (bg_cmd | expr) ~ (#op = op ~ (bg_cmd | expr))* }
-simple_cmd =  { !reserved ~ (redir | cmd_name) ~ (arg_assign | word | redir)* }
-bg_cmd     =  { expr ~ !"&&" ~ "&" ~ word_bound }
+simple_cmd =  { !reserved ~ (redir | cmd_name) ~ (arg_assign | redir | word)* }
+bg_cmd     =  { expr ~ !("&&" | "&>") ~ (disown_op | "&") }
+disown_op  = @{ "&" ~ ("!" | "|") }
 pipeline   =  { (shell_cmd | simple_cmd) ~ ("|" ~ (shell_cmd | simple_cmd))+ }
 expr       = _{ pipeline | shell_cmd | assignment | simple_cmd }
 shell_cmd  =  {
-    (for_cmd | match_cmd | loop_cmd | if_cmd | subshell | brace_grp | assignment | func_def) ~ redir*
+    (coproc_cmd | for_cmd | select_cmd | match_cmd | loop_cmd | if_cmd | arith_cmd | subshell | brace_grp | assignment | func_def) ~ redir*
 }
 
+coproc_kw   = @{ "coproc" ~ word_bound }
+coproc_name = @{ !reserved ~ var_name }
+coproc_cmd  =  { coproc_kw ~ (coproc_name ~ &shell_cmd)? ~ (shell_cmd | simple_cmd) }
+
 
 subshebang = @{ "#!" ~ (!NEWLINE ~ ANY)+ ~ NEWLINE }
 subsh_body = @{ (nested | non_paren)+ }
@@ -238,11 +272,13 @@ nested     = _{ "(" ~ subsh_body* ~ ")"? }
 non_paren  = _{ (!"(" ~ !")" ~ ANY)+ }
 subshell   =  { "(" ~ subshebang? ~ subsh_body ~ ")" ~ (redir | (arg_assign | word | redir))* }
 proc_sub   =  { (in | out) ~ "(" ~ subsh_body ~ ")" }
+arith_cmd  =  { "((" ~ arith_body ~ "))" }
 
 if_cond   = { cmd_list }
 loop_cond = { cmd_list }
-if_body   = { (!("fi" | "elif" | "else") ~ cmd_list ~ sep)+ }
-loop_body = { (!"done" ~ cmd_list ~ sep)+ }
+if_body   = { (!("fi" | "elif" | "else") ~ list_item)+ }
+else_body = { (!"fi" ~ list_item)+ }
+loop_body = { (!"done" ~ list_item)+ }
 
 loop_kind = { "while" | "until" }
 loop_cmd  = { loop_kind ~ NEWLINE* ~ loop_cond ~ sep ~ "do" ~ NEWLINE* ~ loop_body ~ "done" }
@@ -251,6 +287,11 @@ for_vars = { (!"in" ~ word ~ NEWLINE*)+ }
 for_arr  = { (word ~ NEWLINE*)+ }
 for_cmd  = { "for" ~ NEWLINE* ~ for_vars ~ in ~ NEWLINE* ~ for_arr+ ~ sep ~ "do" ~ NEWLINE* ~ loop_body ~ NEWLINE* ~ "done" ~ word_bound }
 
+// Without `in`, the menu is made from the positional parameters
+select_var = @{ var_name }
+select_in  = @{ "in" ~ word_bound }
+select_cmd = { select ~ NEWLINE* ~ select_var ~ NEWLINE* ~ (select_in ~ NEWLINE* ~ for_arr?)? ~ sep ~ "do" ~ NEWLINE* ~ loop_body ~ NEWLINE* ~ "done" ~ word_bound }
+
 match_pat  = { (!"=>" ~ word)+ }
 match_body = { (brace_grp ~ ","? | (!"," ~ ANY)+ ~ ",") }
 match_arm  = { match_pat ~ "=>" ~ NEWLINE* ~ match_body }
@@ -260,7 +301,7 @@ match_cmd  = {
 
 if_cmd     = { "if" ~ NEWLINE* ~ if_cond ~ sep ~ "then" ~ NEWLINE* ~ if_body ~ elif_block* ~ else_block? ~ "fi" ~ word_bound }
 elif_block = { "elif" ~ NEWLINE* ~ if_cond ~ sep ~ "then" ~ NEWLINE* ~ if_body }
-else_block = { "else" ~ NEWLINE* ~ (!("fi") ~ #else_body = cmd_list ~ sep)+ }
+else_block = { "else" ~ NEWLINE* ~ else_body }
 
 // Operator stuff
 and = { "&&" }
@@ -268,8 +309,9 @@ or  = { "||" }
 op  = { (and | or) }
 
 redir_list =  { redir ~ (redir)* }
-fd_out     = @{ number }
+fd_out     = @{ number ~ &("<" | ">") }
 fd_target  = @{ number }
+fd_close   =  { "-" }
 file       = { proc_sub|word }
 pipe       =  { "|" }
 in         =  { "<" }
@@ -293,9 +335,10 @@ redir      =  {
   | (fd_out ~ in ~ "&" ~ fd_target)
   | (out ~ "&" ~ fd_target)
   | (fd_out ~ out ~ "&" ~ fd_target)
-  | (out ~ "&" ~ "-")
-  | (fd_out ~ out ~ "&" ~ "-")
-  | (fd_out ~ out ~ "&" ~ "-")
+  | (in ~ "&" ~ fd_close)
+  | (fd_out ~ in ~ "&" ~ fd_close)
+  | (out ~ "&" ~ fd_close)
+  | (fd_out ~ out ~ "&" ~ fd_close)
   | ("&" ~ out ~ file)
   | (fd_out ~ in_out ~ file)
   | (in_out ~ file)
@@ -313,6 +356,7 @@ custom_esc_path    = @{ custom_esc_seg ~ ("." ~ custom_esc_seg)* }
 esc_sequence       =  {
     esc_bell
   | esc_vis_grp
+  | esc_hide_grp
   | esc_user_seq
   | esc_ansi_seq
   | esc_ascii_oct
@@ -336,6 +380,7 @@ esc_sequence       =  {
   | esc_exit_code
   | esc_success_symbol
   | esc_failure_symbol
+  | esc_dir_depth
 }
 esc_pwd            =  { "\\w" }
 esc_pwd_short      =  { "\\W" }
@@ -347,6 +392,7 @@ esc_prompt_symbol  = @{ "\\$" }
 esc_exit_code      =  { "\\?" }
 esc_success_symbol =  { "\\S" }
 esc_failure_symbol =  { "\\F" }
+esc_dir_depth      =  { "\\p" }
 esc_bell           =  { "\\a" }
 esc_newline        =  { "\\n" }
 esc_return         =  { "\\r" }
@@ -363,6 +409,7 @@ esc_ascii_oct      = ${ "\\" ~ ascii_oct_seq }
 esc_ansi_seq       =  { "\\e" ~ "[" ~ ansi_params ~ ASCII_ALPHA }
 esc_user_seq       = ${ "\\{" ~ custom_esc_path ~ "\\}" }
 esc_vis_grp        =  { "\\(" ~ (raw_text | esc_sequence)* ~ "\\)" }
+esc_hide_grp       =  { "\\[" ~ (raw_text | esc_sequence)* ~ "\\]" }
 
 // Syntax Highlighting
 
@@ -434,6 +481,7 @@ hl_word = ${
   | param_sub
   | arr_index
   | var_sub
+  | arith_sub
   | cmd_sub
   | proc_sub
   | tilde_sub
@@ -485,7 +533,7 @@ brace_grp = { "{" ~ sub_main ~ "}" }
 
 // sub_main is used for brace groups, it essentially allows for holding a script in the brace group
 // It's stored as a static string, but using sub_main makes sure that it actually parses first
-sub_main = _{ NEWLINE* ~ cmd_list? ~ (sep ~ cmd_list?)* ~ NEWLINE* }
-main     =  { SOI ~ NEWLINE* ~ cmd_list? ~ (sep ~ cmd_list?)* ~ NEWLINE* ~ EOI }
-"##]
+sub_main = _{ (sep | list_item)* ~ cmd_list? ~ NEWLINE* }
+main     =  { SOI ~ (sep | list_item)* ~ cmd_list? ~ NEWLINE* ~ EOI }
+"###]
 pub struct SlashParse;
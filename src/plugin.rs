@@ -0,0 +1,320 @@
+//! Sandboxed WASM plugins, enabled with the `wasm-plugins` feature
+//!
+//! Plugins are loaded from `core.plugin_dir`, which defaults to `~/.config/slash/plugins`.
+//! The interface a plugin provides is described in `wit/slash-plugin.wit`. Plugins are core wasm modules,
+//! so that interface is lowered by hand:
+//!
+//! * Strings are passed as a pointer and a length into the plugin's exported `memory`.
+//!   The host writes strings into memory that it allocates with the exported `slash_alloc(len) -> ptr`
+//! * Strings are returned as an i64 with the pointer in the high 32 bits and the length in the low 32 bits.
+//!   A return value of 0 means `none`
+//! * Lists of strings are joined with newlines, and argument lists are joined with null bytes
+//! * `slash_abi_version() -> i32` must return `PLUGIN_ABI_VERSION`
+//!
+//! Every call into a plugin is given a fixed amount of fuel, so a misbehaving plugin can't hang the prompt
+
+use std::sync::Mutex;
+
+use once_cell::sync::Lazy;
+use wasmi::{AsContextMut, Caller, Config, Engine, Extern, Instance, Linker, Memory, Module, Store};
+
+use crate::{error::SlashErr, helper, prelude::*, shellenv::VarTable, utils};
+
+pub const PLUGIN_ABI_VERSION: i32 = 1;
+const FUEL_PER_CALL: u64 = 10_000_000;
+
+#[derive(Default)]
+struct HostState {
+	vars: Option<VarTable>
+}
+
+pub struct Plugin {
+	name: String,
+	store: Store<HostState>,
+	instance: Instance,
+	memory: Memory,
+	builtins: Vec<String>
+}
+
+static PLUGINS: Lazy<Mutex<Vec<Plugin>>> = Lazy::new(|| Mutex::new(vec![]));
+
+fn plugin_err(msg: impl Display) -> SlashErr {
+	Low(SlashErrLow::ExecFailed(format!("plugin: {}",msg)))
+}
+
+/// Lets the registry be locked again after a panic that happened while it was held
+pub fn clear_poison() {
+	PLUGINS.clear_poison();
+}
+
+fn lock_plugins() -> SlashResult<std::sync::MutexGuard<'static, Vec<Plugin>>> {
+	PLUGINS.lock().map_err(|_| Low(SlashErrLow::InternalErr("Failed to lock plugin registry".into())))
+}
+
+fn unpack_ptr(packed: i64) -> Option<(usize,usize)> {
+	if packed == 0 {
+		return None
+	}
+	let ptr = (packed as u64 >> 32) as usize;
+	let len = (packed as u64 & 0xFFFF_FFFF) as usize;
+	Some((ptr,len))
+}
+
+fn read_str(memory: &Memory, ctx: impl wasmi::AsContext, ptr: usize, len: usize) -> Option<String> {
+	let mut buf = vec![0u8; len];
+	memory.read(ctx, ptr, &mut buf).ok()?;
+	String::from_utf8(buf).ok()
+}
+
+/// Copies a string into guest memory through the guest's allocator
+fn write_str(instance: &Instance, memory: &Memory, mut ctx: impl AsContextMut, s: &str) -> Result<(i32,i32),wasmi::Error> {
+	let alloc = instance.get_typed_func::<i32,i32>(&ctx, "slash_alloc")?;
+	let ptr = alloc.call(&mut ctx, s.len() as i32)?;
+	memory.write(&mut ctx, ptr as usize, s.as_bytes()).map_err(|e| wasmi::Error::new(e.to_string()))?;
+	Ok((ptr, s.len() as i32))
+}
+
+fn link_host(engine: &Engine) -> Result<Linker<HostState>,wasmi::Error> {
+	let mut linker = Linker::new(engine);
+	linker.func_wrap("slash", "write", |caller: Caller<'_,HostState>, fd: i32, ptr: i32, len: i32| {
+		let Some(memory) = caller.get_export("memory").and_then(Extern::into_memory) else { return };
+		if let Some(data) = read_str(&memory, &caller, ptr as usize, len as usize) {
+			if let Ok(mut fd) = utils::SmartFD::new(fd) {
+				write!(fd, "{}", data).ok();
+				// The descriptor belongs to the shell, so don't let the SmartFD close it
+				let _ = fd.into_raw_fd();
+			}
+		}
+	})?;
+	linker.func_wrap("slash", "get_var", |mut caller: Caller<'_,HostState>, ptr: i32, len: i32| -> i64 {
+		let Some(memory) = caller.get_export("memory").and_then(Extern::into_memory) else { return 0 };
+		let Some(name) = read_str(&memory, &caller, ptr as usize, len as usize) else { return 0 };
+		let Some(val) = caller.data().vars.as_ref().and_then(|vars| vars.get_var(&name)) else { return 0 };
+		let val = val.to_string();
+		let Some(alloc) = caller.get_export("slash_alloc").and_then(Extern::into_func) else { return 0 };
+		let Ok(alloc) = alloc.typed::<i32,i32>(&caller) else { return 0 };
+		let Ok(out) = alloc.call(&mut caller, val.len() as i32) else { return 0 };
+		if memory.write(&mut caller, out as usize, val.as_bytes()).is_err() {
+			return 0
+		}
+		((out as i64) << 32) | val.len() as i64
+	})?;
+	Ok(linker)
+}
+
+impl Plugin {
+	pub fn load(path: &Path) -> SlashResult<Self> {
+		let name = path.file_stem().map(|stem| stem.to_string_lossy().to_string()).unwrap_or_default();
+		let bytes = std::fs::read(path).map_err(|e| plugin_err(format!("{}: {}",path.display(),e)))?;
+		Self::from_bytes(&name, &bytes)
+	}
+	pub fn from_bytes(name: &str, bytes: &[u8]) -> SlashResult<Self> {
+		let err = |e: wasmi::Error| plugin_err(format!("{}: {}",name,e));
+		let mut config = Config::default();
+		config.consume_fuel(true);
+		let engine = Engine::new(&config);
+		let module = Module::new(&engine, bytes).map_err(err)?;
+		let mut store = Store::new(&engine, HostState::default());
+		store.set_fuel(FUEL_PER_CALL).ok();
+		let linker = link_host(&engine).map_err(err)?;
+		let instance = linker.instantiate(&mut store, &module).and_then(|pre| pre.start(&mut store)).map_err(err)?;
+		let memory = instance.get_memory(&store, "memory").ok_or_else(|| plugin_err(format!("{}: no exported memory",name)))?;
+
+		let version = instance.get_typed_func::<(),i32>(&store, "slash_abi_version")
+			.and_then(|func| func.call(&mut store, ()))
+			.map_err(err)?;
+		if version != PLUGIN_ABI_VERSION {
+			return Err(plugin_err(format!("{} was built for plugin ABI version {}, expected {}",name,version,PLUGIN_ABI_VERSION)))
+		}
+
+		let mut plugin = Self { name: name.to_string(), store, instance, memory, builtins: vec![] };
+		if let Some(names) = plugin.call_str("slash_builtins", None)? {
+			plugin.builtins = names.lines().filter(|name| !name.is_empty()).map(|name| name.to_string()).collect();
+		}
+		Ok(plugin)
+	}
+	pub fn name(&self) -> &str {
+		&self.name
+	}
+	pub fn builtins(&self) -> &[String] {
+		&self.builtins
+	}
+	fn has_export(&self, export: &str) -> bool {
+		self.instance.get_func(&self.store, export).is_some()
+	}
+	fn prepare(&mut self, vars: Option<&VarTable>) {
+		self.store.data_mut().vars = vars.cloned();
+		self.store.set_fuel(FUEL_PER_CALL).ok();
+	}
+	fn read_packed(&self, packed: i64) -> Option<String> {
+		let (ptr,len) = unpack_ptr(packed)?;
+		read_str(&self.memory, &self.store, ptr, len)
+	}
+	/// Calls an export that takes an optional string argument, and returns an optional string
+	fn call_str(&mut self, export: &str, arg: Option<&str>) -> SlashResult<Option<String>> {
+		if !self.has_export(export) {
+			return Ok(None)
+		}
+		let name = self.name.clone();
+		let err = |e: wasmi::Error| plugin_err(format!("{}: {}",name,e));
+		let packed = match arg {
+			Some(arg) => {
+				let (ptr,len) = write_str(&self.instance, &self.memory, &mut self.store, arg).map_err(err)?;
+				let func = self.instance.get_typed_func::<(i32,i32),i64>(&self.store, export).map_err(err)?;
+				func.call(&mut self.store, (ptr,len)).map_err(err)?
+			}
+			None => {
+				let func = self.instance.get_typed_func::<(),i64>(&self.store, export).map_err(err)?;
+				func.call(&mut self.store, ()).map_err(err)?
+			}
+		};
+		Ok(self.read_packed(packed))
+	}
+	pub fn prompt_segment(&mut self, segment: &str, vars: &VarTable) -> SlashResult<Option<String>> {
+		self.prepare(Some(vars));
+		self.call_str("slash_prompt_segment", Some(segment))
+	}
+	pub fn complete(&mut self, line: &str, pos: usize, vars: &VarTable) -> SlashResult<Vec<String>> {
+		if !self.has_export("slash_complete") {
+			return Ok(vec![])
+		}
+		self.prepare(Some(vars));
+		let name = self.name.clone();
+		let err = |e: wasmi::Error| plugin_err(format!("{}: {}",name,e));
+		let (ptr,len) = write_str(&self.instance, &self.memory, &mut self.store, line).map_err(err)?;
+		let func = self.instance.get_typed_func::<(i32,i32,i32),i64>(&self.store, "slash_complete").map_err(err)?;
+		let packed = func.call(&mut self.store, (ptr,len,pos as i32)).map_err(err)?;
+		let candidates = self.read_packed(packed).unwrap_or_default();
+		Ok(candidates.lines().filter(|cand| !cand.is_empty()).map(|cand| cand.to_string()).collect())
+	}
+	pub fn run_builtin(&mut self, argv: &[String], vars: &VarTable) -> SlashResult<i32> {
+		self.prepare(Some(vars));
+		let name = self.name.clone();
+		let err = |e: wasmi::Error| plugin_err(format!("{}: {}",name,e));
+		let args = argv.join("\0");
+		let (ptr,len) = write_str(&self.instance, &self.memory, &mut self.store, &args).map_err(err)?;
+		let func = self.instance.get_typed_func::<(i32,i32),i32>(&self.store, "slash_run_builtin").map_err(err)?;
+		func.call(&mut self.store, (ptr,len)).map_err(err)
+	}
+}
+
+fn plugin_dir(slash: &Slash) -> PathBuf {
+	let dir = slash.meta().borrow_shopts().core.plugin_dir.clone();
+	if dir.is_empty() {
+		let home = env::var("HOME").unwrap_or_default();
+		PathBuf::from(format!("{home}/.config/slash/plugins"))
+	} else {
+		PathBuf::from(dir)
+	}
+}
+
+/// Loads every `.wasm` file in the plugin directory
+/// Plugins that fail to load are reported and skipped
+pub fn load_plugins(slash: &mut Slash) -> SlashResult<()> {
+	let Ok(entries) = std::fs::read_dir(plugin_dir(slash)) else {
+		return Ok(())
+	};
+	let mut paths = entries.flatten().map(|entry| entry.path()).filter(|path| path.extension().is_some_and(|ext| ext == "wasm")).collect::<Vec<_>>();
+	paths.sort();
+	for path in paths {
+		match Plugin::load(&path) {
+			Ok(plugin) => register(plugin)?,
+			Err(e) => eprintln!("{}",e)
+		}
+	}
+	Ok(())
+}
+
+pub fn register(plugin: Plugin) -> SlashResult<()> {
+	let mut plugins = lock_plugins()?;
+	plugins.retain(|loaded| loaded.name() != plugin.name());
+	plugins.push(plugin);
+	Ok(())
+}
+
+/// Asks each plugin for a prompt segment, used by `\{plugin.name\}` in the prompt
+pub fn prompt_segment(slash: &Slash, segment: &str) -> SlashResult<String> {
+	let mut plugins = lock_plugins()?;
+	for plugin in plugins.iter_mut() {
+		if let Some(output) = plugin.prompt_segment(segment, slash.vars())? {
+			return Ok(output)
+		}
+	}
+	Err(plugin_err(format!("no plugin provides the prompt segment `{}'",segment)))
+}
+
+/// Collects completion candidates from every plugin that offers any
+pub fn complete(slash: &Slash, line: &str, pos: usize) -> Vec<String> {
+	let Ok(mut plugins) = lock_plugins() else {
+		return vec![]
+	};
+	plugins.iter_mut().flat_map(|plugin| plugin.complete(line, pos, slash.vars()).unwrap_or_default()).collect()
+}
+
+pub fn has_builtin(name: &str) -> bool {
+	lock_plugins().is_ok_and(|plugins| plugins.iter().any(|plugin| plugin.builtins().iter().any(|builtin| builtin == name)))
+}
+
+/// Runs a builtin provided by a plugin, and sets the exit status to its return value
+pub fn exec_builtin<'a>(call: Pair<'a,Rule>, slash: &mut Slash) -> SlashResult<()> {
+	let blame = call.clone();
+	let redirs = helper::prepare_redirs(call.clone(),slash)?;
+	let argv = helper::prepare_argv(call,slash)?.into_iter().collect::<Vec<_>>();
+	let name = argv.first().cloned().unwrap_or_default();
+	slash.consume_redirs(redirs)?;
+	let code = {
+		let mut plugins = lock_plugins()?;
+		let plugin = plugins.iter_mut()
+			.find(|plugin| plugin.builtins().contains(&name))
+			.ok_or_else(|| High(SlashErrHigh::exec_err(format!("No plugin provides `{}'",name), blame.clone())))?;
+		plugin.run_builtin(&argv, slash.vars()).blame(blame)?
+	};
+	slash.set_code(code);
+	Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+	use crate::execute;
+
+use super::*;
+
+	const TEST_PLUGIN: &str = r#"
+		(module
+			(import "slash" "write" (func $write (param i32 i32 i32)))
+			(import "slash" "get_var" (func $get_var (param i32 i32) (result i64)))
+			(memory (export "memory") 1)
+			(global $next (mut i32) (i32.const 1024))
+			(data (i32.const 16) "wasm_greet")
+			(data (i32.const 32) "hi from wasm\n")
+			(data (i32.const 48) "USER_NAME")
+			(func (export "slash_abi_version") (result i32) (i32.const 1))
+			(func (export "slash_alloc") (param $len i32) (result i32)
+				(local $ptr i32)
+				(local.set $ptr (global.get $next))
+				(global.set $next (i32.add (global.get $next) (local.get $len)))
+				(local.get $ptr))
+			(func (export "slash_builtins") (result i64)
+				(i64.or (i64.shl (i64.const 16) (i64.const 32)) (i64.const 10)))
+			(func (export "slash_prompt_segment") (param i32 i32) (result i64)
+				(call $get_var (i32.const 48) (i32.const 9)))
+			(func (export "slash_run_builtin") (param i32 i32) (result i32)
+				(call $write (i32.const 1) (i32.const 32) (i32.const 13))
+				(i32.const 3)))
+	"#;
+
+	#[test]
+	fn test_wasm_plugin() {
+		let bytes = wat::parse_str(TEST_PLUGIN).unwrap();
+		register(Plugin::from_bytes("test", &bytes).unwrap()).unwrap();
+		assert!(has_builtin("wasm_greet"));
+
+		let mut slash = Slash::new();
+		execute::dispatch::exec_input("USER_NAME=wasm_user".to_string(), &mut slash).unwrap();
+		assert_eq!(prompt_segment(&slash, "user").unwrap(), "wasm_user");
+
+		let output = slash.exec_captured("wasm_greet").unwrap();
+		assert_eq!(output, "hi from wasm\n");
+		assert_eq!(slash.get_status(), 3);
+	}
+}
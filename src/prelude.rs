@@ -72,7 +72,6 @@ pub use nix::{
 		dup2,
 		execve,
 		execvpe,
-		fork,
 		pipe,
 		ForkResult,
 		Pid
@@ -93,6 +92,7 @@ pub use crate::{
 		SlashParse,
 	},
 	shellenv::Slash,
+	utils::fork,
 	helper::{
 		StrExtension,
 		StringExt,
@@ -1,10 +1,14 @@
+use std::time::Duration;
+
 use crossterm::{cursor::{self, MoveTo}, execute, terminal::{Clear, ClearType}};
-use rustyline::{completion::{Candidate, Completer, FilenameCompleter}, error::ReadlineError, Context};
-use skim::{prelude::{Key, SkimItemReader, SkimOptionsBuilder}, Skim};
+use once_cell::sync::Lazy;
+use regex::Regex;
+use rustyline::{completion::{Candidate, Completer, FilenameCompleter}, config::BellStyle, error::ReadlineError, Context};
+use skim::{prelude::{Key, SkimItemReader, SkimItemReaderOption, SkimOptionsBuilder}, Skim};
 
-use crate::{helper, prelude::*};
+use crate::{builtin, execute::dispatch, helper, path_cache, prelude::*, shellenv::{disable_reaping, enable_reaping, SlashVal}, term::{self, Style}};
 
-use super::prompt::SlashHelper;
+use super::{prompt::SlashHelper, rl_init};
 
 pub struct CompRegistry {
 	path_completer: FilenameCompleter,
@@ -46,12 +50,53 @@ pub enum CompType {
 	Services
 }
 
+bitflags! {
+	/// Options that change how the candidates from a completion spec are inserted, set with `complete -o` or `compopt -o`
+	#[derive(Debug,Clone,Copy,PartialEq,Eq)]
+	pub struct CompOpts: u8 {
+		const NOSPACE   = 0b0001; // Don't add a space after a completed word
+		const FILENAMES = 0b0010; // Candidates are paths, so directories get a trailing slash and special characters are escaped
+		const DIRNAMES  = 0b0100; // Fall back to directory names when there are no candidates
+		const NOSORT    = 0b1000; // Keep candidates in the order they were given
+	}
+}
+
+impl CompOpts {
+	const NAMES: [(&'static str, CompOpts);4] = [
+		("nospace", Self::NOSPACE),
+		("filenames", Self::FILENAMES),
+		("dirnames", Self::DIRNAMES),
+		("nosort", Self::NOSORT),
+	];
+	pub fn by_name(name: &str) -> Option<Self> {
+		Self::NAMES.iter().find(|(opt_name,_)| *opt_name == name).map(|(_,opt)| *opt)
+	}
+	/// The options as `-o name` arguments, in the form `complete` and `compopt` take them
+	pub fn to_args(self) -> String {
+		Self::NAMES.iter()
+			.filter(|(_,opt)| self.contains(*opt))
+			.map(|(name,_)| format!("-o {}", name))
+			.collect::<Vec<_>>()
+			.join(" ")
+	}
+}
+
+/// How the arguments of a command are completed, registered with `complete`
+#[derive(Clone,Debug)]
+pub struct CompSpec {
+	/// The function that fills `COMPREPLY` with candidates
+	pub func: Option<String>,
+	pub opts: CompOpts
+}
+
 #[derive(Clone,Debug)]
 pub struct CompOption {
 	value: String,
 	desc: Option<String>,
 	comp_type: CompType,
-	priority: usize
+	priority: usize,
+	/// What is inserted into the line, which can differ from the value by a trailing space or slash, or by escaping
+	replacement: String
 }
 
 impl Candidate for CompOption {
@@ -59,7 +104,7 @@ impl Candidate for CompOption {
 		&self.value
 	}
 	fn replacement(&self) -> &str {
-	  &self.value
+	  &self.replacement
 	}
 }
 
@@ -69,8 +114,38 @@ impl CompOption {
 			value: path.to_string(),
 			desc: None,
 			comp_type: CompType::Paths,
-			priority: 0
+			priority: 0,
+			replacement: path.to_string()
+		}
+	}
+	/// A candidate produced by a completion spec, inserted according to the spec's options
+	pub fn from_spec(value: &str, opts: CompOpts) -> Self {
+		let is_dir = opts.intersects(CompOpts::FILENAMES | CompOpts::DIRNAMES) && Path::new(value).is_dir();
+		let mut replacement = if opts.contains(CompOpts::FILENAMES) {
+			value.chars().fold(String::new(), |mut escaped, ch| {
+				if " \t\"'\\$`&|;<>()*?[]#~".contains(ch) {
+					escaped.push('\\');
+				}
+				escaped.push(ch);
+				escaped
+			})
+		} else {
+			value.to_string()
+		};
+		if is_dir && !replacement.ends_with('/') {
+			replacement.push('/');
+		} else if !is_dir && !opts.contains(CompOpts::NOSPACE) {
+			replacement.push(' ');
 		}
+		Self { value: value.to_string(), desc: None, comp_type: CompType::Paths, priority: 0, replacement }
+	}
+	pub fn replacement_str(&self) -> &str {
+		&self.replacement
+	}
+	/// The option as it is shown in completion listings
+	pub fn styled(&self) -> String {
+		let style = if Path::new(&self.value).is_dir() { Style::CompDirectory } else { Style::CompFile };
+		term::paint(style, &self.value)
 	}
 	pub fn by_type(categories: Vec<CompType>) -> Vec<Self> {
 		let mut options = vec![];
@@ -102,7 +177,42 @@ impl<'a> Completer for SlashHelper<'a> {
 		pos: usize,
 		ctx: &Context<'_>,
 	) -> Result<(usize, Vec<Self::Candidate>), ReadlineError> {
+		let result = self.find_completions(line, pos, ctx);
+		// The editor rings an audible bell on its own when nothing matches, but does nothing for a visible one
+		if result.as_ref().is_ok_and(|(_,comps)| comps.is_empty()) && rl_init::bell_style(self.slash.meta()) == BellStyle::Visible {
+			term::visible_bell();
+		}
+		result
+	}
+}
+
+impl<'a> SlashHelper<'a> {
+	fn find_completions(
+		&self,
+		line: &str,
+		pos: usize,
+		ctx: &Context<'_>,
+	) -> Result<(usize, Vec<CompOption>), ReadlineError> {
 		let mut completions = Vec::new();
+		#[cfg(feature = "wasm-plugins")]
+		{
+			let plugin_comps = crate::plugin::complete(self.slash, line, pos);
+			if !plugin_comps.is_empty() {
+				// Plugin candidates replace the word under the cursor
+				let start = line[..pos].rfind(char::is_whitespace).map(|i| i + 1).unwrap_or(0);
+				return Ok((start, plugin_comps.iter().map(|comp| CompOption::path(comp)).collect()))
+			}
+		}
+		let comps = complete_from_spec(self.slash, line, pos)
+			.or_else(|| complete_from_context(self.slash, line, pos));
+		if let Some((start, mut comp_opts)) = comps {
+			if comp_opts.len() > 1 {
+				if let Some(selected) = skim_comp(comp_opts.clone()) {
+					comp_opts.retain(|opt| opt.value == selected);
+				}
+			}
+			return Ok((start, comp_opts))
+		}
 		let line = line.to_string();
 		let num_words = line.split_whitespace().count();
 
@@ -143,12 +253,7 @@ impl<'a> Completer for SlashHelper<'a> {
 		);
 
 		let mut comp_opts = completions.into_iter().map(|opt| {
-			CompOption {
-				value: opt,
-				desc: None,
-				comp_type: CompType::Paths,
-				priority: 0
-			}
+			CompOption::path(&opt)
 		}).collect::<Vec<CompOption>>();
 		// Invoke fuzzyfinder if there are matches
 		if comp_opts.len() > 1 {
@@ -167,6 +272,393 @@ impl<'a> Completer for SlashHelper<'a> {
 	}
 }
 
+/// Splits the line into the words given to a completion function
+/// Returns the words, the index of the word under the cursor, and where that word starts in the line
+fn comp_words(line: &str, pos: usize) -> (Vec<String>, usize, usize) {
+	let before = &line[..pos];
+	let start = before.rfind(char::is_whitespace).map(|i| i + 1).unwrap_or(0);
+	let mut words = before[..start].split_whitespace().map(String::from).collect::<Vec<_>>();
+	let cword = words.len();
+	let end = line[pos..].find(char::is_whitespace).map(|i| pos + i).unwrap_or(line.len());
+	words.push(line[start..end].to_string());
+	words.extend(line[end..].split_whitespace().map(String::from));
+	(words, cword, start)
+}
+
+/// The paths that start with the word being completed, or only the directories if `dirs_only` is set
+/// Relative words are looked up in `base`, and a leading `~/` is looked up in the home directory
+fn path_candidates(word: &str, base: &Path, dirs_only: bool) -> Vec<String> {
+	let (dir, prefix) = match word.rfind('/') {
+		Some(i) => (&word[..=i], &word[i + 1..]),
+		None => ("", word)
+	};
+	let search = match dir.strip_prefix("~/") {
+		Some(rest) => PathBuf::from(env::var("HOME").unwrap_or_default()).join(rest),
+		None => base.join(dir)
+	};
+	let Ok(entries) = std::fs::read_dir(search) else {
+		return vec![]
+	};
+	let mut paths = entries.flatten()
+		.filter(|entry| !dirs_only || entry.path().is_dir())
+		.filter_map(|entry| entry.file_name().into_string().ok())
+		.filter(|name| name.starts_with(prefix) && (!name.starts_with('.') || prefix.starts_with('.')))
+		.map(|name| format!("{}{}", dir, name))
+		.collect::<Vec<_>>();
+	paths.sort();
+	paths
+}
+
+/// The directories that start with the word being completed
+fn dir_candidates(word: &str) -> Vec<String> {
+	path_candidates(word, Path::new("."), true)
+}
+
+/// External commands whose arguments are directories. Builtins say so in their spec
+const DIR_COMMANDS: [&str;1] = ["rmdir"];
+/// Commands whose arguments are hostnames, optionally given as `user@host`
+const HOST_COMMANDS: [&str;5] = ["ssh", "sftp", "mosh", "ping", "telnet"];
+
+/// The word being completed and the words around it
+pub struct CompCtx<'a> {
+	pub words: Vec<String>,
+	/// The index of the word being completed
+	pub cword: usize,
+	/// Where the word being completed starts in the line
+	pub start: usize,
+	/// The word being completed, up to the cursor
+	pub word: &'a str
+}
+
+impl CompCtx<'_> {
+	pub fn cmd(&self) -> Option<&str> {
+		(self.cword > 0).then(|| self.words[0].as_str())
+	}
+	/// Whether the word is an option, which it can't be once `--` has been given
+	pub fn is_option(&self) -> bool {
+		self.word.starts_with('-') && !self.words[1..self.cword.max(1)].iter().any(|word| word == "--")
+	}
+}
+
+/// Offers candidates for the word being completed, along with where they start in the line,
+/// or returns `None` to leave the word to the next provider
+pub type CompProvider = fn(&Slash, &CompCtx) -> Option<(usize, Vec<CompOption>)>;
+
+/// The providers asked for candidates when a command has no spec, in order. The first one to answer wins
+const PROVIDERS: [CompProvider;8] = [signal_comps, dir_stack_comps, user_comps, help_comps, option_comps, builtin_comps, host_comps, dir_comps];
+
+/// Completes arguments using what is known about the command and the word's position, for commands without a spec.
+/// Returns `None` when no provider has anything to go on, leaving the word to the usual path completion
+pub fn complete_from_context(slash: &Slash, line: &str, pos: usize) -> Option<(usize, Vec<CompOption>)> {
+	let (words, cword, start) = comp_words(line, pos);
+	let ctx = CompCtx { words, cword, start, word: &line[start..pos] };
+	PROVIDERS.iter().find_map(|provider| provider(slash, &ctx))
+}
+
+/// Signal names for `kill`, as `-SIGNAME`, `-NAME`, or the argument of `-s`
+fn signal_comps(_slash: &Slash, ctx: &CompCtx) -> Option<(usize, Vec<CompOption>)> {
+	if ctx.cmd() != Some("kill") {
+		return None
+	}
+	let after_flag = ctx.words[ctx.cword - 1] == "-s";
+	let prefix = if after_flag {
+		""
+	} else if ctx.word.starts_with("-SIG") {
+		"-SIG"
+	} else if ctx.word.starts_with('-') && ctx.word[1..].chars().all(|ch| ch.is_ascii_uppercase()) {
+		"-"
+	} else {
+		return None
+	};
+	let name = &ctx.word[prefix.len()..];
+	let candidates = Signal::iterator()
+		.map(|sig| sig.as_str().trim_start_matches("SIG"))
+		.filter(|sig| sig.starts_with(name))
+		.map(|sig| CompOption::from_spec(&format!("{}{}", prefix, sig), CompOpts::empty()))
+		.collect();
+	Some((ctx.start, candidates))
+}
+
+/// Entries of the directory stack for words like `~+2` or `~-`, which are replaced with the directory they refer to
+fn dir_stack_comps(slash: &Slash, ctx: &CompCtx) -> Option<(usize, Vec<CompOption>)> {
+	let index = ctx.word.strip_prefix("~+").or_else(|| ctx.word.strip_prefix("~-"))?;
+	if !index.chars().all(|ch| ch.is_ascii_digit()) {
+		return None
+	}
+	let sign = &ctx.word[1..2];
+	let count = helper::dir_stack(slash).len();
+	let candidates = (0..count)
+		.map(|i| format!("{}{}", sign, i))
+		.filter(|arg| arg[1..].starts_with(index))
+		.filter_map(|arg| helper::dir_stack_entry(slash, &arg))
+		.map(|dir| CompOption::from_spec(&dir.to_string_lossy(), CompOpts::FILENAMES | CompOpts::NOSPACE))
+		.collect();
+	Some((ctx.start, candidates))
+}
+
+/// Usernames from the passwd database, for words like `~use`
+fn user_comps(_slash: &Slash, ctx: &CompCtx) -> Option<(usize, Vec<CompOption>)> {
+	let name = ctx.word.strip_prefix('~').filter(|name| !name.contains('/'))?;
+	let passwd = std::fs::read_to_string("/etc/passwd").unwrap_or_default();
+	let mut users = passwd.lines()
+		.filter_map(|line| line.split(':').next())
+		.filter(|user| !user.is_empty() && !user.starts_with('#') && user.starts_with(name))
+		.map(|user| format!("~{}/", user))
+		.collect::<Vec<_>>();
+	users.sort();
+	users.dedup();
+	Some((ctx.start, users.iter().map(|user| CompOption::from_spec(user, CompOpts::NOSPACE)).collect()))
+}
+
+/// Words that start with `-` are options, so no paths are offered for them unless they are written `--opt=path`
+fn option_comps(_slash: &Slash, ctx: &CompCtx) -> Option<(usize, Vec<CompOption>)> {
+	if ctx.cword == 0 || !ctx.is_option() {
+		return None
+	}
+	// Only the value of a long option can be a path
+	let Some(eq) = ctx.word.find('=').filter(|_| ctx.word.starts_with("--")) else {
+		return Some((ctx.start, vec![]))
+	};
+	let candidates = path_candidates(&ctx.word[eq + 1..], Path::new("."), false);
+	Some((ctx.start + eq + 1, candidates.iter().map(|path| CompOption::from_spec(path, CompOpts::FILENAMES)).collect()))
+}
+
+/// How long `cmd --help` is given to print its flags before it is killed
+const HELP_TIMEOUT: Duration = Duration::from_millis(500);
+/// The flags found in each executable's `--help` output, so that each one is only run once
+static HELP_FLAGS: Lazy<Mutex<HashMap<PathBuf,Vec<String>>>> = Lazy::new(|| Mutex::new(HashMap::new()));
+
+/// Lets the flag cache be locked again after a panic that happened while it was held
+pub fn clear_poison() {
+	HELP_FLAGS.clear_poison();
+}
+
+/// Flags for executables without a spec, taken from what they print for `--help`
+/// Returns `None` when no flags were found, so that the word is left to the other providers
+fn help_comps(slash: &Slash, ctx: &CompCtx) -> Option<(usize, Vec<CompOption>)> {
+	if !slash.meta().borrow_shopts().prompt.help_flags || ctx.cword == 0 || !ctx.is_option() || ctx.word.contains('=') {
+		return None
+	}
+	let cmd = ctx.cmd().filter(|cmd| !builtin::is_builtin(cmd))?;
+	let path = helper::search_path(slash, cmd)?;
+	let mut cache = HELP_FLAGS.lock().ok()?;
+	let flags = cache.entry(path).or_insert_with_key(|path| parse_help_flags(&run_help(path, HELP_TIMEOUT).unwrap_or_default()));
+	if flags.is_empty() {
+		return None
+	}
+	let candidates = flags.iter()
+		.filter(|flag| flag.starts_with(ctx.word))
+		.map(|flag| CompOption::from_spec(flag, CompOpts::empty()))
+		.collect();
+	Some((ctx.start, candidates))
+}
+
+/// Runs `path --help` and returns everything it printed, or `None` if it didn't finish within `timeout`
+/// The child is kept away from the terminal and the working directory: it reads from `/dev/null`,
+/// runs in the temp directory in its own process group, and any pager it might start just prints.
+/// It only gets `PATH` and `LANG` from the environment, so that secrets in other variables don't reach it,
+/// and it is limited to a second of CPU time, can't write to files, and can't start other processes
+fn run_help(path: &Path, timeout: Duration) -> Option<String> {
+	use std::os::unix::process::CommandExt;
+	use std::process::{Command, Stdio};
+	let kept = ["PATH", "LANG"].into_iter().filter_map(|key| Some((key, env::var_os(key)?)));
+	let mut cmd = Command::new(path);
+	cmd.arg("--help")
+		.current_dir(env::temp_dir())
+		.env_clear()
+		.envs(kept)
+		.env("PAGER", "cat")
+		.env("MANPAGER", "cat")
+		.env("TERM", "dumb")
+		.stdin(Stdio::null())
+		.stdout(Stdio::piped())
+		.stderr(Stdio::piped())
+		.process_group(0);
+	unsafe {
+		cmd.pre_exec(|| {
+			let limits = [(libc::RLIMIT_CPU, 1), (libc::RLIMIT_FSIZE, 0), (libc::RLIMIT_NPROC, 0)];
+			for (resource,max) in limits {
+				let limit = libc::rlimit { rlim_cur: max, rlim_max: max };
+				if libc::setrlimit(resource, &limit) != 0 {
+					return Err(std::io::Error::last_os_error())
+				}
+			}
+			Ok(())
+		});
+	}
+	disable_reaping();
+	let child = cmd.spawn();
+	let Ok(child) = child else {
+		enable_reaping().ok();
+		return None
+	};
+	let pid = Pid::from_raw(child.id() as i32);
+	// The output is read on another thread, so that a child which never finishes can be killed from here
+	let (sender, receiver) = std::sync::mpsc::channel();
+	std::thread::spawn(move || sender.send(child.wait_with_output()));
+	let output = match receiver.recv_timeout(timeout) {
+		Ok(output) => output.ok(),
+		Err(_) => {
+			nix::sys::signal::killpg(pid, Signal::SIGKILL).ok();
+			receiver.recv().ok();
+			None
+		}
+	};
+	enable_reaping().ok();
+	let output = output?;
+	let mut text = String::from_utf8_lossy(&output.stdout).to_string();
+	text.push_str(&String::from_utf8_lossy(&output.stderr));
+	Some(text)
+}
+
+/// The flags mentioned in help text, like `-a`, `--all`, or `--color` from `--color[=WHEN]`
+/// Only words that start a line or follow whitespace, a comma, `[`, or `|` count, so hyphenated prose is skipped
+fn parse_help_flags(text: &str) -> Vec<String> {
+	let re = Regex::new(r"(?:^|[\s,\[|])(--?[[:alnum:]][[:alnum:]_-]*)").unwrap();
+	let mut flags = re.captures_iter(text)
+		.map(|caps| caps[1].trim_end_matches('-').to_string())
+		.collect::<Vec<_>>();
+	flags.sort();
+	flags.dedup();
+	flags
+}
+
+/// Names for the arguments of builtins that take commands, variables, aliases, or other builtins, as their spec says
+fn builtin_comps(slash: &Slash, ctx: &CompCtx) -> Option<(usize, Vec<CompOption>)> {
+	let spec = builtin::lookup(ctx.cmd()?)?;
+	let mut names: Vec<String> = match spec.comp.as_ref()? {
+		CompType::Builtins => builtin::BUILTINS.iter().map(|spec| spec.name.to_string()).collect(),
+		CompType::Aliases => slash.logic().borrow_aliases().keys().cloned().collect(),
+		CompType::Variables => {
+			slash.vars().vars().keys().cloned().collect()
+		}
+		CompType::Commands => {
+			let mut names = builtin::BUILTINS.iter().map(|spec| spec.name.to_string()).collect::<Vec<_>>();
+			names.extend(slash.logic().borrow_aliases().keys().cloned());
+			names.extend(slash.logic().borrow_functions().keys().cloned());
+			names.extend(path_cache::executables(&helper::shell_path(slash)).into_iter().filter(|name| name.starts_with(ctx.word)));
+			names
+		}
+		_ => return None
+	};
+	names.retain(|name| name.starts_with(ctx.word));
+	names.sort();
+	names.dedup();
+	Some((ctx.start, names.iter().map(|name| CompOption::from_spec(name, CompOpts::empty())).collect()))
+}
+
+/// Hostnames from `~/.ssh/known_hosts` and `~/.ssh/config` for commands like `ssh`, keeping any `user@` in front
+fn host_comps(_slash: &Slash, ctx: &CompCtx) -> Option<(usize, Vec<CompOption>)> {
+	if !ctx.cmd().is_some_and(|cmd| HOST_COMMANDS.contains(&cmd)) || ctx.is_option() {
+		return None
+	}
+	let (user, host) = match ctx.word.rfind('@') {
+		Some(i) => ctx.word.split_at(i + 1),
+		None => ("", ctx.word)
+	};
+	let home = PathBuf::from(env::var("HOME").unwrap_or_default());
+	let candidates = ssh_hosts(&home).into_iter()
+		.filter(|name| name.starts_with(host))
+		.map(|name| CompOption::from_spec(&format!("{}{}", user, name), CompOpts::empty()))
+		.collect();
+	Some((ctx.start, candidates))
+}
+
+/// The hosts named in the ssh files under `home`. Hashed entries and patterns are skipped
+fn ssh_hosts(home: &Path) -> Vec<String> {
+	let ssh_dir = home.join(".ssh");
+	let mut hosts = vec![];
+	let known_hosts = std::fs::read_to_string(ssh_dir.join("known_hosts")).unwrap_or_default();
+	for line in known_hosts.lines().filter(|line| !line.starts_with(['#', '|', '@'])) {
+		let names = line.split_whitespace().next().unwrap_or_default();
+		for name in names.split(',') {
+			// Hosts on other ports are written [host]:port
+			let name = name.strip_prefix('[').and_then(|name| name.split_once("]:")).map(|(name,_)| name).unwrap_or(name);
+			hosts.push(name.to_string());
+		}
+	}
+	let config = std::fs::read_to_string(ssh_dir.join("config")).unwrap_or_default();
+	for line in config.lines() {
+		let mut words = line.split_whitespace();
+		if words.next().is_some_and(|key| key.eq_ignore_ascii_case("host")) {
+			hosts.extend(words.filter(|name| !name.contains(['*', '?', '!'])).map(String::from));
+		}
+	}
+	hosts.retain(|name| !name.is_empty());
+	hosts.sort();
+	hosts.dedup();
+	hosts
+}
+
+/// The arguments of builtins like `cd` and `pushd`, and of `rmdir`, are completed with directories only
+/// The builtins also get the directories under `CDPATH`
+fn dir_comps(slash: &Slash, ctx: &CompCtx) -> Option<(usize, Vec<CompOption>)> {
+	let cmd = ctx.cmd()?;
+	let is_builtin = builtin::lookup(cmd).is_some_and(|spec| matches!(spec.comp, Some(CompType::Directories)));
+	if !is_builtin && !DIR_COMMANDS.contains(&cmd) {
+		return None
+	}
+	let word = ctx.word;
+	let mut candidates = dir_candidates(word);
+	let relative = !word.starts_with(['/', '.', '~']);
+	if relative && is_builtin {
+		let cd_path = slash.vars().get_var("CDPATH").map(|val| val.to_string()).unwrap_or_default();
+		for dir in cd_path.split(':').filter(|dir| !dir.is_empty()) {
+			// These aren't directories here, so the slash that marks them is added up front
+			candidates.extend(path_candidates(word, Path::new(dir), true).into_iter().map(|path| format!("{}/", path)));
+		}
+	}
+	candidates.sort();
+	candidates.dedup_by(|a, b| a.trim_end_matches('/') == b.trim_end_matches('/'));
+	let opts = CompOpts::FILENAMES | CompOpts::NOSPACE;
+	Some((ctx.start, candidates.iter().map(|path| CompOption::from_spec(path, opts)).collect()))
+}
+
+/// Completes the arguments of a command that has a spec registered with `complete`
+/// The spec's function runs with `COMP_WORDS`, `COMP_CWORD`, `COMP_LINE`, and `COMP_POINT` set, and is given the command,
+/// the word being completed, and the word before it as `$1`, `$2`, and `$3`. The candidates are read back from `COMPREPLY`, which can be an array or a string of words.
+/// The function runs in a copy of the shell, so that it can't change anything, but it can call `compopt` to change the
+/// options of the completion in progress. Returns the start of the word being completed along with the candidates,
+/// or `None` if the command has no spec
+pub fn complete_from_spec(slash: &Slash, line: &str, pos: usize) -> Option<(usize, Vec<CompOption>)> {
+	let (words, cword, start) = comp_words(line, pos);
+	if cword == 0 {
+		return None
+	}
+	let spec = slash.logic().get_comp_spec(&words[0])?;
+	let mut comp_slash = slash.clone();
+	comp_slash.meta_mut().set_comp_opts(Some(spec.opts));
+	let mut candidates = vec![];
+	if let Some(body) = spec.func.as_ref().and_then(|func| comp_slash.logic().get_func(func)) {
+		let vars = comp_slash.vars_mut();
+		vars.reset_params();
+		for arg in [&words[0], &line[start..pos].to_string(), &words[cword - 1]] {
+			vars.pos_param_pushback(arg);
+		}
+		vars.set_var("COMP_WORDS", SlashVal::Array(words.iter().cloned().map(SlashVal::String).collect()));
+		vars.set_var("COMP_CWORD", SlashVal::Int(cword as i32));
+		vars.set_var("COMP_LINE", SlashVal::String(line.to_string()));
+		vars.set_var("COMP_POINT", SlashVal::Int(pos as i32));
+		vars.unset_var("COMPREPLY");
+		dispatch::exec_input(body, &mut comp_slash).ok();
+		candidates = match comp_slash.vars().get_var("COMPREPLY") {
+			Some(SlashVal::Array(reply)) => reply.iter().map(|val| val.to_string()).collect(),
+			// Array elements aren't expanded, so a string of whitespace separated candidates is accepted too
+			Some(reply) => reply.to_string().split_whitespace().map(String::from).collect(),
+			None => vec![]
+		};
+	}
+	let opts = comp_slash.meta().comp_opts().unwrap_or(spec.opts);
+	if candidates.is_empty() && opts.contains(CompOpts::DIRNAMES) {
+		candidates = dir_candidates(&line[start..pos]);
+	}
+	if !opts.contains(CompOpts::NOSORT) {
+		candidates.sort();
+	}
+	candidates.dedup();
+	Some((start, candidates.iter().map(|value| CompOption::from_spec(value, opts)).collect()))
+}
+
 pub fn skim_comp(options: Vec<CompOption>) -> Option<String> {
 	let mut stdout = io::stdout();
 
@@ -176,8 +668,10 @@ pub fn skim_comp(options: Vec<CompOption>) -> Option<String> {
 	let height = options.len().min(10) as u16; // Set maximum number of options to display
 
 	// Prepare options for skim
-	let options_join = options.iter().map(|opt| opt.to_string()).collect::<Vec<String>>().join("\n");
-	let input = SkimItemReader::default().of_bufread(std::io::Cursor::new(options_join));
+	let options_join = options.iter().map(|opt| opt.styled()).collect::<Vec<String>>().join("\n");
+	// Skim strips the escape codes back out of the selected item
+	let reader = SkimItemReader::new(SkimItemReaderOption::default().ansi(true).build());
+	let input = reader.of_bufread(std::io::Cursor::new(options_join));
 
 	let skim_options = SkimOptionsBuilder::default()
 		.prompt(String::new())
@@ -215,3 +709,80 @@ pub fn skim_comp(options: Vec<CompOption>) -> Option<String> {
 
 		selected
 }
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn values(comps: Option<(usize, Vec<CompOption>)>) -> Vec<String> {
+		comps.unwrap().1.iter().map(|comp| comp.replacement_str().to_string()).collect()
+	}
+
+	#[test]
+	fn test_bell_style() {
+		let mut slash = Slash::new();
+		assert_eq!(rl_init::bell_style(slash.meta()), BellStyle::Audible);
+		dispatch::exec_input("setopt core.bell_style=2".into(), &mut slash).unwrap();
+		assert_eq!(rl_init::bell_style(slash.meta()), BellStyle::Visible);
+		assert!(dispatch::exec_input("setopt core.bell_style=3".into(), &mut slash).is_err());
+		assert_eq!(rl_init::bell_style(slash.meta()), BellStyle::Visible);
+		dispatch::exec_input("setopt core.bell_style=0".into(), &mut slash).unwrap();
+		assert_eq!(rl_init::bell_style(slash.meta()), BellStyle::None);
+	}
+
+	#[test]
+	fn test_comp_providers() {
+		let slash = Slash::new();
+		assert_eq!(values(complete_from_context(&slash, "kill -SIGTE", 11)), ["-SIGTERM "]);
+		assert_eq!(values(complete_from_context(&slash, "kill -KI", 8)), ["-KILL "]);
+		assert_eq!(values(complete_from_context(&slash, "kill -s HU", 10)), ["HUP "]);
+		assert_eq!(values(complete_from_context(&slash, "ls ~roo", 7)), ["~root/"]);
+		// Options are left alone once -- is given
+		assert!(complete_from_context(&slash, "ls -- -", 7).is_none());
+
+		let home = env::temp_dir().join(format!("slash_ssh_hosts_{}",std::process::id()));
+		std::fs::create_dir_all(home.join(".ssh")).unwrap();
+		let known_hosts = "alpha.example,10.0.0.1 ssh-ed25519 AAAA\n[beta.example]:2222 ssh-rsa AAAA\n|1|hashed= ssh-rsa AAAA\n";
+		std::fs::write(home.join(".ssh/known_hosts"), known_hosts).unwrap();
+		std::fs::write(home.join(".ssh/config"), "Host gamma *.internal\n  HostName gamma.example\n").unwrap();
+		assert_eq!(ssh_hosts(&home), ["10.0.0.1", "alpha.example", "beta.example", "gamma"]);
+		std::fs::remove_dir_all(home).ok();
+	}
+
+	#[test]
+	fn test_help_flags() {
+		let help = "Usage: frob [OPTION]... [FILE]...\n  -a, --all            do not ignore entries\n      --color[=WHEN]   colorize the output; non-empty WHEN\n  -v|--verbose\n";
+		assert_eq!(parse_help_flags(help), ["--all", "--color", "--verbose", "-a", "-v"]);
+
+		let dir = env::temp_dir().join(format!("slash_help_flags_{}",std::process::id()));
+		std::fs::create_dir_all(&dir).unwrap();
+		let script = dir.join("frob");
+		std::fs::write(&script, "#!/bin/sh\necho '  -q, --quiet' >&2\n").unwrap();
+		let slow = dir.join("slow");
+		std::fs::write(&slow, "#!/bin/sh\nsleep 5\n").unwrap();
+		for path in [&script, &slow] {
+			std::fs::set_permissions(path, std::os::unix::fs::PermissionsExt::from_mode(0o755)).unwrap();
+		}
+		let timeout = Duration::from_millis(200);
+		assert_eq!(parse_help_flags(&run_help(&script, timeout).unwrap()), ["--quiet", "-q"]);
+		assert!(run_help(&slow, timeout).is_none());
+
+		// Only PATH and LANG are passed on, and files can't be written
+		let nosy = dir.join("nosy");
+		std::fs::write(&nosy, format!(r#"#!/bin/sh
+echo "[$HOME]"
+echo --leak > {}/written
+"#,dir.display())).unwrap();
+		std::fs::set_permissions(&nosy, std::os::unix::fs::PermissionsExt::from_mode(0o755)).unwrap();
+		assert!(run_help(&nosy, Duration::from_secs(2)).unwrap().starts_with("[]\n"));
+		assert_eq!(std::fs::read_to_string(dir.join("written")).unwrap_or_default(), "");
+
+		// Commands are only run for their flags with prompt.help_flags
+		let mut slash = Slash::new();
+		let line = format!("{} -",script.display());
+		assert!(complete_from_context(&slash, &line, line.len()).unwrap().1.is_empty());
+		slash.meta_mut().set_shopt("prompt.help_flags", "true").unwrap();
+		assert_eq!(complete_from_context(&slash, &line, line.len()).unwrap().1.len(), 2);
+		std::fs::remove_dir_all(dir).ok();
+	}
+}
@@ -1,39 +1,9 @@
 use rustyline::highlight::Highlighter;
 
-use crate::{builtin::BUILTINS, prelude::*};
+use crate::{builtin, helper, prelude::*, term::{self, Style}};
 
 use super::prompt::SlashHelper;
 
-pub const RESET: &str = "\x1b[0m";
-pub const BLACK: &str = "\x1b[30m";
-pub const RED: &str = "\x1b[1;31m";
-pub const GREEN: &str = "\x1b[32m";
-pub const YELLOW: &str = "\x1b[33m";
-pub const BLUE: &str = "\x1b[34m";
-pub const MAGENTA: &str = "\x1b[35m";
-pub const CYAN: &str = "\x1b[36m";
-pub const WHITE: &str = "\x1b[37m";
-pub const BRIGHT_BLACK: &str = "\x1b[90m";
-pub const BRIGHT_RED: &str = "\x1b[91m";
-pub const BRIGHT_GREEN: &str = "\x1b[92m";
-pub const BRIGHT_YELLOW: &str = "\x1b[93m";
-pub const BRIGHT_BLUE: &str = "\x1b[94m";
-pub const BRIGHT_MAGENTA: &str = "\x1b[95m";
-pub const BRIGHT_CYAN: &str = "\x1b[96m";
-pub const BRIGHT_WHITE: &str = "\x1b[97m";
-
-pub const ERROR: &str = RED;
-pub const COMMAND: &str = GREEN;
-pub const KEYWORD: &str = YELLOW;
-pub const STRING: &str = BLUE;
-pub const ESCAPED: &str = CYAN;
-pub const OPERATOR: &str = CYAN;
-pub const NUMBER: &str = BRIGHT_BLUE;
-pub const PATH: &str = BRIGHT_CYAN;
-pub const VARSUB: &str = MAGENTA;
-pub const COMMENT: &str = BRIGHT_BLACK;
-pub const FUNCNAME: &str = CYAN;
-
 #[derive(Debug)]
 struct SlashHighlighter<'a> {
 	expect: Vec<Vec<Rule>>,
@@ -53,17 +23,15 @@ impl<'a> SlashHighlighter<'a> {
 		self.expect.last().is_some_and(|expect| expect.contains(&rule))
 	}
 
-	pub fn validate_cmd(&self,target: &str, path: &str) -> bool {
-		if target.is_empty() || path.is_empty() {
+	pub fn validate_cmd(&self,target: &str) -> bool {
+		if target.is_empty() {
 			return false
 		}
 		let logic = self.slash.logic().clone();
-		let is_cmd = path.split(':')
-			.map(Path::new)
-			.any(|p| p.join(target).exists());
+		let is_cmd = helper::search_path(self.slash, target).is_some();
 			let is_func = logic.get_func(target).is_some();
 			let is_alias = logic.get_alias(target).is_some();
-			let is_builtin = BUILTINS.contains(&target);
+			let is_builtin = builtin::is_builtin(target);
 			let is_file = {
 				let mut path_cand = target.to_string();
 				if path_cand.starts_with("~/") {
@@ -78,8 +46,8 @@ impl<'a> SlashHighlighter<'a> {
 			is_cmd | is_func | is_alias | is_builtin | is_file
 	}
 
-	fn style_text(&self,code: &str, text: &str) -> String {
-		format!("{code}{text}{RESET}")
+	fn style_text(&self,style: Style, text: &str) -> String {
+		term::paint(style, text)
 	}
 
 	fn highlight_struct(&mut self,pair: Pair<'a,Rule>, mut buffer: String) -> String {
@@ -90,76 +58,76 @@ impl<'a> SlashHighlighter<'a> {
 			Rule::select |
 			Rule::r#for => {
 				self.expect.push(vec![Rule::in_kw]);
-				let kw = self.style_text(KEYWORD, struct_pair.as_str());
+				let kw = self.style_text(Style::HlKeyword, struct_pair.as_str());
 				buffer.replace_span(span,&kw);
 				buffer
 			}
 			Rule::r#if | Rule::elif => {
 				self.expect.push(vec![Rule::r#then]);
-				let kw = self.style_text(KEYWORD, struct_pair.as_str());
+				let kw = self.style_text(Style::HlKeyword, struct_pair.as_str());
 				buffer.replace_span(span,&kw);
 				buffer
 			}
 			Rule::then if self.expecting(Rule::then) => {
 				self.expect.pop();
 				self.expect.push(vec![Rule::elif,Rule::r#else,Rule::fi]);
-				let kw = self.style_text(KEYWORD, struct_pair.as_str());
+				let kw = self.style_text(Style::HlKeyword, struct_pair.as_str());
 				buffer.replace_span(span,&kw);
 				buffer
 			}
 			Rule::r#else if self.expecting(Rule::r#else) => {
 				self.expect.pop();
 				self.expect.push(vec![Rule::fi]);
-				let kw = self.style_text(KEYWORD, struct_pair.as_str());
+				let kw = self.style_text(Style::HlKeyword, struct_pair.as_str());
 				buffer.replace_span(span,&kw);
 				buffer
 			}
 			Rule::fi if self.expecting(Rule::fi) => {
 				self.expect.pop();
-				let kw = self.style_text(KEYWORD, struct_pair.as_str());
+				let kw = self.style_text(Style::HlKeyword, struct_pair.as_str());
 				buffer.replace_span(span,&kw);
 				buffer
 			}
 			Rule::r#while |
 			Rule::until => {
 				self.expect.push(vec![Rule::r#do]);
-				let kw = self.style_text(KEYWORD, struct_pair.as_str());
+				let kw = self.style_text(Style::HlKeyword, struct_pair.as_str());
 				buffer.replace_span(span,&kw);
 				buffer
 			}
 			Rule::r#do if self.expecting(Rule::r#do) => {
 				self.expect.pop();
 				self.expect.push(vec![Rule::done]);
-				let kw = self.style_text(KEYWORD, struct_pair.as_str());
+				let kw = self.style_text(Style::HlKeyword, struct_pair.as_str());
 				buffer.replace_span(span,&kw);
 				buffer
 			}
 			Rule::done => {
 				self.expect.pop();
-				let kw = self.style_text(KEYWORD, struct_pair.as_str());
+				let kw = self.style_text(Style::HlKeyword, struct_pair.as_str());
 				buffer.replace_span(span,&kw);
 				buffer
 			}
 			Rule::in_kw if self.expecting(Rule::in_kw) => {
 				self.expect.pop();
 				self.expect.push(vec![Rule::r#do]);
-				let kw = self.style_text(KEYWORD, struct_pair.as_str());
+				let kw = self.style_text(Style::HlKeyword, struct_pair.as_str());
 				buffer.replace_span(span,&kw);
 				buffer
 			}
 			Rule::hl_subshell => {
 				let body = struct_pair.scry(Rule::subsh_body).unwrap();
 				let highlighted = self.highlight_input(body.as_str()).fill_from(body.as_str());
-				let sub_left = self.style_text(STRING,"(");
-				let sub_right = self.style_text(STRING,")");
+				let sub_left = self.style_text(Style::HlString,"(");
+				let sub_right = self.style_text(Style::HlString,")");
 				let subsh = format!("{sub_left}{highlighted}{sub_right}");
 				buffer.replace_span( span, &subsh);
 				buffer
 				}
 			Rule::hl_assign => {
 				let (var,val) = struct_pair.as_str().split_once('=').unwrap();
-				let styled_var = self.style_text(VARSUB,var);
-				let styled_val = self.style_text(STRING,val);
+				let styled_var = self.style_text(Style::HlVarsub,var);
+				let styled_val = self.style_text(Style::HlString,val);
 				let display = [styled_var,styled_val].join("=").to_string();
 				buffer.replace_span(span,&display);
 				buffer
@@ -184,8 +152,8 @@ impl<'a> SlashHighlighter<'a> {
 				while let Some(word) = inner.pop_back() {
 					let span = word.as_span();
 					let code = match word.as_rule() {
-						Rule::var => VARSUB,
-						_ => KEYWORD
+						Rule::var => Style::HlVarsub,
+						_ => Style::HlKeyword
 					};
 					let styled = self.style_text(code, word.as_str());
 					buffer.replace_span( span, &styled);
@@ -194,7 +162,7 @@ impl<'a> SlashHighlighter<'a> {
 			}
 			Rule::func_name => {
 				let stripped = struct_pair.as_str().strip_suffix("()").unwrap();
-				let styled = self.style_text(FUNCNAME, stripped);
+				let styled = self.style_text(Style::HlFuncname, stripped);
 				let display = format!("{}()",styled);
 				buffer.replace_span( span, &display);
 				buffer
@@ -219,14 +187,14 @@ impl<'a> SlashHighlighter<'a> {
 					let fd_path = format!("/proc/self/fd/{fd}");
 					let exists = Path::new(&fd_path).exists();
 					let styled = if exists {
-						self.style_text(COMMAND, fd)
+						self.style_text(Style::HlCommand, fd)
 					} else {
-						self.style_text(ERROR, fd)
+						self.style_text(Style::HlError, fd)
 					};
 					body.replace_span(span, &styled)
 				}
 				_ => {
-					let styled = self.style_text(OPERATOR, part.as_str());
+					let styled = self.style_text(Style::HlOperator, part.as_str());
 					body.replace_span(span, &styled)
 				}
 			}
@@ -253,14 +221,14 @@ impl<'a> SlashHighlighter<'a> {
 								Rule::cmd_sub => {
 									let body = wd_type.as_str().trim_start_matches("$(").trim_end_matches(')');
 									let highlighted = self.highlight_input(body).fill_from(body);
-									let sub_left = self.style_text(STRING,"$(");
-									let sub_right = format!("{}{}",STRING,")");
+									let sub_left = self.style_text(Style::HlString,"$(");
+									let sub_right = format!("{}{}",term::sgr(Style::HlString),")");
 									let cmd_sub = format!("{sub_left}{highlighted}{sub_right}");
 									buffer.replace_span(span, &cmd_sub);
 								}
 								Rule::var_sub | Rule::param_sub => {
 									let word = wd_type.as_str();
-									let styled = format!("{}{}{}",VARSUB,word,STRING);
+									let styled = format!("{}{}{}",term::sgr(Style::HlVarsub),word,term::sgr(Style::HlString));
 									buffer.replace_span(span, &styled);
 								}
 								_ => { /* Do nothing */ }
@@ -269,13 +237,13 @@ impl<'a> SlashHighlighter<'a> {
 					}
 				}
 			}
-			format!("\"{}{}{}\"",STRING,buffer,RESET)
+			format!("\"{}\"",self.style_text(Style::HlString,&buffer))
 		} else {
 			pair.as_str().to_string()
 		}
 	}
 
-	fn highlight_words(&mut self,pair: Pair<'a,Rule>, mut buffer: String, path: &str) -> String {
+	fn highlight_words(&mut self,pair: Pair<'a,Rule>, mut buffer: String) -> String {
 		let mut is_cmd = true;
 		let mut words = pair.to_deque();
 		while let Some(word_pair) = words.pop_back() {
@@ -306,27 +274,34 @@ impl<'a> SlashHighlighter<'a> {
 					}
 					Rule::squoted => {
 						let body = sub_type.as_str().trim_matches('\'');
-						let styled = self.style_text(STRING,body);
+						let styled = self.style_text(Style::HlString,body);
 						let squoted = format!("{}{}{}",'\'',styled,'\'');
 						buffer.replace_span(span,&squoted);
 					}
 					Rule::param_sub | Rule::var_sub => {
 						let word = sub_type.as_str();
-						let styled = self.style_text(VARSUB,word);
+						let styled = self.style_text(Style::HlVarsub,word);
 						buffer.replace_span(span,&styled);
 					}
 					Rule::arr_index => {
 						// If it works, it works
 						let (left,right) = sub_type.as_str().split_once('[').unwrap();
-						let styled_name = self.style_text(VARSUB,&left);
+						let styled_name = self.style_text(Style::HlVarsub,&left);
 						let styled = [styled_name,right.to_string()].join("[").to_string();
 						buffer.replace_span(span,&styled);
 					}
+					Rule::arith_sub => {
+						let body = &sub_type.as_str()[3..sub_type.as_str().len() - 2];
+						let sub_left = self.style_text(Style::HlString,"$((");
+						let sub_right = self.style_text(Style::HlString,"))");
+						let styled_body = self.style_text(Style::HlNumber,body);
+						buffer.replace_span(span, &format!("{sub_left}{styled_body}{sub_right}"));
+					}
 					Rule::cmd_sub => {
 						let body = sub_type.as_str().trim_start_matches("$(").trim_end_matches(')');
 						let highlighted = self.highlight_input(body).fill_from(body);
-						let sub_left = self.style_text(STRING,"$(");
-						let sub_right = self.style_text(STRING,")");
+						let sub_left = self.style_text(Style::HlString,"$(");
+						let sub_right = self.style_text(Style::HlString,")");
 						let cmd_sub = format!("{sub_left}{highlighted}{sub_right}");
 						buffer.replace_span(span, &cmd_sub);
 					}
@@ -334,11 +309,11 @@ impl<'a> SlashHighlighter<'a> {
 						let body = sub_type.as_str().trim_start_matches(">(").trim_start_matches("<(").trim_end_matches(')');
 						let highlighted = self.highlight_input(body).fill_from(body);
 						let sub_left = if sub_type.as_str().starts_with("<(") {
-							self.style_text(STRING,"<(")
+							self.style_text(Style::HlString,"<(")
 						} else {
-							self.style_text(STRING,">(")
+							self.style_text(Style::HlString,">(")
 						};
-						let sub_right = self.style_text(STRING,")");
+						let sub_right = self.style_text(Style::HlString,")");
 						let proc_sub = format!("{sub_left}{highlighted}{sub_right}");
 						buffer.replace_span(span, &proc_sub);
 					}
@@ -349,13 +324,14 @@ impl<'a> SlashHighlighter<'a> {
 							let glob = hl_glob.step(1).unwrap();
 							match glob.as_rule() {
 								Rule::glob_opt | Rule::glob_wild => {
-									let styled = self.style_text(KEYWORD,glob.as_str());
+									let styled = self.style_text(Style::HlKeyword,glob.as_str());
 									buffer.replace_span(glob_span, &styled);
 								}
 								Rule::glob_brackets => {
-									let body = glob.as_str().trim_matches(['[',']']);
-									let left_brack = format!("{}{}{}",KEYWORD,'[',RESET);
-									let right_brack = format!("{}{}{}",KEYWORD,']',RESET);
+									// Only strip the outer brackets, since classes like `[:alpha:]` have their own
+									let body = glob.as_str().strip_prefix('[').and_then(|body| body.strip_suffix(']')).unwrap_or_default();
+									let left_brack = self.style_text(Style::HlKeyword,"[");
+									let right_brack = self.style_text(Style::HlKeyword,"]");
 									let rebuilt = format!("{left_brack}{body}{right_brack}");
 									buffer.replace_span(glob_span,&rebuilt);
 								}
@@ -365,8 +341,8 @@ impl<'a> SlashHighlighter<'a> {
 					}
 					Rule::hl_brace_word => {
 						let body = sub_type.scry(Rule::brace_expand).unwrap().as_str().trim_matches(['{','}']);
-						let left_brace = format!("{}{}{}",KEYWORD,'{',RESET);
-						let right_brace = format!("{}{}{}",KEYWORD,'}',RESET);
+						let left_brace = self.style_text(Style::HlKeyword,"{");
+						let right_brace = self.style_text(Style::HlKeyword,"}");
 						let rebuilt = format!("{left_brace}{body}{right_brace}");
 						buffer.replace_span(span,&rebuilt);
 					}
@@ -376,15 +352,15 @@ impl<'a> SlashHighlighter<'a> {
 				let word = word_pair.as_str();
 				let span = word_pair.as_span();
 				if words.is_empty() {
-					let code = if self.validate_cmd(word, path) {
-						COMMAND
+					let code = if self.validate_cmd(word) {
+						Style::HlCommand
 					} else {
-						ERROR
+						Style::HlError
 					};
 					let styled_word = self.style_text(code, word);
 					buffer.replace_span(span, &styled_word);
 				} else {
-					let code = RESET;
+					let code = Style::HlArgument;
 					let styled_word = self.style_text(code, word);
 					buffer.replace_span(span, &styled_word);
 				}
@@ -395,18 +371,17 @@ impl<'a> SlashHighlighter<'a> {
 	}
 
 	fn highlight_pair(&mut self,pair: Pair<'a,Rule>, mut buffer: String) -> String {
-		let path = env::var("PATH").unwrap_or_default();
 		let span = pair.as_span();
 		match pair.as_rule() {
 			Rule::loud_sep => {
-				let hl = self.style_text(RESET, &pair.as_str());
+				let hl = self.style_text(Style::HlArgument, &pair.as_str());
 				buffer.replace_span(span, &hl)
 			}
 			Rule::loud_operator => {
-				let hl = self.style_text(OPERATOR, &pair.as_str());
+				let hl = self.style_text(Style::HlOperator, &pair.as_str());
 				buffer.replace_span( span, &hl)
 			}
-			Rule::words => buffer = self.highlight_words(pair, buffer, &path),
+			Rule::words => buffer = self.highlight_words(pair, buffer),
 			Rule::shell_struct => buffer = self.highlight_struct(pair, buffer),
 			_ => unreachable!("Reached highlight pair with this unexpected rule: {:?}",pair.as_rule())
 		}
@@ -445,7 +420,14 @@ impl<'a> Highlighter for SlashHelper<'a> {
 		default: bool,
 	) -> std::borrow::Cow<'b, str> {
 		let _ = default;
-		std::borrow::Cow::Borrowed(prompt)
+		if self.prompt.is_empty() {
+			return std::borrow::Cow::Borrowed(prompt)
+		}
+		// With prompt.exit_status.color, a failed command turns the whole prompt the failure color
+		if self.slash.meta().borrow_shopts().prompt.exit_status.color && self.slash.prompt_status() != 0 {
+			return std::borrow::Cow::Owned(term::paint(Style::PromptFailure, &term::visible_text(&self.prompt)))
+		}
+		std::borrow::Cow::Owned(self.prompt.clone())
 	}
 
 	fn highlight_hint<'h>(&self, hint: &'h str) -> std::borrow::Cow<'h, str> {
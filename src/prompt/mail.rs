@@ -0,0 +1,107 @@
+//! Mail notification
+//!
+//! Before the prompt is drawn, the mailboxes named by `MAILPATH`, or by `MAIL` if `MAILPATH` is unset, are checked
+//! at most once every `MAILCHECK` seconds. A mailbox that has grown since the last check is reported
+
+use std::time::{Duration, Instant};
+
+use crate::prelude::*;
+
+/// How many seconds pass between checks when `MAILCHECK` is unset
+const DEFAULT_MAILCHECK: u64 = 60;
+
+/// When the mailboxes were last checked, and how large each one was then
+#[derive(Debug,Clone,Default)]
+pub struct MailState {
+	last_check: Option<Instant>,
+	sizes: HashMap<PathBuf,u64>
+}
+
+/// Checks the mailboxes if it's time to, and returns a message for each one that has grown
+/// The first check of a mailbox only records its size. A mailbox that doesn't exist counts as empty
+pub fn check_mail(slash: &mut Slash) -> Vec<String> {
+	let Some(interval) = mail_interval(slash) else {
+		return vec![]
+	};
+	let mailboxes = mailboxes(slash);
+	if mailboxes.is_empty() {
+		return vec![]
+	}
+	let now = Instant::now();
+	let state = slash.meta_mut().mail_mut();
+	if state.last_check.is_some_and(|last| now.duration_since(last) < interval) {
+		return vec![]
+	}
+	state.last_check = Some(now);
+
+	let mut messages = vec![];
+	for (path,message) in mailboxes {
+		let size = std::fs::metadata(&path).map(|meta| meta.len()).unwrap_or(0);
+		if state.sizes.insert(path, size).is_some_and(|last_size| size > last_size) {
+			messages.push(message);
+		}
+	}
+	messages
+}
+
+/// How long to wait between checks. A `MAILCHECK` that isn't a number of seconds turns mail checking off
+fn mail_interval(slash: &Slash) -> Option<Duration> {
+	match slash.vars().get_var("MAILCHECK") {
+		Some(val) => val.to_string().trim().parse::<u64>().ok().map(Duration::from_secs),
+		None => Some(Duration::from_secs(DEFAULT_MAILCHECK))
+	}
+}
+
+/// The mailboxes to check, along with the message for each one
+/// `MAILPATH` is a colon separated list of files, each of which can be followed by `?message`. `$_` in a message is replaced with the file's name
+fn mailboxes(slash: &Slash) -> Vec<(PathBuf,String)> {
+	let var = |name: &str| slash.vars().get_var(name).map(|val| val.to_string()).filter(|val| !val.is_empty());
+	if let Some(mailpath) = var("MAILPATH") {
+		return mailpath.split(':')
+			.filter(|entry| !entry.is_empty())
+			.map(|entry| match entry.split_once('?') {
+				Some((path,message)) => (PathBuf::from(path), message.replace("$_", path)),
+				None => (PathBuf::from(entry), default_message(entry))
+			})
+			.collect()
+	}
+	var("MAIL").map(|path| vec![(PathBuf::from(&path), default_message(&path))]).unwrap_or_default()
+}
+
+fn default_message(path: &str) -> String {
+	format!("You have new mail in {}",path)
+}
+
+#[cfg(test)]
+mod tests {
+	use std::io::Write;
+
+	use crate::execute::dispatch;
+
+	use super::*;
+
+	#[test]
+	fn test_check_mail() {
+		let path = env::temp_dir().join(format!("slash_mailbox_{}",std::process::id()));
+		std::fs::write(&path, "").unwrap();
+		let append = |text: &str| std::fs::OpenOptions::new().append(true).open(&path).unwrap().write_all(text.as_bytes()).unwrap();
+		let mut slash = Slash::new();
+		dispatch::exec_input(format!("MAIL={}; MAILCHECK=0", path.display()), &mut slash).unwrap();
+
+		assert!(check_mail(&mut slash).is_empty());
+		append("From someone\n");
+		assert_eq!(check_mail(&mut slash), vec![format!("You have new mail in {}", path.display())]);
+		assert!(check_mail(&mut slash).is_empty());
+
+		// MAILPATH takes precedence over MAIL, and can give its own message
+		dispatch::exec_input(format!("MAILPATH='{}?mail for $_'", path.display()), &mut slash).unwrap();
+		append("From someone else\n");
+		assert_eq!(check_mail(&mut slash), vec![format!("mail for {}", path.display())]);
+
+		// Nothing is checked again until MAILCHECK seconds have passed
+		dispatch::exec_input("MAILCHECK=3600".into(), &mut slash).unwrap();
+		append("From a third person\n");
+		assert!(check_mail(&mut slash).is_empty());
+		std::fs::remove_file(path).ok();
+	}
+}
@@ -1,18 +1,21 @@
-use std::{env, path::Path};
+use std::{env, sync::{mpsc, Arc, Mutex}};
 
-use nix::{sys::signal::{kill, Signal}, unistd::Pid};
 use rustyline::{completion::FilenameCompleter, error::ReadlineError, history::History, Helper};
 
 use crate::prelude::*;
-use crate::{error::{SlashErr::*, SlashErrLow}, expand, shellenv::Slash, SlashResult};
+use crate::{error::{SlashErr::*, SlashErrLow}, execute::dispatch, expand, helper, path_cache, shellenv::{self, write_jobs, Slash}, signal, term, SlashResult};
 
-use super::rl_init;
+use super::{mail, rl_init};
 
 #[derive(Helper)]
 pub struct SlashHelper<'a> {
 	pub filename_comp: FilenameCompleter,
 	pub slash: &'a mut Slash,
-	pub commands: Vec<String>
+	pub commands: Vec<String>,
+	/// The prompt as it is displayed. The editor is given a copy with the non-printing parts removed, so that it can measure it
+	pub prompt: String,
+	/// The buffer as it was when Ctrl-x Ctrl-e was pressed, which is edited and run once the editor gives up the terminal
+	pub edit_request: Arc<Mutex<Option<String>>>
 }
 
 impl<'a> SlashHelper<'a> {
@@ -29,6 +32,8 @@ impl<'a> SlashHelper<'a> {
 			filename_comp: FilenameCompleter::new(),
 			slash,
 			commands,
+			prompt: String::new(),
+			edit_request: Arc::new(Mutex::new(None)),
 		};
 		helper.update_commands_from_path();
 		helper
@@ -49,30 +54,28 @@ impl<'a> SlashHelper<'a> {
 
 	// Dynamically add commands (if needed, e.g., external binaries in $PATH)
 	pub fn update_commands_from_path(&mut self) {
-		if let Ok(paths) = env::var("PATH") {
-			let mut external_commands = HashSet::new();
-			for path in env::split_paths(&paths) {
-				if let Ok(entries) = std::fs::read_dir(path) {
-					for entry in entries.flatten() {
-						if let Ok(file_name) = entry.file_name().into_string() {
-							external_commands.insert(file_name);
-						}
-					}
-				}
-			}
-			self.commands.extend(external_commands);
-		}
+		let external_commands = path_cache::executables(&helper::shell_path(self.slash));
+		self.commands.extend(external_commands);
 	}
 }
 
 pub fn run_prompt(slash: &mut Slash) -> SlashResult<String> {
 	slash.stop_timer()?;
-	slash.meta_mut().enter_prompt();
+	// precmd runs before each prompt is drawn, and can read the last command's status and duration
+	// It runs in the shell itself, so that the variables, directory, and prompt settings it changes are kept
+	if slash.logic().get_func("precmd").is_some() {
+		let status = slash.get_status();
+		if let Err(e) = dispatch::exec_input("precmd".into(), slash) {
+			if let SlashErrLow::CleanExit(_) = e.low() {
+				return Err(e)
+			}
+			eprintln!("{}",e);
+		}
+		slash.set_code(status);
+	}
+	let status = slash.get_status();
+	slash.meta_mut().enter_prompt(status);
 
-	let hist_path = slash.vars().get_evar("HIST_FILE").unwrap_or_else(|| -> String {
-		let home = slash.vars().get_evar("HOME").unwrap_or_default();
-		format!("{}/.slash_hist",home)
-	});
 	let prompt = match expand::misc::expand_prompt(None,slash) {
 		Ok(expanded) => expanded,
 		Err(e) => {
@@ -81,30 +84,66 @@ pub fn run_prompt(slash: &mut Slash) -> SlashResult<String> {
 		}
 	};
 
-	let mut slash_clone = slash.clone();
-	let mut rl = rl_init::init_prompt(&mut slash_clone)?;
-	match rl.readline(&prompt) {
+	// Background jobs that changed while the last command ran are reported before the prompt
+	for report in write_jobs(|j| j.take_reports())? {
+		eprintln!("{}",report);
+	}
+	// Jobs that have been reported as done leave the table, and only their statuses are kept
+	let child_max = slash.meta().borrow_shopts().core.child_max;
+	write_jobs(|j| j.retire_finished(child_max))?;
+	for message in mail::check_mail(slash) {
+		eprintln!("{}",message);
+	}
+
+	let highlight = slash.meta().get_shopt("prompt.prompt_highlight")?.parse::<bool>().unwrap_or(true);
+	let (result, edit_buffer) = match helper::input_timeout(slash) {
+		Some(timeout) => {
+			// The editor can't be woken up while it waits, so with `TMOUT` it runs on a thread of its own,
+			// and the shell stops waiting for it once time runs out. The shell then exits the usual way, running the EXIT trap
+			let modes = shellenv::get_tty_modes();
+			let (sender,receiver) = mpsc::channel();
+			let slash_clone = slash.clone();
+			std::thread::spawn(move || sender.send(read_prompt_line(slash_clone, prompt, highlight)));
+			match receiver.recv_timeout(timeout) {
+				Ok(result) => result?,
+				Err(_) => {
+					signal::set_prompt_printer(None);
+					if let Some(modes) = &modes {
+						shellenv::set_tty_modes(modes);
+					}
+					slash.meta_mut().leave_prompt();
+					eprintln!("\n{}",helper::AUTO_LOGOUT_MSG);
+					return Err(Low(SlashErrLow::CleanExit(slash.get_status())))
+				}
+			}
+		}
+		None => read_prompt_line(slash.clone(), prompt, highlight)?
+	};
+	if !matches!(result, Err(ReadlineError::Eof)) {
+		slash.meta_mut().reset_eof_count();
+	}
+	match result {
 		Ok(line) => {
 			slash.meta_mut().leave_prompt();
-			if !line.is_empty() {
-				rl.history_mut()
-					.add(&line)
-					.map_err(|_| Low(SlashErrLow::InternalErr("Failed to write to history file".into())))?;
-					rl.history_mut()
-						.save(Path::new(&hist_path))
-						.map_err(|_| Low(SlashErrLow::InternalErr("Failed to write to history file".into())))?;
-					slash.meta_mut().set_last_input(&line);
-			}
-			Ok(line)
+			expand_line(slash, line)
 		}
 		Err(ReadlineError::Interrupted) => {
 			slash.meta_mut().leave_prompt();
-			Ok(String::new())
+			match edit_buffer {
+				Some(buffer) => {
+					let line = edit_in_editor(slash, &buffer)?;
+					// The edited command is shown as if it had been typed
+					if !line.is_empty() {
+						println!("{}",line);
+					}
+					enter_line(slash, line)
+				}
+				None => Ok(String::new())
+			}
 		}
 		Err(ReadlineError::Eof) => {
 			slash.meta_mut().leave_prompt();
-			kill(Pid::this(), Signal::SIGQUIT).map_err(|_| Low(SlashErrLow::from_io()))?;
-			Ok(String::new())
+			handle_eof(slash)
 		}
 		Err(e) => {
 			slash.meta_mut().leave_prompt();
@@ -112,3 +151,160 @@ pub fn run_prompt(slash: &mut Slash) -> SlashResult<String> {
 		}
 	}
 }
+
+/// Reads a line with the line editor, which works on its own copy of the shell
+/// Along with the editor's result, returns the buffer that Ctrl-x Ctrl-e asked to open in an editor, if it was pressed
+fn read_prompt_line(mut slash: Slash, prompt: String, highlight: bool) -> SlashResult<(Result<String,ReadlineError>, Option<String>)> {
+	let mut rl = rl_init::init_prompt(&mut slash)?;
+	if let Ok(printer) = rl.create_external_printer() {
+		signal::set_prompt_printer(Some(Box::new(printer)));
+	}
+	// The editor only calls highlight_prompt() when highlighting is enabled, so otherwise it has to be given the full prompt
+	let prompt = if highlight {
+		if let Some(helper) = rl.helper_mut() {
+			helper.prompt = term::strip_ignore_markers(&prompt);
+		}
+		term::visible_text(&prompt)
+	} else {
+		term::strip_ignore_markers(&prompt)
+	};
+	let edit_request = rl.helper().map(|helper| helper.edit_request.clone());
+	let result = rl.readline(&prompt);
+	signal::set_prompt_printer(None);
+	let edit_buffer = edit_request.and_then(|request| request.lock().unwrap().take());
+	Ok((result, edit_buffer))
+}
+
+/// End of input leaves the shell with the status of the last command
+/// If `IGNOREEOF` is set, that many end of file characters in a row are ignored first, and the prompt is drawn again
+fn handle_eof(slash: &mut Slash) -> SlashResult<String> {
+	if slash.meta().eof_count() < ignoreeof_limit(slash) {
+		slash.meta_mut().count_eof();
+		eprintln!("Use \"exit\" to leave the shell.");
+		return Ok(String::new())
+	}
+	Err(Low(SlashErrLow::CleanExit(slash.get_status())))
+}
+
+/// How many end of file characters in a row `IGNOREEOF` asks to ignore
+/// Leaving it unset ignores none of them, and a value that isn't a number counts as 10, like in bash
+fn ignoreeof_limit(slash: &Slash) -> usize {
+	match slash.vars().get_var("IGNOREEOF") {
+		Some(val) => val.to_string().trim().parse().unwrap_or(10),
+		None => 0
+	}
+}
+
+/// Expands the history references in a line typed at the prompt, then adds it to the history
+/// An expanded line is shown before it runs, and one with a `:p` modifier is only shown
+fn expand_line(slash: &mut Slash, line: String) -> SlashResult<String> {
+	let Some(expanded) = expand::history::expand_history(&line, slash.meta().history())? else {
+		return enter_line(slash, line)
+	};
+	println!("{}",expanded.line);
+	let line = enter_line(slash, expanded.line)?;
+	if expanded.print_only {
+		return Ok(String::new())
+	}
+	Ok(line)
+}
+
+/// Adds a line that is about to run to the history
+fn enter_line(slash: &mut Slash, line: String) -> SlashResult<String> {
+	if !line.is_empty() {
+		let core = &slash.meta().borrow_shopts().core;
+		let (ignore_dupes, max_len, autosave) = (core.hist_ignore_dupes, core.max_hist, core.hist_autosave);
+		slash.meta_mut().history_mut().push(&line, ignore_dupes, max_len);
+		if autosave {
+			let hist_path = helper::hist_file(slash);
+			slash.meta_mut().history_mut().append_file(&hist_path)
+				.map_err(|_| Low(SlashErrLow::InternalErr("Failed to write to history file".into())))?;
+		}
+		slash.meta_mut().set_last_input(&line);
+	}
+	Ok(line)
+}
+
+/// Opens a command in `VISUAL` or `EDITOR`, or `vi` if neither is set, and returns what was saved
+/// Nothing is run if the editor fails
+pub fn edit_in_editor(slash: &mut Slash, buffer: &str) -> SlashResult<String> {
+	let editor = ["VISUAL", "EDITOR"].iter()
+		.filter_map(|var| slash.vars().get_var(var).map(|val| val.to_string()))
+		.find(|editor| !editor.is_empty())
+		.unwrap_or_else(|| "vi".into());
+	// The file gets a name nobody else can predict or claim first, and only the user can read it
+	let template = env::temp_dir().join("slash_edit_XXXXXX");
+	let (fd,path) = nix::unistd::mkstemp(&template).map_err(|_| Low(SlashErrLow::from_io()))?;
+	let mut file = unsafe { std::fs::File::from_raw_fd(fd) };
+	let written = writeln!(file,"{}",buffer);
+	drop(file);
+	let result = match written {
+		Ok(_) => dispatch::exec_input(format!("{} {}",editor,helper::shell_quote(&path.to_string_lossy())), slash),
+		Err(e) => Err(e.into())
+	};
+	let edited = std::fs::read_to_string(&path);
+	std::fs::remove_file(&path).ok();
+	result?;
+	if slash.get_status() != 0 {
+		return Ok(String::new())
+	}
+	Ok(edited?.trim_end_matches('\n').to_string())
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn test_ignoreeof() {
+		let mut slash = Slash::new();
+		dispatch::exec_input("false".into(), &mut slash).unwrap();
+		assert!(matches!(handle_eof(&mut slash), Err(Low(SlashErrLow::CleanExit(1)))));
+
+		dispatch::exec_input("IGNOREEOF=2".into(), &mut slash).unwrap();
+		assert_eq!(handle_eof(&mut slash).unwrap(), "");
+		assert_eq!(handle_eof(&mut slash).unwrap(), "");
+		assert!(matches!(handle_eof(&mut slash), Err(Low(SlashErrLow::CleanExit(_)))));
+
+		// Anything else read at the prompt starts the count over
+		slash.meta_mut().reset_eof_count();
+		dispatch::exec_input("IGNOREEOF=".into(), &mut slash).unwrap();
+		assert_eq!(ignoreeof_limit(&slash), 10);
+		assert_eq!(handle_eof(&mut slash).unwrap(), "");
+		assert_eq!(slash.meta().eof_count(), 1);
+	}
+
+	#[test]
+	fn test_tmout() {
+		let mut slash = Slash::new();
+		assert_eq!(helper::input_timeout(&slash), None);
+		dispatch::exec_input("TMOUT=30".into(), &mut slash).unwrap();
+		assert_eq!(helper::input_timeout(&slash), Some(std::time::Duration::from_secs(30)));
+		for disabled in ["0", "-5", "soon"] {
+			dispatch::exec_input(format!("TMOUT={}",disabled), &mut slash).unwrap();
+			assert_eq!(helper::input_timeout(&slash), None);
+		}
+
+		// A pipe with nothing written to it never becomes readable
+		let (r_pipe,mut w_pipe) = crate::utils::SmartFD::pipe().unwrap();
+		assert!(!helper::wait_for_input(r_pipe.as_raw_fd(), std::time::Duration::from_millis(20)).unwrap());
+		write!(w_pipe,"x").unwrap();
+		assert!(helper::wait_for_input(r_pipe.as_raw_fd(), std::time::Duration::from_millis(20)).unwrap());
+	}
+
+	#[test]
+	fn test_edit_in_editor() {
+		let mut slash = Slash::new();
+		let report = env::temp_dir().join(format!("slash_edit_report_{}",std::process::id()));
+		let define = format!("fake_edit() {{ stat -c '%n %a' \"$1\" > {}; echo 'echo edited' > \"$1\"; }}; EDITOR=fake_edit",report.display());
+		dispatch::exec_input(define, &mut slash).unwrap();
+		assert_eq!(edit_in_editor(&mut slash, "echo original").unwrap(), "echo edited");
+
+		// The file could only be read by the user, and it is gone once the editor is done
+		let seen = std::fs::read_to_string(&report).unwrap();
+		std::fs::remove_file(&report).ok();
+		let (path,mode) = seen.trim().rsplit_once(' ').unwrap();
+		assert_eq!(mode, "600");
+		assert!(!Path::new(path).exists());
+	}
+}
@@ -1,22 +1,36 @@
-use rustyline::{config::Configurer, history::DefaultHistory, ColorMode, Config, EditMode, Editor};
+use std::sync::{Arc, Mutex};
 
-use crate::{prelude::*, shellenv::EnvMeta};
+use rustyline::{config::{BellStyle, Configurer}, error::ReadlineError, history::{DefaultHistory, History}, Behavior, Cmd, ColorMode, ConditionalEventHandler, Config, EditMode, Editor, Event, EventContext, EventHandler, Helper, KeyCode, KeyEvent, Modifiers, RepeatCount};
+
+use crate::{prelude::*, shellenv::{CmdHistory, EnvMeta}};
 
 use super::prompt::SlashHelper;
 
-pub fn load_history(path: &Path, rl: &mut Editor<SlashHelper, DefaultHistory>) -> SlashResult<()> {
-	if let Err(e) = rl.load_history(path) {
-		eprintln!("No previous history found or failed to load history: {}", e);
+/// Gives the editor the shell's command history. The shell keeps the history itself, since a new editor is made for every prompt
+/// Multi-line entries keep their newlines, so a recalled block is edited and run as a whole
+pub fn load_history<H: Helper>(history: &CmdHistory, rl: &mut Editor<H, DefaultHistory>) -> SlashResult<()> {
+	for entry in history.entries() {
+		rl.history_mut().add(entry).map_err(|e| Low(SlashErrLow::InternalErr(format!("Failed to load history: {}", e))))?;
 	}
 	Ok(())
 }
 
+/// Ctrl-x Ctrl-e hands the buffer to the prompt to be opened in an editor
+/// The editor can't run while the line editor has the terminal, so the buffer is stashed and the line is abandoned
+struct EditCommand(Arc<Mutex<Option<String>>>);
+
+impl ConditionalEventHandler for EditCommand {
+	fn handle(&self, _evt: &Event, _n: RepeatCount, _positive: bool, ctx: &EventContext) -> Option<Cmd> {
+		*self.0.lock().unwrap() = Some(ctx.line().to_string());
+		Some(Cmd::Interrupt)
+	}
+}
+
 pub fn init_prompt<'a>(slash: &'a mut Slash) -> SlashResult<Editor<SlashHelper<'a>, DefaultHistory>> {
 	let config = build_editor_config(slash.meta())?;
-	let path = format!("{}/.slash_hist",env::var("HOME").unwrap_or_default());
-	let hist_path = Path::new(&path);
+	let history = slash.meta().history().clone();
 	let mut rl = initialize_editor(slash,config)?;
-	load_history(hist_path,&mut rl)?;
+	load_history(&history,&mut rl)?;
 	Ok(rl)
 }
 
@@ -26,10 +40,28 @@ pub fn initialize_editor<'a>(slash: &'a mut Slash,config: Config) -> SlashResult
 		std::process::exit(1);
 	});
 	rl.set_completion_type(rustyline::CompletionType::List);
-	rl.set_helper(Some(SlashHelper::new(slash)));
+	// Enter runs the whole buffer once it parses, so Alt-Enter is how a line is added to a complete command, like one recalled from history
+	rl.bind_sequence(KeyEvent(KeyCode::Enter, Modifiers::ALT), EventHandler::Simple(Cmd::Newline));
+	// Up and Down already move between the lines of a multi-line buffer before moving through the history, and Ctrl-P and Ctrl-N do the same
+	rl.bind_sequence(KeyEvent::ctrl('P'), EventHandler::Simple(Cmd::LineUpOrPreviousHistory(1)));
+	rl.bind_sequence(KeyEvent::ctrl('N'), EventHandler::Simple(Cmd::LineDownOrNextHistory(1)));
+	let helper = SlashHelper::new(slash);
+	let edit_request = EditCommand(helper.edit_request.clone());
+	rl.bind_sequence(Event::KeySeq(vec![KeyEvent::ctrl('X'), KeyEvent::ctrl('E')]), EventHandler::Conditional(Box::new(edit_request)));
+	rl.set_helper(Some(helper));
 	Ok(rl)
 }
 
+/// The bell set by `core.bell_style`: 0 is no bell, 1 is an audible bell, and 2 is a visible one
+/// The editor rings an audible bell itself when a completion or history search comes up empty, but it can't flash the screen
+pub fn bell_style(meta: &EnvMeta) -> BellStyle {
+	match meta.borrow_shopts().core.bell_style {
+		0 => BellStyle::None,
+		2 => BellStyle::Visible,
+		_ => BellStyle::Audible
+	}
+}
+
 pub fn build_editor_config(meta: &EnvMeta) -> SlashResult<Config> {
 	let mut config = Config::builder();
 
@@ -61,7 +93,59 @@ pub fn build_editor_config(meta: &EnvMeta) -> SlashResult<Config> {
 		.edit_mode(edit_mode)
 		.auto_add_history(auto_hist)
 		.color_mode(prompt_highlight)
+		.bell_style(bell_style(meta))
 		.tab_stop(tab_stop);
 
 	Ok(config.build())
 }
+
+/// Reads one line from the terminal for `read` and `select`, with the same editing keys as the prompt
+/// Nothing is added to the history, and the editor talks to the terminal directly, so standard output is left alone.
+/// Returns None at end of input, and an interrupt if Ctrl-C is pressed
+pub fn read_line(meta: &EnvMeta, prompt: &str) -> SlashResult<Option<String>> {
+	let edit_mode = match meta.borrow_shopts().prompt.edit_mode.trim_matches('"') {
+		"emacs" => EditMode::Emacs,
+		_ => EditMode::Vi
+	};
+	let config = Config::builder()
+		.edit_mode(edit_mode)
+		.auto_add_history(false)
+		.bell_style(bell_style(meta))
+		.tab_stop(meta.borrow_shopts().prompt.tab_stop)
+		.behavior(Behavior::PreferTerm)
+		.build();
+	let mut rl = Editor::<(), DefaultHistory>::with_config(config)
+		.map_err(|e| Low(SlashErrLow::InternalErr(format!("Failed to start the line editor: {}", e))))?;
+	match rl.readline(prompt) {
+		Ok(line) => Ok(Some(line)),
+		Err(ReadlineError::Eof) => Ok(None),
+		Err(ReadlineError::Interrupted) => Err(Low(SlashErrLow::Interrupted)),
+		Err(e) => Err(Low(SlashErrLow::InternalErr(format!("rustyline error: {}", e))))
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use rustyline::history::SearchDirection;
+
+	use super::*;
+
+	#[test]
+	fn test_multiline_history() {
+		let path = env::temp_dir().join(format!("slash_multiline_hist_{}", std::process::id()));
+		let entry = "for i in 1 2; do\n\techo \\\n\t\t$i\ndone";
+		let mut history = CmdHistory::default();
+		history.push(entry, false, 100);
+		history.append_file(&path).unwrap();
+
+		// The entry comes back from the file, and out of the editor's history, as one block
+		let mut history = CmdHistory::default();
+		history.read_file(&path, 100).unwrap();
+		std::fs::remove_file(&path).ok();
+		let mut rl = Editor::<(), DefaultHistory>::new().unwrap();
+		load_history(&history, &mut rl).unwrap();
+		assert_eq!(rl.history().len(), 1);
+		let recalled = rl.history().get(0, SearchDirection::Forward).unwrap().unwrap();
+		assert_eq!(recalled.entry, entry);
+	}
+}
@@ -1,11 +1,15 @@
 use rustyline::validate::Validator;
 
-use crate::prelude::*;
+use crate::{helper, prelude::*};
 
 use super::prompt::SlashHelper;
 
+/// Input is complete once it parses, every heredoc in it has been closed, and its last line doesn't end in a backslash
 fn try_parse(input: &str) -> bool {
-	SlashParse::parse(Rule::main, input).is_ok()
+	if helper::ends_in_continuation(input) {
+		return false
+	}
+	helper::inline_heredocs(input).is_some_and(|input| SlashParse::parse(Rule::main, &helper::join_continued_lines(&input)).is_ok())
 }
 
 impl<'a> Validator for SlashHelper<'a> {
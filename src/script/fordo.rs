@@ -1,4 +1,4 @@
-use crate::{prelude::*, shellenv::SlashVal};
+use crate::{prelude::*, shellenv::SlashVal, signal};
 
 pub fn exec_for_cmd<'a>(cmd: Pair<'a,Rule>,slash: &mut Slash) -> SlashResult<()> {
 	let mut saved_vars = HashMap::new();
@@ -22,6 +22,7 @@ pub fn exec_for_cmd<'a>(cmd: Pair<'a,Rule>,slash: &mut Slash) -> SlashResult<()>
 	}
 
 	for (i,element) in loop_arr.iter().enumerate() {
+		signal::check_interrupt()?;
 		let var_index = i % vars_len;
 		slash.vars_mut().set_var(loop_vars[var_index], element.clone());
 		slash.exec_as_body(loop_body)?;
@@ -1,12 +1,25 @@
-use crate::prelude::*;
+use crate::{expand::arithmetic::CompiledArith, prelude::*, signal};
 
 pub fn exec_loop_cmd<'a>(cmd: Pair<'a,Rule>, slash: &mut Slash) -> SlashResult<()> {
 	let loop_kind = cmd.scry(Rule::loop_kind).unpack()?.as_str();
-	let loop_cond = cmd.scry(Rule::loop_cond).unpack()?.as_str().to_string();
+	let cond_pair = cmd.scry(Rule::loop_cond).unpack()?;
+	let loop_cond = cond_pair.as_str().to_string();
 	let loop_body = cmd.scry(Rule::loop_body).unpack()?.as_str().to_string();
+	// A condition that is nothing but `(( expr ))` is evaluated directly, instead of going back through the parser on every iteration
+	let arith_cond = cond_pair.scry(Rule::arith_cmd)
+		.filter(|arith| arith.as_str() == loop_cond.trim())
+		.and_then(|arith| arith.scry(Rule::arith_body))
+		.map(|body| CompiledArith::new(body.as_str()));
 
 	loop {
-		slash.exec_as_cond(&loop_cond)?;
+		signal::check_interrupt()?;
+		match &arith_cond {
+			Some(arith) => {
+				let value = arith.eval(slash).blame(cond_pair.clone())?;
+				slash.set_code(if value != 0 { 0 } else { 1 });
+			}
+			None => { slash.exec_as_cond(&loop_cond)?; }
+		}
 		let is_success = slash.get_status() == 0;
 		match loop_kind {
 			"while" => {
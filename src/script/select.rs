@@ -1,5 +1,102 @@
-use crate::prelude::*;
+use nix::unistd::isatty;
 
-pub fn exec_select_cmd<'a>(pair: Pair<'a,Rule>, slash: &mut Slash) -> SlashResult<()> {
-	todo!()
+use crate::{builtin::read, helper, prelude::*, prompt::rl_init, shellenv::SlashVal, signal, utils};
+
+/// Runs a `select` loop
+/// The items are printed to stderr as a numbered menu, and a line is read from stdin after prompting with `PS3`.
+/// The line is stored in `REPLY`, and the loop variable is set to the item with that number, or to an empty string if there is no such item.
+/// An empty line shows the menu again without running the body. The loop goes on until `break` or the end of input,
+/// and its exit status is that of the last command in the body, or 0 if the body never ran
+pub fn exec_select_cmd<'a>(cmd: Pair<'a,Rule>, slash: &mut Slash) -> SlashResult<()> {
+	let loop_var = cmd.scry(Rule::select_var).unpack()?.as_str().to_string();
+	let loop_body = cmd.scry(Rule::loop_body).unpack()?.as_str().to_string();
+	let items = match cmd.scry(Rule::for_arr) {
+		Some(arr) => helper::prepare_argv(arr, slash)?.into_iter().collect::<Vec<_>>(),
+		None if cmd.scry(Rule::select_in).is_some() => vec![],
+		None => slash.vars().borrow_pos_params().iter().cloned().collect()
+	};
+
+	slash.set_code(0);
+	if items.is_empty() {
+		return Ok(())
+	}
+	let mut show_menu = true;
+	loop {
+		signal::check_interrupt()?;
+		if show_menu {
+			print_menu(&items)?;
+		}
+		let prompt = slash.vars().get_var("PS3").map(|ps3| ps3.to_string()).unwrap_or("#? ".into());
+		let Some(reply) = read_reply(slash, &prompt).blame(cmd.clone())? else {
+			break
+		};
+		show_menu = reply.trim().is_empty();
+		if show_menu {
+			continue
+		}
+		let choice = reply.trim().parse::<usize>().ok()
+			.and_then(|num| num.checked_sub(1))
+			.and_then(|index| items.get(index))
+			.cloned()
+			.unwrap_or_default();
+		slash.vars_mut().set_var("REPLY", SlashVal::String(reply));
+		slash.vars_mut().set_var(&loop_var, SlashVal::String(choice));
+
+		if let Err(err) = slash.exec_as_body(&loop_body) {
+			match err.low() {
+				SlashErrLow::LoopBreak(code) => {
+					slash.set_code(*code);
+					break
+				}
+				SlashErrLow::LoopCont => continue,
+				_ => return Err(err)
+			}
+		}
+	}
+	Ok(())
+}
+
+/// Prints the numbered menu to stderr, with the numbers lined up on the right
+fn print_menu(items: &[String]) -> SlashResult<()> {
+	let mut stderr = utils::SmartFD::new(STDERR_FILENO)?;
+	let width = items.len().to_string().len();
+	for (i,item) in items.iter().enumerate() {
+		writeln!(stderr,"{:>width$}) {}",i + 1,item,width = width)?;
+	}
+	Ok(())
+}
+
+/// Reads one line of input for the menu
+/// A terminal is read with the line editor, like `read` does. If `TMOUT` runs out first, it counts as the end of input
+fn read_reply(slash: &mut Slash, prompt: &str) -> SlashResult<Option<String>> {
+	if isatty(STDIN_FILENO).unwrap_or(false) {
+		if let Some(timeout) = helper::input_timeout(slash) {
+			if !helper::wait_for_input(STDIN_FILENO, timeout)? {
+				return Ok(None)
+			}
+		}
+		return rl_init::read_line(slash.meta(), prompt)
+	}
+	let mut stderr = utils::SmartFD::new(STDERR_FILENO)?;
+	write!(stderr,"{}",prompt)?;
+	let (record,found_delim) = read::read_record(STDIN_FILENO, b'\n', true)?;
+	if record.is_empty() && !found_delim {
+		return Ok(None)
+	}
+	Ok(Some(String::from_utf8_lossy(&record).into_owned()))
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn test_select() {
+		let mut slash = Slash::new();
+		// An empty line shows the menu again, and a number with no item leaves the variable empty
+		let output = slash.exec_captured("printf '2\\n\\n9\\n' | select x in a 'b c' d; do echo \"$REPLY:$x\"; done").unwrap();
+		assert_eq!(output, "2:b c\n9:\n");
+		let output = slash.exec_captured("printf '3\\n1\\n' | select x in a b c; do echo $x; break; done; echo $?").unwrap();
+		assert_eq!(output, "c\n0\n");
+	}
 }
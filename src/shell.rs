@@ -0,0 +1,395 @@
+use std::path::{Path, PathBuf};
+
+use crate::{error::SlashErrLow, execute::dispatch, helper, shellenv::{Slash, SlashVal}, SlashResult};
+
+/// The exit status of a command run through a `Shell`
+#[derive(Debug,Clone,Copy,PartialEq,Eq)]
+pub struct ExitStatus(i32);
+
+impl ExitStatus {
+	pub fn code(&self) -> i32 {
+		self.0
+	}
+	pub fn success(&self) -> bool {
+		self.0 == 0
+	}
+}
+
+/// An embeddable slash interpreter
+///
+/// ```no_run
+/// let mut shell = slash::Shell::builder()
+/// 	.with_var("greeting", "hello")
+/// 	.build()
+/// 	.unwrap();
+/// let (output, status) = shell.capture("echo $greeting world").unwrap();
+/// assert!(status.success());
+/// assert_eq!(output, "hello world\n");
+/// ```
+pub struct Shell {
+	slash: Slash
+}
+
+impl Shell {
+	pub fn new() -> Self {
+		Self { slash: Slash::new() }
+	}
+	pub fn builder() -> ShellBuilder {
+		ShellBuilder::new()
+	}
+	/// Runs a string of shell input. Errors raised by the input itself are reported on stderr,
+	/// and only errors that prevent the input from running at all are returned
+	pub fn run_str(&mut self, input: &str) -> SlashResult<ExitStatus> {
+		let fds_before = helper::fd_audit_start(&self.slash);
+		let result = dispatch::exec_toplevel(input.to_string(), &mut self.slash);
+		helper::fd_audit_finish(&self.slash, fds_before);
+		self.finish(result)
+	}
+	/// Runs a string of shell input like `run_str()`, with errors naming `name` as the place that the input came from
+	/// `slash -c` runs its command this way, so that its errors point at `-c string:line:col`
+	pub fn run_named(&mut self, input: &str, name: &str) -> SlashResult<ExitStatus> {
+		self.slash.meta_mut().set_input_name(Some(name));
+		let fds_before = helper::fd_audit_start(&self.slash);
+		let result = dispatch::exec_toplevel(input.to_string(), &mut self.slash).map_err(|e| e.attribute_to(name));
+		helper::fd_audit_finish(&self.slash, fds_before);
+		self.slash.meta_mut().set_input_name(None);
+		self.finish(result)
+	}
+	pub fn run_script(&mut self, path: impl AsRef<Path>) -> SlashResult<ExitStatus> {
+		let path = path.as_ref().to_string_lossy().to_string();
+		let fds_before = helper::fd_audit_start(&self.slash);
+		let result = self.slash.run_script(&path);
+		helper::fd_audit_finish(&self.slash, fds_before);
+		self.finish(result)
+	}
+	/// Runs a string of shell input, returning everything it wrote to stdout along with its exit status
+	pub fn capture(&mut self, input: &str) -> SlashResult<(String,ExitStatus)> {
+		let output = self.slash.exec_captured(input)?;
+		Ok((output, ExitStatus(self.slash.get_status())))
+	}
+	/// Looks up a variable, checking shell variables, parameters, and then the environment
+	pub fn get_var(&self, name: &str) -> Option<String> {
+		self.slash.vars().get_var(name).map(|val| val.to_string())
+	}
+	pub fn set_var(&mut self, name: &str, val: &str) {
+		self.slash.vars_mut().set_var(name, SlashVal::parse(val).unwrap_or(SlashVal::String(val.to_string())));
+	}
+	pub fn export_var(&mut self, name: &str, val: &str) {
+		self.slash.vars_mut().export_var(name, val);
+	}
+	pub fn last_status(&self) -> ExitStatus {
+		ExitStatus(self.slash.get_status())
+	}
+	/// Direct access to the underlying shell environment
+	pub fn env(&self) -> &Slash {
+		&self.slash
+	}
+	pub fn env_mut(&mut self) -> &mut Slash {
+		&mut self.slash
+	}
+
+	fn finish(&mut self, result: SlashResult<()>) -> SlashResult<ExitStatus> {
+		let err = match result {
+			Ok(_) => return Ok(self.last_status()),
+			Err(e) => e
+		};
+		match err.low() {
+			SlashErrLow::CleanExit(code) => self.slash.set_code(*code),
+			SlashErrLow::Interrupted => self.slash.set_code(130),
+			_ => helper::report_err(&mut self.slash, &err)
+		}
+		Ok(self.last_status())
+	}
+}
+
+impl Drop for Shell {
+	fn drop(&mut self) {
+		self.slash.meta_mut().close_open_fds();
+	}
+}
+
+impl From<Slash> for Shell {
+	fn from(slash: Slash) -> Self {
+		Self { slash }
+	}
+}
+
+impl Default for Shell {
+	fn default() -> Self {
+		Self::new()
+	}
+}
+
+/// Configures a `Shell` before it is created
+#[derive(Default)]
+pub struct ShellBuilder {
+	vars: Vec<(String,String)>,
+	env_vars: Vec<(String,String)>,
+	args: Vec<String>,
+	rc_path: Option<PathBuf>
+}
+
+impl ShellBuilder {
+	pub fn new() -> Self {
+		Self::default()
+	}
+	pub fn with_var(mut self, name: &str, val: &str) -> Self {
+		self.vars.push((name.to_string(), val.to_string()));
+		self
+	}
+	pub fn with_env_var(mut self, name: &str, val: &str) -> Self {
+		self.env_vars.push((name.to_string(), val.to_string()));
+		self
+	}
+	/// Sets the positional parameters, starting at `$1`
+	pub fn with_args<I,S>(mut self, args: I) -> Self
+	where I: IntoIterator<Item = S>, S: Into<String> {
+		self.args.extend(args.into_iter().map(Into::into));
+		self
+	}
+	/// Sources the given rc file when the shell is built. No rc file is sourced otherwise
+	pub fn with_rc(mut self, path: impl Into<PathBuf>) -> Self {
+		self.rc_path = Some(path.into());
+		self
+	}
+	pub fn build(self) -> SlashResult<Shell> {
+		let mut shell = Shell::new();
+		for (name,val) in &self.env_vars {
+			shell.export_var(name, val);
+		}
+		for (name,val) in &self.vars {
+			shell.set_var(name, val);
+		}
+		for arg in &self.args {
+			shell.slash.vars_mut().pos_param_pushback(arg);
+		}
+		if let Some(path) = self.rc_path {
+			shell.run_script(path)?;
+		}
+		Ok(shell)
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use crate::utils;
+
+	use super::*;
+
+	#[test]
+	fn test_embedded_shell() {
+		let mut shell = Shell::builder()
+			.with_var("greeting", "hello")
+			.build()
+			.unwrap();
+		let (output, status) = shell.capture("echo $greeting world").unwrap();
+		assert!(status.success());
+		assert_eq!(output, "hello world\n");
+
+		assert_eq!(shell.run_str("false").unwrap().code(), 1);
+		shell.run_str("foo=bar").unwrap();
+		assert_eq!(shell.get_var("foo").as_deref(), Some("bar"));
+	}
+
+	#[test]
+	fn test_env_file() {
+		let path = std::env::temp_dir().join(format!("slash_env_file_{}",std::process::id()));
+		std::fs::write(&path, "env_file_loaded=yes").unwrap();
+		let mut slash = Slash::new();
+		slash.vars_mut().export_var("OX_ENV", &path.to_string_lossy());
+		slash.source_env_file().unwrap();
+		assert_eq!(slash.vars().get_var("env_file_loaded").map(|val| val.to_string()).as_deref(), Some("yes"));
+		std::fs::remove_file(path).ok();
+	}
+
+	#[test]
+	fn test_exec_open_fds() {
+		let path = std::env::temp_dir().join(format!("slash_exec_fds_{}",std::process::id()));
+		let mut shell = Shell::new();
+		shell.run_str(&format!("exec 8> {}", path.display())).unwrap();
+		assert_eq!(shell.env().meta().open_fds().get(&8), Some(&path.display().to_string()));
+		shell.capture("echo one >&8; echo two >&8").unwrap();
+		shell.run_str("printf -u 8 '%s\\n' three").unwrap();
+		assert_eq!(std::fs::read_to_string(&path).unwrap(), "one\ntwo\nthree\n");
+
+		shell.run_str("exec 8>&-").unwrap();
+		assert!(shell.env().meta().open_fds().is_empty());
+		assert!(!shell.run_str("printf -u 8 x").unwrap().success());
+		std::fs::remove_file(path).ok();
+	}
+
+	#[test]
+	fn test_special_vars() {
+		let mut shell = Shell::new();
+		assert_eq!(shell.get_var("IFS").as_deref(), Some(" \t\n"));
+		assert_eq!(shell.get_var("OPTIND").as_deref(), Some("1"));
+		assert_eq!(shell.get_var("OPTERR").as_deref(), Some("1"));
+
+		// Special parameters can't be shadowed
+		shell.run_str("false").unwrap();
+		shell.set_var("?", "0");
+		assert_eq!(shell.get_var("?").as_deref(), Some("1"));
+		assert!(!shell.run_str("printf -v '#' x").unwrap().success());
+		assert!(!shell.run_str("read '?' <<< x").unwrap().success());
+		let mut shell = Shell::builder().with_args(["a", "b"]).build().unwrap();
+		shell.set_var("#", "5");
+		assert_eq!(shell.capture("echo $#").unwrap().0, "2\n");
+	}
+
+	#[test]
+	fn test_prompt_vars() {
+		let mut shell = Shell::new();
+		assert_eq!(shell.get_var("PS2").as_deref(), Some("> "));
+		assert_eq!(shell.get_var("PS4").as_deref(), Some("+ "));
+		// The prompt picks up a new PS1 the next time it is drawn
+		shell.run_str("PS1='\\u> '").unwrap();
+		let user = shell.env().vars().get_evar("USER").unwrap_or("unknown".into());
+		assert_eq!(crate::expand::misc::expand_prompt(None, shell.env_mut()).unwrap(), format!("{}> ", user));
+		shell.run_str("export PS1=exported").unwrap();
+		assert_eq!(crate::expand::misc::expand_prompt(None, shell.env_mut()).unwrap(), "exported");
+		shell.env_mut().vars_mut().unset_var("PS1");
+	}
+
+	#[test]
+	fn test_fg_sigint() {
+		let mut shell = Shell::new();
+		// A job killed by SIGINT abandons the rest of the line
+		let status = shell.run_str("/bin/sh -c 'kill -INT $$'; reached=1").unwrap();
+		assert_eq!(status.code(), 130);
+		assert!(shell.get_var("reached").is_none());
+
+		// A job that handles SIGINT itself doesn't
+		let status = shell.run_str("/bin/sh -c 'trap \"exit 3\" INT; kill -INT $$'; reached=1").unwrap();
+		assert!(status.success());
+		assert_eq!(shell.get_var("reached").as_deref(), Some("1"));
+	}
+
+	#[test]
+	fn test_stray_control_flow() {
+		let mut shell = Shell::new();
+		// `break` and `return` with nothing to leave are reported, and the rest of the input still runs
+		let status = shell.run_str("break; after_break=1; return; after_return=$?").unwrap();
+		assert!(status.success());
+		assert_eq!(shell.get_var("after_break").as_deref(), Some("1"));
+		assert_eq!(shell.get_var("after_return").as_deref(), Some("1"));
+	}
+
+	#[test]
+	fn test_term_size() {
+		let (mut master, mut slave) = (0, 0);
+		let size = libc::winsize { ws_row: 24, ws_col: 100, ws_xpixel: 0, ws_ypixel: 0 };
+		let result = unsafe { libc::openpty(&mut master, &mut slave, std::ptr::null_mut(), std::ptr::null(), &size) };
+		assert_eq!(result, 0);
+		assert_eq!(utils::term_size(slave), Some((100, 24)));
+		assert_eq!(utils::term_size(master), Some((100, 24)));
+		nix::unistd::close(master).unwrap();
+		nix::unistd::close(slave).unwrap();
+
+		let mut shell = Shell::new();
+		shell.env_mut().set_term_size((100, 24));
+		assert_eq!(shell.capture("echo $COLUMNS $LINES").unwrap().0, "100 24\n");
+		assert_eq!(std::env::var("COLUMNS").as_deref(), Ok("100"));
+	}
+
+	#[test]
+	fn test_prompt_hook_timeout() {
+		let mut shell = Shell::new();
+		let timeout = Some(std::time::Duration::from_millis(200));
+		let output = shell.env_mut().exec_hook("echo quick", timeout).unwrap();
+		assert_eq!(output.as_deref(), Some("quick\n"));
+
+		// Slow hooks are killed instead of holding up the prompt
+		let start = std::time::Instant::now();
+		assert!(shell.env_mut().exec_hook("sleep 5; echo late", timeout).unwrap().is_none());
+		assert!(start.elapsed() < std::time::Duration::from_secs(2));
+
+		assert!(shell.env_mut().meta_mut().set_shopt("prompt.hook_timeout", "-1").is_err());
+	}
+
+	#[test]
+	fn test_fd_audit() {
+		let mut shell = Shell::new();
+		shell.run_str("setopt core.fd_audit=true").unwrap();
+		let fds_before = helper::fd_audit_start(shell.env());
+		assert!(fds_before.is_some());
+		shell.run_str("exec 9> /dev/null").unwrap();
+		let leak = nix::fcntl::open("/dev/null", nix::fcntl::OFlag::O_RDONLY, nix::sys::stat::Mode::empty()).unwrap();
+		let leaked = helper::fd_audit_finish(shell.env(), fds_before);
+		// Descriptors opened by exec are meant to stay open
+		assert!(leaked.contains(&leak));
+		assert!(!leaked.contains(&9));
+		nix::unistd::close(leak).unwrap();
+		shell.run_str("exec 9>&-").unwrap();
+	}
+
+	#[test]
+	fn test_call_stack() {
+		let path = std::env::temp_dir().join(format!("slash_call_stack_{}",std::process::id()));
+		std::fs::write(&path, "inner() { echo $FUNCNAME[0] $FUNCNAME[1] $FUNCNAME[2] $OX_LINENO[1]; }\nouter() { inner; }\n\nouter\n").unwrap();
+		let mut shell = Shell::new();
+		let (output,_) = shell.capture(&format!("source {}", path.display())).unwrap();
+		assert_eq!(output, "inner outer source 4\n");
+		// The stack is gone once the file finishes, but functions remember where they were defined
+		shell.run_str(&format!("source {} > /dev/null", path.display())).unwrap();
+		assert!(shell.get_var("FUNCNAME").is_none());
+		assert_eq!(shell.capture("inner").unwrap().0.trim_end(), "inner");
+		let source = shell.env().logic().borrow_functions().get("inner").map(|func| func.source().to_string());
+		assert_eq!(source, Some(path.display().to_string()));
+		std::fs::remove_file(path).ok();
+	}
+
+	#[test]
+	fn test_err_policy() {
+		use crate::error::{ErrCategory, Recovery};
+		assert_eq!(ErrCategory::Parse.policy().recovery(true), Recovery::Continue);
+		assert_eq!(ErrCategory::Parse.policy().recovery(false), Recovery::Abort);
+
+		// Failed commands and redirections are ordinary failures, so the next command still runs
+		let mut shell = Shell::new();
+		let status = shell.run_str("cd /slash_no_such_dir || fell_back=1; true > /slash_no_such_dir/file; redir=$?; after=1").unwrap();
+		assert!(status.success());
+		assert_eq!(shell.get_var("fell_back").as_deref(), Some("1"));
+		assert_eq!(shell.get_var("redir").as_deref(), Some("4"));
+		assert_eq!(shell.get_var("after").as_deref(), Some("1"));
+
+		// Expansion errors throw out the rest of the input
+		assert_eq!(shell.run_str("echo $((1/0)); skipped=1").unwrap().code(), 3);
+		assert!(shell.get_var("skipped").is_none());
+		assert_eq!(shell.run_str("echo; if").unwrap().code(), 2);
+
+		// set -e applies to failed commands
+		assert_eq!(shell.run_str("set -e; cd /slash_no_such_dir; skipped=1").unwrap().code(), 1);
+		assert!(shell.get_var("skipped").is_none());
+	}
+
+	#[test]
+	fn test_special_builtin_err() {
+		// A failed special builtin is an ordinary failure, unless the shell is in POSIX mode
+		let mut shell = Shell::new();
+		assert!(shell.run_str("trap : BOGUS; after=1").unwrap().success());
+		assert_eq!(shell.get_var("after").as_deref(), Some("1"));
+		assert_eq!(shell.run_str("set -o posix; trap : BOGUS; skipped=1").unwrap().code(), 1);
+		assert!(shell.get_var("skipped").is_none());
+		// Other builtins and control flow don't end the shell, even in POSIX mode
+		assert!(shell.run_str("cd /slash_no_such_dir; for i < 1 2; do break; done; after_cd=1").unwrap().success());
+		assert_eq!(shell.get_var("after_cd").as_deref(), Some("1"));
+	}
+
+	#[test]
+	fn test_sourced_err() {
+		let path = std::env::temp_dir().join(format!("slash_sourced_err_{}",std::process::id()));
+		let path_str = path.display().to_string();
+		std::fs::write(&path, "sourced=1\ncd /slash_no_such_dir\n").unwrap();
+		let mut slash = Slash::new();
+		let err = slash.source_file(&path_str).unwrap_err();
+		assert!(err.to_string().contains(&format!("--> {}:2:1", path_str)));
+		assert_eq!(err.line(), Some(2));
+		// Sourcing runs against the live environment
+		assert_eq!(slash.vars().get_var("sourced").map(|val| val.to_string()).as_deref(), Some("1"));
+
+		std::fs::write(&path, "if true; then echo\n").unwrap();
+		let err = slash.source_file(&path_str).unwrap_err();
+		assert!(err.to_string().contains(&format!("--> {}:2:1", path_str)));
+		std::fs::remove_file(path).ok();
+	}
+}
@@ -1,12 +1,14 @@
-use std::{collections::{BTreeMap, VecDeque}, env, ffi::{CString, OsStr}, fmt, hash::Hash, io::{self, Read}, mem::take, os::fd::BorrowedFd, path::{Path, PathBuf}, sync::{Arc, LazyLock}, time::{Duration, Instant}};
+use std::{collections::{BTreeMap, VecDeque}, env, ffi::{CString, OsStr}, fmt, hash::Hash, io::{self, Read}, mem::take, os::fd::BorrowedFd, path::{Path, PathBuf}, sync::{atomic::{AtomicBool, Ordering}, Arc, LazyLock}, time::{Duration, Instant}};
+use std::os::unix::fs::{FileTypeExt, OpenOptionsExt};
 use std::collections::HashMap;
 
 use bitflags::bitflags;
-use nix::{sys::{signal::{kill, killpg, signal, SigHandler, SigmaskHow, Signal::{self, SIGCHLD, SIGTSTP, SIGTTIN, SIGTTOU}}, wait::{waitpid, WaitPidFlag, WaitStatus}}, unistd::{gethostname, getpgrp, isatty, setpgid, tcgetpgrp, tcsetpgrp, Pid, User}};
+use nix::sys::termios::{self, Termios};
+use nix::{errno::Errno, sys::{signal::{kill, killpg, signal, SigHandler, SigmaskHow, Signal::{self, SIGCHLD, SIGTSTP, SIGTTIN, SIGTTOU}}, wait::{waitpid, WaitPidFlag, WaitStatus}}, unistd::{gethostname, getpgrp, isatty, setpgid, tcgetpgrp, tcsetpgrp, Pid, User}};
 use once_cell::sync::Lazy;
 use std::sync::RwLock;
 
-use crate::{execute::dispatch, prelude::*, utils::{self, Redir}};
+use crate::{execute::dispatch, prelude::*, prompt::{comp::{CompOpts, CompSpec}, mail::MailState}, signal, utils::{self, Redir}};
 use crate::{error::{SlashErr::*, SlashErrLow}, helper::{self, VecDequeExtension}, shopt::ShOpts, SlashResult};
 
 
@@ -15,6 +17,22 @@ pub struct DisplayWaitStatus(pub WaitStatus);
 
 pub const PARAMS: [&str;8] = ["#", "@", "*", "$", "!", "?", "-", "_"];
 
+/// Exported functions are stored in the environment as `OX_FUNC_name%%=() { body }`
+/// This mirrors the way bash encodes exported functions with `BASH_FUNC_name%%`
+pub const FUNC_EXPORT_PREFIX: &str = "OX_FUNC_";
+pub const FUNC_EXPORT_SUFFIX: &str = "%%";
+
+/// The value IFS starts with, and the one used when IFS is unset
+pub const DEFAULT_IFS: &str = " \t\n";
+/// The prompt strings and the values they start with. `PS1` is the main prompt, `PS2` continues an unfinished line,
+/// `PS3` is shown by `select`, and `PS4` comes before each command traced by `set -x`
+pub const PROMPT_VARS: [(&str,&str);4] = [("PS1", "$> "), ("PS2", "> "), ("PS3", "#? "), ("PS4", "+ ")];
+/// Parameters that only the shell sets. These can't be assigned to or shadowed by variables
+pub const SPECIAL_PARAMS: [&str;7] = ["?", "#", "$", "!", "@", "*", "-"];
+/// The arrays that describe the call stack, innermost call first
+/// These are the equivalents of bash's `FUNCNAME`, `BASH_SOURCE`, and `BASH_LINENO`
+pub const CALL_STACK_VARS: [&str;3] = ["FUNCNAME", "OX_SOURCE", "OX_LINENO"];
+
 impl fmt::Display for DisplayWaitStatus {
 	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
 		match &self.0 {
@@ -48,6 +66,11 @@ impl fmt::Display for DisplayWaitStatus {
 
 pub static RSH_PATH: Lazy<String> = Lazy::new(|| std::env::current_exe().unwrap().to_str().unwrap().to_string());
 
+/// Cleared in background children of the shell, which must never take the terminal from the foreground job
+static TTY_ACCESS: AtomicBool = AtomicBool::new(true);
+/// Cleared in forked children that run compound commands, which wait on each of their own children explicitly
+static ASYNC_REAPING: AtomicBool = AtomicBool::new(true);
+
 pub static JOBS: LazyLock<Arc<RwLock<JobTable>>> = LazyLock::new(|| {
 	Arc::new(
 		RwLock::new(
@@ -90,15 +113,79 @@ bitflags! {
 		const INHERIT_RET      = 0b00000100000000000000000000000000; // set -T
 		const SOURCING         = 0b00001000000000000000000000000000;
 		const INITIALIZED      = 0b00010000000000000000000000000000;
+		const IN_CHPWD         = 0b00100000000000000000000000000000; // Keeps the chpwd hook from re-triggering itself
+		const POSIX            = 0b01000000000000000000000000000000; // set -o posix
 	}
 	#[derive(Debug,Copy,Clone)]
-	pub struct JobCmdFlags: i8 { // Options for the jobs builtin
+	pub struct JobCmdFlags: u8 { // Options for the jobs builtin
 		const LONG      = 0b00000001;
 		const PIDS      = 0b00000010;
 		const NEW_ONLY  = 0b00000100;
 		const RUNNING   = 0b00001000;
 		const STOPPED   = 0b00010000;
 		const INIT      = 0b00100000;
+		const JSON      = 0b01000000;
+		const PORCELAIN = 0b10000000;
+	}
+}
+
+impl EnvFlags {
+	/// Every option letter understood by `set`, in the order they are reported
+	pub const SET_OPTS: &'static str = "abefhkmnrtuvxBCEHPT";
+
+	/// Maps an option letter used by the `set` builtin to its flag
+	pub fn from_set_opt(opt: char) -> Option<Self> {
+		match opt {
+			'a' => Some(Self::EXPORT_ALL_VARS),
+			'b' => Some(Self::REPORT_JOBS_ASAP),
+			'e' => Some(Self::EXIT_ON_ERROR),
+			'f' => Some(Self::NO_GLOB),
+			'h' => Some(Self::HASH_CMDS),
+			'k' => Some(Self::ASSIGN_ANYWHERE),
+			'm' => Some(Self::ENABLE_JOB_CTL),
+			'n' => Some(Self::NO_EXECUTE),
+			'r' => Some(Self::ENABLE_RSHELL),
+			't' => Some(Self::EXIT_AFTER_EXEC),
+			'u' => Some(Self::UNSET_IS_ERROR),
+			'v' => Some(Self::PRINT_INPUT),
+			'x' => Some(Self::STACK_TRACE),
+			'B' => Some(Self::EXPAND_BRACES),
+			'C' => Some(Self::NO_OVERWRITE),
+			'E' => Some(Self::INHERIT_ERR),
+			'H' => Some(Self::HIST_SUB),
+			'P' => Some(Self::NO_CD_SYMLINKS),
+			'T' => Some(Self::INHERIT_RET),
+			_ => None
+		}
+	}
+
+	/// Every long option name understood by `set -o`, paired with its flag
+	pub const LONG_OPTS: [(&'static str, Self);20] = [
+		("allexport", Self::EXPORT_ALL_VARS),
+		("notify", Self::REPORT_JOBS_ASAP),
+		("errexit", Self::EXIT_ON_ERROR),
+		("noglob", Self::NO_GLOB),
+		("hashall", Self::HASH_CMDS),
+		("keyword", Self::ASSIGN_ANYWHERE),
+		("monitor", Self::ENABLE_JOB_CTL),
+		("noexec", Self::NO_EXECUTE),
+		("restricted", Self::ENABLE_RSHELL),
+		("onecmd", Self::EXIT_AFTER_EXEC),
+		("nounset", Self::UNSET_IS_ERROR),
+		("verbose", Self::PRINT_INPUT),
+		("xtrace", Self::STACK_TRACE),
+		("braceexpand", Self::EXPAND_BRACES),
+		("noclobber", Self::NO_OVERWRITE),
+		("errtrace", Self::INHERIT_ERR),
+		("histexpand", Self::HIST_SUB),
+		("physical", Self::NO_CD_SYMLINKS),
+		("functrace", Self::INHERIT_RET),
+		("posix", Self::POSIX)
+	];
+
+	/// Maps an option name used by `set -o` to its flag
+	pub fn from_long_opt(name: &str) -> Option<Self> {
+		Self::LONG_OPTS.iter().find(|(opt,_)| *opt == name).map(|(_,flag)| *flag)
 	}
 }
 
@@ -118,7 +205,9 @@ impl Slash {
 		let meta = EnvMeta::new(EnvFlags::empty());
 		let ctx = ExecCtx::new();
 
-		Self { vars, logic, meta, ctx }
+		let mut slash = Self { vars, logic, meta, ctx };
+		slash.import_funcs();
+		slash
 	}
 	pub fn vars(&self) -> &VarTable {
 		&self.vars
@@ -138,6 +227,14 @@ impl Slash {
 	pub fn meta_mut(&mut self) -> &mut EnvMeta {
 		&mut self.meta
 	}
+	/// Whether the shell is running in POSIX mode, with ox extensions disabled
+	pub fn is_posix(&self) -> bool {
+		self.meta.flags().contains(EnvFlags::POSIX)
+	}
+	/// Aliases are only expanded in interactive shells when running in POSIX mode
+	pub fn aliases_enabled(&self) -> bool {
+		!self.is_posix() || self.meta.flags().contains(EnvFlags::INTERACTIVE)
+	}
 	pub fn ctx(&self) -> &ExecCtx {
 		&self.ctx
 	}
@@ -158,6 +255,11 @@ impl Slash {
 
 		self.vars.set_param("?", &code.to_string())
 	}
+	/// The exit status that the prompt reports. While the prompt is up, this is the status of the last command line
+	/// as it was when the prompt was entered, so hooks that run while it is drawn can't change it. Otherwise it is `$?`
+	pub fn prompt_status(&self) -> i32 {
+		self.meta.prompt_status().unwrap_or_else(|| self.get_status())
+	}
 	pub fn in_pipe(&self) -> bool {
 		self.meta.flags().contains(EnvFlags::IN_SUB_PROC)
 	}
@@ -183,14 +285,72 @@ impl Slash {
 		Ok(())
 	}
 
+	/// Exports the terminal's size as `COLUMNS` and `LINES`, if it has changed since it was last recorded
+	pub fn set_term_size(&mut self, size: (u16,u16)) {
+		if self.meta.term_size == Some(size) {
+			return
+		}
+		self.meta.term_size = Some(size);
+		self.vars.export_var("COLUMNS", &size.0.to_string());
+		self.vars.export_var("LINES", &size.1.to_string());
+	}
+	/// Reads the terminal's size from whichever standard descriptor is connected to it
+	pub fn update_term_size(&mut self) {
+		if let Some(size) = [STDIN_FILENO, STDOUT_FILENO, STDERR_FILENO].into_iter().find_map(utils::term_size) {
+			self.set_term_size(size);
+		}
+	}
+
+	/// Changes the working directory, following symlinks logically unless `set -P` is on
 	pub fn change_dir(&mut self, path: &Path) -> SlashResult<()> {
-		let cwd = env::var("PWD").map_err(|_| Low(SlashErrLow::from_io()))?;
-		self.vars.export_var("OLDPWD", &cwd);
-		env::set_current_dir(path)?;
-		let cwd = env::current_dir().map_err(|_| Low(SlashErrLow::from_io()))?;
-		self.vars.export_var("PWD", cwd.to_str().unwrap());
+		let physical = self.meta.flags().contains(EnvFlags::NO_CD_SYMLINKS);
+		self.change_dir_as(path, physical)
+	}
+	/// Changes the working directory and updates `PWD` and `OLDPWD`
+	/// In logical mode, `PWD` is built from the old `PWD` and the path as it was written, so `..` leaves a symlinked directory the way it was entered.
+	/// If the logical path can't be entered, or with `physical`, the path is resolved by the system and `PWD` gets the real path.
+	/// Nothing changes if the directory can't be entered
+	pub fn change_dir_as(&mut self, path: &Path, physical: bool) -> SlashResult<()> {
+		let old_pwd = helper::logical_cwd(self);
+		let logical = helper::logical_path(&old_pwd, path);
+		let cwd = if !physical && env::set_current_dir(&logical).is_ok() {
+			logical
+		} else {
+			env::set_current_dir(path)?;
+			env::current_dir()?
+		};
+		self.vars.export_var("OLDPWD", &old_pwd.to_string_lossy());
+		self.vars.export_var("PWD", &cwd.to_string_lossy());
+		self.on_dir_change(&cwd);
 		Ok(())
 	}
+	/// Runs everything that follows a change of the working directory, like zsh's chpwd machinery:
+	/// per-directory env files, the `core.chpwd_title` and `core.chpwd_ls` options, and the `chpwd` function
+	pub fn on_dir_change(&mut self, cwd: &Path) {
+		crate::builtin::env::update_dir_env(self, cwd);
+		if self.meta.flags().contains(EnvFlags::IN_CHPWD) {
+			return
+		}
+		self.meta.mod_flags(|f| *f |= EnvFlags::IN_CHPWD);
+		let core = &self.meta.borrow_shopts().core;
+		let (set_title, list_dir) = (core.chpwd_title, core.chpwd_ls);
+
+		if set_title && isatty(STDERR_FILENO).unwrap_or(false) {
+			let home = env::var("HOME").unwrap_or_default();
+			let mut title = cwd.to_string_lossy().to_string();
+			if !home.is_empty() && title.starts_with(&home) {
+				title = title.replacen(&home, "~", 1);
+			}
+			eprint!("\x1b]0;{}\x07",title);
+		}
+		if list_dir {
+			dispatch::exec_input("ls".into(), self).catch();
+		}
+		if self.logic.get_func("chpwd").is_some() {
+			dispatch::exec_input("chpwd".into(), self).catch();
+		}
+		self.meta.mod_flags(|f| f.remove(EnvFlags::IN_CHPWD));
+	}
 
 	pub fn source_rc(&mut self, path: Option<PathBuf>) -> SlashResult<()> {
 		let path = if let Some(path) = path {
@@ -199,32 +359,169 @@ impl Slash {
 			let home = env::var("HOME").unwrap();
 			PathBuf::from(format!("{home}/.slashrc"))
 		};
-		if let Err(e) = self.source_file(path.to_str().unwrap()) {
+		if let Err(e) = self.source_startup_file(path.to_str().unwrap()) {
 			self.set_code(1);
 			eprintln!("Failed to source slashrc: {}",e);
 		}
 		Ok(())
 	}
+	/// Sources a file that is read at startup, like the rc file
+	/// Ctrl-C stops the file at the command that is running and skips the rest of it, instead of leaving startup stuck on it.
+	/// The interrupt is reported along with the line it stopped at, so that it is clear that the file was only partly applied
+	fn source_startup_file(&mut self, path: &str) -> SlashResult<()> {
+		let Err(e) = self.source_file(path) else {
+			return Ok(())
+		};
+		if !matches!(e.low(), SlashErrLow::Interrupted) {
+			return Err(e)
+		}
+		self.set_code(130);
+		let line = e.line().map(|line| format!(" at line {}",line)).unwrap_or_default();
+		eprintln!("slash: {}: interrupted{}, the rest of the file was skipped",path,line);
+		Ok(())
+	}
+
+	/// Sources the startup file named by the environment
+	/// Non-interactive shells read `OX_ENV`, falling back to `ENV`. Interactive shells read `ENV` in POSIX mode,
+	/// and nothing otherwise, since they have the rc file
+	pub fn source_env_file(&mut self) -> SlashResult<()> {
+		let interactive = self.meta.flags().contains(EnvFlags::INTERACTIVE);
+		let names: &[&str] = match (interactive, self.is_posix()) {
+			(false, _) => &["OX_ENV", "ENV"],
+			(true, true) => &["ENV"],
+			(true, false) => &[]
+		};
+		// These come from the parent's environment, which isn't copied into the shell's own table
+		let Some(path) = names.iter().find_map(|name| env::var(name).ok().filter(|path| !path.is_empty())) else {
+			return Ok(())
+		};
+		let path = match path.strip_prefix("~/") {
+			Some(rest) => format!("{}/{rest}",env::var("HOME").unwrap_or_default()),
+			None => path
+		};
+		self.source_startup_file(&path)
+	}
 
 
 	pub fn source_file<'a>(&mut self, path: &str) -> SlashResult<()> {
+		self.source_in_frame(path, "main", 0)
+	}
+	/// Runs a file as a new frame on the call stack, named `name` and called from `line`
+	/// The call stack arrays are put back once the file finishes
+	pub fn source_in_frame(&mut self, path: &str, name: &str, line: usize) -> SlashResult<()> {
+		self.run_file(path, name, line, dispatch::exec_input)
+	}
+	/// Runs a file as the main script, where an error in one command doesn't always stop the ones after it
+	pub fn run_script(&mut self, path: &str) -> SlashResult<()> {
+		self.run_file(path, "main", 0, dispatch::exec_toplevel)
+	}
+	fn run_file(&mut self, path: &str, name: &str, line: usize, run: fn(String,&mut Slash) -> SlashResult<()>) -> SlashResult<()> {
 		let mut file = utils::SmartFD::std_open(Path::new(path))?;
 		let mut buffer = String::new();
 		file.read_to_string(&mut buffer).map_err(|_| Low(SlashErrLow::from_io()))?;
 		file.close()?;
 
-		dispatch::exec_input(buffer, self)
+		if self.meta.flags().contains(EnvFlags::PRINT_INPUT) {
+			eprintln!("{}",buffer);
+		}
+		let saved_stack = CALL_STACK_VARS.map(|var| self.vars.get_var(var));
+		self.vars.push_call_frame(name, path, line);
+		let result = run(buffer, self).map_err(|e| e.attribute_to(path));
+		for (var,val) in CALL_STACK_VARS.iter().zip(saved_stack) {
+			match val {
+				Some(val) => self.vars.set_var(var, val),
+				None => self.vars.unset_var(var)
+			}
+		}
+		result
 	}
 
-	pub fn get_cstring_evars<'a>(&self) -> SlashResult<Vec<CString>> {
-		let env = self.vars.borrow_evars();
-		let env = env.iter().map(|(k,v)| CString::new(format!("{}={}",k,v).as_str()).unwrap()).collect::<Vec<CString>>();
-		Ok(env)
+	/// The environment given to external commands, as `NAME=value` strings
+	/// Only exported variables are included, so shell variables never reach a command, even ones set in a subshell
+	pub fn child_envp(&self) -> Vec<CString> {
+		self.vars.evars().iter().map(|(key,val)| CString::new(format!("{}={}",key,val)).unwrap()).collect()
+	}
+	/// Prepares a freshly forked child to run shell code, like a command substitution or a pipeline stage
+	/// The child is a copy of this shell, so nothing it changes reaches the parent. Its commands run in its own foreground,
+	/// and it only keeps the ERR and RETURN traps if `set -E` or `set -T` is on. Traps on signals are dropped, except for ones that ignore the signal
+	pub fn enter_child_env(&mut self) {
+		self.ctx.flags_mut().remove(utils::ExecFlags::BACKGROUND | utils::ExecFlags::NO_FORK);
+		self.enter_trap_scope(false);
+		// Caught signals go back to their usual handling, while ignored ones stay ignored
+		for sig in signal::trapped_signals() {
+			self.logic.take_trap(sig.as_str());
+			signal::set_signal_trap(sig, None).ok();
+		}
+	}
+	/// Runs shell code against a copy of this shell, for subshells that aren't forked
+	/// Everything the code changes is put back afterwards, including the process environment that exports write to
+	/// and the working directory. Only the exit status is kept.
+	/// Only the variables that the code could have written to the process environment are put back, which are the ones
+	/// exported before or after it ran, so that variables set by anything else in the process are left alone
+	pub fn in_child_env<T>(&mut self, f: impl FnOnce(&mut Slash) -> SlashResult<T>) -> SlashResult<T> {
+		let snapshot = self.clone();
+		let process_env = env::vars_os().collect::<HashMap<_,_>>();
+		let cwd = env::current_dir().ok();
+		self.enter_trap_scope(false);
+
+		let result = f(self);
+		let status = self.get_status();
+		let mut touched = self.vars.exported_names().cloned().collect::<HashSet<_>>();
+		touched.extend(snapshot.vars.exported_names().cloned());
+		*self = snapshot;
+		for key in touched {
+			let before = process_env.get(OsStr::new(&key));
+			if env::var_os(&key).as_ref() == before {
+				continue
+			}
+			match before {
+				Some(val) => env::set_var(&key, val),
+				None => env::remove_var(&key)
+			}
+		}
+		if let Some(cwd) = cwd.filter(|cwd| env::current_dir().ok().as_ref() != Some(cwd)) {
+			env::set_current_dir(cwd).ok();
+		}
+		self.set_code(status);
+		result
 	}
 	pub fn is_func(&self, name: &str) -> SlashResult<bool> {
 		let result = self.logic.get_func(name).is_some();
 		Ok(result)
 	}
+	/// Places a function definition in the environment so that child slash processes can pick it up
+	pub fn export_func(&mut self, name: &str) -> SlashResult<()> {
+		let body = self.logic.get_func(name).ok_or_else(|| Low(SlashErrLow::ExecFailed(format!("export: `{}' is not a function",name))))?;
+		let key = format!("{FUNC_EXPORT_PREFIX}{name}{FUNC_EXPORT_SUFFIX}");
+		self.vars.export_var(&key, &format!("() {{ {body} }}"));
+		Ok(())
+	}
+	/// Removes a function, along with its exported definition if it has one
+	pub fn unset_func(&mut self, name: &str) -> SlashResult<()> {
+		if self.logic.func_attrs(name).contains(FuncAttrs::READONLY) {
+			return Err(Low(SlashErrLow::ExecFailed(format!("unset: {}: cannot unset: readonly function",name))))
+		}
+		self.logic.remove_func(name);
+		let key = format!("{FUNC_EXPORT_PREFIX}{name}{FUNC_EXPORT_SUFFIX}");
+		self.vars.unset_var(&key);
+		Ok(())
+	}
+	/// Looks for functions exported by a parent slash process and defines them in this one
+	/// The environment variables are kept so that they continue to be passed down to further children
+	pub fn import_funcs(&mut self) {
+		for (key,val) in env::vars() {
+			let name = key.strip_prefix(FUNC_EXPORT_PREFIX).and_then(|key| key.strip_suffix(FUNC_EXPORT_SUFFIX));
+			let body = val.trim()
+				.strip_prefix("()")
+				.map(|val| val.trim_start())
+				.and_then(|val| val.strip_prefix('{'))
+				.and_then(|val| val.strip_suffix('}'));
+			if let (Some(name),Some(body)) = (name,body) {
+				self.logic.new_func(name, body.trim(), "");
+				self.vars.export_var(&key, &val);
+			}
+		}
+	}
 
 	pub fn init_env_vars(clean: bool) -> HashMap<String,String> {
 		let pathbuf_to_string = |pb: Result<PathBuf, std::io::Error>| pb.unwrap_or_default().to_string_lossy().to_string();
@@ -233,16 +530,11 @@ impl Slash {
 		if !clean {
 			env_vars = std::env::vars().collect::<HashMap<String,String>>();
 		}
-		let term = {
-			if isatty(1).unwrap() {
-				if let Ok(term) = std::env::var("TERM") {
-					term
-				} else {
-					"linux".to_string()
-				}
-			} else {
-				"xterm-256color".to_string()
-			}
+		// A TERM passed down by the parent is kept, whether or not stdout is a terminal
+		let term = match std::env::var("TERM") {
+			Ok(term) => term,
+			Err(_) if isatty(1).unwrap_or(false) => "linux".to_string(),
+			Err(_) => "xterm-256color".to_string()
 		};
 		let home;
 		let username;
@@ -304,6 +596,190 @@ impl Slash {
 		self.set_code(status);
 		Ok(status)
 	}
+	/// Functions, command substitutions, and subshells only inherit the ERR trap if `set -E` is on,
+	/// and the RETURN trap if `set -T` is on. `traced` is set for functions with the trace attribute, which always inherit the RETURN trap.
+	/// Called once the new scope has its own copy of the environment
+	pub fn enter_trap_scope(&mut self, traced: bool) {
+		if !self.meta.flags().contains(EnvFlags::INHERIT_ERR) {
+			self.logic.take_trap("ERR");
+		}
+		if !traced && !self.meta.flags().contains(EnvFlags::INHERIT_RET) {
+			self.logic.take_trap("RETURN");
+		}
+	}
+	/// Runs the trap set for `condition`, if there is one, leaving `$?` as it was
+	/// The trap is removed while it runs, so that it can't trigger itself. If it calls `exit`, the error is returned
+	pub fn run_trap(&mut self, condition: &str) -> SlashResult<()> {
+		let Some(action) = self.logic.take_trap(condition) else {
+			return Ok(())
+		};
+		let status = self.get_status();
+		let result = dispatch::exec_input(action.clone(), self);
+		self.logic.set_trap(condition, &action);
+		if let Err(e) = result {
+			let low = match &e {
+				Low(low) => low,
+				High(high) => high.get_err()
+			};
+			if let SlashErrLow::CleanExit(_) = low {
+				return Err(e)
+			}
+			eprintln!("{}",e);
+		}
+		self.set_code(status);
+		Ok(())
+	}
+	/// Runs the traps of the signals that arrived since the last safe point
+	/// Called between commands, so that a trap never runs in the middle of one
+	pub fn run_signal_traps(&mut self) -> SlashResult<()> {
+		for sig in signal::take_pending_traps() {
+			self.run_trap(sig.as_str())?;
+		}
+		Ok(())
+	}
+	/// Runs a custom prompt escape sequence in a child that gets at most `timeout` to finish, and returns its output
+	/// A child that runs out of time is killed, since its output would come too late, and `None` is returned.
+	/// The child reads from `/dev/null`, since the line editor owns the terminal
+	pub fn exec_hook(&mut self, input: &str, timeout: Option<Duration>) -> SlashResult<Option<String>> {
+		let (mut r_pipe, mut w_pipe) = utils::SmartFD::pipe()?;
+		disable_reaping();
+		let child = match unsafe { fork() } {
+			Ok(ForkResult::Child) => {
+				r_pipe.close()?;
+				if let Ok(null) = nix::fcntl::open("/dev/null", nix::fcntl::OFlag::O_RDONLY, nix::sys::stat::Mode::empty()) {
+					nix::unistd::dup2(null, STDIN_FILENO).ok();
+					close(null).ok();
+				}
+				w_pipe.dup2(&STDOUT_FILENO)?;
+				w_pipe.close()?;
+				self.enter_child_env();
+				let code = match dispatch::exec_input(input.to_string(), self) {
+					Ok(_) => self.get_status(),
+					Err(e) => {
+						eprintln!("{}",e);
+						1
+					}
+				};
+				std::process::exit(code)
+			}
+			Ok(ForkResult::Parent { child }) => child,
+			Err(_) => {
+				enable_reaping()?;
+				return Err(Low(SlashErrLow::ExecFailed("Failed to fork for prompt hook".into())))
+			}
+		};
+		w_pipe.close()?;
+
+		// The pipe reaches end of file once the child exits and everything holding its output has closed it
+		let deadline = timeout.map(|timeout| Instant::now() + timeout);
+		let mut output = vec![];
+		let mut buffer = [0u8;4096];
+		let mut finished = false;
+		loop {
+			let remaining = match deadline {
+				Some(deadline) => deadline.saturating_duration_since(Instant::now()).as_millis().min(libc::c_int::MAX as u128) as libc::c_int,
+				None => -1
+			};
+			let mut poll_fd = libc::pollfd { fd: r_pipe.as_raw_fd(), events: libc::POLLIN, revents: 0 };
+			let ready = unsafe { libc::poll(&mut poll_fd, 1, remaining) };
+			if ready == 0 {
+				break
+			}
+			if ready < 0 {
+				if Errno::last() == Errno::EINTR {
+					continue
+				}
+				break
+			}
+			match nix::unistd::read(r_pipe.as_raw_fd(), &mut buffer) {
+				Ok(0) => {
+					finished = true;
+					break
+				}
+				Ok(n) => output.extend_from_slice(&buffer[..n]),
+				Err(Errno::EINTR) => continue,
+				Err(_) => break
+			}
+		}
+		r_pipe.close()?;
+
+		if !finished {
+			kill(child, Signal::SIGKILL).ok();
+		}
+		waitpid(child, None).ok();
+		enable_reaping()?;
+		Ok(finished.then(|| String::from_utf8_lossy(&output).into_owned()))
+	}
+	/// Runs the EXIT trap if one is set, then removes the temporary files that are left over
+	/// The trap is removed first, so it only ever runs once. `$?` holds the status the shell is exiting with,
+	/// and is left unchanged unless the trap calls `exit`. The files are removed after the trap, so that it can still use them
+	pub fn run_exit_trap(&mut self) {
+		if let Some(action) = self.logic.take_trap("EXIT") {
+			self.exec_exit_trap(action);
+		}
+		self.meta.remove_temp_files(0);
+	}
+	fn exec_exit_trap(&mut self, action: String) {
+		signal::clear_pending_exit();
+		let status = self.get_status();
+		let Err(e) = dispatch::exec_input(action, self) else {
+			self.set_code(status);
+			return
+		};
+		let low = match &e {
+			Low(low) => low,
+			High(high) => high.get_err()
+		};
+		match low {
+			SlashErrLow::CleanExit(code) => self.set_code(*code),
+			_ => {
+				eprintln!("{}",e);
+				self.set_code(status)
+			}
+		}
+	}
+	/// Executes the input in a forked child, and returns everything that it writes to stdout
+	/// The output is drained from a pipe while the child runs, so it can be arbitrarily large
+	/// The child's exit status becomes the current exit status
+	pub fn exec_captured(&mut self, input: &str) -> SlashResult<String> {
+		let (mut r_pipe, mut w_pipe) = utils::SmartFD::pipe()?;
+		disable_reaping();
+		match unsafe { fork() } {
+			Ok(ForkResult::Child) => {
+				r_pipe.close()?;
+				w_pipe.dup2(&STDOUT_FILENO)?;
+				w_pipe.close()?;
+				self.enter_child_env();
+				let code = match dispatch::exec_input(input.to_string(), self) {
+					Ok(_) => self.get_status(),
+					Err(e) => {
+						eprintln!("{}",e);
+						1
+					}
+				};
+				std::process::exit(code)
+			}
+			Ok(ForkResult::Parent { child }) => {
+				w_pipe.close()?;
+				let mut buffer = String::new();
+				let read_result = r_pipe.read_to_string(&mut buffer);
+				r_pipe.close()?;
+				let code = match waitpid(child, None) {
+					Ok(WaitStatus::Exited(_, code)) => code,
+					Ok(WaitStatus::Signaled(_, sig, _)) => utils::SIG_EXIT_OFFSET + sig as i32,
+					_ => 0
+				};
+				enable_reaping()?;
+				read_result.map_err(|_| Low(SlashErrLow::from_io()))?;
+				self.set_code(code);
+				Ok(buffer)
+			}
+			Err(_) => {
+				enable_reaping()?;
+				Err(Low(SlashErrLow::ExecFailed("Failed to fork for output capture".into())))
+			}
+		}
+	}
 }
 
 #[derive(Debug,Clone)]
@@ -311,8 +787,7 @@ pub struct ExecCtx {
 	redir_queue: VecDeque<utils::Redir>,
 	flags: utils::ExecFlags,
 	depth: usize,
-	state_stack: Vec<Box<ExecCtx>>,
-	max_recurse_depth: usize
+	state_stack: Vec<Box<ExecCtx>>
 }
 
 impl ExecCtx {
@@ -321,8 +796,7 @@ impl ExecCtx {
 			redir_queue: VecDeque::new(),
 			flags: utils::ExecFlags::empty(),
 			depth: 0,
-			state_stack: vec![], // Each alteration is local to a single layer of recursion
-			max_recurse_depth: 1000
+			state_stack: vec![] // Each alteration is local to a single layer of recursion
 		}
 	}
 	/// Creates a new instance of ExecCtx which retains only the standard input of the original
@@ -331,6 +805,7 @@ impl ExecCtx {
 		let mut clone = self.clone();
 		let (cond_redirs,_) = self.sort_redirs();
 		clone.redir_queue = cond_redirs.into();
+		clone.flags |= utils::ExecFlags::IN_COND;
 		clone
 	}
 	/// Creates a new instance of ExecCtx which retains only the stdout and stderr of the original
@@ -359,29 +834,33 @@ impl ExecCtx {
 		(in_redirs,out_redirs)
 	}
 	pub fn push_state(&mut self) -> SlashResult<()> {
+		// Saved states don't carry the stack below them, so each push is a shallow copy
+		let stack = std::mem::take(&mut self.state_stack);
 		let saved_state = Box::new(self.clone());
+		self.state_stack = stack;
 		self.state_stack.push(saved_state);
 		Ok(())
 	}
 	pub fn pop_state(&mut self) -> SlashResult<()> {
 		if let Some(state) = self.state_stack.pop() {
+			let stack = std::mem::take(&mut self.state_stack);
 			*self = *state;
+			self.state_stack = stack;
 		}
 		Ok(())
 	}
+	/// Restores the state saved by the matching `descend()`, including the depth
 	pub fn ascend(&mut self) -> SlashResult<()> {
-		self.pop_state()?;
-		self.depth = self.depth.saturating_sub(1);
-		Ok(())
+		self.pop_state()
 	}
-	pub fn descend(&mut self) -> SlashResult<()> {
+	pub fn descend(&mut self, max_depth: usize) -> SlashResult<()> {
+		if self.depth >= max_depth {
+			let msg = format!("Maximum nesting depth of {} exceeded. The limit can be raised with `setopt core.max_recurse_depth=N`",max_depth);
+			return Err(Low(SlashErrLow::ExecFailed(msg)))
+		}
 		self.push_state()?;
 		self.depth += 1;
-		if self.depth >= self.max_recurse_depth {
-			Err(Low(SlashErrLow::InternalErr(format!("Hit maximum recursion depth during execution: {}",self.max_recurse_depth))))
-		} else {
-			Ok(())
-		}
+		Ok(())
 	}
 	pub fn depth(&self) -> usize {
 		self.depth
@@ -530,7 +1009,9 @@ impl JobBuilder {
 		Job {
 			table_id: self.table_id,
 			pgid: self.pgid.unwrap(),
-			children: self.children
+			children: self.children,
+			tty_modes: None,
+			nohup: false
 		}
 	}
 }
@@ -540,15 +1021,48 @@ pub struct Job {
 	table_id: Option<usize>,
 	pgid: Pid,
 	children: Vec<ChildProc>,
+	/// The terminal modes the job had when it was stopped, given back to it when it is resumed in the foreground
+	/// Kept in libc's form, since nix's `Termios` can't be shared with the signal handlers that use the job table
+	tty_modes: Option<libc::termios>,
+	/// Set by `disown -h`, so that the job is left running when the shell hangs up
+	nohup: bool
 }
 
 impl Job {
+	pub fn set_tty_modes(&mut self, modes: Option<Termios>) {
+		self.tty_modes = modes.map(Into::into)
+	}
+	pub fn take_tty_modes(&mut self) -> Option<Termios> {
+		self.tty_modes.take().map(Into::into)
+	}
 	pub fn set_table_id(&mut self, id: usize) {
 		self.table_id = Some(id)
 	}
+	pub fn set_nohup(&mut self) {
+		self.nohup = true
+	}
+	pub fn is_nohup(&self) -> bool {
+		self.nohup
+	}
+	pub fn is_stopped(&self) -> bool {
+		self.children.iter().any(|chld| chld.is_stopped())
+	}
 	pub fn is_alive(&self) -> bool {
 		!self.children.iter().all(|chld| chld.is_done())
 	}
+	/// The job's exit status, once every process in it has exited or been killed by a signal
+	/// Like a pipeline, the status is that of the last process
+	pub fn exit_code(&self) -> Option<i32> {
+		let statuses = self.get_statuses();
+		if !statuses.iter().all(|status| matches!(status, WaitStatus::Exited(..) | WaitStatus::Signaled(..))) {
+			return None
+		}
+		match statuses.last() {
+			Some(WaitStatus::Exited(_, code)) => Some(*code),
+			Some(WaitStatus::Signaled(_, sig, _)) => Some(utils::SIG_EXIT_OFFSET + *sig as i32),
+			_ => Some(0)
+		}
+	}
 	pub fn table_id(&self) -> Option<usize> {
 		self.table_id
 	}
@@ -637,12 +1151,11 @@ impl Job {
 		attach_tty(self.pgid)
 	}
 	pub fn killpg(&mut self, signal: Signal) -> SlashResult<()> {
-		let status = match signal {
-			Signal::SIGTSTP => WaitStatus::Stopped(self.pgid, Signal::SIGTSTP),
-			Signal::SIGCONT => WaitStatus::Continued(self.pgid),
-			_ => unimplemented!()
-		};
-		self.set_statuses(status);
+		match signal {
+			Signal::SIGTSTP => self.set_statuses(WaitStatus::Stopped(self.pgid, Signal::SIGTSTP)),
+			Signal::SIGCONT => self.set_statuses(WaitStatus::Continued(self.pgid)),
+			_ => { /* The new status is picked up by the sigchld handler */ }
+		}
 		killpg(self.pgid, Some(signal)).map_err(|_| Low(SlashErrLow::from_io()))?;
 		Ok(())
 	}
@@ -650,7 +1163,15 @@ impl Job {
 		let mut statuses = Vec::new();
 
 		for child in self.children.iter_mut() {
-			let result = child.waitpid(Some(WaitPidFlag::WUNTRACED));
+			if matches!(child.status(), WaitStatus::Exited(..) | WaitStatus::Signaled(..)) {
+				// Already reaped
+				statuses.push(child.status());
+				continue
+			}
+			let mut result = child.waitpid(Some(WaitPidFlag::WUNTRACED));
+			while result == Err(Errno::EINTR) {
+				result = child.waitpid(Some(WaitPidFlag::WUNTRACED));
+			}
 			match result {
 				Ok(status) => {
 					statuses.push(status);
@@ -694,6 +1215,33 @@ impl Job {
 
 		output
 	}
+	/// Machine readable form of the job, used by `jobs --json`
+	pub fn to_json(&self, job_order: &[usize]) -> serde_json::Value {
+		let id = self.table_id.unwrap_or_default();
+		let processes = self.children.iter().map(|child| {
+			serde_json::json!({
+				"pid": child.pid().as_raw(),
+				"command": child.command().unwrap_or_default(),
+				"status": DisplayWaitStatus(child.status()).to_string()
+			})
+		}).collect::<Vec<_>>();
+		serde_json::json!({
+			"id": id + 1,
+			"pgid": self.pgid.as_raw(),
+			"current": job_order.last() == Some(&id),
+			"processes": processes
+		})
+	}
+	/// Plain form of the job, used by `jobs --porcelain`
+	/// Each process gets its own line of tab separated fields: `id pgid pid status command`
+	pub fn porcelain(&self) -> String {
+		let id = self.table_id.unwrap_or_default() + 1;
+		self.children.iter().map(|child| {
+			let status = DisplayWaitStatus(child.status()).to_string();
+			let cmd = child.command().unwrap_or_default();
+			format!("{}\t{}\t{}\t{}\t{}", id, self.pgid, child.pid(), status, utils::escape_porcelain(&cmd))
+		}).collect::<Vec<_>>().join("\n")
+	}
 }
 
 #[derive(Debug,Clone)]
@@ -701,12 +1249,15 @@ pub struct JobTable {
 	fg: Option<Job>,
 	jobs: Vec<Option<Job>>,
 	order: Vec<usize>,
-	new_updates: Vec<usize>
+	new_updates: Vec<usize>,
+	reaped: HashMap<Pid,WaitStatus>, // Children that were reaped before their job was registered
+	exited: VecDeque<(Vec<Pid>,i32)>, // Finished background jobs that left the table, kept so that `wait` can still find their status
+	reports: Vec<String> // Background job changes waiting to be printed before the next prompt
 }
 
 impl JobTable {
 	pub fn new() -> Self {
-		Self { fg: None, jobs: vec![], order: vec![], new_updates: vec![] }
+		Self { fg: None, jobs: vec![], order: vec![], new_updates: vec![], reaped: HashMap::new(), exited: VecDeque::new(), reports: vec![] }
 	}
 	pub fn reset_fg(&mut self) {
 		std::mem::take(&mut self.fg);
@@ -718,6 +1269,18 @@ impl JobTable {
 			self.jobs.len()
 		}
 	}
+	/// Holds on to the status of a child that was reaped by the sigchld handler before its job was registered
+	pub fn stash_status(&mut self, pid: Pid, status: WaitStatus) {
+		self.reaped.insert(pid, status);
+	}
+	/// Gives a newly registered job any statuses that were reaped ahead of time
+	fn claim_statuses(&mut self, job: &mut Job) {
+		for child in job.get_children_mut() {
+			if let Some(status) = self.reaped.remove(&child.pid()) {
+				child.set_status(status);
+			}
+		}
+	}
 	pub fn mut_jobs(&mut self) -> &mut Vec<Option<Job>> {
 		&mut self.jobs
 	}
@@ -734,6 +1297,7 @@ impl JobTable {
 	}
 	pub fn insert_job(&mut self, mut job: Job, silent: bool) -> SlashResult<usize> {
 		self.prune_jobs();
+		self.claim_statuses(&mut job);
 		let table_position = if let Some(id) = job.table_id() { id } else { self.next_open_pos() };
 		job.set_table_id(table_position);
 		self.order.push(table_position);
@@ -751,8 +1315,9 @@ impl JobTable {
 	pub fn job_order(&self) -> &[usize] {
 		&self.order
 	}
-	pub fn new_fg<'a>(&mut self, job: Job) -> SlashResult<Vec<WaitStatus>> {
+	pub fn new_fg<'a>(&mut self, mut job: Job) -> SlashResult<Vec<WaitStatus>> {
 		let pgid = job.pgid();
+		self.claim_statuses(&mut job);
 		self.fg = Some(job);
 		attach_tty(pgid)?;
 		let statuses = self.fg.as_mut().unwrap().wait_pgrp()?;
@@ -817,8 +1382,10 @@ impl JobTable {
 	/// # Examples
 	///
 	/// ```rust
-	/// let job_table = JobTable::new(); // Assuming JobTable is defined elsewhere
-	/// if let Some(job) = job_table.query(JobID::Pgid(1234)) {
+	/// # use slash::shellenv::{JobTable, JobID};
+	/// # use nix::unistd::Pid;
+	/// let job_table = JobTable::new();
+	/// if let Some(job) = job_table.query(JobID::Pgid(Pid::from_raw(1234))) {
 	///     println!("Found job with PGID 1234: {:?}", job);
 	/// } else {
 	///     println!("No job found with PGID 1234");
@@ -900,12 +1467,18 @@ impl JobTable {
 		}
 	}
 	pub fn hang_up(&mut self) {
-		for job in self.jobs.iter_mut() {
-			if let Some(ref mut job) = job {
-				job.killpg(Signal::SIGHUP).ok();
-			}
+		for job in self.jobs.iter_mut().flatten().filter(|job| !job.is_nohup()) {
+			job.killpg(Signal::SIGHUP).ok();
 		}
 	}
+	/// Takes a job out of the table for good, so that it is no longer listed, reported, or waited on
+	pub fn disown(&mut self, table_id: usize) -> Option<Job> {
+		self.order.retain(|pos| *pos != table_id);
+		self.new_updates.retain(|pos| *pos != table_id);
+		let job = self.jobs.get_mut(table_id).and_then(Option::take);
+		self.prune_jobs();
+		job
+	}
 	pub fn prune_jobs(&mut self) {
 		while let Some(job) = self.jobs.last() {
 			if job.is_none() {
@@ -915,12 +1488,47 @@ impl JobTable {
 			}
 		}
 	}
+	/// Takes finished jobs out of the table, keeping their statuses for `wait`
+	/// Only the `limit` most recent statuses are kept, so that a shell starting many jobs doesn't hold on to all of them
+	pub fn retire_finished(&mut self, limit: usize) {
+		for slot in self.jobs.iter_mut() {
+			let Some(code) = slot.as_ref().and_then(|job| job.exit_code()) else {
+				continue
+			};
+			let job = slot.take().unwrap();
+			if let Some(id) = job.table_id() {
+				self.order.retain(|pos| *pos != id);
+				self.new_updates.retain(|pos| *pos != id);
+			}
+			self.exited.push_back((job.get_pids(), code));
+		}
+		while self.exited.len() > limit {
+			self.exited.pop_front();
+		}
+		self.prune_jobs();
+	}
+	/// Removes and returns the saved status of a retired job that `pid` belonged to
+	pub fn take_exited(&mut self, pid: Pid) -> Option<i32> {
+		let pos = self.exited.iter().position(|(pids,_)| pids.contains(&pid))?;
+		self.exited.remove(pos).map(|(_,code)| code)
+	}
 	pub fn prune_finished(&mut self) {
 		self.jobs.retain(|job| job.as_ref().is_some_and(|job| job.is_alive()));
 	}
 	pub fn reset_recents(&mut self) {
 		self.new_updates.clear()
 	}
+	pub fn queue_report(&mut self, report: String) {
+		self.reports.push(report)
+	}
+	pub fn take_reports(&mut self) -> Vec<String> {
+		take(&mut self.reports)
+	}
+	pub fn mark_updated(&mut self, table_id: usize) {
+		if !self.new_updates.contains(&table_id) {
+			self.new_updates.push(table_id)
+		}
+	}
 	pub fn print_jobs(&self, flags: &JobCmdFlags, mut fmt: impl Write) -> SlashResult<()> {
 		let jobs = if flags.contains(JobCmdFlags::NEW_ONLY) {
 			&self.jobs
@@ -934,6 +1542,7 @@ impl JobTable {
 				.map(|job| job.as_ref())
 				.collect::<Vec<Option<&Job>>>()
 		};
+		let mut json_jobs = vec![];
 		for job in jobs.iter().flatten() {
 			// Skip foreground job
 			let id = job.table_id().unwrap();
@@ -945,7 +1554,16 @@ impl JobTable {
 				continue;
 			}
 			// Print the job in the selected format
-			writeln!(fmt,"{}",job.display(&self.order,*flags))?;
+			if flags.contains(JobCmdFlags::JSON) {
+				json_jobs.push(job.to_json(&self.order));
+			} else if flags.contains(JobCmdFlags::PORCELAIN) {
+				writeln!(fmt,"{}",job.porcelain())?;
+			} else {
+				writeln!(fmt,"{}",job.display(&self.order,*flags))?;
+			}
+		}
+		if flags.contains(JobCmdFlags::JSON) {
+			writeln!(fmt,"{}",serde_json::Value::Array(json_jobs))?;
 		}
 		Ok(())
 	}
@@ -1219,21 +1837,50 @@ impl fmt::Display for SlashVal {
 
 
 
+/// Holds the shell's variables, parameters, and environment
+///
+/// A few standard variables are set up when the table is created:
+/// * `IFS` starts as space, tab, and newline. Unsetting it makes word splitting fall back to the same default
+/// * `OPTIND` starts as 1, and `OPTERR` starts as 1, which lets `getopts` report errors. Setting `OPTERR` to 0 silences them
+/// * `SECONDS` counts the seconds since the shell started, or since it was last assigned to
+/// * `PS1` through `PS4` hold the prompt strings, unless the environment already provides them. `PS1` is read again every time the prompt is drawn
+///
+/// `REPLY` is left unset until `read` is called without any names. Special parameters like `$?` and `$#`
+/// live in a separate table and can't be shadowed, so assigning to them has no effect
+///
+/// Being exported is an attribute of a variable, not a separate copy of it. Inherited environment variables are
+/// ordinary variables with the attribute set, and the environment given to a command is built from the attributes when it runs
 #[derive(Debug,Clone)]
 pub struct VarTable {
-	env: HashMap<String,String>,
 	params: HashMap<String,String>,
 	pos_params: VecDeque<String>,
-	vars: HashMap<String,SlashVal>
+	vars: HashMap<String,SlashVal>,
+	/// The names of the variables that are passed on to commands
+	exported: HashSet<String>,
+	/// The time that `SECONDS` counts from
+	seconds_base: Instant
 }
 
 impl VarTable {
 	pub fn new(env: HashMap<String,String>) -> Self {
+		let mut vars = HashMap::from([
+			("IFS".to_string(), SlashVal::String(DEFAULT_IFS.into())),
+			("OPTIND".to_string(), SlashVal::Int(1)),
+			("OPTERR".to_string(), SlashVal::Int(1)),
+		]);
+		for (name,default) in PROMPT_VARS {
+			if !env.contains_key(name) {
+				vars.insert(name.to_string(), SlashVal::String(default.into()));
+			}
+		}
+		let exported = env.keys().cloned().collect();
+		vars.extend(env.into_iter().map(|(key,val)| (key, SlashVal::String(val))));
 		Self {
-			env,
 			params: HashMap::new(),
 			pos_params: VecDeque::new(),
-			vars: HashMap::new()
+			vars,
+			exported,
+			seconds_base: Instant::now()
 		}
 	}
 
@@ -1241,28 +1888,55 @@ impl VarTable {
 		&self.vars
 	}
 
-	pub fn borrow_evars(&self) -> &HashMap<String, String> {
-		&self.env
+	/// The exported variables and their current values, which is the environment a command is given
+	/// A name that was exported before it was given a value is left out until it has one
+	pub fn evars(&self) -> HashMap<String, String> {
+		self.exported.iter()
+			.filter_map(|key| self.vars.get(key).map(|val| (key.clone(), val.to_string())))
+			.collect()
 	}
 
-	// Getters and setters for `env`
+	// Getters and setters for the export attribute
+	pub fn exported_names(&self) -> impl Iterator<Item = &String> {
+		self.exported.iter()
+	}
+	pub fn is_exported(&self, key: &str) -> bool {
+		self.exported.contains(key)
+	}
 	pub fn get_evar(&self, key: &str) -> Option<String> {
-		self.env.get(key).cloned().map(|evar| evar.to_string())
+		if !self.is_exported(key) {
+			return None
+		}
+		self.vars.get(key).map(|val| val.to_string())
 	}
+	/// Assigns a value to a variable and exports it. An empty value is still a value, so `export FOO=` passes on `FOO` as an empty string
 	pub fn export_var(&mut self, key: &str, val: &str) {
 		let value = val.trim_matches(['"', '\'']).to_string();
-		self.env.insert(key.into(), value.clone());
-		std::env::set_var(key, value);
+		self.exported.insert(key.into());
+		self.set_var(key, SlashVal::String(value));
 	}
-	pub fn unset_evar(&mut self, key: &str) {
-		self.env.remove(key);
-		std::env::remove_var(key);
+	/// Gives a variable the export attribute without changing its value
+	pub fn export(&mut self, key: &str) {
+		self.exported.insert(key.into());
+		if let Some(val) = self.vars.get(key) {
+			std::env::set_var(key, val.to_string());
+		}
+	}
+	/// Takes the export attribute away from a variable, keeping its value as a shell variable
+	pub fn unexport(&mut self, key: &str) {
+		if self.exported.remove(key) {
+			std::env::remove_var(key);
+		}
 	}
 
 	// Getters, setters, and unsetters for `params`
 	pub fn get_param(&self, key: &str) -> Option<String> {
 		if let Ok(index) = key.parse::<usize>() {
-			self.pos_params.get(index).cloned().map(|param| param.to_string())
+			// $0 is the name of the shell or script, and $1 onward are the positional parameters
+			match index {
+				0 => Some(self.params.get("0").cloned().unwrap_or_else(|| "slash".into())),
+				_ => self.pos_params.get(index - 1).cloned()
+			}
 		} else {
 			let result = self.params.get(key).cloned().map(|param| param.to_string());
 			result
@@ -1285,31 +1959,100 @@ impl VarTable {
 		self.set_param("@".into(), &self.pos_params.clone().to_vec().join(" "));
 		self.set_param("#".into(), &self.pos_params.len().to_string());
 	}
+	/// Replaces every positional parameter, like `set -- a b c` does
+	pub fn set_pos_params(&mut self, params: VecDeque<String>) {
+		self.pos_params = params;
+		self.set_param("@", &self.pos_params.clone().to_vec().join(" "));
+		self.set_param("#", &self.pos_params.len().to_string());
+	}
 	pub fn set_param(&mut self, key: &str, value: &str) {
 		self.params.insert(key.into(), value.into());
 	}
+	/// Clears the parameters for a new function or subshell scope. `$0` is kept
 	pub fn reset_params(&mut self) {
+		let name = self.params.remove("0");
 		self.params.clear();
+		self.pos_params.clear();
+		if let Some(name) = name {
+			self.params.insert("0".into(), name);
+		}
 	}
 	pub fn unset_param(&mut self, key: &str) {
 		self.params.remove(key);
 	}
 
+	/// Whether a name refers to a parameter that only the shell can set, like `?` or `1`
+	pub fn is_special_param(key: &str) -> bool {
+		SPECIAL_PARAMS.contains(&key) || (!key.is_empty() && key.chars().all(|ch| ch.is_ascii_digit()))
+	}
 	pub fn set_var(&mut self, key: &str, val: SlashVal) {
+		if Self::is_special_param(key) {
+			return
+		}
+		// Assigning to SECONDS makes it count up from the assigned value
+		if key == "SECONDS" {
+			if let Ok(secs) = val.to_string().parse::<u64>() {
+				let now = Instant::now();
+				self.seconds_base = now.checked_sub(Duration::from_secs(secs)).unwrap_or(now);
+				return
+			}
+		}
+		// The shell looks some exported variables up in its own process environment, like `HOME`, so that has to follow them
+		if self.is_exported(key) {
+			std::env::set_var(key, val.to_string());
+		}
 		self.vars.insert(key.to_string(),val);
 	}
+	/// Removes a variable along with its export attribute
 	pub fn unset_var(&mut self, key: &str) {
 		self.vars.remove(key);
+		self.unexport(key);
+	}
+	/// Adds a call to the stack described by `FUNCNAME`, `OX_SOURCE`, and `OX_LINENO`
+	/// `source` is the file that the called code comes from, and `line` is the line that the call was made on,
+	/// counted from the start of the code that made it
+	pub fn push_call_frame(&mut self, name: &str, source: &str, line: usize) {
+		let frame = [SlashVal::String(name.to_string()), SlashVal::String(source.to_string()), SlashVal::Int(line as i32)];
+		for (var,val) in CALL_STACK_VARS.iter().zip(frame) {
+			let mut stack = match self.vars.remove(*var) {
+				Some(SlashVal::Array(stack)) => stack,
+				_ => vec![]
+			};
+			stack.insert(0, val);
+			self.vars.insert(var.to_string(), SlashVal::Array(stack));
+		}
+	}
+	/// The file that the running code was read from, or an empty string if it wasn't read from a file
+	pub fn current_source(&self) -> String {
+		match self.vars.get("OX_SOURCE") {
+			Some(SlashVal::Array(stack)) => stack.first().map(|source| source.to_string()).unwrap_or_default(),
+			_ => String::new()
+		}
 	}
 	pub fn get_var(&self, key: &str) -> Option<SlashVal> {
+		if Self::is_special_param(key) {
+			return self.get_param(key).map(SlashVal::String)
+		}
 		if let Some(var) = self.vars.get(key).cloned() {
 			Some(var)
 		} else if let Some(var) = self.params.get(key).cloned() {
 			let val = SlashVal::String(var);
 			Some(val)
 		} else {
-			let var = self.env.get(key).cloned().map(SlashVal::String);
-			var
+			self.dynamic_var(key).map(SlashVal::String)
+		}
+	}
+	/// Variables whose values are computed each time they are read, unless they have been assigned to
+	fn dynamic_var(&self, key: &str) -> Option<String> {
+		let now = || std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH).unwrap_or_default();
+		match key {
+			"SECONDS" => Some(self.seconds_base.elapsed().as_secs().to_string()),
+			"EPOCHSECONDS" => Some(now().as_secs().to_string()),
+			"EPOCHREALTIME" => {
+				let now = now();
+				Some(format!("{}.{:06}",now.as_secs(),now.subsec_micros()))
+			}
+			_ => None
 		}
 	}
 	pub fn get_var_mut(&mut self, key: &str) -> Option<&mut SlashVal> {
@@ -1331,21 +2074,116 @@ impl VarTable {
 			Err(Low(SlashErrLow::ExecFailed(format!("{} is not a variable",key))))
 		}
 	}
+
+	pub fn unset_arr_index(&mut self, key: &str, index: usize) -> SlashResult<()> {
+		if let Some(var) = self.vars.get_mut(key) {
+			if let SlashVal::Array(arr) = var {
+				if index < arr.len() {
+					arr.remove(index);
+					Ok(())
+				} else {
+					Err(Low(SlashErrLow::ExecFailed(format!("Index `{}` out of range for array `{}`",index,key))))
+				}
+			} else {
+				Err(Low(SlashErrLow::ExecFailed(format!("{} is not an array",key))))
+			}
+		} else {
+			Err(Low(SlashErrLow::ExecFailed(format!("{} is not a variable",key))))
+		}
+	}
+}
+
+bitflags! {
+	/// Attributes that `declare -f` and `readonly -f` give to a function
+	#[derive(Debug,Copy,Clone,PartialEq)]
+	pub struct FuncAttrs: u8 {
+		const READONLY = 0b01; // Can't be redefined or unset
+		const TRACE    = 0b10; // Inherits the DEBUG and RETURN traps, like set -T does for every function
+	}
+}
+
+/// A function definition, along with its attributes
+#[derive(Debug,Clone)]
+pub struct ShFunc {
+	body: String,
+	attrs: FuncAttrs,
+	/// The file that the function was defined in, if any
+	source: String
+}
+
+impl ShFunc {
+	pub fn body(&self) -> &str {
+		&self.body
+	}
+	pub fn source(&self) -> &str {
+		&self.source
+	}
+	pub fn attrs(&self) -> FuncAttrs {
+		self.attrs
+	}
 }
 
 #[derive(Debug,Clone)]
 pub struct LogicTable {
-	functions: HashMap<String,String>,
-	aliases: HashMap<String,String>
+	functions: HashMap<String,ShFunc>,
+	aliases: HashMap<String,String>,
+	/// The command hash table, mapping command names to the executables they were last resolved to
+	hashed: BTreeMap<String,PathBuf>,
+	/// The `PATH` that the hash table was filled from
+	hashed_path: String,
+	/// Names whose paths were given with `hash -p`, which are kept when `PATH` changes
+	pinned: HashSet<String>,
+	/// Commands set with `trap`, keyed by condition name
+	traps: BTreeMap<String,String>,
+	/// Completion specs registered with `complete`, keyed by command name
+	comp_specs: BTreeMap<String,CompSpec>
 }
 
 impl LogicTable {
 	pub fn new() -> Self {
 		Self {
 			functions: HashMap::new(),
-			aliases: HashMap::new()
+			aliases: HashMap::new(),
+			hashed: BTreeMap::new(),
+			hashed_path: String::new(),
+			pinned: HashSet::new(),
+			traps: BTreeMap::new(),
+			comp_specs: BTreeMap::new()
 		}
 	}
+	pub fn hash_cmd(&mut self, name: &str, path: PathBuf) {
+		self.hashed.insert(name.to_string(),path);
+	}
+	pub fn get_hashed(&self, name: &str) -> Option<PathBuf> {
+		self.hashed.get(name).cloned()
+	}
+	pub fn borrow_hashed(&self) -> &BTreeMap<String,PathBuf> {
+		&self.hashed
+	}
+	pub fn hashed_path(&self) -> &str {
+		&self.hashed_path
+	}
+	/// Maps a name to a path for good, without searching `PATH` for it
+	pub fn pin_cmd(&mut self, name: &str, path: PathBuf) {
+		self.pinned.insert(name.to_string());
+		self.hashed.insert(name.to_string(),path);
+	}
+	pub fn is_pinned(&self, name: &str) -> bool {
+		self.pinned.contains(name)
+	}
+	/// Empties the hash table, which will be filled again from `path`
+	/// Pinned names stay, since they were never looked up in `PATH`
+	pub fn clear_hashed(&mut self, path: &str) {
+		let pinned = &self.pinned;
+		self.hashed.retain(|name,_| pinned.contains(name));
+		self.hashed_path = path.to_string();
+	}
+	/// Empties the hash table completely, pinned names included, like `hash -r`
+	pub fn reset_hashed(&mut self, path: &str) {
+		self.pinned.clear();
+		self.hashed.clear();
+		self.hashed_path = path.to_string();
+	}
 	pub fn new_alias(&mut self, name: &str, value: String) {
 		self.aliases.insert(name.to_string(),value);
 	}
@@ -1358,18 +2196,60 @@ impl LogicTable {
 	pub fn get_alias(&self, name: &str) -> Option<String> {
 		self.aliases.get(name).cloned()
 	}
-	pub fn new_func(&mut self, name: &str, instructions: &str) {
-		self.functions.insert(name.to_string(),instructions.to_string());
+	/// Defines a function, keeping the attributes of the one it replaces
+	pub fn new_func(&mut self, name: &str, instructions: &str, source: &str) {
+		let attrs = self.func_attrs(name);
+		self.functions.insert(name.to_string(),ShFunc { body: instructions.to_string(), attrs, source: source.to_string() });
 	}
 	pub fn get_func(&self, name: &str) -> Option<String> {
-		self.functions.get(name).cloned()
+		self.functions.get(name).map(|func| func.body.clone())
 	}
-	pub fn borrow_functions(&self) -> &HashMap<String,String> {
+	pub fn borrow_functions(&self) -> &HashMap<String,ShFunc> {
 		&self.functions
 	}
+	pub fn func_attrs(&self, name: &str) -> FuncAttrs {
+		self.functions.get(name).map(|func| func.attrs).unwrap_or(FuncAttrs::empty())
+	}
+	/// Replaces the attributes of a function. Returns false if there is no such function
+	pub fn set_func_attrs(&mut self, name: &str, attrs: FuncAttrs) -> bool {
+		match self.functions.get_mut(name) {
+			Some(func) => {
+				func.attrs = attrs;
+				true
+			}
+			None => false
+		}
+	}
 	pub fn remove_func(&mut self, name: &str) {
 		self.functions.remove(name);
 	}
+	pub fn set_trap(&mut self, condition: &str, action: &str) {
+		self.traps.insert(condition.to_string(),action.to_string());
+	}
+	pub fn get_trap(&self, condition: &str) -> Option<String> {
+		self.traps.get(condition).cloned()
+	}
+	pub fn take_trap(&mut self, condition: &str) -> Option<String> {
+		self.traps.remove(condition)
+	}
+	pub fn borrow_traps(&self) -> &BTreeMap<String,String> {
+		&self.traps
+	}
+	pub fn set_comp_spec(&mut self, cmd: &str, spec: CompSpec) {
+		self.comp_specs.insert(cmd.to_string(),spec);
+	}
+	pub fn get_comp_spec(&self, cmd: &str) -> Option<CompSpec> {
+		self.comp_specs.get(cmd).cloned()
+	}
+	pub fn get_comp_spec_mut(&mut self, cmd: &str) -> Option<&mut CompSpec> {
+		self.comp_specs.get_mut(cmd)
+	}
+	pub fn remove_comp_spec(&mut self, cmd: &str) -> Option<CompSpec> {
+		self.comp_specs.remove(cmd)
+	}
+	pub fn borrow_comp_specs(&self) -> &BTreeMap<String,CompSpec> {
+		&self.comp_specs
+	}
 }
 
 impl Default for LogicTable {
@@ -1381,13 +2261,162 @@ impl Default for LogicTable {
 #[derive(Debug,Clone)]
 pub struct EnvMeta {
 	last_input: String,
+	/// What errors call the input being run when it wasn't read from a file, like `-c string`
+	input_name: Option<String>,
 	last_command: Option<String>,
 	timer_start: Option<Instant>,
 	cmd_duration: Option<Duration>,
+	/// The directories saved by `pushd`, most recent last. The current directory is not included
 	dir_stack: Vec<PathBuf>,
 	shopts: ShOpts,
 	flags: EnvFlags,
-	in_prompt: bool
+	in_prompt: bool,
+	/// The exit status of the last command line, recorded when the prompt is entered
+	prompt_status: Option<i32>,
+	/// How many times in a row end of file was read at the prompt and ignored because of `IGNOREEOF`
+	eof_count: usize,
+	/// How far `getopts` got into a group of options like `-abc`, as the `OPTIND` it belongs to and the offset into that argument
+	getopts_pos: Option<(usize,usize)>,
+	/// What the mailboxes looked like when they were last checked
+	mail: MailState,
+	dir_env: Option<DirEnv>,
+	func_depth: usize,
+	kept_stdio: utils::ExecFlags,
+	/// Descriptors opened by `exec` that stay open across commands, mapped to what they were opened on
+	open_fds: BTreeMap<RawFd,String>,
+	/// Temporary files and fifos made for the commands that are running, like the fifos behind process substitution
+	temp_files: Vec<PathBuf>,
+	history: CmdHistory,
+	/// The options of the completion in progress, which `compopt` can change while a completion function runs
+	comp_opts: Option<CompOpts>,
+	/// The terminal's size as columns and lines, when it was last checked
+	term_size: Option<(u16,u16)>
+}
+
+/// A per-directory environment file that is currently applied
+/// `saved` holds the previous value of every environment variable that the file changed
+#[derive(Debug,Clone)]
+pub struct DirEnv {
+	pub file: PathBuf,
+	pub saved: Vec<(String,Option<String>)>
+}
+
+/// Removes a temporary file or fifo
+/// A process substitution whose fifo was never opened is still blocked opening its own end of it. Opening the fifo for both
+/// reading and writing lets that open finish, and once it is closed again the process sees end of file or a broken pipe
+fn remove_temp_file(path: &Path) {
+	if std::fs::metadata(path).is_ok_and(|meta| meta.file_type().is_fifo()) {
+		std::fs::OpenOptions::new().read(true).write(true).custom_flags(libc::O_NONBLOCK).open(path).ok();
+	}
+	std::fs::remove_file(path).ok();
+}
+
+/// The command history of an interactive shell
+/// Each entry remembers whether it was entered during this session and hasn't been appended to the history file yet.
+/// The file uses the line editor's format: a `#V2` header, then one entry per line with newlines and backslashes escaped
+#[derive(Debug,Clone,Default)]
+pub struct CmdHistory {
+	entries: Vec<(String,bool)>,
+	/// How many entries of the history file have been read, so that `history -n` only reads the ones after them
+	file_len: usize
+}
+
+impl CmdHistory {
+	const FILE_HEADER: &'static str = "#V2";
+
+	pub fn entries(&self) -> impl Iterator<Item = &str> {
+		self.entries.iter().map(|(entry,_)| entry.as_str())
+	}
+	/// Adds a command that was just entered. Returns false if it was left out as a duplicate of the previous one
+	pub fn push(&mut self, line: &str, ignore_dupes: bool, max_len: usize) -> bool {
+		if ignore_dupes && self.entries.last().is_some_and(|(last,_)| last == line) {
+			return false
+		}
+		self.entries.push((line.to_string(), true));
+		self.truncate(max_len);
+		true
+	}
+	pub fn clear(&mut self) {
+		self.entries.clear()
+	}
+	fn truncate(&mut self, max_len: usize) {
+		let excess = self.entries.len().saturating_sub(max_len);
+		self.entries.drain(..excess);
+	}
+	fn escape(entry: &str) -> String {
+		entry.replace('\\', "\\\\").replace('\n', "\\n")
+	}
+	fn unescape(line: &str) -> String {
+		let mut entry = String::with_capacity(line.len());
+		let mut chars = line.chars();
+		while let Some(ch) = chars.next() {
+			match (ch, chars.clone().next()) {
+				('\\', Some('n')) => { chars.next(); entry.push('\n') }
+				('\\', Some('\\')) => { chars.next(); entry.push('\\') }
+				_ => entry.push(ch)
+			}
+		}
+		entry
+	}
+	/// Reads every entry in a history file. A missing file is treated as an empty one
+	fn read_entries(path: &Path) -> SlashResult<Vec<String>> {
+		let contents = match std::fs::read_to_string(path) {
+			Ok(contents) => contents,
+			Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(vec![]),
+			Err(_) => return Err(Low(SlashErrLow::from_io()))
+		};
+		let mut lines = contents.lines().peekable();
+		// Files written before the header was introduced hold entries as they are
+		let escaped = lines.next_if_eq(&Self::FILE_HEADER).is_some();
+		Ok(lines.filter(|line| !line.is_empty())
+			.map(|line| if escaped { Self::unescape(line) } else { line.to_string() })
+			.collect())
+	}
+	/// Adds the entries of a history file to the list, as `history -r` does
+	pub fn read_file(&mut self, path: &Path, max_len: usize) -> SlashResult<()> {
+		let entries = Self::read_entries(path)?;
+		self.file_len = entries.len();
+		self.entries.extend(entries.into_iter().map(|entry| (entry,false)));
+		self.truncate(max_len);
+		Ok(())
+	}
+	/// Adds only the entries that were written to a history file since it was last read, as `history -n` does
+	pub fn read_new(&mut self, path: &Path, max_len: usize) -> SlashResult<()> {
+		let entries = Self::read_entries(path)?;
+		let already_read = self.file_len.min(entries.len());
+		self.file_len = entries.len();
+		self.entries.extend(entries.into_iter().skip(already_read).map(|entry| (entry,false)));
+		self.truncate(max_len);
+		Ok(())
+	}
+	/// Appends the entries from this session that the file doesn't have yet, as `history -a` does
+	pub fn append_file(&mut self, path: &Path) -> SlashResult<()> {
+		let new = self.entries.iter_mut().filter(|(_,unsaved)| *unsaved).collect::<Vec<_>>();
+		if new.is_empty() {
+			return Ok(())
+		}
+		let needs_header = std::fs::metadata(path).map(|meta| meta.len() == 0).unwrap_or(true);
+		let mut file = std::fs::OpenOptions::new().create(true).append(true).open(path).map_err(|_| Low(SlashErrLow::from_io()))?;
+		let mut text = if needs_header { format!("{}\n", Self::FILE_HEADER) } else { String::new() };
+		for (entry,unsaved) in new {
+			text.push_str(&Self::escape(entry));
+			text.push('\n');
+			*unsaved = false;
+			self.file_len += 1;
+		}
+		io::Write::write_all(&mut file, text.as_bytes()).map_err(|_| Low(SlashErrLow::from_io()))
+	}
+	/// Replaces the contents of a history file with the whole list, as `history -w` does
+	pub fn write_file(&mut self, path: &Path) -> SlashResult<()> {
+		let mut text = format!("{}\n", Self::FILE_HEADER);
+		for (entry,unsaved) in self.entries.iter_mut() {
+			text.push_str(&Self::escape(entry));
+			text.push('\n');
+			*unsaved = false;
+		}
+		self.file_len = self.entries.len();
+		std::fs::write(path, text).map_err(|_| Low(SlashErrLow::from_io()))
+	}
 }
 
 impl EnvMeta {
@@ -1395,20 +2424,45 @@ impl EnvMeta {
 		let in_prompt = flags.contains(EnvFlags::INTERACTIVE);
 		Self {
 			last_input: String::new(),
+			input_name: None,
 			last_command: None,
 			timer_start: None,
 			cmd_duration: None,
-			dir_stack: vec![std::env::current_dir().unwrap()],
+			dir_stack: vec![],
 			shopts: ShOpts::new(),
 			flags,
 			in_prompt,
+			prompt_status: None,
+			eof_count: 0,
+			getopts_pos: None,
+			mail: MailState::default(),
+			dir_env: None,
+			func_depth: 0,
+			kept_stdio: utils::ExecFlags::empty(),
+			open_fds: BTreeMap::new(),
+			temp_files: vec![],
+			history: CmdHistory::default(),
+			comp_opts: None,
+			term_size: None
 		}
 	}
+	pub fn dir_env(&self) -> Option<&DirEnv> {
+		self.dir_env.as_ref()
+	}
+	pub fn set_dir_env(&mut self, dir_env: DirEnv) {
+		self.dir_env = Some(dir_env)
+	}
+	pub fn take_dir_env(&mut self) -> Option<DirEnv> {
+		self.dir_env.take()
+	}
 	pub fn get_cmd_duration(&self) -> Option<Duration> {
 		self.cmd_duration
 	}
-	pub fn reset_dir_stack(&mut self, path: PathBuf) {
-		self.dir_stack = vec![path]
+	pub fn clear_dir_stack(&mut self) {
+		self.dir_stack.clear()
+	}
+	pub fn saved_dirs(&self) -> &[PathBuf] {
+		&self.dir_stack
 	}
 	pub fn push_dir(&mut self, path: PathBuf) {
 		self.dir_stack.push(path)
@@ -1420,20 +2474,104 @@ impl EnvMeta {
 		self.last_command.clone()
 	}
 	pub fn pop_dir(&mut self) -> Option<PathBuf> {
-		if self.dir_stack.len() > 1 {
-			self.dir_stack.pop()
-		} else {
-			None
-		}
+		self.dir_stack.pop()
 	}
 	pub fn top_dir(&self) -> Option<&PathBuf> {
 		self.dir_stack.last()
 	}
 	pub fn leave_prompt(&mut self) {
-		self.in_prompt = false
+		self.in_prompt = false;
+		self.prompt_status = None
+	}
+	pub fn eof_count(&self) -> usize {
+		self.eof_count
+	}
+	pub fn count_eof(&mut self) {
+		self.eof_count += 1
+	}
+	pub fn reset_eof_count(&mut self) {
+		self.eof_count = 0
+	}
+	pub fn getopts_pos(&self) -> Option<(usize,usize)> {
+		self.getopts_pos
+	}
+	pub fn set_getopts_pos(&mut self, pos: Option<(usize,usize)>) {
+		self.getopts_pos = pos
+	}
+	pub fn mail_mut(&mut self) -> &mut MailState {
+		&mut self.mail
 	}
-	pub fn enter_prompt(&mut self) {
-		self.in_prompt = true
+	/// How many function calls deep the shell currently is
+	pub fn func_depth(&self) -> usize {
+		self.func_depth
+	}
+	pub fn set_func_depth(&mut self, depth: usize) {
+		self.func_depth = depth
+	}
+	/// Marks standard descriptors that `exec` redirected, so they aren't restored once the current command finishes
+	pub fn keep_stdio(&mut self, flags: utils::ExecFlags) {
+		self.kept_stdio |= flags
+	}
+	pub fn kept_stdio(&self) -> utils::ExecFlags {
+		self.kept_stdio
+	}
+	pub fn take_kept_stdio(&mut self) -> utils::ExecFlags {
+		std::mem::replace(&mut self.kept_stdio, utils::ExecFlags::empty())
+	}
+	pub fn open_fds(&self) -> &BTreeMap<RawFd,String> {
+		&self.open_fds
+	}
+	/// Records a descriptor that `exec` opened for the rest of the session
+	/// Close-on-exec is cleared, so that commands run afterwards inherit it
+	pub fn track_fd(&mut self, fd: RawFd, target: String) {
+		fcntl(fd, nix::fcntl::FcntlArg::F_SETFD(nix::fcntl::FdFlag::empty())).ok();
+		self.open_fds.insert(fd, target);
+	}
+	pub fn untrack_fd(&mut self, fd: RawFd) {
+		self.open_fds.remove(&fd);
+	}
+	/// Closes every descriptor opened by `exec`. Called when the shell exits
+	pub fn close_open_fds(&mut self) {
+		for fd in take(&mut self.open_fds).into_keys() {
+			close(fd).ok();
+		}
+	}
+	/// Registers a temporary file to be removed once the command that made it is done
+	pub fn track_temp_file(&mut self, path: PathBuf) {
+		self.temp_files.push(path);
+	}
+	pub fn temp_files(&self) -> &[PathBuf] {
+		&self.temp_files
+	}
+	/// Removes the temporary files registered since `mark`, which is the number of files that were registered when a command started
+	/// Files registered before that belong to an outer command, which is still running
+	pub fn remove_temp_files(&mut self, mark: usize) {
+		let mark = mark.min(self.temp_files.len());
+		for path in self.temp_files.drain(mark..).rev() {
+			remove_temp_file(&path);
+		}
+	}
+	pub fn enter_prompt(&mut self, status: i32) {
+		self.in_prompt = true;
+		self.prompt_status = Some(status)
+	}
+	pub fn prompt_status(&self) -> Option<i32> {
+		self.prompt_status
+	}
+	pub fn history(&self) -> &CmdHistory {
+		&self.history
+	}
+	pub fn history_mut(&mut self) -> &mut CmdHistory {
+		&mut self.history
+	}
+	pub fn term_size(&self) -> Option<(u16,u16)> {
+		self.term_size
+	}
+	pub fn comp_opts(&self) -> Option<CompOpts> {
+		self.comp_opts
+	}
+	pub fn set_comp_opts(&mut self, opts: Option<CompOpts>) {
+		self.comp_opts = opts
 	}
 	pub fn set_last_input(&mut self,input: &str) {
 		self.last_input = input.to_string()
@@ -1441,6 +2579,12 @@ impl EnvMeta {
 	pub fn get_last_input(&self) -> String {
 		self.last_input.clone()
 	}
+	pub fn set_input_name(&mut self, name: Option<&str>) {
+		self.input_name = name.map(str::to_string)
+	}
+	pub fn input_name(&self) -> Option<&str> {
+		self.input_name.as_deref()
+	}
 	pub fn borrow_shopts(&self) -> &ShOpts {
 		&self.shopts
 	}
@@ -1455,7 +2599,9 @@ impl EnvMeta {
 	}
 	pub fn mod_flags<F>(&mut self, flag_mod: F)
 		where F: FnOnce(&mut EnvFlags) {
-			flag_mod(&mut self.flags)
+			flag_mod(&mut self.flags);
+			// Job reports are printed from the SIGCHLD handler, which can't see the flags
+			crate::signal::set_notify_asap(self.flags.contains(EnvFlags::REPORT_JOBS_ASAP));
 	}
 	pub fn flags(&self) -> EnvFlags {
 		self.flags
@@ -1468,27 +2614,64 @@ pub fn disable_reaping() {
 	unsafe { signal(Signal::SIGCHLD, SigHandler::Handler(crate::signal::ignore_sigchld)) }.unwrap();
 }
 
+/// Permanently disable the sigchld handler in this process
+/// Used by forked children running compound commands, so that their children are not reaped before they can be waited on
+pub fn stop_reaping() {
+	ASYNC_REAPING.store(false, Ordering::SeqCst);
+	disable_reaping();
+}
+
 /// Re-enable the sigchld handler
 pub fn enable_reaping<'a>() -> SlashResult<()> {
+	if !ASYNC_REAPING.load(Ordering::SeqCst) {
+		return Ok(())
+	}
 	write_jobs(|j| j.update_job_statuses())??;
-	unsafe { signal(Signal::SIGCHLD, SigHandler::Handler(crate::signal::handle_sigchld)) }.unwrap();
+	unsafe { signal(Signal::SIGCHLD, crate::signal::reaping_handler()) }.unwrap();
 	Ok(())
 }
 
+/// Runs `f` with SIGCHLD blocked on this thread
+/// The SIGCHLD handler locks the job table, so it must not run on a thread that is already holding it
+fn without_sigchld<T>(f: impl FnOnce() -> T) -> T {
+	let mut mask = nix::sys::signal::SigSet::empty();
+	let mut mask_backup = nix::sys::signal::SigSet::empty();
+	mask.add(SIGCHLD);
+	nix::sys::signal::pthread_sigmask(SigmaskHow::SIG_BLOCK, Some(&mask), Some(&mut mask_backup)).ok();
+	let result = f();
+	nix::sys::signal::pthread_sigmask(SigmaskHow::SIG_SETMASK, Some(&mask_backup), None).ok();
+	result
+}
+
 pub fn read_jobs<'a,F,T>(f: F) -> SlashResult<T>
 where F: FnOnce(&JobTable) -> T {
-	let lock = JOBS.read().map_err(|_| Low(SlashErrLow::InternalErr("Failed to obtain write lock; lock might be poisoned".into())))?;
-	Ok(f(&lock))
+	without_sigchld(|| {
+		let lock = JOBS.read().map_err(|_| Low(SlashErrLow::InternalErr("Failed to obtain write lock; lock might be poisoned".into())))?;
+		Ok(f(&lock))
+	})
 }
 
 pub fn write_jobs<'a,F,T>(f: F) -> SlashResult<T>
 where F: FnOnce(&mut JobTable) -> T {
-	let mut lock = JOBS.write().map_err(|_| Low(SlashErrLow::InternalErr("Failed to obtain write lock; lock might be poisoned".into())))?;
-	Ok(f(&mut lock))
+	without_sigchld(|| {
+		let mut lock = JOBS.write().map_err(|_| Low(SlashErrLow::InternalErr("Failed to obtain write lock; lock might be poisoned".into())))?;
+		Ok(f(&mut lock))
+	})
+}
+
+/// Lets the job table be locked again after a panic that happened while it was held
+pub fn clear_jobs_poison() {
+	JOBS.clear_poison();
+}
+
+/// Prevents this process from handing the terminal to any process group
+/// Used by forked children that run in the background
+pub fn revoke_tty_access() {
+	TTY_ACCESS.store(false, Ordering::SeqCst);
 }
 
 pub fn attach_tty<'a>(pgid: Pid) -> SlashResult<()> {
-	if !isatty(0).unwrap_or(false) || pgid == term_controller() {
+	if !isatty(0).unwrap_or(false) || !TTY_ACCESS.load(Ordering::SeqCst) || pgid == term_controller() {
 		return Ok(())
 	}
 
@@ -1507,25 +2690,50 @@ pub fn attach_tty<'a>(pgid: Pid) -> SlashResult<()> {
 	nix::sys::signal::pthread_sigmask(SigmaskHow::SIG_BLOCK, Some(&mut new_mask), Some(&mut mask_backup))
 		.map_err(|_| io::Error::last_os_error())?;
 
-	if unsafe { tcgetpgrp(BorrowedFd::borrow_raw(0)) == Ok(pgid) } {
-		return Ok(())
+	if !set_tty_pgrp(pgid) && pgid != getpgrp() {
+		// The group couldn't take the terminal, most likely because every process in it already died
+		// Take the terminal back, so that it isn't left pointing at a group that doesn't exist
+		set_tty_pgrp(getpgrp());
 	}
 
-	// Attempt to set the process group for the terminal
-	// FIXME: If this fails, it fails silently. Consider finding a more robust way to do this.
-	let result = unsafe { tcsetpgrp(BorrowedFd::borrow_raw(0), pgid) };
-
 	nix::sys::signal::pthread_sigmask(SigmaskHow::SIG_SETMASK, Some(&mut mask_backup), Some(&mut new_mask))
 		.map_err(|_| io::Error::last_os_error())?;
+	Ok(())
+}
 
-	match result {
-		Ok(_) => Ok(()),
-		Err(_) => {
-			// Something weird has probably happened - let's take back the terminal
-			unsafe { tcsetpgrp(BorrowedFd::borrow_raw(0), getpgrp()).ok(); }
-			Ok(())
+/// Hands the terminal to a process group, returning whether it worked
+/// EPERM and ENOTTY are retried after a short pause, since they can be transient: a new child may not have
+/// joined its process group yet, or the terminal may be in the middle of being handed back by a dying job
+fn set_tty_pgrp(pgid: Pid) -> bool {
+	const ATTEMPTS: u32 = 5;
+	for attempt in 0..ATTEMPTS {
+		if unsafe { tcgetpgrp(BorrowedFd::borrow_raw(0)) } == Ok(pgid) {
+			return true
+		}
+		match unsafe { tcsetpgrp(BorrowedFd::borrow_raw(0), pgid) } {
+			Ok(_) => return true,
+			Err(Errno::EINTR) => continue,
+			Err(Errno::EPERM | Errno::ENOTTY) => std::thread::sleep(Duration::from_millis(1 << attempt)),
+			Err(_) => return false
 		}
 	}
+	false
+}
+
+/// Reads the terminal's current modes, if stdin is a terminal
+pub fn get_tty_modes() -> Option<Termios> {
+	if !isatty(0).unwrap_or(false) {
+		return None
+	}
+	termios::tcgetattr(unsafe { BorrowedFd::borrow_raw(0) }).ok()
+}
+
+/// Applies terminal modes read by `get_tty_modes()`
+/// TCSADRAIN lets output that is already queued finish in the old modes
+pub fn set_tty_modes(modes: &Termios) {
+	if isatty(0).unwrap_or(false) {
+		termios::tcsetattr(unsafe { BorrowedFd::borrow_raw(0) }, termios::SetArg::TCSADRAIN, modes).ok();
+	}
 }
 
 pub fn term_controller() -> Pid {
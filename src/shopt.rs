@@ -1,6 +1,6 @@
 use std::collections::{BTreeMap, VecDeque};
 
-use crate::{error::{SlashErr, SlashErrLow}, shellenv::SlashVal, SlashResult};
+use crate::{error::{SlashErr, SlashErrLow}, shellenv::SlashVal, term::{self, ColorMode}, SlashResult};
 
 #[derive(Clone, Debug)]
 pub struct ShOpts {
@@ -18,8 +18,22 @@ impl ShOpts {
 			max_hist: 1000,
 			int_comments: true,
 			auto_hist: true,
+			hist_autosave: true,
 			bell_style: 1,
 			max_recurse_depth: 500,
+			max_func_depth: 256,
+			max_alias_depth: 10,
+			max_brace_expand: 10000,
+			plugin_dir: String::new(),
+			chpwd_title: false,
+			chpwd_ls: false,
+			color: "auto".into(),
+			huponexit: false,
+			fd_audit: false,
+			checkwinsize: true,
+			child_max: default_child_max(),
+			glob_sort: "locale".into(),
+			lastpipe: false,
 		};
 		let prompt = ShOptsPrompt {
 			trunc_prompt_path: 4,
@@ -27,9 +41,13 @@ impl ShOpts {
 			comp_limit: 100,
 			prompt_highlight: true,
 			tab_stop: 8,
+			cmd_time_threshold: 2000,
+			hook_timeout: 1000,
+			help_flags: false,
 			exit_status: PromptStatus {
 				success: " ".into(),
 				failure: "✗".into(),
+				color: false,
 			},
 			custom: PromptCustom {
 				opts: SlashVal::Dict(BTreeMap::new()),
@@ -68,16 +86,39 @@ impl Default for ShOpts {
 	}
 }
 
+/// The system's limit on child processes per user, kept between 32 and 8192 like bash does
+fn default_child_max() -> usize {
+	let limit = unsafe { libc::sysconf(libc::_SC_CHILD_MAX) };
+	if limit < 0 {
+		return 8192
+	}
+	(limit as usize).clamp(32, 8192)
+}
+
 #[derive(Clone, Debug)]
 pub struct ShOptsCore {
 	pub dotglob: bool,
 	pub autocd: bool,
 	pub hist_ignore_dupes: bool,
 	pub max_hist: usize,
-	pub int_comments: bool,
+	pub int_comments: bool, // Whether a word starting with '#' at the prompt begins a comment. Scripts always have comments
 	pub auto_hist: bool,
-	pub bell_style: usize,
-	pub max_recurse_depth: usize,
+	pub hist_autosave: bool, // Append each command to the history file as soon as it is entered, instead of leaving it to the history builtin
+	pub bell_style: usize, // 0 for no bell, 1 to beep, 2 to flash the screen
+	pub max_recurse_depth: usize, // How deeply commands can nest, including subshells and input nesting
+	pub max_func_depth: usize, // How deeply function calls can nest
+	pub max_alias_depth: usize, // How many times aliases can expand into other aliases
+	pub max_brace_expand: usize,
+	pub plugin_dir: String, // Empty means ~/.config/slash/plugins
+	pub chpwd_title: bool, // Set the terminal title to the cwd after changing directories
+	pub chpwd_ls: bool, // List the new directory after changing directories
+	pub color: String, // auto, always, or never
+	pub huponexit: bool, // Send SIGHUP to every job when an interactive shell exits
+	pub fd_audit: bool, // Report descriptors that a command opened and never closed, for debugging redirections
+	pub checkwinsize: bool, // Check the terminal size after every command, instead of only when the terminal reports a resize
+	pub child_max: usize, // How many statuses of finished background jobs are kept for `wait`
+	pub glob_sort: String, // The order of glob matches: locale, for the collation order of LC_COLLATE, or bytes
+	pub lastpipe: bool, // Run the last builtin of a pipeline of builtins in the current shell, so that `echo foo | read x` sets x
 }
 
 impl ShOptsCore {
@@ -90,8 +131,22 @@ impl ShOptsCore {
 			"max_hist" => Ok(SlashVal::Int(self.max_hist as i32)),
 			"int_comments" => Ok(SlashVal::Bool(self.int_comments)),
 			"auto_hist" => Ok(SlashVal::Bool(self.auto_hist)),
+			"hist_autosave" => Ok(SlashVal::Bool(self.hist_autosave)),
 			"bell_style" => Ok(SlashVal::Int(self.bell_style as i32)),
 			"max_recurse_depth" => Ok(SlashVal::Int(self.max_recurse_depth as i32)),
+			"max_func_depth" => Ok(SlashVal::Int(self.max_func_depth as i32)),
+			"max_alias_depth" => Ok(SlashVal::Int(self.max_alias_depth as i32)),
+			"max_brace_expand" => Ok(SlashVal::Int(self.max_brace_expand as i32)),
+			"plugin_dir" => Ok(SlashVal::String(self.plugin_dir.clone())),
+			"chpwd_title" => Ok(SlashVal::Bool(self.chpwd_title)),
+			"chpwd_ls" => Ok(SlashVal::Bool(self.chpwd_ls)),
+			"color" => Ok(SlashVal::String(self.color.clone())),
+			"huponexit" => Ok(SlashVal::Bool(self.huponexit)),
+			"fd_audit" => Ok(SlashVal::Bool(self.fd_audit)),
+			"checkwinsize" => Ok(SlashVal::Bool(self.checkwinsize)),
+			"child_max" => Ok(SlashVal::Int(self.child_max as i32)),
+			"glob_sort" => Ok(SlashVal::String(self.glob_sort.clone())),
+			"lastpipe" => Ok(SlashVal::Bool(self.lastpipe)),
 			_ => Err(SlashErr::Low(SlashErrLow::ExecFailed(format!("Invalid core opts key: {}",key))))
 		}
 	}
@@ -128,8 +183,13 @@ impl ShOptsCore {
 					return Err(SlashErr::Low(SlashErrLow::ExecFailed(format!("Invalid value for core.auto_hist: {:?}", value))))
 				};
 			}
+			"hist_autosave" => {
+				self.hist_autosave = if let SlashVal::Bool(val) = value { val } else {
+					return Err(SlashErr::Low(SlashErrLow::ExecFailed(format!("Invalid value for core.hist_autosave: {:?}", value))))
+				};
+			}
 			"bell_style" => {
-				self.bell_style = if let SlashVal::Int(val) = value { val as usize } else {
+				self.bell_style = if let SlashVal::Int(val @ 0..=2) = value { val as usize } else {
 					return Err(SlashErr::Low(SlashErrLow::ExecFailed(format!("Invalid value for core.bell_style: {:?}", value))))
 				};
 			}
@@ -138,6 +198,75 @@ impl ShOptsCore {
 					return Err(SlashErr::Low(SlashErrLow::ExecFailed(format!("Invalid value for core.max_recurse_depth: {:?}", value))))
 				};
 			}
+			"max_func_depth" => {
+				self.max_func_depth = if let SlashVal::Int(val) = value { val as usize } else {
+					return Err(SlashErr::Low(SlashErrLow::ExecFailed(format!("Invalid value for core.max_func_depth: {:?}", value))))
+				};
+			}
+			"max_alias_depth" => {
+				self.max_alias_depth = if let SlashVal::Int(val) = value { val as usize } else {
+					return Err(SlashErr::Low(SlashErrLow::ExecFailed(format!("Invalid value for core.max_alias_depth: {:?}", value))))
+				};
+			}
+			"max_brace_expand" => {
+				self.max_brace_expand = if let SlashVal::Int(val @ 0..) = value { val as usize } else {
+					return Err(SlashErr::Low(SlashErrLow::ExecFailed(format!("Invalid value for core.max_brace_expand: {:?}", value))))
+				};
+			}
+			"plugin_dir" => {
+				self.plugin_dir = if let SlashVal::String(val) = value { val } else {
+					return Err(SlashErr::Low(SlashErrLow::ExecFailed(format!("Invalid value for core.plugin_dir: {:?}", value))))
+				};
+			}
+			"chpwd_title" => {
+				self.chpwd_title = if let SlashVal::Bool(val) = value { val } else {
+					return Err(SlashErr::Low(SlashErrLow::ExecFailed(format!("Invalid value for core.chpwd_title: {:?}", value))))
+				};
+			}
+			"chpwd_ls" => {
+				self.chpwd_ls = if let SlashVal::Bool(val) = value { val } else {
+					return Err(SlashErr::Low(SlashErrLow::ExecFailed(format!("Invalid value for core.chpwd_ls: {:?}", value))))
+				};
+			}
+			"color" => {
+				let mode = if let SlashVal::String(val) = &value { ColorMode::parse(val) } else { None };
+				let Some(mode) = mode else {
+					return Err(SlashErr::Low(SlashErrLow::ExecFailed(format!("Invalid value for core.color: {:?}", value))))
+				};
+				term::set_color_mode(mode);
+				self.color = value.to_string();
+			}
+			"huponexit" => {
+				self.huponexit = if let SlashVal::Bool(val) = value { val } else {
+					return Err(SlashErr::Low(SlashErrLow::ExecFailed(format!("Invalid value for core.huponexit: {:?}", value))))
+				};
+			}
+			"fd_audit" => {
+				self.fd_audit = if let SlashVal::Bool(val) = value { val } else {
+					return Err(SlashErr::Low(SlashErrLow::ExecFailed(format!("Invalid value for core.fd_audit: {:?}", value))))
+				};
+			}
+			"checkwinsize" => {
+				self.checkwinsize = if let SlashVal::Bool(val) = value { val } else {
+					return Err(SlashErr::Low(SlashErrLow::ExecFailed(format!("Invalid value for core.checkwinsize: {:?}", value))))
+				};
+			}
+			"child_max" => {
+				self.child_max = if let SlashVal::Int(val @ 1..) = value { val as usize } else {
+					return Err(SlashErr::Low(SlashErrLow::ExecFailed(format!("Invalid value for core.child_max: {:?}", value))))
+				};
+			}
+			"glob_sort" => {
+				self.glob_sort = match &value {
+					SlashVal::String(val) if matches!(val.as_str(), "locale" | "bytes") => val.clone(),
+					_ => return Err(SlashErr::Low(SlashErrLow::ExecFailed(format!("Invalid value for core.glob_sort: {:?}", value))))
+				};
+			}
+			"lastpipe" => {
+				self.lastpipe = if let SlashVal::Bool(val) = value { val } else {
+					return Err(SlashErr::Low(SlashErrLow::ExecFailed(format!("Invalid value for core.lastpipe: {:?}", value))))
+				};
+			}
 			_ => {
 				return Err(SlashErr::Low(SlashErrLow::ExecFailed(format!("Invalid core opts key: {}", key))))
 			}
@@ -152,7 +281,14 @@ pub struct ShOptsPrompt {
 	pub edit_mode: String,
 	pub comp_limit: usize,
 	pub prompt_highlight: bool,
+	/// The width of a tab in the line editor and in the source lines of error messages
 	pub tab_stop: usize,
+	/// How many milliseconds a command has to run for before `\D` shows its duration
+	pub cmd_time_threshold: usize,
+	/// How many milliseconds custom escape sequences get to finish before the prompt is drawn without them. 0 means no limit
+	pub hook_timeout: usize,
+	/// Whether the flags of commands without a completion spec are taken from what they print for `--help`. Off by default, since it runs the command
+	pub help_flags: bool,
 	pub exit_status: PromptStatus, // Sub-group for exit status symbols
 	pub custom: PromptCustom
 }
@@ -166,6 +302,9 @@ impl ShOptsPrompt {
 			"comp_limit" => Ok(SlashVal::Int(self.comp_limit as i32)),
 			"prompt_highlight" => Ok(SlashVal::Bool(self.prompt_highlight)),
 			"tab_stop" => Ok(SlashVal::Int(self.tab_stop as i32)),
+			"cmd_time_threshold" => Ok(SlashVal::Int(self.cmd_time_threshold as i32)),
+			"hook_timeout" => Ok(SlashVal::Int(self.hook_timeout as i32)),
+			"help_flags" => Ok(SlashVal::Bool(self.help_flags)),
 			"exit_status" => Ok(self.exit_status.get(query)?),
 			"custom" => Ok(self.custom.get(query)?),
 			_ => Err(SlashErr::Low(SlashErrLow::ExecFailed(format!("Invalid key for prompt opts: {}",key))))
@@ -196,8 +335,24 @@ impl ShOptsPrompt {
 				};
 			}
 			"tab_stop" => {
-				self.tab_stop = if let SlashVal::Int(val) = value { val as usize } else {
-					return Err(SlashErr::Low(SlashErrLow::ExecFailed(format!("Invalid value for core.tab_stop: {:?}", value))))
+				// Tabs are expanded to the next multiple of the tab stop, so it can't be zero
+				self.tab_stop = if let SlashVal::Int(val @ 1..) = value { val as usize } else {
+					return Err(SlashErr::Low(SlashErrLow::ExecFailed(format!("Invalid value for prompt.tab_stop: {:?}", value))))
+				};
+			}
+			"cmd_time_threshold" => {
+				self.cmd_time_threshold = if let SlashVal::Int(val) = value { val as usize } else {
+					return Err(SlashErr::Low(SlashErrLow::ExecFailed(format!("Invalid value for prompt.cmd_time_threshold: {:?}", value))))
+				};
+			}
+			"hook_timeout" => {
+				self.hook_timeout = if let SlashVal::Int(val @ 0..) = value { val as usize } else {
+					return Err(SlashErr::Low(SlashErrLow::ExecFailed(format!("Invalid value for prompt.hook_timeout: {:?}", value))))
+				};
+			}
+			"help_flags" => {
+				self.help_flags = if let SlashVal::Bool(val) = value { val } else {
+					return Err(SlashErr::Low(SlashErrLow::ExecFailed(format!("Invalid value for prompt.help_flags: {:?}", value))))
 				};
 			}
 			"exit_status" => self.exit_status.set(query, value)?,
@@ -332,6 +487,8 @@ impl PromptCustom {
 pub struct PromptStatus {
 	pub success: String,
 	pub failure: String,
+	/// Whether the highlighter draws the whole prompt in the `prompt.failure` style after a failed command
+	pub color: bool,
 }
 
 impl PromptStatus {
@@ -340,6 +497,7 @@ impl PromptStatus {
 		match key.as_str() {
 			"success" => Ok(SlashVal::String(self.success.clone())),
 			"failure" => Ok(SlashVal::String(self.failure.clone())),
+			"color" => Ok(SlashVal::Bool(self.color)),
 			_ => Err(SlashErr::Low(SlashErrLow::ExecFailed(format!("Invalid key for prompt exit status opts: {}",key))))
 		}
 	}
@@ -357,6 +515,11 @@ impl PromptStatus {
 					return Err(SlashErr::Low(SlashErrLow::ExecFailed(format!("Invalid value for core.failure: {:?}", value))))
 				};
 			}
+			"color" => {
+				self.color = if let SlashVal::Bool(val) = value { val } else {
+					return Err(SlashErr::Low(SlashErrLow::ExecFailed(format!("Invalid value for prompt.exit_status.color: {:?}", value))))
+				};
+			}
 			_ => {
 				return Err(SlashErr::Low(SlashErrLow::ExecFailed(format!("Invalid key for prompt_status: {:?}", value))))
 			}
@@ -1,42 +1,271 @@
+use std::sync::{atomic::{AtomicBool, AtomicI32, AtomicU64, Ordering}, Mutex};
+
+use rustyline::ExternalPrinter;
+
 use nix::{sys::{signal::{killpg, signal, SigHandler, Signal} , wait::{waitpid, WaitPidFlag, WaitStatus}}, unistd::{getpgid, getpgrp, Pid}};
 
-use crate::{error::{SlashErr, SlashErrLow}, helper, shellenv::{self, read_jobs, write_jobs, JobCmdFlags, JobID}, SlashResult};
+use crate::{error::{SlashErr, SlashErrLow}, helper, shellenv::{self, read_jobs, write_jobs, JobCmdFlags, JobID}, utils, SlashResult};
+
+/// Set by the SIGINT handler, and checked cooperatively by long running execution paths like loops
+pub static INTERRUPTED: AtomicBool = AtomicBool::new(false);
+/// The termination signal that the shell has received, or 0
+/// The shell doesn't die on the spot, it exits at the next safe point so that the EXIT trap runs and the terminal is restored
+static PENDING_EXIT: AtomicI32 = AtomicI32::new(0);
+/// Set by `set -b`, so that background jobs are reported as soon as they change instead of before the next prompt
+static NOTIFY_ASAP: AtomicBool = AtomicBool::new(false);
+/// Set by the SIGWINCH handler, so that `COLUMNS` and `LINES` are brought up to date at the next safe point
+static WINCH_PENDING: AtomicBool = AtomicBool::new(false);
+/// Set once an interactive shell has started ignoring SIGTERM and SIGQUIT, so that removing a trap on them ignores them again
+static TERMINATION_IGNORED: AtomicBool = AtomicBool::new(false);
+/// The signals that have a trap set, with a bit for each signal number
+/// Signal handlers belong to the process, so there is one set of trapped signals per process, shared by every `Slash` in it.
+/// The trap actions themselves are stored per `Slash`, and the most recent `trap` call decides how a signal is handled
+static TRAPPED: AtomicU64 = AtomicU64::new(0);
+/// The trapped signals that have arrived since their traps last ran
+static PENDING_TRAPS: AtomicU64 = AtomicU64::new(0);
+/// Prints above the line editor while a prompt is being read, so that job reports don't garble the line being edited
+static PROMPT_PRINTER: Mutex<Option<Box<dyn ExternalPrinter + Send>>> = Mutex::new(None);
+
+pub fn set_notify_asap(enable: bool) {
+	NOTIFY_ASAP.store(enable, Ordering::SeqCst);
+}
+
+/// Lets the prompt printer be locked again after a panic that happened while it was held
+pub fn clear_printer_poison() {
+	PROMPT_PRINTER.clear_poison();
+}
+
+/// Installs or removes the printer used to report jobs while the line editor is active
+pub fn set_prompt_printer(printer: Option<Box<dyn ExternalPrinter + Send>>) {
+	if let Ok(mut slot) = PROMPT_PRINTER.lock() {
+		*slot = printer;
+	}
+}
+
+/// Reports a change in a background job
+/// Normally the report waits until the next prompt. With `set -b` it is printed right away,
+/// and if the line editor is active, the line being edited is redrawn underneath it
+fn report_job(report: String) -> SlashResult<()> {
+	if !NOTIFY_ASAP.load(Ordering::SeqCst) {
+		return write_jobs(|j| j.queue_report(report))
+	}
+	// Blocking here would deadlock if the signal arrived while the printer was being swapped out
+	if let Ok(mut slot) = PROMPT_PRINTER.try_lock() {
+		if let Some(printer) = slot.as_mut() {
+			if printer.print(format!("{}\n",report)).is_ok() {
+				return Ok(())
+			}
+		}
+	}
+	eprintln!();
+	eprintln!("{}",report);
+	Ok(())
+}
+
+/// Returns an error if SIGINT has been received since the flag was last cleared
+/// If a termination signal has been received, the error is a clean exit with the status that the signal would have given
+pub fn check_interrupt() -> SlashResult<()> {
+	if let Some(code) = pending_exit() {
+		Err(SlashErr::Low(SlashErrLow::CleanExit(code)))
+	} else if INTERRUPTED.swap(false, Ordering::SeqCst) {
+		Err(SlashErr::Low(SlashErrLow::Interrupted))
+	} else {
+		Ok(())
+	}
+}
+
+/// The exit status the shell should leave with, if it has received SIGTERM, SIGHUP, or SIGQUIT
+pub fn pending_exit() -> Option<i32> {
+	match PENDING_EXIT.load(Ordering::SeqCst) {
+		0 => None,
+		sig => Some(utils::SIG_EXIT_OFFSET + sig)
+	}
+}
+
+/// Forgets a termination signal once the shell has started exiting, so that the EXIT trap isn't cut short by it
+pub fn clear_pending_exit() {
+	PENDING_EXIT.store(0, Ordering::SeqCst);
+}
+
+/// Whether the terminal has been resized since this was last called
+pub fn take_winch() -> bool {
+	WINCH_PENDING.swap(false, Ordering::SeqCst)
+}
+
+pub fn clear_interrupt() {
+	INTERRUPTED.store(false, Ordering::SeqCst);
+}
+
+/// Records the outcome of SIGINT for a foreground job that just finished
+/// If the job was killed by SIGINT, the rest of the command line is abandoned, the same as if the shell had been interrupted itself.
+/// If every process survived, then the job handled the signal on its own, so the shell ignores any SIGINT it received while waiting.
+/// With a trap on SIGINT, the trap runs instead of the command line being abandoned
+pub fn settle_fg_interrupt(killed_by_sigint: bool) {
+	if killed_by_sigint && is_trapped(Signal::SIGINT) {
+		PENDING_TRAPS.fetch_or(signal_bit(Signal::SIGINT), Ordering::SeqCst);
+		INTERRUPTED.store(false, Ordering::SeqCst);
+	} else {
+		INTERRUPTED.store(killed_by_sigint, Ordering::SeqCst);
+	}
+}
+
+fn signal_bit(sig: Signal) -> u64 {
+	1 << sig as i32
+}
+
+fn is_trapped(sig: Signal) -> bool {
+	TRAPPED.load(Ordering::SeqCst) & signal_bit(sig) != 0
+}
+
+/// The names of every signal, without the `SIG` prefix, as `kill -l` and `trap -l` list them
+pub fn signal_names() -> Vec<&'static str> {
+	Signal::iterator().map(|sig| sig.as_str().trim_start_matches("SIG")).collect()
+}
+
+/// Points a signal at its trap, which runs at the next safe point after the signal arrives
+/// An empty action ignores the signal, and `None` gives it back the handling that the shell gives it without a trap.
+/// This changes the handling for the whole process, not only for the shell that set the trap
+pub fn set_signal_trap(sig: Signal, action: Option<&str>) -> SlashResult<()> {
+	let handler = match action {
+		Some("") => SigHandler::SigIgn,
+		Some(_) => SigHandler::Handler(handle_trapped),
+		None => default_handler(sig)
+	};
+	unsafe { signal(sig, handler) }.map_err(|errno| SlashErr::Low(SlashErrLow::ErrNo(errno)))?;
+	if action.is_some_and(|action| !action.is_empty()) {
+		TRAPPED.fetch_or(signal_bit(sig), Ordering::SeqCst);
+	} else {
+		TRAPPED.fetch_and(!signal_bit(sig), Ordering::SeqCst);
+		PENDING_TRAPS.fetch_and(!signal_bit(sig), Ordering::SeqCst);
+	}
+	Ok(())
+}
+
+/// The signals that have a trap set
+pub fn trapped_signals() -> Vec<Signal> {
+	let trapped = TRAPPED.load(Ordering::SeqCst);
+	Signal::iterator().filter(|sig| trapped & signal_bit(*sig) != 0).collect()
+}
+
+/// Takes the trapped signals that have arrived since this was last called, in order of their numbers
+pub fn take_pending_traps() -> Vec<Signal> {
+	let pending = PENDING_TRAPS.swap(0, Ordering::SeqCst);
+	Signal::iterator().filter(|sig| pending & signal_bit(*sig) != 0).collect()
+}
+
+/// The SIGCHLD handler to put back once the shell reaps its children asynchronously again, which also runs a trap on SIGCHLD if one is set
+pub fn reaping_handler() -> SigHandler {
+	if is_trapped(Signal::SIGCHLD) {
+		SigHandler::Handler(handle_trapped)
+	} else {
+		SigHandler::Handler(handle_sigchld)
+	}
+}
+
+/// How the shell handles a signal that has no trap set
+fn default_handler(sig: Signal) -> SigHandler {
+	match sig {
+		Signal::SIGCHLD => SigHandler::Handler(handle_sigchld),
+		Signal::SIGQUIT | Signal::SIGTERM if TERMINATION_IGNORED.load(Ordering::SeqCst) => SigHandler::Handler(ignore_signal),
+		Signal::SIGQUIT | Signal::SIGTERM => SigHandler::Handler(handle_termination),
+		Signal::SIGTSTP => SigHandler::Handler(handle_sigtstp),
+		Signal::SIGHUP => SigHandler::Handler(handle_sighup),
+		Signal::SIGINT => SigHandler::Handler(handle_sigint),
+		Signal::SIGWINCH => SigHandler::Handler(handle_sigwinch),
+		Signal::SIGTTIN | Signal::SIGTTOU => SigHandler::SigIgn,
+		_ => SigHandler::SigDfl
+	}
+}
+
+/// Ends a forked child that was interrupted while running shell code, like a loop in a pipeline
+/// The child kills itself with SIGINT so that its parent sees the same status that an external command would give it
+pub fn exit_interrupted() -> ! {
+	unsafe {
+		signal(Signal::SIGINT, SigHandler::SigDfl).ok();
+	}
+	nix::sys::signal::raise(Signal::SIGINT).ok();
+	std::process::exit(130)
+}
 
 pub fn sig_handler_setup() {
+	let handled = [
+		Signal::SIGCHLD, Signal::SIGQUIT, Signal::SIGTERM, Signal::SIGTSTP, Signal::SIGHUP,
+		Signal::SIGINT, Signal::SIGWINCH, Signal::SIGTTIN, Signal::SIGTTOU
+	];
+	for sig in handled {
+		unsafe {
+			signal(sig, default_handler(sig)).unwrap();
+		}
+	}
+}
+
+/// Interactive shells ignore SIGTERM and SIGQUIT, so that `kill 0` and Ctrl-\ don't take the shell down with its jobs
+/// A handler that does nothing is used instead of SIG_IGN, because ignored signals stay ignored in the commands that the shell runs
+pub fn ignore_termination() {
+	TERMINATION_IGNORED.store(true, Ordering::SeqCst);
 	unsafe {
-		signal(Signal::SIGCHLD, SigHandler::Handler(handle_sigchld)).unwrap();
-		signal(Signal::SIGQUIT, SigHandler::Handler(handle_sigquit)).unwrap();
-		signal(Signal::SIGTSTP, SigHandler::Handler(handle_sigtstp)).unwrap();
-		signal(Signal::SIGHUP, SigHandler::Handler(handle_sighup)).unwrap();
-		signal(Signal::SIGINT, SigHandler::Handler(handle_sigint)).unwrap();
-		signal(Signal::SIGTTIN, SigHandler::SigIgn).unwrap();
-		signal(Signal::SIGTTOU, SigHandler::SigIgn).unwrap();
+		signal(Signal::SIGQUIT, SigHandler::Handler(ignore_signal)).ok();
+		signal(Signal::SIGTERM, SigHandler::Handler(ignore_signal)).ok();
 	}
 }
 
-extern "C" fn handle_sighup(_: libc::c_int) {
-	write_jobs(|j| {
-		for job in j.mut_jobs().iter_mut().flatten() {
-			job.killpg(Signal::SIGTERM).unwrap();
+/// Sends SIGHUP to every job, and wakes stopped jobs so that they receive it
+/// Jobs marked with `disown -h` are skipped
+pub fn hangup_jobs() {
+	// The job table may already be locked by the code that the signal interrupted
+	if let Ok(mut j) = shellenv::JOBS.try_write() {
+		for job in j.mut_jobs().iter_mut().flatten().filter(|job| !job.is_nohup()) {
+			let stopped = job.is_stopped();
+			job.killpg(Signal::SIGHUP).ok();
+			if stopped {
+				job.killpg(Signal::SIGCONT).ok();
+			}
 		}
-	}).unwrap();
-	std::process::exit(0);
+	}
+}
+
+extern "C" fn ignore_signal(_: libc::c_int) {}
+
+extern "C" fn handle_trapped(sig: libc::c_int) {
+	// Children still have to be reaped when SIGCHLD is trapped
+	if sig == Signal::SIGCHLD as libc::c_int {
+		handle_sigchld(sig);
+	}
+	if let Ok(sig) = Signal::try_from(sig) {
+		PENDING_TRAPS.fetch_or(signal_bit(sig), Ordering::SeqCst);
+	}
+}
+
+extern "C" fn handle_sigwinch(_: libc::c_int) {
+	WINCH_PENDING.store(true, Ordering::SeqCst);
+}
+
+extern "C" fn handle_sighup(sig: libc::c_int) {
+	hangup_jobs();
+	PENDING_EXIT.store(sig, Ordering::SeqCst);
+}
+
+extern "C" fn handle_termination(sig: libc::c_int) {
+	PENDING_EXIT.store(sig, Ordering::SeqCst);
 }
 
 extern "C" fn handle_sigtstp(_: libc::c_int) {
 	write_jobs(|j| {
 		if let Some(job) = j.get_fg_mut() {
-			job.killpg(Signal::SIGTSTP).unwrap();
+			job.killpg(Signal::SIGTSTP).ok();
 		}
-	}).unwrap();
+	}).ok();
 }
 
 extern "C" fn handle_sigint(_: libc::c_int) {
-	write_jobs(|j| {
+	INTERRUPTED.store(true, Ordering::SeqCst);
+	// Blocking on the job table here would deadlock if the interrupted code is holding the lock
+	// In that case the foreground job still receives SIGINT from the terminal
+	if let Ok(mut j) = shellenv::JOBS.try_write() {
 		if let Some(job) = j.get_fg_mut() {
-			job.killpg(Signal::SIGINT).unwrap();
+			job.killpg(Signal::SIGINT).ok();
 		}
-	}).unwrap();
+	}
 }
 
 pub extern "C" fn ignore_sigchld(_: libc::c_int) {
@@ -52,21 +281,14 @@ pub extern "C" fn ignore_sigchld(_: libc::c_int) {
 	*/
 }
 
-extern "C" fn handle_sigquit(_: libc::c_int) {
-	write_jobs(|j| {
-		for job in j.mut_jobs().iter_mut().flatten() {
-			job.killpg(Signal::SIGTERM).unwrap();
-		}
-	}).unwrap();
-	std::process::exit(0);
-}
-
 pub extern "C" fn handle_sigchld(_: libc::c_int) {
 	/*
 	 * This is the signal handler's real job
 	 * Each WaitStatus has logic associated with it
 	 * But handle_child_exit() is the most important one
 	 */
+	// The interrupted code may be about to read errno, so the handler's own failures mustn't show through
+	let errno = nix::errno::Errno::last_raw();
 	let flags = WaitPidFlag::WNOHANG | WaitPidFlag::WUNTRACED;
 	while let Ok(status) = waitpid(None, Some(flags)) {
 		let _ = match status {
@@ -78,21 +300,23 @@ pub extern "C" fn handle_sigchld(_: libc::c_int) {
 			_ => unreachable!(),
 		};
 	}
+	nix::errno::Errno::set_raw(errno);
 }
 
 //TODO: extract some of this logic from the closure to spend less time holding a write lock
 pub fn handle_child_signal<'a>(pid: Pid, sig: Signal) -> SlashResult<()> {
-	let pgid = getpgid(Some(pid)).unwrap_or(pid);
+	// The child is already gone, so its job is found by pid rather than by asking for its process group
 	write_jobs(|j| {
-		if let Some(job) = j.query_mut(JobID::Pgid(pgid)) {
-			let child = job.get_children_mut().iter_mut().find(|chld| pid == chld.pid()).unwrap();
-			let status = WaitStatus::Signaled(pid, sig, false);
-			child.set_status(status);
+		if let Some(job) = j.query_mut(JobID::Pid(pid)) {
+			if let Some(child) = job.get_children_mut().iter_mut().find(|chld| pid == chld.pid()) {
+				child.set_status(WaitStatus::Signaled(pid, sig, false));
+			}
+		} else {
+			j.stash_status(pid, WaitStatus::Signaled(pid, sig, false));
 		}
 	})?;
-	if matches!(sig,Signal::SIGINT) {
-		shellenv::attach_tty(getpgrp())?; // Reclaim terminal
-	}
+	// A child that dies abnormally may have been holding the terminal
+	shellenv::attach_tty(getpgrp())?; // Reclaim terminal
 	Ok(())
 }
 
@@ -100,13 +324,16 @@ pub fn handle_child_stop<'a>(pid: Pid, signal: Signal) -> SlashResult<()> {
 	let pgid = getpgid(Some(pid)).unwrap_or(pid);
 	write_jobs(|j| {
 		if let Some(job) = j.query_mut(JobID::Pgid(pgid)) {
-			let child = job.get_children_mut().iter_mut().find(|chld| pid == chld.pid()).unwrap();
-			let status = WaitStatus::Stopped(pid, signal);
-			child.set_status(status);
+			if let Some(child) = job.get_children_mut().iter_mut().find(|chld| pid == chld.pid()) {
+				child.set_status(WaitStatus::Stopped(pid, signal));
+			}
+			Ok(())
 		} else if j.get_fg_mut().is_some_and(|fg| fg.pgid() == pgid) {
-			j.fg_to_bg(WaitStatus::Stopped(pid, signal)).unwrap();
+			j.fg_to_bg(WaitStatus::Stopped(pid, signal))
+		} else {
+			Ok(())
 		}
-	})?;
+	})??;
 	let job = read_jobs(|j| j.query(JobID::Pid(pid)).cloned())?;
 	shellenv::attach_tty(getpgrp())?; // Reclaim terminal
 	Ok(())
@@ -118,39 +345,44 @@ pub fn handle_child_exit<'a>(pid: Pid, status: WaitStatus) -> SlashResult<()> {
 	 * Then if the discovered job is the fg task, return terminal control to rsh
 	 * If it is not the fg task, print the display info for the job in the job table
 	 * We can reasonably assume that if it is not a foreground job, then it exists in the job table
-	 * If this assumption is incorrect, the code has gone wrong somewhere.
+	 * The exception is a child that exits before its job is registered, whose status is stashed until it is
 	 */
-	let (
-		pgid,
-		is_fg,
-		is_finished
-	) = write_jobs(|j| {
+	let result = write_jobs(|j| {
 		let fg_pgid = j.get_fg().map(|job| job.pgid());
 		if let Some(job) = j.query_mut(JobID::Pid(pid)) {
 			let pgid = job.pgid();
 			let is_fg = fg_pgid.is_some_and(|fg| fg == pgid);
-			job.update_by_id(JobID::Pid(pid), status).unwrap();
+			job.update_by_id(JobID::Pid(pid), status).ok();
 			let is_finished = !job.is_alive();
+			let table_id = job.table_id();
 
 			if let Some(child) = job.get_children_mut().iter_mut().find(|chld| pid == chld.pid()) {
 				child.set_status(status);
 			}
+			// Let `jobs -n` know that this background job has changed
+			if let Some(id) = table_id.filter(|_| !is_fg) {
+				j.mark_updated(id);
+			}
 
-			Ok((pgid, is_fg, is_finished))
+			Some((pgid, is_fg, is_finished))
 		} else {
-			Err(SlashErr::Low(SlashErrLow::InternalErr("Job not found".into())))
+			// The child exited before its job made it into the table
+			j.stash_status(pid, status);
+			None
 		}
-	})??;
+	})?;
+	let Some((pgid, is_fg, is_finished)) = result else {
+		return Ok(())
+	};
 
 	if is_finished {
 		if is_fg {
 			shellenv::attach_tty(getpgrp())?; // Reclaim terminal control
 		} else {
-			println!();
 			let job_order = read_jobs(|j| j.job_order().to_vec())?;
 			let result = read_jobs(|j| j.query(JobID::Pgid(pgid)).cloned())?;
 			if let Some(job) = result {
-				println!("{}",job.display(&job_order,JobCmdFlags::PIDS))
+				report_job(job.display(&job_order,JobCmdFlags::PIDS))?;
 			}
 		}
 	}
@@ -166,3 +398,25 @@ pub fn handle_child_continue<'a>(pid: Pid) -> SlashResult<()> {
 	})?;
 	Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+	use crate::{execute::dispatch::exec_input, shellenv::Slash};
+
+	use super::*;
+
+	#[test]
+	fn test_job_reports() {
+		let mut slash = Slash::new();
+		exec_input("set -b".into(), &mut slash).unwrap();
+		assert!(NOTIFY_ASAP.load(Ordering::SeqCst));
+		exec_input("set +b".into(), &mut slash).unwrap();
+		assert!(!NOTIFY_ASAP.load(Ordering::SeqCst));
+
+		// Without set -b, reports wait for the next prompt
+		write_jobs(|j| j.take_reports()).unwrap();
+		report_job("[1]  Done  sleep 1".into()).unwrap();
+		assert_eq!(write_jobs(|j| j.take_reports()).unwrap(), vec!["[1]  Done  sleep 1".to_string()]);
+		assert!(write_jobs(|j| j.take_reports()).unwrap().is_empty());
+	}
+}
@@ -0,0 +1,519 @@
+//! Terminal capabilities and named styles
+//!
+//! Anything that wants to color its output should go through `paint()` with a named `Style`,
+//! instead of writing escape codes directly. Colors are disabled when `NO_COLOR` is set,
+//! when stdout isn't a terminal, when the terminal can't display them according to terminfo,
+//! or when `core.color` says so
+//!
+//! Each style has a default escape code, which can be overridden at runtime with the `theme` builtin
+
+use std::sync::{atomic::{AtomicU8, Ordering}, Mutex, RwLock};
+
+use once_cell::sync::Lazy;
+use unicode_segmentation::UnicodeSegmentation;
+use unicode_width::UnicodeWidthStr;
+
+use crate::prelude::*;
+
+/// The numeric capability index of `colors` in compiled terminfo entries
+const TI_COLORS: usize = 13;
+const TI_MAGIC: u16 = 0o432;
+const TI_MAGIC_32BIT: u16 = 0o1036;
+
+#[derive(Debug,Clone,Copy,PartialEq,Eq)]
+pub enum ColorMode {
+	Auto,
+	Always,
+	Never
+}
+
+impl ColorMode {
+	pub fn parse(mode: &str) -> Option<Self> {
+		match mode {
+			"auto" => Some(Self::Auto),
+			"always" => Some(Self::Always),
+			"never" => Some(Self::Never),
+			_ => None
+		}
+	}
+}
+
+static COLOR_MODE: AtomicU8 = AtomicU8::new(0);
+static COLOR_CACHE: Lazy<Mutex<HashMap<String,Option<i32>>>> = Lazy::new(|| Mutex::new(HashMap::new()));
+
+pub fn set_color_mode(mode: ColorMode) {
+	COLOR_MODE.store(mode as u8, Ordering::Relaxed)
+}
+
+pub fn color_mode() -> ColorMode {
+	match COLOR_MODE.load(Ordering::Relaxed) {
+		1 => ColorMode::Always,
+		2 => ColorMode::Never,
+		_ => ColorMode::Auto
+	}
+}
+
+#[derive(Debug,Clone,Copy,PartialEq,Eq,Hash)]
+pub enum Style {
+	Error,
+	Warning,
+	JobDone,
+	JobFailed,
+	JobRunning,
+	CompDirectory,
+	CompFile,
+	PromptSuccess,
+	PromptFailure,
+	PromptPath,
+	HlError,
+	HlCommand,
+	HlArgument,
+	HlKeyword,
+	HlString,
+	HlEscaped,
+	HlOperator,
+	HlNumber,
+	HlPath,
+	HlVarsub,
+	HlComment,
+	HlFuncname
+}
+
+impl Style {
+	pub const ALL: [Style;22] = [
+		Style::Error, Style::Warning, Style::JobDone, Style::JobFailed, Style::JobRunning, Style::CompDirectory, Style::CompFile,
+		Style::PromptSuccess, Style::PromptFailure, Style::PromptPath, Style::HlError, Style::HlCommand, Style::HlArgument,
+		Style::HlKeyword, Style::HlString, Style::HlEscaped, Style::HlOperator, Style::HlNumber, Style::HlPath, Style::HlVarsub,
+		Style::HlComment, Style::HlFuncname
+	];
+
+	/// The name used for this style in the `theme` builtin
+	pub fn name(&self) -> &'static str {
+		match self {
+			Style::Error => "error",
+			Style::Warning => "warning",
+			Style::JobDone => "job.done",
+			Style::JobFailed => "job.failed",
+			Style::JobRunning => "job.running",
+			Style::CompDirectory => "comp.directory",
+			Style::CompFile => "comp.file",
+			Style::PromptSuccess => "prompt.success",
+			Style::PromptFailure => "prompt.failure",
+			Style::PromptPath => "prompt.path",
+			Style::HlError => "hl.error",
+			Style::HlCommand => "hl.command",
+			Style::HlArgument => "hl.argument",
+			Style::HlKeyword => "hl.keyword",
+			Style::HlString => "hl.string",
+			Style::HlEscaped => "hl.escaped",
+			Style::HlOperator => "hl.operator",
+			Style::HlNumber => "hl.number",
+			Style::HlPath => "hl.path",
+			Style::HlVarsub => "hl.varsub",
+			Style::HlComment => "hl.comment",
+			Style::HlFuncname => "hl.funcname"
+		}
+	}
+
+	pub fn from_name(name: &str) -> Option<Self> {
+		Self::ALL.into_iter().find(|style| style.name() == name)
+	}
+
+	/// The escape code used when the theme doesn't override this style
+	pub fn default_code(&self) -> &'static str {
+		match self {
+			Style::Error => "\x1b[1;31m",
+			Style::Warning => "\x1b[33m",
+			Style::JobDone => "\x1b[32m",
+			Style::JobFailed => "\x1b[31m",
+			Style::JobRunning => "\x1b[36m",
+			Style::CompDirectory => "\x1b[1;34m",
+			Style::CompFile => "",
+			Style::PromptSuccess => "",
+			Style::PromptFailure => "",
+			Style::PromptPath => "",
+			Style::HlError => "\x1b[1;31m",
+			Style::HlCommand => "\x1b[32m",
+			Style::HlArgument => RESET,
+			Style::HlKeyword => "\x1b[33m",
+			Style::HlString => "\x1b[34m",
+			Style::HlEscaped => "\x1b[36m",
+			Style::HlOperator => "\x1b[36m",
+			Style::HlNumber => "\x1b[94m",
+			Style::HlPath => "\x1b[96m",
+			Style::HlVarsub => "\x1b[35m",
+			Style::HlComment => "\x1b[90m",
+			Style::HlFuncname => "\x1b[36m"
+		}
+	}
+
+	/// The escape code for this style in the current theme
+	pub fn code(&self) -> String {
+		THEME.read()
+			.ok()
+			.and_then(|theme| theme.get(self).map(|(_,code)| code.clone()))
+			.unwrap_or_else(|| self.default_code().to_string())
+	}
+
+	/// The color spec this style was set to with `theme set`, if any
+	pub fn spec(&self) -> Option<String> {
+		THEME.read().ok().and_then(|theme| theme.get(self).map(|(spec,_)| spec.clone()))
+	}
+}
+
+/// Styles overridden by the `theme` builtin, holding the spec as written and the escape code it compiles to
+static THEME: Lazy<RwLock<HashMap<Style,(String,String)>>> = Lazy::new(|| RwLock::new(HashMap::new()));
+
+/// Lets the theme and the color cache be locked again after a panic that happened while one was held
+pub fn clear_poison() {
+	THEME.clear_poison();
+	COLOR_CACHE.clear_poison();
+}
+
+pub fn set_style(style: Style, spec: &str) -> SlashResult<()> {
+	let code = parse_color_spec(spec)
+		.ok_or_else(|| Low(SlashErrLow::ExecFailed(format!("theme: invalid color spec: {}",spec))))?;
+	let mut theme = THEME.write().map_err(|_| Low(SlashErrLow::InternalErr("Failed to lock theme".into())))?;
+	theme.insert(style, (spec.to_string(), code));
+	Ok(())
+}
+
+/// Restores a style to its default, or every style if `style` is None
+pub fn reset_style(style: Option<Style>) {
+	if let Ok(mut theme) = THEME.write() {
+		match style {
+			Some(style) => { theme.remove(&style); }
+			None => theme.clear()
+		}
+	}
+}
+
+/// Compiles a color spec into an escape code
+///
+/// A spec is a space separated list of attributes (`bold`, `dim`, `italic`, `underline`, `blink`, `reverse`)
+/// and colors. Colors can be a name like `red` or `bright-blue`, a 256 color index, or a hex code like `#ff8800`,
+/// and are applied to the background when prefixed with `on-`. Raw SGR parameters like `1;31` are passed through,
+/// and `none` compiles to an empty code
+pub fn parse_color_spec(spec: &str) -> Option<String> {
+	let spec = spec.trim();
+	if spec == "none" {
+		return Some(String::new())
+	}
+	if spec.contains(';') && spec.chars().all(|ch| ch.is_ascii_digit() || ch == ';') {
+		return Some(format!("\x1b[{spec}m"))
+	}
+	let mut params = vec![];
+	for word in spec.split_whitespace() {
+		let param = match word {
+			"bold" => "1".to_string(),
+			"dim" => "2".to_string(),
+			"italic" => "3".to_string(),
+			"underline" => "4".to_string(),
+			"blink" => "5".to_string(),
+			"reverse" => "7".to_string(),
+			_ => match word.strip_prefix("on-") {
+				Some(color) => parse_color(color, true)?,
+				None => parse_color(word, false)?
+			}
+		};
+		params.push(param);
+	}
+	if params.is_empty() {
+		return None
+	}
+	Some(format!("\x1b[{}m",params.join(";")))
+}
+
+fn parse_color(color: &str, background: bool) -> Option<String> {
+	const NAMES: [&str;8] = ["black", "red", "green", "yellow", "blue", "magenta", "cyan", "white"];
+	let (base, extended) = if background { (40, 48) } else { (30, 38) };
+	if let Some(hex) = color.strip_prefix('#') {
+		if hex.len() != 6 {
+			return None
+		}
+		let channel = |i: usize| u8::from_str_radix(hex.get(i..i + 2)?, 16).ok();
+		return Some(format!("{extended};2;{};{};{}",channel(0)?,channel(2)?,channel(4)?))
+	}
+	if let Ok(index) = color.parse::<u8>() {
+		return Some(format!("{extended};5;{index}"))
+	}
+	let (name, bright) = match color.strip_prefix("bright-") {
+		Some(name) => (name, true),
+		None => (color, false)
+	};
+	let offset = NAMES.iter().position(|candidate| *candidate == name)?;
+	let base = if bright { base + 60 } else { base };
+	Some((base + offset).to_string())
+}
+
+pub const RESET: &str = "\x1b[0m";
+
+/// Marks the start of a non-printing group in an expanded prompt, the same way readline does
+pub const IGNORE_START: char = '\x01';
+/// Marks the end of a non-printing group in an expanded prompt
+pub const IGNORE_END: char = '\x02';
+
+/// Wraps text in the escape codes for a style, if colors are enabled
+pub fn paint(style: Style, text: &str) -> String {
+	let code = sgr(style);
+	if code.is_empty() || text.is_empty() {
+		text.to_string()
+	} else {
+		format!("{code}{text}{RESET}")
+	}
+}
+
+/// The escape code for a style, or an empty string if colors are disabled
+pub fn sgr(style: Style) -> String {
+	if colors_enabled() {
+		style.code()
+	} else {
+		String::new()
+	}
+}
+
+/// Flashes the screen for a visible bell, by switching the terminal to reverse video for a moment
+pub fn visible_bell() {
+	let mut stdout = std::io::stdout();
+	write!(stdout, "\x1b[?5h").and_then(|_| stdout.flush()).ok();
+	std::thread::sleep(std::time::Duration::from_millis(100));
+	write!(stdout, "\x1b[?5l").and_then(|_| stdout.flush()).ok();
+}
+
+/// Removes escape sequences and non-printing groups, leaving only the text that takes up space on screen
+pub fn visible_text(text: &str) -> String {
+	let mut result = String::new();
+	let mut chars = text.chars().peekable();
+	while let Some(ch) = chars.next() {
+		match ch {
+			IGNORE_START => {
+				for ch in chars.by_ref() {
+					if ch == IGNORE_END {
+						break
+					}
+				}
+			}
+			IGNORE_END => continue,
+			'\x1b' => match chars.next() {
+				// CSI sequences end with a byte in the range `@` to `~`
+				Some('[') => {
+					for ch in chars.by_ref() {
+						if ('@'..='~').contains(&ch) {
+							break
+						}
+					}
+				}
+				// OSC sequences end with BEL or ST
+				Some(']') => {
+					while let Some(ch) = chars.next() {
+						if ch == '\x07' || (ch == '\x1b' && chars.next_if_eq(&'\\').is_some()) {
+							break
+						}
+					}
+				}
+				_ => continue
+			}
+			_ => result.push(ch)
+		}
+	}
+	result
+}
+
+/// Removes the markers around non-printing groups, but keeps their contents
+pub fn strip_ignore_markers(text: &str) -> String {
+	text.replace([IGNORE_START, IGNORE_END], "")
+}
+
+/// The number of terminal columns a string occupies
+/// Escape sequences take no space, wide characters take two columns, and each grapheme cluster is measured as a unit
+pub fn display_width(text: &str) -> usize {
+	visible_text(text).graphemes(true).map(|grapheme| grapheme.width()).sum()
+}
+
+/// Replaces each tab with enough spaces to reach the next multiple of `tab_stop`, counting columns from the start of the text
+pub fn expand_tabs(text: &str, tab_stop: usize) -> String {
+	let tab_stop = tab_stop.max(1);
+	let mut result = String::new();
+	let mut column = 0;
+	for grapheme in text.graphemes(true) {
+		if grapheme == "\t" {
+			let pad = tab_stop - column % tab_stop;
+			result.extend(std::iter::repeat(' ').take(pad));
+			column += pad;
+		} else {
+			result.push_str(grapheme);
+			column += grapheme.width();
+		}
+	}
+	result
+}
+
+/// The terminal column that the character at `index` is drawn at, with tabs expanded to `tab_stop`
+pub fn column_of(text: &str, index: usize, tab_stop: usize) -> usize {
+	let prefix = text.chars().take(index).collect::<String>();
+	display_width(&expand_tabs(&prefix, tab_stop))
+}
+
+pub fn colors_enabled() -> bool {
+	match color_mode() {
+		ColorMode::Always => true,
+		ColorMode::Never => false,
+		ColorMode::Auto => {
+			if env::var("NO_COLOR").is_ok_and(|val| !val.is_empty()) {
+				return false
+			}
+			// Redirections are applied to the shell's own descriptors, so this also catches output sent to a pipe or a file
+			if !isatty(STDOUT_FILENO).unwrap_or(false) {
+				return false
+			}
+			let term = env::var("TERM").unwrap_or_default();
+			if term.is_empty() || term == "dumb" {
+				return false
+			}
+			// Terminals without a terminfo entry are assumed to handle the basic colors
+			term_colors(&term).is_none_or(|colors| colors >= 8)
+		}
+	}
+}
+
+/// The number of colors the terminal supports, according to its terminfo entry
+pub fn term_colors(term: &str) -> Option<i32> {
+	let mut cache = COLOR_CACHE.lock().ok()?;
+	if let Some(colors) = cache.get(term) {
+		return *colors
+	}
+	let colors = read_terminfo(term).and_then(|entry| parse_colors(&entry));
+	cache.insert(term.to_string(), colors);
+	colors
+}
+
+fn terminfo_dirs() -> Vec<PathBuf> {
+	let mut dirs = vec![];
+	if let Ok(dir) = env::var("TERMINFO") {
+		dirs.push(PathBuf::from(dir));
+	}
+	if let Ok(home) = env::var("HOME") {
+		dirs.push(PathBuf::from(home).join(".terminfo"));
+	}
+	if let Ok(list) = env::var("TERMINFO_DIRS") {
+		dirs.extend(list.split(':').filter(|dir| !dir.is_empty()).map(PathBuf::from));
+	}
+	dirs.extend(["/etc/terminfo", "/lib/terminfo", "/usr/share/terminfo"].into_iter().map(PathBuf::from));
+	dirs
+}
+
+fn read_terminfo(term: &str) -> Option<Vec<u8>> {
+	let first = term.chars().next()?;
+	for dir in terminfo_dirs() {
+		// Entries are filed under their first letter, or its hex code on some systems
+		let candidates = [
+			dir.join(first.to_string()).join(term),
+			dir.join(format!("{:x}",first as u32)).join(term)
+		];
+		if let Some(entry) = candidates.iter().find_map(|path| std::fs::read(path).ok()) {
+			return Some(entry)
+		}
+	}
+	None
+}
+
+/// Reads the `colors` capability out of a compiled terminfo entry
+fn parse_colors(entry: &[u8]) -> Option<i32> {
+	let short = |i: usize| entry.get(i..i + 2).map(|b| u16::from_le_bytes([b[0],b[1]]));
+	let magic = short(0)?;
+	let num_size = match magic {
+		TI_MAGIC => 2,
+		TI_MAGIC_32BIT => 4,
+		_ => return None
+	};
+	let names_size = short(2)? as usize;
+	let bool_count = short(4)? as usize;
+	let num_count = short(6)? as usize;
+	if TI_COLORS >= num_count {
+		return None
+	}
+	let mut offset = 12 + names_size + bool_count;
+	if offset % 2 != 0 {
+		offset += 1; // The numbers section is aligned to an even byte
+	}
+	let pos = offset + TI_COLORS * num_size;
+	let bytes = entry.get(pos..pos + num_size)?;
+	let colors = if num_size == 2 {
+		i16::from_le_bytes([bytes[0],bytes[1]]) as i32
+	} else {
+		i32::from_le_bytes([bytes[0],bytes[1],bytes[2],bytes[3]])
+	};
+	(colors >= 0).then_some(colors)
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn test_terminfo_colors() {
+		// A minimal entry: the names section, no booleans, and 14 numbers with colors set to 256
+		let mut entry = vec![];
+		for short in [TI_MAGIC, 4, 0, 14, 0, 0] {
+			entry.extend(short.to_le_bytes());
+		}
+		entry.extend(b"foo\0");
+		for i in 0..14 {
+			let num: i16 = if i == TI_COLORS { 256 } else { -1 };
+			entry.extend(num.to_le_bytes());
+		}
+		assert_eq!(parse_colors(&entry), Some(256));
+		assert_eq!(parse_colors(b"garbage"), None);
+
+		set_color_mode(ColorMode::Never);
+		assert_eq!(paint(Style::Error, "oops"), "oops");
+		set_color_mode(ColorMode::Always);
+		assert_eq!(paint(Style::Error, "oops"), "\x1b[1;31moops\x1b[0m");
+		set_color_mode(ColorMode::Auto);
+	}
+
+	#[test]
+	fn test_color_spec() {
+		assert_eq!(parse_color_spec("bold red").as_deref(), Some("\x1b[1;31m"));
+		assert_eq!(parse_color_spec("bright-blue on-black").as_deref(), Some("\x1b[94;40m"));
+		assert_eq!(parse_color_spec("208").as_deref(), Some("\x1b[38;5;208m"));
+		assert_eq!(parse_color_spec("1;31").as_deref(), Some("\x1b[1;31m"));
+		assert_eq!(parse_color_spec("#ff8800").as_deref(), Some("\x1b[38;2;255;136;0m"));
+		assert_eq!(parse_color_spec("on-#000000 italic").as_deref(), Some("\x1b[48;2;0;0;0;3m"));
+		assert_eq!(parse_color_spec("none").as_deref(), Some(""));
+		assert_eq!(parse_color_spec("chartreuse"), None);
+		assert_eq!(parse_color_spec(""), None);
+
+		assert_eq!(Style::from_name("hl.keyword"), Some(Style::HlKeyword));
+		set_style(Style::Warning, "underline magenta").unwrap();
+		assert_eq!(Style::Warning.code(), "\x1b[4;35m");
+		assert!(set_style(Style::Warning, "nope").is_err());
+		reset_style(Some(Style::Warning));
+		assert_eq!(Style::Warning.code(), Style::Warning.default_code());
+	}
+
+	#[test]
+	fn test_display_width() {
+		assert_eq!(display_width("abc"), 3);
+		assert_eq!(display_width("日本語"), 6);
+		assert_eq!(display_width("e\u{301}"), 1);
+		assert_eq!(display_width("👨\u{200d}👩\u{200d}👧"), 2);
+		assert_eq!(display_width("\x1b[1;31m❯\x1b[0m "), 2);
+		assert_eq!(display_width("\x1b]0;title\x07$ "), 2);
+		assert_eq!(display_width("\x1b]8;;file:///\x1b\\~\x1b]8;;\x1b\\ "), 2);
+
+		let prompt = "\x01\x1b]0;term\x07\x02λ ";
+		assert_eq!(visible_text(prompt), "λ ");
+		assert_eq!(strip_ignore_markers(prompt), "\x1b]0;term\x07λ ");
+	}
+
+	#[test]
+	fn test_expand_tabs() {
+		assert_eq!(expand_tabs("a\tb", 8), "a       b");
+		assert_eq!(expand_tabs("\tx\t", 4), "    x   ");
+		assert_eq!(expand_tabs("日本\tx", 4), "日本    x");
+		assert_eq!(expand_tabs("no tabs", 0), "no tabs");
+		assert_eq!(column_of("\t\techo", 2, 4), 8);
+		assert_eq!(column_of("日\tx", 2, 8), 8);
+	}
+}
@@ -32,13 +32,21 @@ pub static REGEX: Lazy<HashMap<&'static str, Regex>> = Lazy::new(|| {
 	regex
 });
 
-pub const SHELL_CMDS: [&str;6] = [
+/// The shell's own executable, which runs files that have no shebang and aren't binaries either
+/// It is resolved by `main()` before anything else runs, so that a child never has to look it up
+pub static SHELL_EXE: Lazy<Option<CString>> = Lazy::new(|| {
+	let path = std::env::current_exe().ok()?;
+	CString::new(std::os::unix::ffi::OsStringExt::into_vec(path.into_os_string())).ok()
+});
+
+pub const SHELL_CMDS: [&str;7] = [
 	"for",
 	"while",
 	"select",
 	"match",
 	"until",
-	"if"
+	"if",
+	"coproc"
 ];
 
 bitflags::bitflags! {
@@ -51,6 +59,8 @@ bitflags::bitflags! {
 		const NO_RESET_IN   = 0b00000000000000000000000000010000;
 		const NO_RESET_OUT  = 0b00000000000000000000000000100000;
 		const NO_RESET_ERR  = 0b00000000000000000000000001000000;
+		const DISOWN        = 0b00000000000000000000000010000000;
+		const IN_COND       = 0b00000000000000000000000100000000; // In the condition of an if or while, where failures are expected
 	}
 }
 
@@ -60,7 +70,10 @@ pub struct Redir {
 	redir_type: Rule,
 	our_fd: i32,
 	their_fd: Option<i32>,
-	file_target: Option<PathBuf>
+	file_target: Option<PathBuf>,
+	/// Whether `their_fd` belongs to this redirection and is closed once it has been duplicated, like a pipe end
+	/// Descriptors named by the user, as in `>&3`, are left open for later commands
+	close_target: bool
 }
 
 impl Redir {
@@ -71,6 +84,7 @@ impl Redir {
 			let mut our_fd = None;
 			let mut their_fd = None;
 			let mut file_target = None;
+			let mut close = false;
 			while let Some(pair) = inner.next() {
 				match pair.as_rule() {
 					Rule::fd_out => {
@@ -92,22 +106,27 @@ impl Redir {
 					Rule::append |
 					Rule::heredoc |
 					Rule::herestring => redir_type = Some(pair.as_rule()),
+					Rule::fd_close => close = true,
 					_ => unreachable!()
 				}
 			}
 			let our_fd = our_fd.unwrap_or(match redir_type.unwrap() {
 				Rule::r#in |
+				Rule::in_out |
 				Rule::herestring |
 				Rule::heredoc => 0,
 				_ => 1
 			});
 
+			// Closing a descriptor, like `3<&-`, is recorded as its own redirection type
+			let redir_type = if close { Rule::fd_close } else { redir_type.unwrap() };
 			Ok(
 				Self {
-					redir_type: redir_type.unwrap(),
+					redir_type,
 					our_fd,
 					their_fd,
-					file_target
+					file_target,
+					close_target: false
 				}
 			)
 		} else {
@@ -119,11 +138,25 @@ impl Redir {
 			0 => Rule::r#in,
 			_ => Rule::out
 		};
-		Self { redir_type, our_fd, their_fd: Some(their_fd), file_target: None }
+		Self { redir_type, our_fd, their_fd: Some(their_fd), file_target: None, close_target: true }
 	}
 	pub fn redir_type(&self) -> Rule {
 		self.redir_type
 	}
+	pub fn our_fd(&self) -> RawFd {
+		self.our_fd
+	}
+	pub fn set_file_target(&mut self, path: PathBuf) {
+		self.file_target = Some(path)
+	}
+	/// Describes what the descriptor is redirected to, either a file path or another descriptor like `&1`
+	pub fn target(&self) -> String {
+		match (&self.file_target, self.their_fd) {
+			(Some(path), _) => path.display().to_string(),
+			(None, Some(fd)) => format!("&{}", fd),
+			(None, None) => String::new()
+		}
+	}
 }
 
 #[derive(Debug)]
@@ -138,7 +171,7 @@ impl CmdRedirs {
 		let mut targets_fd = vec![];
 		let mut targets_file = vec![];
 		while let Some(redir) = redirs.pop_back() {
-			let Redir { redir_type: _, our_fd: _, their_fd, file_target: _ } = &redir;
+			let Redir { redir_type: _, our_fd: _, their_fd, file_target: _, close_target: _ } = &redir;
 			if their_fd.is_some() {
 				targets_fd.push(redir);
 			} else {
@@ -152,6 +185,13 @@ impl CmdRedirs {
 		self.open_their_fds()?;
 		Ok(())
 	}
+	/// Leaves the redirected descriptors open once this is dropped
+	/// Used by `exec`, whose redirections apply to the shell for the rest of the session
+	pub fn persist(mut self) {
+		for fd in self.open_fds.drain(..) {
+			fd.into_raw_fd();
+		}
+	}
 	pub fn close_all(mut self) -> SlashResult<()> {
 		for fd in self.open_fds.iter_mut() {
 			fd.close()?
@@ -160,30 +200,59 @@ impl CmdRedirs {
 	}
 	pub fn open_file_targets(&mut self) -> SlashResult<()> {
 		for redir in &self.targets_file {
-			let Redir { redir_type, our_fd, their_fd: _, file_target } = redir;
+			let Redir { redir_type, our_fd, their_fd: _, file_target, close_target: _ } = redir;
+			if *redir_type == Rule::fd_close {
+				close(*our_fd).ok();
+				continue
+			}
 			let src_fd = SmartFD::new(*our_fd)?;
 			let path = file_target.as_ref().unwrap(); // We know that there's a file target so unwrap is safe
+			if matches!(redir_type, Rule::herestring | Rule::heredoc) {
+				// The text is read from an in-memory file, which is rewound so that the command reads it from the start
+				let mut text_fd = SmartFD::new_memfd("heredoc", false)?;
+				// A herestring gets a trailing newline, while a heredoc's body already ends with one
+				let mut text = path.to_string_lossy().to_string();
+				if *redir_type == Rule::herestring {
+					text.push('\n');
+				}
+				text_fd.write_all(text.as_bytes())?;
+				nix::unistd::lseek(text_fd.as_raw_fd(), 0, nix::unistd::Whence::SeekSet).map_err(|_| Low(SlashErrLow::from_io()))?;
+				text_fd.dup2(&src_fd)?;
+				text_fd.close()?;
+				self.open_fds.push(src_fd);
+				continue
+			}
 			let flags = match redir_type {
 				Rule::r#in => OFlag::O_RDONLY,
-				Rule::out => OFlag::O_WRONLY | OFlag::O_CREAT | OFlag::O_TRUNC,
+				Rule::out | Rule::force_out => OFlag::O_WRONLY | OFlag::O_CREAT | OFlag::O_TRUNC,
 				Rule::append => OFlag::O_WRONLY | OFlag::O_CREAT | OFlag::O_APPEND,
+				Rule::in_out => OFlag::O_RDWR | OFlag::O_CREAT,
 				_ => unreachable!(),
 			};
 			let mode = Mode::from_bits(0o644).unwrap();
 			let mut file_fd = SmartFD::open(path, flags, mode)?;
-			file_fd.dup2(&src_fd)?;
-			file_fd.close()?;
+			if file_fd.as_raw_fd() == *our_fd {
+				// The file was opened on the descriptor it's meant for, which happens when that descriptor wasn't open yet
+				file_fd.into_raw_fd();
+			} else {
+				file_fd.dup2(&src_fd)?;
+				file_fd.close()?;
+			}
 			self.open_fds.push(src_fd);
 		}
 		Ok(())
 	}
 	pub fn open_their_fds(&mut self) -> SlashResult<()> {
 		for redir in &self.targets_fd {
-			let Redir { redir_type: _, our_fd, their_fd, file_target: _ } = redir;
+			let Redir { redir_type: _, our_fd, their_fd, file_target: _, close_target } = redir;
 			let mut tgt_fd = SmartFD::new(their_fd.unwrap())?;
 			let src_fd = SmartFD::new(*our_fd)?;
 			tgt_fd.dup2(&src_fd)?;
-			tgt_fd.close()?;
+			if *close_target {
+				tgt_fd.close()?;
+			} else {
+				tgt_fd.into_raw_fd();
+			}
 			self.open_fds.push(src_fd);
 		}
 		Ok(())
@@ -363,7 +432,7 @@ impl<'a> SmartFD {
 			MemFdCreateFlag::MFD_CLOEXEC
 		};
 		let fd = memfd_create(&c_name, flags).map_err(|_| Low(SlashErrLow::from_io()))?;
-		Ok(SmartFD { fd: fd.as_raw_fd() })
+		SmartFD::from_owned_fd(fd)
 	}
 
 	/// Wrapper for nix::unistd::pipe(), simply produces two `SmartFDs` that point to a read and write pipe respectfully
@@ -476,20 +545,61 @@ impl FromRawFd for SmartFD {
 	}
 }
 
+/// `nix::unistd::fork()` with SIGCHLD blocked until it returns
+/// fork() holds the allocator's locks while it runs, and the SIGCHLD handler allocates, so it would deadlock if it ran in between
+pub unsafe fn fork() -> nix::Result<ForkResult> {
+	let mut mask = nix::sys::signal::SigSet::empty();
+	let mut mask_backup = nix::sys::signal::SigSet::empty();
+	mask.add(Signal::SIGCHLD);
+	nix::sys::signal::pthread_sigmask(nix::sys::signal::SigmaskHow::SIG_BLOCK, Some(&mask), Some(&mut mask_backup)).ok();
+	let result = nix::unistd::fork();
+	nix::sys::signal::pthread_sigmask(nix::sys::signal::SigmaskHow::SIG_SETMASK, Some(&mask_backup), None).ok();
+	result
+}
+
+/// Replaces the current process with an external command
+/// `command` should already be resolved with `helper::which()`. A bare name means that the PATH search failed, so it isn't searched for again
 pub fn exec_external(command: CString, argv: Vec<CString>, envp: Vec<CString>,blame: Pair<Rule>) -> ! {
-	let Err(e) = execvpe(&command, &argv, &envp);
+	// execvpe() would hand files without a shebang to /bin/sh, so the path is run directly instead
+	let result = if command.as_bytes().contains(&b'/') {
+		execve(&command, &argv, &envp)
+	} else {
+		Err(Errno::ENOENT)
+	};
+	let Err(e) = result;
+	let name = command.to_string_lossy();
 	match e {
 		Errno::ENOENT => {
-			let error = High(SlashErrHigh::cmd_not_found(command.to_str().unwrap(), blame));
+			let error = High(SlashErrHigh::cmd_not_found(name, blame));
 			eprintln!("{}",error);
+			std::process::exit(127)
 		}
 		Errno::EACCES => {
-			let error = High(SlashErrHigh::no_permission(command.to_str().unwrap(), blame));
+			let error = High(SlashErrHigh::no_permission(name, blame));
+			eprintln!("{}",error);
+			// Found but not runnable, which includes directories
+			std::process::exit(126)
+		}
+		Errno::ENOEXEC => {
+			// A file without a shebang that isn't a binary either is run as a slash script, like other shells do
+			// It gets a fresh shell, which skips the rc file like any other script does
+			let mut script_argv = vec![CString::new("slash").unwrap(), CString::new("--no-rc").unwrap(), command.clone()];
+			script_argv.extend(argv.into_iter().skip(1));
+			if let Some(shell) = SHELL_EXE.as_ref() {
+				execve(shell, &script_argv, &envp).ok();
+			}
+			let error = High(SlashErrHigh::exec_err(format!("{}: cannot execute", name), blame));
+			eprintln!("{}",error);
+			std::process::exit(126)
+		}
+		// The command was found, but can't be run, e.g. ENOTDIR for a path through a file, ELOOP for a symlink cycle,
+		// or E2BIG for arguments that don't fit
+		_ => {
+			let error = High(SlashErrHigh::exec_err(format!("{}: {}", name, e.desc()), blame));
 			eprintln!("{}",error);
+			std::process::exit(126)
 		}
-		_ => unimplemented!("Case for `{}` not implemented", e.to_string())
 	}
-	std::process::exit(e as i32)
 }
 
 pub fn handle_parent_process<'a>(child: Pid, command: String, slash: &mut Slash) -> SlashResult<()> {
@@ -501,20 +611,59 @@ pub fn handle_parent_process<'a>(child: Pid, command: String, slash: &mut Slash)
 		.with_pgid(child)
 		.build();
 
-	helper::handle_fg(slash,job)?;
+	if slash.ctx().flags().contains(ExecFlags::BACKGROUND) {
+		helper::handle_bg(slash,job)?;
+	} else {
+		helper::handle_fg(slash,job)?;
+	}
 	Ok(())
 }
 
+/// Escapes a field for `--porcelain` output, which uses one record per line and tab separated fields
+pub fn escape_porcelain(field: &str) -> String {
+	field.replace('\\', "\\\\").replace('\t', "\\t").replace('\n', "\\n")
+}
+
+/// The lowest descriptor used for the shell's own copies of stdio
+/// Lower numbers are left free for scripts, so that `exec 3< file` can't land on a saved copy
+pub const SAVED_FD_MIN: RawFd = 10;
+
+/// Saves copies of the standard descriptors, to be put back with `restore_fds()`
+/// The copies are close-on-exec, so commands never inherit them
 pub fn save_fds() -> SlashResult<(SmartFD,SmartFD,SmartFD)> {
+	let save = |fd: RawFd| -> SlashResult<SmartFD> {
+		let saved = fcntl(fd, nix::fcntl::FcntlArg::F_DUPFD_CLOEXEC(SAVED_FD_MIN)).map_err(|_| Low(SlashErrLow::from_io()))?;
+		SmartFD::new(saved)
+	};
 	Ok((
-		SmartFD::from_stdin()?,
-		SmartFD::from_stdout()?,
-		SmartFD::from_stderr()?
+		save(STDIN_FILENO)?,
+		save(STDOUT_FILENO)?,
+		save(STDERR_FILENO)?
 	))
 }
 
+/// The size of the terminal that `fd` refers to, as columns and lines
+/// Returns `None` if `fd` isn't a terminal, or if the terminal doesn't know its size
+pub fn term_size(fd: RawFd) -> Option<(u16,u16)> {
+	let mut size = libc::winsize { ws_row: 0, ws_col: 0, ws_xpixel: 0, ws_ypixel: 0 };
+	let result = unsafe { libc::ioctl(fd, libc::TIOCGWINSZ, &mut size) };
+	(result == 0 && size.ws_col > 0 && size.ws_row > 0).then_some((size.ws_col, size.ws_row))
+}
+
+/// Lists the descriptors that the shell currently has open
+pub fn list_open_fds() -> Vec<RawFd> {
+	let Ok(entries) = std::fs::read_dir("/proc/self/fd") else {
+		return vec![]
+	};
+	let fds = entries.flatten()
+		.filter_map(|entry| entry.file_name().to_str()?.parse::<RawFd>().ok())
+		.collect::<Vec<_>>();
+	// The directory's own descriptor shows up in the listing, but it is closed by now
+	fds.into_iter().filter(|fd| fcntl(*fd, F_GETFD).is_ok()).collect()
+}
+
 pub fn restore_fds(mut stdio: (SmartFD,SmartFD,SmartFD), slash: &mut Slash) -> SlashResult<()> {
-	let flags = slash.ctx().flags();
+	let flags = slash.ctx().flags() | slash.meta_mut().take_kept_stdio();
 	if !flags.contains(ExecFlags::NO_RESET_IN) {
 		stdio.0.dup2(&0)?;
 		stdio.0.close()?;
@@ -529,3 +678,58 @@ pub fn restore_fds(mut stdio: (SmartFD,SmartFD,SmartFD), slash: &mut Slash) -> S
 	}
 	Ok(())
 }
+
+/// Runs `f` with copies of the standard descriptors saved, and puts them back afterwards, even if `f` failed
+/// Descriptors that `exec` redirected while `f` ran stay as they are, and stay marked for the commands around this one.
+/// Marks left by an earlier `exec` are set aside meanwhile, since the copies saved here already have its redirections
+pub fn with_saved_fds<T>(slash: &mut Slash, f: impl FnOnce(&mut Slash) -> SlashResult<T>) -> SlashResult<T> {
+	let kept_before = slash.meta_mut().take_kept_stdio();
+	let saved_fds = save_fds()?;
+	let result = f(slash);
+	let kept_now = slash.meta().kept_stdio();
+	let restored = restore_fds(saved_fds, slash);
+	slash.meta_mut().keep_stdio(kept_before | kept_now);
+	restored?;
+	result
+}
+
+/// Moves the rest of a test into a forked child, for tests that change what the whole process shares, like the environment,
+/// the working directory, or the locale. Tests run on threads of one process, so the others would see the changes otherwise
+///
+/// The parent gets `None` once the child is done, and fails if the test failed there. The child gets a guard that ends it
+/// when the test returns or panics. The outcome comes back through a pipe, since another test's SIGCHLD handler may reap the child
+#[cfg(test)]
+pub fn in_own_process() -> Option<OwnProcess> {
+	static FORKING: Mutex<()> = Mutex::new(());
+	let _serial = FORKING.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+	let (r_pipe,w_pipe) = nix::unistd::pipe2(nix::fcntl::OFlag::O_CLOEXEC).unwrap();
+	// The job table is held across the fork, so that the child can't inherit it locked by another thread
+	match crate::shellenv::write_jobs(|_| unsafe { fork() }).unwrap().unwrap() {
+		ForkResult::Child => {
+			drop(r_pipe);
+			Some(OwnProcess(w_pipe))
+		}
+		ForkResult::Parent { child } => {
+			drop(w_pipe);
+			let mut outcome = String::new();
+			std::fs::File::from(r_pipe).read_to_string(&mut outcome).unwrap();
+			nix::sys::wait::waitpid(child, None).ok();
+			assert_eq!(outcome, "passed", "the test failed in its own process");
+			None
+		}
+	}
+}
+
+#[cfg(test)]
+pub struct OwnProcess(std::os::fd::OwnedFd);
+
+#[cfg(test)]
+impl Drop for OwnProcess {
+	fn drop(&mut self) {
+		let passed = !std::thread::panicking();
+		if passed {
+			nix::unistd::write(&self.0, b"passed").ok();
+		}
+		unsafe { libc::_exit(if passed { 0 } else { 1 }) }
+	}
+}